@@ -20,6 +20,12 @@ impl QueryRoot {
     let handle: &FloObserverEdgeHandle = ctx.data()?;
     handle.list_games().await.map_err(Into::into)
   }
+
+  async fn max_spectators(&self, ctx: &Context<'_>, game_id: i32) -> Result<Option<u32>> {
+    let handle: &FloObserverEdgeHandle = ctx.data()?;
+    let max = handle.get_max_spectators(game_id).await?;
+    Ok(max.map(|v| v as u32))
+  }
 }
 
 pub struct MutationRoot;
@@ -65,6 +71,25 @@ impl MutationRoot {
       token: flo_observer::token::create_observer_token(game_id, delay_secs)?,
     })
   }
+
+  /// Limits the number of spectators of a game, `null` removes the limit.
+  /// Lowering the limit does not disconnect existing spectators.
+  async fn set_max_spectators(
+    &self,
+    ctx: &Context<'_>,
+    game_id: i32,
+    max: Option<u32>,
+  ) -> Result<Option<u32>> {
+    let data: &RequestData = ctx.data()?;
+    if !data.is_admin {
+      return Err(Error::new("Only admin can set max spectators."));
+    }
+    let handle: &FloObserverEdgeHandle = ctx.data()?;
+    handle
+      .set_max_spectators(game_id, max.map(|v| v as usize))
+      .await?;
+    Ok(max)
+  }
 }
 
 #[derive(SimpleObject)]
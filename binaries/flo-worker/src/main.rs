@@ -1,9 +1,13 @@
 mod log;
 
 use anyhow::Result;
-use flo_client::StartConfig;
+use flo_client::{
+  LanGameOptions, MapSizeCheck, NodeReconnectPolicy, ObserverPlacement, StartConfig,
+};
 use std::io::Write;
+use std::net::Ipv4Addr;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::runtime::Runtime;
 
@@ -30,6 +34,92 @@ struct Opt {
 
   #[structopt(long)]
   ptr: Option<bool>,
+
+  #[structopt(long)]
+  lan_bind_addr: Option<Ipv4Addr>,
+
+  /// Wait for the `MapSize` packet before a LAN lobby is ready
+  #[structopt(long)]
+  lan_require_map_size: bool,
+
+  #[structopt(long)]
+  lan_map_size_timeout_secs: Option<u64>,
+
+  /// Slot of the FLO stream observer: `last`, `first-open` or a slot index
+  #[structopt(long, default_value = "last")]
+  lan_observer_slot: ObserverPlacement,
+
+  /// Require observers joining the LAN lobby to know this secret
+  #[structopt(long)]
+  lan_spectator_secret: Option<String>,
+
+  /// Write a W3GS packet capture of every LAN game to this directory
+  #[structopt(long, parse(from_os_str))]
+  lan_record_dir: Option<PathBuf>,
+
+  /// Delay the game by this many seconds when watching from an observer slot
+  #[structopt(long)]
+  lan_observer_delay_secs: Option<u64>,
+
+  /// Link sent in lobby chat to players who don't have the map
+  #[structopt(long)]
+  lan_map_download_url: Option<String>,
+
+  /// Highest suffix appended to a LAN game name that is already taken, e.g. `9` for ` (9)`
+  #[structopt(long, default_value = "9")]
+  lan_name_suffix_max: u32,
+
+  /// Keep the chat messages of LAN games so the UI can fetch them for moderation reports
+  #[structopt(long)]
+  lan_chat_log: bool,
+
+  /// Compare the game state checksums with the other players and report desyncs to the UI
+  #[structopt(long)]
+  lan_desync_monitor: bool,
+
+  /// Give up reconnecting to the node after this many seconds, `0` disables reconnecting
+  #[structopt(long)]
+  lan_reconnect_secs: Option<u64>,
+
+  /// Skip the LAN lobby countdown, for automated tests
+  #[cfg(debug_assertions)]
+  #[structopt(long)]
+  lan_instant_start: bool,
+}
+
+impl Opt {
+  fn lan_game_options(&self) -> LanGameOptions {
+    let mut map_size_check = MapSizeCheck::default();
+    map_size_check.assume_ok = !self.lan_require_map_size;
+    if let Some(secs) = self.lan_map_size_timeout_secs {
+      map_size_check.timeout = Duration::from_secs(secs);
+    }
+    let mut reconnect_policy = NodeReconnectPolicy::default();
+    if let Some(secs) = self.lan_reconnect_secs {
+      reconnect_policy.max_elapsed = Duration::from_secs(secs);
+    }
+    LanGameOptions {
+      bind_addr: self.lan_bind_addr,
+      port_range: None,
+      map_size_check,
+      observer_placement: self.lan_observer_slot,
+      spectator_secret: self.lan_spectator_secret.clone(),
+      record_dir: self.lan_record_dir.clone(),
+      observer_delay: self
+        .lan_observer_delay_secs
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs),
+      // the worker has no API to attach observers
+      max_observers: 0,
+      map_download_url: self.lan_map_download_url.clone(),
+      max_name_suffix: self.lan_name_suffix_max,
+      desync_monitor: self.lan_desync_monitor,
+      chat_log: self.lan_chat_log,
+      reconnect_policy,
+      #[cfg(debug_assertions)]
+      instant_start: self.lan_instant_start,
+    }
+  }
 }
 
 fn main() {
@@ -45,6 +135,7 @@ fn main() {
 
   log::init(opt.debug);
 
+  let lan_game_options = opt.lan_game_options();
   let res = std::panic::catch_unwind(|| -> Result<_> {
     let rt = Runtime::new()?;
     let client = rt.block_on(flo_client::start_ws(StartConfig {
@@ -54,6 +145,7 @@ fn main() {
       controller_host: opt.controller_host.clone(),
       version: opt.version.clone(),
       ptr: opt.ptr.clone(),
+      lan_game_options,
       ..Default::default()
     }))?;
     let port = client.port();
@@ -6,8 +6,10 @@ pub use crate::controller::stream::GameReceivedEvent;
 use crate::controller::stream::{ControllerEvent, ControllerEventData, PlayerSessionUpdateEvent};
 pub use crate::controller::stream::{ControllerStream, SendFrame};
 use crate::error::*;
+use crate::lan::game::{ChatLogEntry, ProxyStats};
 use crate::lan::{
-  KillLanGame, Lan, LanEvent, ReplaceLanGame, StopLanGame, UpdateLanGamePlayerStatus,
+  GetLanGameChatLog, GetLanGameProxyStats, KillLanGame, Lan, LanEvent, ReplaceLanGame,
+  SendLanGameObserverChat, SetLanGameAdvertised, StopLanGame, UpdateLanGamePlayerStatus,
   UpdateLanGameStatus,
 };
 use crate::message::messages::{self, OutgoingMessage};
@@ -41,6 +43,7 @@ pub struct ControllerClient {
   initial_token: Option<String>,
   mute_list: Vec<i32>,
   lobby_countdown_notify: Option<Arc<Notify>>,
+  force_start_notify: Option<Arc<Notify>>,
 }
 
 impl ControllerClient {
@@ -101,6 +104,7 @@ impl ControllerClient {
       player_token: event.player_token,
       game: event.game_info,
       lobby_countdown_notify: self.lobby_countdown_notify.clone(),
+      force_start_notify: self.force_start_notify.clone(),
     };
 
     if let Err(err) = self
@@ -164,6 +168,7 @@ impl Service<StartConfig> for ControllerClient {
       initial_token: registry.data().token.clone(),
       mute_list: vec![],
       lobby_countdown_notify: registry.data().lobby_countdown_notify.clone(),
+      force_start_notify: registry.data().force_start_notify.clone(),
     })
   }
 }
@@ -386,6 +391,13 @@ impl Handler<LanEvent> for ControllerClient {
       LanEvent::LanGameDisconnected { game_id } => {
         self.lan.notify(StopLanGame { game_id }).await.ok();
       }
+      LanEvent::GameLoaded { game_id } => {
+        self
+          .ws_send(OutgoingMessage::GameLoaded(messages::GameLoaded {
+            game_id,
+          }))
+          .await;
+      }
       LanEvent::NodeStreamEvent { game_id, inner } => match inner {
         NodeStreamEvent::SlotClientStatusUpdate(update) => {
           self
@@ -608,6 +620,42 @@ impl Handler<ClearNodeAddrOverrides> for ControllerClient {
   }
 }
 
+#[async_trait]
+impl Handler<GetLanGameProxyStats> for ControllerClient {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    msg: GetLanGameProxyStats,
+  ) -> Option<ProxyStats> {
+    self.lan.send(msg).await.ok().flatten()
+  }
+}
+
+#[async_trait]
+impl Handler<SetLanGameAdvertised> for ControllerClient {
+  async fn handle(&mut self, _: &mut Context<Self>, msg: SetLanGameAdvertised) {
+    self.lan.send(msg).await.ok();
+  }
+}
+
+#[async_trait]
+impl Handler<SendLanGameObserverChat> for ControllerClient {
+  async fn handle(&mut self, _: &mut Context<Self>, msg: SendLanGameObserverChat) {
+    self.lan.send(msg).await.ok();
+  }
+}
+
+#[async_trait]
+impl Handler<GetLanGameChatLog> for ControllerClient {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    msg: GetLanGameChatLog,
+  ) -> Option<Vec<ChatLogEntry>> {
+    self.lan.send(msg).await.ok().flatten()
+  }
+}
+
 pub struct GetWeakOutgoingMessageSender;
 
 impl Message for GetWeakOutgoingMessageSender {
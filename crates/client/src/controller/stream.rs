@@ -7,19 +7,26 @@ use crate::message::messages::OutgoingMessage;
 use crate::node::{AddNode, GetNodePingMap, NodeRegistry, RemoveNode, UpdateNodes};
 use crate::ping::PingUpdate;
 use crate::platform::{CalcMapChecksum, GetClientPlatformInfo, Platform};
+use flo_net::connect::Capabilities;
 use flo_net::packet::*;
 use flo_net::proto::flo_connect as proto;
 use flo_net::stream::FloStream;
 use flo_state::{async_trait, Actor, Addr, Context, Handler, Message};
 use flo_types::game::*;
+use flo_types::ping::PingStats;
 use s2_grpc_utils::S2ProtoPack;
 use s2_grpc_utils::{S2ProtoEnum, S2ProtoUnpack};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::time::sleep;
 use tracing_futures::Instrument;
 
+/// How long queued frames are given to reach the controller on shutdown
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(3);
+
 pub struct ControllerStream {
   id: u64,
   domain: String,
@@ -30,6 +37,10 @@ pub struct ControllerStream {
   current_game_info: Option<Arc<LocalGameInfo>>,
   platform: Addr<Platform>,
   nodes: Addr<NodeRegistry>,
+  /// Features supported by both sides, set once the connection is accepted
+  capabilities: Capabilities,
+  /// Dropped with the actor, the worker then drains the queued frames and exits
+  shutdown_tx: Option<oneshot::Sender<()>>,
 }
 
 impl ControllerStream {
@@ -52,38 +63,44 @@ impl ControllerStream {
       current_game_info: None,
       platform,
       nodes,
+      capabilities: Capabilities::empty(),
+      shutdown_tx: None,
     }
   }
 
   async fn report_ping(
     id: u64,
     frame_tx: Sender<Frame>,
+    owner: &Addr<Self>,
     parent: &Addr<ControllerClient>,
     nodes: &Addr<NodeRegistry>,
   ) -> Result<()> {
+    let capabilities = owner.send(GetCapabilities).await?;
     let ping_map = nodes.send(GetNodePingMap).await??;
+    // the UI gets the ping map even if the controller doesn't take it
+    let frame = if capabilities.contains(Capabilities::PING_MAP) {
+      let ping_map = ping_map
+        .iter()
+        .map(|(k, v)| Ok((*k, v.clone().pack()?)))
+        .collect::<Result<Vec<(i32, proto::PingStats)>>>()?
+        .into_iter()
+        .collect();
+      Some(proto::PacketPlayerPingMapUpdateRequest { ping_map }.encode_as_frame()?)
+    } else {
+      None
+    };
     parent
       .notify(SendWs::new(
         id,
-        OutgoingMessage::PingUpdate(PingUpdate {
-          ping_map: ping_map.clone(),
-        }),
+        OutgoingMessage::PingUpdate(PingUpdate { ping_map }),
       ))
       .await?;
-    frame_tx
-      .send(
-        proto::PacketPlayerPingMapUpdateRequest {
-          ping_map: ping_map
-            .into_iter()
-            .map(|(k, v)| Ok((k, v.pack()?)))
-            .collect::<Result<Vec<(i32, proto::PingStats)>>>()?
-            .into_iter()
-            .collect(),
-        }
-        .encode_as_frame()?,
-      )
-      .await
-      .map_err(|_| Error::TaskCancelled(anyhow::format_err!("controller stream worker gone")))?;
+    if let Some(frame) = frame {
+      frame_tx
+        .send(frame)
+        .await
+        .map_err(|_| Error::TaskCancelled(anyhow::format_err!("controller stream worker gone")))?;
+    }
     Ok(())
   }
 
@@ -92,6 +109,7 @@ impl ControllerStream {
     domain: &str,
     token: String,
     mut frame_receiver: Receiver<Frame>,
+    mut shutdown: oneshot::Receiver<()>,
     owner: Addr<Self>,
     parent: Addr<ControllerClient>,
     nodes_reg: Addr<NodeRegistry>,
@@ -99,30 +117,43 @@ impl ControllerStream {
     let addr = format!("{}:{}", domain, flo_constants::CONTROLLER_SOCKET_PORT);
     tracing::debug!("connect addr: {}", addr);
 
-    let mut stream = FloStream::connect_no_delay(addr).await?;
+    let handshake = async {
+      let mut stream = FloStream::connect_no_delay(addr).await?;
 
-    tracing::debug!("connected");
+      tracing::debug!("connected");
 
-    stream
-      .send(proto::PacketClientConnect {
-        connect_version: Some(crate::version::FLO_VERSION.into()),
-        token,
-      })
-      .await?;
+      stream
+        .send(proto::PacketClientConnect {
+          connect_version: Some(crate::version::FLO_VERSION.into()),
+          token,
+          capabilities: Some(Capabilities::SUPPORTED.bits()),
+        })
+        .await?;
 
-    let reply = stream.recv_frame().await?;
+      let reply = stream.recv_frame().await?;
 
-    let (session, nodes): (PlayerSession, _) = flo_net::try_flo_packet! {
-      reply => {
-        p: proto::PacketClientConnectAccept => {
-          (
-            PlayerSession::unpack(p.session)?,
-            p.nodes
-          )
-        }
-        p: proto::PacketClientConnectReject => {
-          return Err(Error::ConnectionRequestRejected(S2ProtoEnum::unpack_enum(p.reason())))
+      let (session, nodes, capabilities): (PlayerSession, _, _) = flo_net::try_flo_packet! {
+        reply => {
+          p: proto::PacketClientConnectAccept => {
+            (
+              PlayerSession::unpack(p.session)?,
+              p.nodes,
+              Capabilities::SUPPORTED.negotiate(p.capabilities)
+            )
+          }
+          p: proto::PacketClientConnectReject => {
+            return Err(Error::ConnectionRequestRejected(S2ProtoEnum::unpack_enum(p.reason())))
+          }
         }
+      };
+      Ok::<_, Error>((stream, session, nodes, capabilities))
+    };
+
+    let (mut stream, session, nodes, capabilities) = tokio::select! {
+      res = handshake => res?,
+      _ = &mut shutdown => {
+        tracing::debug!("exiting: shutdown before connected");
+        return Ok(())
       }
     };
 
@@ -130,11 +161,14 @@ impl ControllerStream {
 
     tracing::debug!(
       player_id,
-      "player = {}, status = {:?}",
+      "player = {}, status = {:?}, capabilities = {:?}",
       session.player.id,
-      session.status
+      session.status,
+      capabilities
     );
 
+    owner.send(SetCapabilities(capabilities)).await?;
+
     parent
       .notify(ControllerEventData::Connected.wrap(id))
       .await?;
@@ -153,9 +187,18 @@ impl ControllerStream {
       .await?;
 
     let mut disconnect_handled = false;
+    let mut drain = false;
 
     loop {
       tokio::select! {
+        _ = &mut shutdown => {
+          tracing::debug!("exiting: shutdown");
+          let sent = drain_frames(&mut stream, &mut frame_receiver, DRAIN_TIMEOUT).await;
+          if sent > 0 {
+            tracing::debug!("drained {} frames", sent);
+          }
+          return Ok(())
+        }
         next_send = frame_receiver.recv() => {
           if let Some(frame) = next_send {
             match stream.send_frame_timeout(frame).await {
@@ -167,6 +210,7 @@ impl ControllerStream {
             }
           } else {
             tracing::debug!("exiting: sender dropped");
+            drain = true;
             break;
           }
         }
@@ -200,6 +244,7 @@ impl ControllerStream {
             },
             Err(e) => {
               tracing::debug!("exiting: recv: {}", e);
+              drain = matches!(e, flo_net::error::Error::StreamClosed);
               break;
             }
           }
@@ -207,6 +252,13 @@ impl ControllerStream {
       }
     }
 
+    if drain {
+      let sent = drain_frames(&mut stream, &mut frame_receiver, DRAIN_TIMEOUT).await;
+      if sent > 0 {
+        tracing::debug!("drained {} frames", sent);
+      }
+    }
+
     if !disconnect_handled {
       parent
         .notify(SendWs::new(
@@ -242,7 +294,7 @@ impl ControllerStream {
       frame => {
         p: proto::PacketClientDisconnect => {
           SendWs::new(id, OutgoingMessage::Disconnect(messages::Disconnect {
-              reason: S2ProtoEnum::unpack_i32(p.reason)?,
+              reason: messages::DisconnectReason::unpack_i32_or_unknown(p.reason),
               message: format!("Server closed the connection: {:?}", p.reason)
             })).notify(parent).await?;
         }
@@ -256,7 +308,22 @@ impl ControllerStream {
           let local_game_info = Arc::new(local_game_from_game_info(player_id, &game)?);
           owner.send(SetLocalGameInfo(local_game_info.clone().into())).await??;
 
-          SendWs::new(id, OutgoingMessage::CurrentGameInfo(game)).notify(parent).await?;
+          // the game info is sent without the ping rather than not at all
+          let ping_map = match nodes.send(GetNodePingMap).await {
+            Ok(Ok(ping_map)) => ping_map,
+            Ok(Err(err)) => {
+              tracing::warn!("get node ping map: {}", err);
+              BTreeMap::new()
+            }
+            Err(err) => {
+              tracing::warn!("get node ping map: {}", err);
+              BTreeMap::new()
+            }
+          };
+          SendWs::new(
+            id,
+            OutgoingMessage::CurrentGameInfo(current_game_info(game, &ping_map))
+          ).notify(parent).await?;
         }
         p: proto::PacketGamePlayerEnter => {
           let slot_index = p.slot_index;
@@ -314,6 +381,18 @@ impl ControllerStream {
             OutgoingMessage::GameSlotUpdate(S2ProtoUnpack::unpack(p)?)
           ).notify(parent).await?;
         }
+        p: proto::PacketServerConfig => {
+          SendWs::new(
+            id,
+            OutgoingMessage::ServerConfig(p)
+          ).notify(parent).await?;
+        }
+        p: proto::PacketGameLobbyMessage => {
+          SendWs::new(
+            id,
+            OutgoingMessage::GameLobbyMessage(p)
+          ).notify(parent).await?;
+        }
         p: proto::PacketPlayerSessionUpdate => {
           let session = PlayerSessionUpdate::unpack(p)?;
           parent.notify(ControllerEventData::PlayerSessionUpdate(PlayerSessionUpdateEvent::Partial(session.clone())).wrap(id)).await?;
@@ -439,16 +518,19 @@ impl Actor for ControllerStream {
       self.frame_tx = frame_tx;
       frame_rx
     };
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    self.shutdown_tx = Some(shutdown_tx);
 
     ctx.spawn({
       let id = self.id;
       let frame_tx = self.frame_tx.clone();
+      let owner = ctx.addr();
       let parent = self.parent.clone();
       let nodes = self.nodes.clone();
       async move {
         sleep(Duration::from_secs(2)).await;
         loop {
-          if let Err(err) = Self::report_ping(id, frame_tx.clone(), &parent, &nodes).await {
+          if let Err(err) = Self::report_ping(id, frame_tx.clone(), &owner, &parent, &nodes).await {
             tracing::error!("report ping: {}", err)
           }
           sleep(Duration::from_secs(5)).await;
@@ -456,7 +538,8 @@ impl Actor for ControllerStream {
       }
     });
 
-    ctx.spawn(
+    // not tied to the actor, so the queued frames can be drained after it stopped
+    tokio::spawn(
       {
         let id = self.id;
         let domain = self.domain.clone();
@@ -465,9 +548,17 @@ impl Actor for ControllerStream {
         let parent = self.parent.clone();
         let nodes = self.nodes.clone();
         async move {
-          if let Err(err) =
-            Self::connect_and_serve(id, &domain, token, frame_rx, owner, parent.clone(), nodes)
-              .await
+          if let Err(err) = Self::connect_and_serve(
+            id,
+            &domain,
+            token,
+            frame_rx,
+            shutdown_rx,
+            owner,
+            parent.clone(),
+            nodes,
+          )
+          .await
           {
             tracing::error!("controller stream error: {}", err);
 
@@ -556,6 +647,36 @@ where
   }
 }
 
+struct SetCapabilities(Capabilities);
+
+impl Message for SetCapabilities {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<SetCapabilities> for ControllerStream {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    SetCapabilities(capabilities): SetCapabilities,
+  ) {
+    self.capabilities = capabilities;
+  }
+}
+
+struct GetCapabilities;
+
+impl Message for GetCapabilities {
+  type Result = Capabilities;
+}
+
+#[async_trait]
+impl Handler<GetCapabilities> for ControllerStream {
+  async fn handle(&mut self, _: &mut Context<Self>, _: GetCapabilities) -> Capabilities {
+    self.capabilities
+  }
+}
+
 struct SetLocalGameInfo(Option<Arc<LocalGameInfo>>);
 
 impl Message for SetLocalGameInfo {
@@ -731,3 +852,190 @@ pub struct GameReceivedEvent {
   pub game_info: Arc<LocalGameInfo>,
   pub player_token: Vec<u8>,
 }
+
+/// Sends frames still queued in `rx`, gives up on the first error or after `timeout`
+async fn drain_frames(
+  stream: &mut FloStream,
+  rx: &mut Receiver<Frame>,
+  timeout: Duration,
+) -> usize {
+  let mut sent = 0;
+  let drain = async {
+    while let Ok(frame) = rx.try_recv() {
+      if let Err(err) = stream.send_frame(frame).await {
+        tracing::debug!("drain: send error: {}", err);
+        return;
+      }
+      sent += 1;
+    }
+    stream.flush().await.ok();
+  };
+  if tokio::time::timeout(timeout, drain).await.is_err() {
+    tracing::debug!("drain: timeout");
+  }
+  sent
+}
+
+fn current_game_info(
+  game: GameInfo,
+  ping_map: &BTreeMap<i32, PingStats>,
+) -> messages::CurrentGameInfo {
+  let node_ping = game
+    .node
+    .as_ref()
+    .and_then(|node| ping_map.get(&node.id))
+    .and_then(|stats| stats.current);
+  messages::CurrentGameInfo { game, node_ping }
+}
+
+#[tokio::test]
+async fn test_drain_frames() {
+  use std::net::{Ipv4Addr, SocketAddrV4};
+  use tokio::net::{TcpListener, TcpStream};
+
+  let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+    .await
+    .unwrap();
+  let addr = listener.local_addr().unwrap();
+  let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+  let mut client = FloStream::new(client.unwrap());
+  let mut server = FloStream::new(server.unwrap().0);
+
+  let (tx, mut rx) = channel(5);
+  for game_id in 0..3 {
+    tx.send(
+      proto::PacketGamePlayerLeave {
+        game_id,
+        ..Default::default()
+      }
+      .encode_as_frame()
+      .unwrap(),
+    )
+    .await
+    .unwrap();
+  }
+  drop(tx);
+
+  assert_eq!(drain_frames(&mut client, &mut rx, DRAIN_TIMEOUT).await, 3);
+  for game_id in 0..3 {
+    let p: proto::PacketGamePlayerLeave = server.recv().await.unwrap();
+    assert_eq!(p.game_id, game_id);
+  }
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_frames() {
+  use flo_net::listener::FloListener;
+  use flo_state::mock::Mock;
+  use futures::StreamExt;
+
+  async fn handle_event(_: ControllerEvent) {}
+  async fn handle_send_ws(_: SendWs) {}
+  async fn handle_update_nodes(_: UpdateNodes) -> Result<()> {
+    Ok(())
+  }
+
+  let parent = Mock::<ControllerClient>::builder()
+    .handle(handle_event)
+    .handle(handle_send_ws)
+    .handle(handle_update_nodes)
+    .build();
+  let platform = Mock::<Platform>::builder().build();
+  let nodes = Mock::<NodeRegistry>::builder().build();
+
+  let mut listener = FloListener::bind_v4(flo_constants::CONTROLLER_SOCKET_PORT)
+    .await
+    .unwrap();
+  let (accepted_tx, accepted_rx) = oneshot::channel::<()>();
+
+  // stub controller: accepts the connection, then collects frames until the worker hangs up
+  let controller = tokio::spawn(async move {
+    let mut stream = listener.incoming().next().await.unwrap().unwrap();
+    stream.recv::<proto::PacketClientConnect>().await.unwrap();
+    stream
+      .send(proto::PacketClientConnectAccept {
+        session: Some(proto::Session {
+          player: Some(proto::PlayerInfo {
+            id: 1,
+            ..Default::default()
+          }),
+          ..Default::default()
+        }),
+        ..Default::default()
+      })
+      .await
+      .unwrap();
+    accepted_tx.send(()).unwrap();
+
+    let mut game_ids = vec![];
+    while let Ok(frame) = stream.recv_frame().await {
+      if frame.type_id == proto::PacketGamePlayerLeave::TYPE_ID {
+        let p: proto::PacketGamePlayerLeave = frame.decode().unwrap();
+        game_ids.push(p.game_id);
+      }
+    }
+    game_ids
+  });
+
+  let stream = ControllerStream::new(
+    parent.addr(),
+    platform.addr(),
+    nodes.addr(),
+    1,
+    "127.0.0.1",
+    "token".to_string(),
+  )
+  .start();
+  accepted_rx.await.unwrap();
+
+  for game_id in 0..3 {
+    let frame = proto::PacketGamePlayerLeave {
+      game_id,
+      ..Default::default()
+    }
+    .encode_as_frame()
+    .unwrap();
+    stream.send(SendFrame(frame)).await.unwrap().unwrap();
+  }
+  stream.shutdown().await.unwrap();
+
+  let game_ids = tokio::time::timeout(DRAIN_TIMEOUT * 2, controller)
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(game_ids, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_current_game_info_node_ping() {
+  let game = GameInfo::unpack(proto::GameInfo {
+    id: 1,
+    map: Some(Default::default()),
+    node: Some(proto::Node {
+      id: 2,
+      ..Default::default()
+    }),
+    ..Default::default()
+  })
+  .unwrap();
+  let mut ping_map = BTreeMap::new();
+
+  assert_eq!(current_game_info(game.clone(), &ping_map).node_ping, None);
+
+  ping_map.insert(2, PingStats::default());
+  assert_eq!(current_game_info(game.clone(), &ping_map).node_ping, None);
+
+  ping_map.insert(
+    2,
+    PingStats {
+      current: Some(42),
+      ..Default::default()
+    },
+  );
+  let msg = OutgoingMessage::CurrentGameInfo(current_game_info(game, &ping_map));
+  let value: serde_json::Value = serde_json::from_str(&msg.serialize().unwrap()).unwrap();
+  assert_eq!(value["type"], "CurrentGameInfo");
+  assert_eq!(value["id"], 1);
+  assert_eq!(value["node"]["id"], 2);
+  assert_eq!(value["node_ping"], 42);
+}
@@ -20,10 +20,20 @@ pub enum Error {
   GameVersionMismatch,
   #[error("FLO observer slot occupied")]
   FloObserverSlotOccupied,
+  #[error("Observer limit reached: {0}")]
+  ObserverLimitReached(usize),
   #[error("Unexpected w3gs packet: {0:?}")]
   UnexpectedW3GSPacket(flo_w3gs::packet::Packet),
   #[error("Slot not resolved")]
   SlotNotResolved,
+  #[error("Invalid slot layout: {0}")]
+  InvalidSlotLayout(String),
+  #[error("Invalid handicap for slot {slot_index}: {handicap}")]
+  InvalidHandicap { slot_index: usize, handicap: u8 },
+  #[error("Invalid color for slot {slot_index}: {color}")]
+  InvalidSlotColor { slot_index: usize, color: u8 },
+  #[error("Game client can't connect to IPv6 address {0}, set a LAN bind address")]
+  LanIpv6AddrNotSupported(std::net::Ipv6Addr),
   #[error("Stream closed unexpectedly")]
   StreamClosed,
   #[error("Disconnected from Flo controller")]
@@ -1,5 +1,5 @@
 use crate::error::{Error, Result};
-use flo_types::game::{GameInfo, LocalGameInfo};
+use flo_types::game::{GameFlags, GameInfo, LocalGameInfo};
 
 pub fn local_game_from_game_info(player_id: i32, game: &GameInfo) -> Result<LocalGameInfo> {
   Ok(LocalGameInfo {
@@ -26,6 +26,7 @@ pub fn local_game_from_game_info(player_id: i32, game: &GameInfo) -> Result<Loca
     slots: game.slots.clone(),
     host_player: game.created_by.clone(),
     mask_player_names: game.mask_player_names,
+    game_flags: GameFlags::from_bits_truncate(game.game_flags),
     map_twelve_p: game.map.twelve_p,
   })
 }
@@ -1,14 +1,14 @@
 use crate::error::Result;
 use crate::game::local_game_from_game_info;
 //use crate::game::LocalGameInfo;
-use crate::lan::game::{LanGameInfo, LobbyAction, LobbyHandler};
+use crate::lan::game::slot::SlotInfoOptions;
+use crate::lan::game::{LanGameInfo, LobbyAction, LobbyHandler, W3gsProfile};
 use crate::messages::OutgoingMessage;
 use flo_lan::MdnsPublisher;
 use flo_types::game::{
   GameInfo, GameStatus, Map, PlayerInfo, PlayerSource, Slot, SlotSettings, SlotStatus,
 };
 use flo_types::node::SlotClientStatus;
-use flo_util::binary::CString;
 use flo_w3gs::constants::GameSettingFlags;
 use flo_w3gs::game::GameSettings;
 use flo_w3gs::net::W3GSListener;
@@ -29,6 +29,62 @@ pub async fn run_test_lobby(
   weak_outgoing_tx: WeakSender<OutgoingMessage>,
 ) -> Result<Option<LobbyAction>> {
   let map_sha1 = map_checksum.sha1;
+  let info = test_lan_game_info(
+    name,
+    map_path,
+    map_twelve_p,
+    map_width,
+    map_height,
+    map_checksum,
+  )?;
+
+  let (_tx, mut rx) = channel(None);
+
+  let mut listener = W3GSListener::bind().await.unwrap();
+  tracing::debug!("listening on {}", listener.local_addr());
+  let port = listener.port();
+
+  let lan_game_info = {
+    let mut game_info = flo_lan::GameInfo::new(1, name, map_path, map_sha1, 0xFFFFFFFF)?;
+    game_info.set_port(port);
+    game_info
+  };
+
+  let _p = MdnsPublisher::start(
+    game_version,
+    lan_game_info,
+    None,
+    flo_lan::DEFAULT_MAX_NAME_SUFFIX,
+  )
+  .await?;
+
+  while let Some(mut stream) = listener.incoming().try_next().await? {
+    return LobbyHandler::new(
+      &info,
+      &mut stream,
+      None,
+      &mut rx,
+      Some(weak_outgoing_tx),
+      None,
+    )
+    .run()
+    .await
+    .map(Some);
+  }
+
+  Ok(None)
+}
+
+/// Single player game used by the test lobby
+pub(crate) fn test_lan_game_info(
+  name: &str,
+  map_path: &str,
+  map_twelve_p: bool,
+  map_width: u16,
+  map_height: u16,
+  map_checksum: MapChecksum,
+) -> Result<LanGameInfo> {
+  let map_sha1 = map_checksum.sha1;
 
   let player = PlayerInfo {
     id: 1,
@@ -60,62 +116,40 @@ pub async fn run_test_lobby(
     random_seed: 0,
     created_by: None,
     mask_player_names: false,
+    game_flags: 0,
   };
 
-  let info = LanGameInfo {
+  Ok(LanGameInfo {
     game: Arc::new(local_game_from_game_info(1, &game)?),
     slot_info: crate::lan::game::slot::build_player_slot_info(
       1,
       game.random_seed,
       &game.slots,
       map_twelve_p,
+      SlotInfoOptions::default(),
     )?,
     map_checksum,
-    game_settings: GameSettings {
-      game_setting_flags: GameSettingFlags::SPEED_FAST
-        | GameSettingFlags::TERRAIN_DEFAULT
-        | GameSettingFlags::OBS_ENABLED
-        | GameSettingFlags::OBS_FULL
-        | GameSettingFlags::TEAMS_TOGETHER
-        | GameSettingFlags::TEAMS_FIXED,
-      unk_1: 0,
-      map_width,
-      map_height,
-      map_checksum: 0xFFFFFFFF,
-      map_path: CString::new(map_path).map_err(|_| flo_lan::error::Error::NullByteInString)?,
-      host_name: CString::new("FLO").unwrap(),
-      map_sha1,
-    },
+    game_settings: GameSettings::builder(map_path, map_sha1, 0xFFFFFFFF)
+      .flags(
+        GameSettingFlags::SPEED_FAST
+          | GameSettingFlags::TERRAIN_DEFAULT
+          | GameSettingFlags::OBS_ENABLED
+          | GameSettingFlags::OBS_FULL
+          | GameSettingFlags::TEAMS_TOGETHER
+          | GameSettingFlags::TEAMS_FIXED,
+      )
+      .map_size(map_width, map_height)
+      .build(),
     lan_game_name_override: Some(name.to_string()),
-  };
-
-  let (_tx, mut rx) = channel(None);
-
-  let mut listener = W3GSListener::bind().await.unwrap();
-  tracing::debug!("listening on {}", listener.local_addr());
-  let port = listener.port();
-
-  let lan_game_info = {
-    let mut game_info = flo_lan::GameInfo::new(1, name, map_path, map_sha1, 0xFFFFFFFF)?;
-    game_info.set_port(port);
-    game_info
-  };
-
-  let _p = MdnsPublisher::start(game_version, lan_game_info).await?;
-
-  while let Some(mut stream) = listener.incoming().try_next().await? {
-    return LobbyHandler::new(
-      &info,
-      &mut stream,
-      None,
-      &mut rx,
-      Some(weak_outgoing_tx),
-      None,
-    )
-    .run()
-    .await
-    .map(Some);
-  }
-
-  Ok(None)
+    bind_addr: None,
+    w3gs_profile: W3gsProfile::default(),
+    spectator_secret: None,
+    observer_delay: None,
+    desync_monitor: false,
+    chat_log: false,
+    instant_start: false,
+    max_observers: 0,
+    map_download_url: None,
+    map_size_check: Default::default(),
+  })
 }
@@ -0,0 +1,83 @@
+use crate::error::*;
+use flo_w3gs::capture::{CaptureDirection, CaptureRecord};
+use flo_w3gs::protocol::packet::Packet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+const CAPTURE_CHANNEL_SIZE: usize = 1024;
+
+/// Writes packets forwarded by the proxy to a capture file on a dedicated thread.
+/// Recording never waits for the writer, packets are dropped if it falls behind.
+#[derive(Debug)]
+pub struct PacketRecorder {
+  start: Instant,
+  tx: mpsc::Sender<CaptureRecord>,
+  dropped: AtomicU64,
+}
+
+impl PacketRecorder {
+  pub fn start(path: PathBuf) -> Result<Self> {
+    let file = File::create(&path)?;
+    let (tx, rx) = mpsc::channel(CAPTURE_CHANNEL_SIZE);
+    std::thread::Builder::new()
+      .name("w3gs-capture".to_string())
+      .spawn(move || write_records(path, BufWriter::new(file), rx))?;
+    Ok(Self {
+      start: Instant::now(),
+      tx,
+      dropped: AtomicU64::new(0),
+    })
+  }
+
+  /// Records a packet forwarded from the game to the node
+  pub fn record_sent(&self, pkt: &Packet) {
+    self.record(CaptureDirection::Sent, pkt)
+  }
+
+  /// Records a packet forwarded from the node to the game
+  pub fn record_received(&self, pkt: &Packet) {
+    self.record(CaptureDirection::Received, pkt)
+  }
+
+  fn record(&self, direction: CaptureDirection, pkt: &Packet) {
+    let record = CaptureRecord {
+      direction,
+      time: self.start.elapsed(),
+      packet: pkt.clone(),
+    };
+    match self.tx.try_send(record) {
+      Ok(_) => {}
+      Err(TrySendError::Full(_)) => {
+        if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+          tracing::warn!("capture writer is falling behind, dropping packets");
+        }
+      }
+      Err(TrySendError::Closed(_)) => {}
+    }
+  }
+}
+
+impl Drop for PacketRecorder {
+  fn drop(&mut self) {
+    let dropped = self.dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+      tracing::warn!("capture dropped {} packets", dropped);
+    }
+  }
+}
+
+fn write_records(path: PathBuf, mut w: BufWriter<File>, mut rx: mpsc::Receiver<CaptureRecord>) {
+  while let Some(record) = rx.blocking_recv() {
+    if let Err(err) = record.write_to(&mut w) {
+      tracing::error!("write capture `{}`: {}", path.display(), err);
+      return;
+    }
+  }
+  if let Err(err) = w.flush() {
+    tracing::error!("flush capture `{}`: {}", path.display(), err);
+  }
+}
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Upper bound of message bytes kept in a game's chat log
+pub const CHAT_LOG_MAX_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatLogEntry {
+  pub time: Instant,
+  pub player_id: i32,
+  pub message: String,
+}
+
+/// Chat messages relayed during a game, kept for moderation review.
+///
+/// Messages are stored as sent. When `max_bytes` is exceeded the oldest messages are dropped.
+#[derive(Debug)]
+pub struct ChatLog {
+  max_bytes: usize,
+  bytes: usize,
+  entries: VecDeque<ChatLogEntry>,
+}
+
+impl ChatLog {
+  pub fn new(max_bytes: usize) -> Self {
+    Self {
+      max_bytes,
+      bytes: 0,
+      entries: VecDeque::new(),
+    }
+  }
+
+  pub fn push(&mut self, time: Instant, player_id: i32, message: String) {
+    self.bytes += message.len();
+    self.entries.push_back(ChatLogEntry {
+      time,
+      player_id,
+      message,
+    });
+    while self.bytes > self.max_bytes {
+      match self.entries.pop_front() {
+        Some(entry) => self.bytes -= entry.message.len(),
+        None => break,
+      }
+    }
+  }
+
+  /// Messages oldest first
+  pub fn to_vec(&self) -> Vec<ChatLogEntry> {
+    self.entries.iter().cloned().collect()
+  }
+}
+
+#[test]
+fn test_chat_log() {
+  use std::time::Duration;
+
+  let t = Instant::now();
+  let mut log = ChatLog::new(10);
+  log.push(t, 1, "glhf".to_string());
+  log.push(t + Duration::from_secs(1), 2, "gg".to_string());
+  assert_eq!(
+    log.to_vec(),
+    vec![
+      ChatLogEntry {
+        time: t,
+        player_id: 1,
+        message: "glhf".to_string(),
+      },
+      ChatLogEntry {
+        time: t + Duration::from_secs(1),
+        player_id: 2,
+        message: "gg".to_string(),
+      },
+    ]
+  );
+
+  // over the cap, the oldest message is dropped
+  log.push(t + Duration::from_secs(2), 1, "nooooo".to_string());
+  assert_eq!(
+    log
+      .to_vec()
+      .into_iter()
+      .map(|entry| entry.message)
+      .collect::<Vec<_>>(),
+    vec!["gg", "nooooo"]
+  );
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Upper bound of packets held for a delayed observer,
+/// about 15 minutes of a busy game
+pub const OBSERVER_DELAY_MAX_PACKETS: usize = 20_000;
+
+/// Holds items for `delay` before releasing them in order.
+///
+/// Time is passed in by the caller. Items are never released early,
+/// the caller stops pushing while the queue `is_full`.
+#[derive(Debug)]
+pub struct DelayQueue<T> {
+  delay: Duration,
+  capacity: usize,
+  items: VecDeque<(Instant, T)>,
+}
+
+impl<T> DelayQueue<T> {
+  pub fn new(delay: Duration, capacity: usize) -> Self {
+    Self {
+      delay,
+      capacity: capacity.max(1),
+      items: VecDeque::new(),
+    }
+  }
+
+  /// Queues `item` received at `now`
+  pub fn push(&mut self, now: Instant, item: T) {
+    debug_assert!(!self.is_full());
+    self.items.push_back((now + self.delay, item));
+  }
+
+  /// Holds `capacity` items, nothing can be pushed until the oldest is released
+  pub fn is_full(&self) -> bool {
+    self.items.len() >= self.capacity
+  }
+
+  /// Releases the oldest item if its delay elapsed at `now`
+  pub fn pop_ready(&mut self, now: Instant) -> Option<T> {
+    match self.items.front() {
+      Some((deadline, _)) if *deadline <= now => self.items.pop_front().map(|(_, item)| item),
+      _ => None,
+    }
+  }
+
+  /// When the oldest item becomes ready
+  pub fn next_deadline(&self) -> Option<Instant> {
+    self.items.front().map(|(deadline, _)| *deadline)
+  }
+
+  /// Releases all items regardless of their delay
+  pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+    self.items.drain(..).map(|(_, item)| item)
+  }
+}
+
+#[test]
+fn test_delay_queue() {
+  let t = Instant::now();
+  let mut q = DelayQueue::new(Duration::from_secs(10), 8);
+  q.push(t, 1);
+  q.push(t + Duration::from_secs(1), 2);
+  assert_eq!(q.next_deadline(), Some(t + Duration::from_secs(10)));
+
+  assert_eq!(q.pop_ready(t), None);
+  assert_eq!(q.pop_ready(t + Duration::from_millis(9999)), None);
+  assert_eq!(q.pop_ready(t + Duration::from_secs(10)), Some(1));
+  assert_eq!(q.pop_ready(t + Duration::from_secs(10)), None);
+  assert_eq!(q.pop_ready(t + Duration::from_secs(11)), Some(2));
+  assert_eq!(q.next_deadline(), None);
+}
+
+#[test]
+fn test_delay_queue_bounded() {
+  let t = Instant::now();
+  let mut q = DelayQueue::new(Duration::from_secs(10), 2);
+  q.push(t, 1);
+  assert!(!q.is_full());
+  q.push(t, 2);
+  assert!(q.is_full());
+
+  // nothing is released before its delay
+  assert_eq!(q.pop_ready(t + Duration::from_secs(9)), None);
+  assert!(q.is_full());
+  assert_eq!(q.pop_ready(t + Duration::from_secs(10)), Some(1));
+  assert!(!q.is_full());
+  q.push(t + Duration::from_secs(10), 3);
+
+  assert_eq!(q.drain().collect::<Vec<_>>(), vec![2, 3]);
+  assert!(!q.is_full());
+}
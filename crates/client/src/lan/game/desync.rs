@@ -0,0 +1,181 @@
+use crate::node::stream::TickChecksum;
+use std::collections::BTreeMap;
+
+/// Ticks kept while waiting for the other side of the comparison
+pub const DESYNC_MONITOR_MAX_PENDING_TICKS: usize = 512;
+
+#[derive(Debug, PartialEq)]
+pub struct Desync {
+  pub tick: u32,
+  /// Players whose checksum differs from the checksum the other players agreed on
+  pub players: Vec<i32>,
+}
+
+/// Compares the game state checksums the local client reports in `OutgoingKeepAlive`,
+/// one per tick, with the checksums all players agreed on at the node.
+///
+/// Only the checksums are kept, so the cost per packet is a map insert.
+#[derive(Debug)]
+pub struct DesyncMonitor {
+  player_id: i32,
+  tick: u32,
+  local: BTreeMap<u32, u32>,
+  node: BTreeMap<u32, u32>,
+}
+
+impl DesyncMonitor {
+  pub fn new(player_id: i32) -> Self {
+    DesyncMonitor {
+      player_id,
+      tick: 0,
+      local: BTreeMap::new(),
+      node: BTreeMap::new(),
+    }
+  }
+
+  /// Records the checksum of the next tick of the local client
+  pub fn record(&mut self, checksum: u32) -> Option<Desync> {
+    self.tick += 1;
+    match self.node.remove(&self.tick) {
+      Some(agreed) => self.check(self.tick, checksum, agreed),
+      None => {
+        insert_pending(&mut self.local, self.tick, checksum);
+        None
+      }
+    }
+  }
+
+  /// Records the checksum the players agreed on at the node
+  pub fn record_node(&mut self, TickChecksum { tick, checksum }: TickChecksum) -> Option<Desync> {
+    match self.local.remove(&tick) {
+      Some(local) => self.check(tick, local, checksum),
+      None => {
+        insert_pending(&mut self.node, tick, checksum);
+        None
+      }
+    }
+  }
+
+  fn check(&self, tick: u32, local: u32, agreed: u32) -> Option<Desync> {
+    if local == agreed {
+      return None;
+    }
+    Some(Desync {
+      tick,
+      players: vec![self.player_id],
+    })
+  }
+}
+
+fn insert_pending(map: &mut BTreeMap<u32, u32>, tick: u32, checksum: u32) {
+  map.insert(tick, checksum);
+  while map.len() > DESYNC_MONITOR_MAX_PENDING_TICKS {
+    let oldest = map.keys().next().cloned();
+    if let Some(oldest) = oldest {
+      map.remove(&oldest);
+    }
+  }
+}
+
+#[test]
+fn test_desync_monitor() {
+  let mut monitor = DesyncMonitor::new(1);
+  assert_eq!(monitor.record(0xAA), None);
+  assert_eq!(
+    monitor.record_node(TickChecksum {
+      tick: 1,
+      checksum: 0xAA
+    }),
+    None
+  );
+
+  // the node reports a tick before the local client
+  assert_eq!(
+    monitor.record_node(TickChecksum {
+      tick: 2,
+      checksum: 0xBB
+    }),
+    None
+  );
+  assert_eq!(
+    monitor.record(0xCC),
+    Some(Desync {
+      tick: 2,
+      players: vec![1],
+    })
+  );
+
+  assert_eq!(monitor.record(0xDD), None);
+  assert_eq!(
+    monitor.record_node(TickChecksum {
+      tick: 3,
+      checksum: 0xEE
+    }),
+    Some(Desync {
+      tick: 3,
+      players: vec![1],
+    })
+  );
+}
+
+#[test]
+fn test_desync_monitor_two_players() {
+  let mut monitors = vec![DesyncMonitor::new(1), DesyncMonitor::new(2)];
+  for tick in 1..=10 {
+    for monitor in &mut monitors {
+      assert_eq!(monitor.record(0x11), None);
+      assert_eq!(
+        monitor.record_node(TickChecksum {
+          tick,
+          checksum: 0x11
+        }),
+        None
+      );
+    }
+  }
+
+  // player 2 diverges at tick 11, only their monitor fires
+  assert_eq!(monitors[0].record(0x22), None);
+  assert_eq!(monitors[1].record(0x33), None);
+  let agreed = TickChecksum {
+    tick: 11,
+    checksum: 0x22,
+  };
+  assert_eq!(monitors[0].record_node(agreed.clone()), None);
+  assert_eq!(
+    monitors[1].record_node(agreed),
+    Some(Desync {
+      tick: 11,
+      players: vec![2],
+    })
+  );
+}
+
+#[test]
+fn test_desync_monitor_pending_limit() {
+  let mut monitor = DesyncMonitor::new(1);
+  let ticks = DESYNC_MONITOR_MAX_PENDING_TICKS as u32 + 10;
+  for _ in 0..ticks {
+    assert_eq!(monitor.record(0x11), None);
+  }
+  assert_eq!(monitor.local.len(), DESYNC_MONITOR_MAX_PENDING_TICKS);
+
+  // the oldest ticks were dropped and are not compared
+  assert_eq!(
+    monitor.record_node(TickChecksum {
+      tick: 1,
+      checksum: 0x22
+    }),
+    None
+  );
+  assert_eq!(
+    monitor.record_node(TickChecksum {
+      tick: ticks,
+      checksum: 0x22
+    }),
+    Some(Desync {
+      tick: ticks,
+      players: vec![1],
+    })
+  );
+}
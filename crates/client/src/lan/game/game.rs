@@ -1,7 +1,16 @@
-use crate::controller::{ControllerClient, GetMuteList, MutePlayer, UnmutePlayer};
+use crate::controller::{
+  ControllerClient, GetMuteList, GetWeakOutgoingMessageSender, MutePlayer, UnmutePlayer,
+};
 use crate::error::*;
+use crate::lan::game::capture::PacketRecorder;
+use crate::lan::game::chat_log::ChatLog;
+use crate::lan::game::delay::{DelayQueue, OBSERVER_DELAY_MAX_PACKETS};
+use crate::lan::game::desync::{Desync, DesyncMonitor};
+use crate::lan::game::observer_mux::ObserverMux;
+use crate::lan::game::stats::ProxyCounters;
 use crate::lan::game::{GameEndReason, LanGameInfo};
-use crate::node::stream::NodeStreamSender;
+use crate::messages::{DesyncDetected, OutgoingMessage};
+use crate::node::stream::{NodeStreamSender, TickChecksum};
 use crate::node::NodeInfo;
 use flo_net::w3gs::W3GSPacket;
 use flo_replay::generate_replay_from_packets;
@@ -24,10 +33,10 @@ use parking_lot::Mutex;
 use std::collections::BTreeSet;
 use std::fs;
 use std::path::Path;
-use std::time::Duration;
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Receiver, Sender, WeakSender};
 use tokio::sync::watch::Receiver as WatchReceiver;
-use tokio::time::interval;
+use tokio::time::{interval, sleep_until};
 
 #[derive(Debug)]
 pub enum GameResult {
@@ -43,13 +52,22 @@ pub struct GameHandler<'a> {
   status_rx: &'a mut WatchReceiver<Option<NodeGameStatus>>,
   w3gs_tx: &'a mut Sender<Packet>,
   w3gs_rx: &'a mut Receiver<Packet>,
+  checksum_rx: Option<&'a mut Receiver<TickChecksum>>,
   client: &'a mut Addr<ControllerClient>,
   muted_players: BTreeSet<u8>,
   end_reason: &'a Mutex<Option<GameEndReason>>,
+  counters: &'a ProxyCounters,
+  recorder: Option<&'a PacketRecorder>,
+  chat_log: Option<&'a Mutex<ChatLog>>,
+  observer_mux: &'a Mutex<ObserverMux>,
   saved_packets: Vec<Packet>,
   save_replay: bool,
   game_version_string: String,
   user_replay_path: String,
+  /// Packets from the node held back for a delayed observer, players are never delayed
+  delayed: Option<DelayQueue<Packet>>,
+  desync: Option<DesyncMonitor>,
+  weak_outgoing_tx: Option<WeakSender<OutgoingMessage>>,
 }
 
 impl<'a> GameHandler<'a> {
@@ -61,8 +79,13 @@ impl<'a> GameHandler<'a> {
     status_rx: &'a mut WatchReceiver<Option<NodeGameStatus>>,
     w3gs_tx: &'a mut Sender<Packet>,
     w3gs_rx: &'a mut Receiver<Packet>,
+    checksum_rx: Option<&'a mut Receiver<TickChecksum>>,
     client: &'a mut Addr<ControllerClient>,
     end_reason: &'a Mutex<Option<GameEndReason>>,
+    counters: &'a ProxyCounters,
+    recorder: Option<&'a PacketRecorder>,
+    chat_log: Option<&'a Mutex<ChatLog>>,
+    observer_mux: &'a Mutex<ObserverMux>,
     game_version_string: String,
     save_replay: bool,
     user_replay_path: String,
@@ -75,13 +98,28 @@ impl<'a> GameHandler<'a> {
       status_rx,
       w3gs_tx,
       w3gs_rx,
+      checksum_rx,
       client,
       muted_players: BTreeSet::new(),
       end_reason,
+      counters,
+      recorder,
+      chat_log,
+      observer_mux,
       saved_packets: vec![],
       save_replay,
       game_version_string,
       user_replay_path,
+      delayed: info
+        .observer_delay
+        .filter(|_| info.slot_info.is_observer())
+        .map(|delay| DelayQueue::new(delay, OBSERVER_DELAY_MAX_PACKETS)),
+      desync: if info.desync_monitor {
+        Some(DesyncMonitor::new(info.game.player_id))
+      } else {
+        None
+      },
+      weak_outgoing_tx: None,
     }
   }
 
@@ -99,6 +137,15 @@ impl<'a> GameHandler<'a> {
     #[cfg(feature = "blacklist")]
     let mut blacklisted = vec![];
 
+    if self.desync.is_some() {
+      self.weak_outgoing_tx = self
+        .client
+        .send(GetWeakOutgoingMessageSender)
+        .await
+        .ok()
+        .flatten();
+    }
+
     // Disable auto-mute (mutef) for FFA games
     if !self.info.game.mask_player_names {
       for p in &self.info.slot_info.player_infos {
@@ -133,6 +180,8 @@ impl<'a> GameHandler<'a> {
 
     for pkt in deferred_out_packets {
       tracing::warn!("deferred out packet: {:?}", pkt.type_id());
+      self.record_keepalive(&pkt).await?;
+      self.record_sent(&pkt);
       self.node_stream.send_w3gs(pkt).await?;
     }
 
@@ -140,6 +189,8 @@ impl<'a> GameHandler<'a> {
     let ping_packet = Packet::simple(PingFromHost::with_payload(0))?;
 
     loop {
+      // the guard can't be held across the select
+      let observer_deadline = self.observer_mux.lock().next_deadline();
       tokio::select! {
         _ = ping.tick() => {
           self.w3gs_stream.send(ping_packet.clone()).await?;
@@ -180,13 +231,39 @@ impl<'a> GameHandler<'a> {
             None => {},
           }
         }
-        next = self.w3gs_rx.recv() => {
+        // stops reading from the node while the delay queue is full
+        next = self.w3gs_rx.recv(), if !self.delayed.as_ref().map(|q| q.is_full()).unwrap_or(false) => {
           if let Some(pkt) = next {
-            self.handle_incoming_w3gs(pkt).await?;
+            self.observer_mux.lock().dispatch(Instant::now(), &pkt);
+            if let Some(delayed) = self.delayed.as_mut() {
+              delayed.push(Instant::now(), pkt);
+            } else {
+              self.handle_incoming_w3gs(pkt).await?;
+            }
           } else {
+            self.release_delayed(None).await?;
             return Err(Error::TaskCancelled(anyhow::format_err!("W3GS tx dropped")))
           }
         }
+        _ = sleep_until_deadline(self.delayed.as_ref().and_then(|q| q.next_deadline())) => {
+          self.release_delayed(Some(Instant::now())).await?;
+        }
+        _ = sleep_until_deadline(observer_deadline) => {
+          self.observer_mux.lock().release(Instant::now());
+        }
+        next = recv_tick_checksum(self.checksum_rx.as_deref_mut()) => {
+          match next {
+            Some(checksum) => {
+              let desync = self.desync.as_mut().and_then(|monitor| monitor.record_node(checksum));
+              if let Some(desync) = desync {
+                self.report_desync(desync).await;
+              }
+            }
+            None => {
+              self.checksum_rx.take();
+            }
+          }
+        }
       }
     }
   }
@@ -240,14 +317,39 @@ impl<'a> GameHandler<'a> {
     }
   }
 
+  /// Sends delayed packets ready at `now`, or all of them if `now` is `None`
+  async fn release_delayed(&mut self, now: Option<Instant>) -> Result<()> {
+    let packets: Vec<_> = match self.delayed.as_mut() {
+      Some(delayed) => match now {
+        Some(now) => std::iter::from_fn(|| delayed.pop_ready(now)).collect(),
+        None => delayed.drain().collect(),
+      },
+      None => return Ok(()),
+    };
+    for pkt in packets {
+      self.handle_incoming_w3gs(pkt).await?;
+    }
+    Ok(())
+  }
+
   #[inline]
   async fn handle_incoming_w3gs(&mut self, pkt: Packet) -> Result<()> {
     match pkt.type_id() {
       OutgoingKeepAlive::PACKET_TYPE_ID => {}
       OutgoingAction::PACKET_TYPE_ID => {}
       ChatFromHost::PACKET_TYPE_ID => {
-        if !self.muted_players.is_empty() {
+        if self.chat_log.is_some() || !self.muted_players.is_empty() {
           let pkt: ChatFromHost = pkt.decode_simple()?;
+          let player_id = self
+            .info
+            .slot_info
+            .player_infos
+            .iter()
+            .find(|p| p.slot_player_id == pkt.from_player())
+            .map(|p| p.player_id);
+          if let Some(player_id) = player_id {
+            self.log_chat(player_id, &pkt.0.message);
+          }
           if let ChatToHost {
             message: ChatMessage::Scoped { .. },
             ..
@@ -267,6 +369,7 @@ impl<'a> GameHandler<'a> {
       self.saved_packets.push(pkt.clone())
     }
 
+    self.record_received(&pkt);
     self.w3gs_stream.send(pkt).await?;
     Ok(())
   }
@@ -290,7 +393,7 @@ impl<'a> GameHandler<'a> {
 
         let pkt: ChatToHost = pkt.decode_simple()?;
 
-        match pkt.message {
+        match &pkt.message {
           ChatMessage::Scoped { message, .. } => {
             if let Some(cmd) = parse_chat_command(message.as_bytes()) {
               if self.handle_chat_command(cmd) {
@@ -300,8 +403,11 @@ impl<'a> GameHandler<'a> {
           }
           _ => {}
         }
+        self.log_chat(self.info.game.player_id, &pkt.message);
+      }
+      OutgoingKeepAlive::PACKET_TYPE_ID => {
+        self.record_keepalive(&pkt).await?;
       }
-      OutgoingKeepAlive::PACKET_TYPE_ID => {}
       OutgoingAction::PACKET_TYPE_ID => {}
       PacketTypeId::DropReq => {}
       PacketTypeId::LeaveReq => {
@@ -312,6 +418,7 @@ impl<'a> GameHandler<'a> {
           .lock()
           .replace(GameEndReason::LeaveReq(payload.reason()));
 
+        self.record_sent(&pkt);
         if let Err(err) = self.node_stream.send_w3gs(pkt).await {
           tracing::error!("report request to leave: {}", err);
         }
@@ -324,11 +431,74 @@ impl<'a> GameHandler<'a> {
       }
     }
 
+    self.record_sent(&pkt);
     self.node_stream.send_w3gs(pkt).await?;
 
     Ok(())
   }
 
+  fn log_chat(&self, player_id: i32, message: &ChatMessage) {
+    let log = match self.chat_log {
+      Some(log) => log,
+      None => return,
+    };
+    let text = match message {
+      ChatMessage::Chat(text) | ChatMessage::Scoped { message: text, .. } => text,
+      _ => return,
+    };
+    log.lock().push(
+      Instant::now(),
+      player_id,
+      text.to_string_lossy().to_string(),
+    );
+  }
+
+  /// Passes the checksum of a keepalive sent by the game client to the desync monitor
+  async fn record_keepalive(&mut self, pkt: &Packet) -> Result<()> {
+    if pkt.type_id() != OutgoingKeepAlive::PACKET_TYPE_ID {
+      return Ok(());
+    }
+    if let Some(monitor) = self.desync.as_mut() {
+      let payload: OutgoingKeepAlive = pkt.decode_simple()?;
+      if let Some(desync) = monitor.record(payload.checksum) {
+        self.report_desync(desync).await;
+      }
+    }
+    Ok(())
+  }
+
+  async fn report_desync(&self, desync: Desync) {
+    tracing::warn!(
+      game_id = self.info.game.game_id,
+      tick = desync.tick,
+      "desync detected: {:?}",
+      desync.players
+    );
+    if let Some(tx) = self.weak_outgoing_tx.as_ref().and_then(|tx| tx.upgrade()) {
+      tx.send(OutgoingMessage::DesyncDetected(DesyncDetected {
+        game_id: self.info.game.game_id,
+        tick: desync.tick,
+        player_ids: desync.players,
+      }))
+      .await
+      .ok();
+    }
+  }
+
+  fn record_sent(&self, pkt: &Packet) {
+    self.counters.record_sent(pkt);
+    if let Some(recorder) = self.recorder {
+      recorder.record_sent(pkt);
+    }
+  }
+
+  fn record_received(&self, pkt: &Packet) {
+    self.counters.record_received(pkt);
+    if let Some(recorder) = self.recorder {
+      recorder.record_received(pkt);
+    }
+  }
+
   fn handle_chat_command(&mut self, cmd: ChatCommand) -> bool {
     let is_ffa = self.info.game.mask_player_names;
 
@@ -866,6 +1036,20 @@ impl<'a> GameHandler<'a> {
   }
 }
 
+pub(crate) async fn sleep_until_deadline(deadline: Option<Instant>) {
+  match deadline {
+    Some(deadline) => sleep_until(deadline.into()).await,
+    None => futures::future::pending().await,
+  }
+}
+
+async fn recv_tick_checksum(rx: Option<&mut Receiver<TickChecksum>>) -> Option<TickChecksum> {
+  match rx {
+    Some(rx) => rx.recv().await,
+    None => futures::future::pending().await,
+  }
+}
+
 async fn send_chats_to_self(tx: &mut Sender<Packet>, player_id: u8, messages: Vec<String>) {
   for message in messages {
     match Packet::simple(ChatFromHost::private_to_self(player_id, message)) {
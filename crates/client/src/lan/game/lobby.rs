@@ -1,4 +1,7 @@
-use std::net::SocketAddr;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::WeakSender;
@@ -12,21 +15,57 @@ use flo_w3gs::protocol::chat::{ChatFromHost, ChatToHost};
 use flo_w3gs::protocol::game::{CountDownEnd, CountDownStart};
 use flo_w3gs::protocol::join::{ReqJoin, SlotInfoJoin};
 use flo_w3gs::protocol::leave::{LeaveAck, LeaveReq};
-use flo_w3gs::protocol::map::{MapCheck, MapSize};
+use flo_w3gs::protocol::map::MapSize;
 use flo_w3gs::protocol::packet::*;
 use flo_w3gs::protocol::ping::{PingFromHost, PongToHost};
 use flo_w3gs::protocol::player::{PlayerInfo, PlayerProfileMessage, PlayerSkinsMessage};
+use flo_w3gs::protocol::pool::PacketBufferPool;
 
 use crate::error::*;
-use crate::lan::game::slot::index_to_player_id;
+use crate::lan::game::game::sleep_until_deadline;
+use crate::lan::game::slot::{index_to_player_id, LanSlotInfo, SlotInfoDelta};
+use crate::lan::game::status::GameStatusMachine;
 use crate::lan::game::LanGameInfo;
 use crate::lan::get_lan_game_name;
-use crate::messages::{LanGameJoined, OutgoingMessage};
+use crate::messages::{
+  LanGameJoined, LanGameMapMismatch, LanGameSlotLayout, LanGameSlotPlayer, LobbyPing,
+  OutgoingMessage,
+};
 use crate::node::stream::NodeStreamSender;
 use flo_types::node::{NodeGameStatus, SlotClientStatus};
 use flo_w3gs::protocol::constants::ProtoBufMessageTypeId;
 
 const LOBBY_PING_INTERVAL: Duration = Duration::from_secs(15);
+const LOBBY_PING_WINDOW: usize = 4;
+const LOBBY_PING_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Time allowed between `ReqJoin` and the last join packet
+const LOBBY_JOIN_TIMEOUT: Duration = Duration::from_secs(30);
+/// `PlayerUnknown5` is not sent by observer clients, nor by game builds whose join
+/// sequence predates the message and ends with `PlayerSkins`. The first build that sends
+/// it isn't tracked, so instead of keying on the game version the lobby waits at most
+/// this long for it once all other join packets arrived.
+const LOBBY_UNK5_GRACE: Duration = Duration::from_secs(2);
+
+/// Controls how the lobby treats the `MapSize` packet.
+///
+/// `MapSize` is only used to detect a local map file that differs from the one
+/// the game was created with. Some clients never send it, so by default a missing
+/// `MapSize` is assumed to be fine. With `assume_ok` disabled, readiness waits for
+/// the packet and the lobby fails if it isn't received within `timeout`.
+#[derive(Debug, Clone, Copy)]
+pub struct MapSizeCheck {
+  pub assume_ok: bool,
+  pub timeout: Duration,
+}
+
+impl Default for MapSizeCheck {
+  fn default() -> Self {
+    MapSizeCheck {
+      assume_ok: true,
+      timeout: Duration::from_secs(10),
+    }
+  }
+}
 
 #[derive(Debug)]
 pub enum LobbyAction {
@@ -43,6 +82,13 @@ pub struct LobbyHandler<'a> {
   starting: bool,
   weak_outgoing_tx: Option<WeakSender<OutgoingMessage>>,
   lobby_countdown_notify: Option<Arc<Notify>>,
+  force_start_notify: Option<Arc<Notify>>,
+  ping_tracker: LobbyPingTracker,
+  join_timeout: Duration,
+  left_players: Option<&'a Mutex<BTreeSet<i32>>>,
+  map_download_url_sent: Option<&'a AtomicBool>,
+  /// Last slot layout sent to the game
+  sent_slot_info: Option<LanSlotInfo>,
 }
 
 impl<'a> LobbyHandler<'a> {
@@ -62,12 +108,52 @@ impl<'a> LobbyHandler<'a> {
       starting: false,
       weak_outgoing_tx,
       lobby_countdown_notify,
+      force_start_notify: None,
+      ping_tracker: LobbyPingTracker::new(LOBBY_PING_WINDOW, LOBBY_PING_REPORT_INTERVAL),
+      join_timeout: LOBBY_JOIN_TIMEOUT,
+      left_players: None,
+      map_download_url_sent: None,
+      sent_slot_info: None,
     }
   }
 
+  /// A client that joined but didn't send all join packets within `timeout`
+  /// most likely failed the map check, the lobby leaves to wait for a new connection.
+  pub fn with_join_timeout(mut self, timeout: Duration) -> Self {
+    self.join_timeout = timeout;
+    self
+  }
+
+  /// Completes the countdown as soon as `notify` fires instead of waiting for
+  /// `lobby_countdown_notify` or the timeout
+  pub fn with_force_start_notify(mut self, notify: Option<Arc<Notify>>) -> Self {
+    self.force_start_notify = notify;
+    self
+  }
+
+  /// Players who left after the slot layout was built,
+  /// their slots are opened in the slot info sent to the game
+  pub fn with_left_players(mut self, left_players: &'a Mutex<BTreeSet<i32>>) -> Self {
+    self.left_players = Some(left_players);
+    self
+  }
+
+  /// Shared between the lobbies of a game so `map_download_url` is sent only once
+  pub fn with_map_download_url_sent(mut self, sent: &'a AtomicBool) -> Self {
+    self.map_download_url_sent = Some(sent);
+    self
+  }
+
+  fn left_players(&self) -> BTreeSet<i32> {
+    self
+      .left_players
+      .map(|left_players| left_players.lock().clone())
+      .unwrap_or_default()
+  }
+
   pub async fn run(&mut self) -> Result<LobbyAction> {
     let initial_game_state = { self.status_rx.borrow().clone() };
-    let mut join_state = JoinPacketRecvState::new(initial_game_state, {
+    let mut join_state = JoinPacketRecvState::new(initial_game_state, self.info.map_size_check, {
       self.info.slot_info.player_infos.len()
         + if self.info.slot_info.stream_ob_slot.is_some() {
           1
@@ -81,6 +167,10 @@ impl<'a> LobbyHandler<'a> {
     );
     let base_t = Instant::now();
     let mut reported = false;
+    let map_size_deadline = sleep(self.info.map_size_check.timeout);
+    tokio::pin!(map_size_deadline);
+    let join_deadline = sleep(self.join_timeout);
+    tokio::pin!(join_deadline);
 
     loop {
       tokio::select! {
@@ -92,30 +182,45 @@ impl<'a> LobbyHandler<'a> {
               continue;
             }
 
-            self.handle_packet(&mut join_state, base_t, pkt).await?;
-            if join_state.is_ready() {
-              // report to node that all players have joined
-              if !reported {
-                tracing::debug!("all join packets received");
-                if let Some(node_stream) = self.node_stream.as_mut() {
-                  node_stream.report_slot_status(SlotClientStatus::Joined).await.ok();
-                }
-                reported = true;
-                if let Some(tx) = self.weak_outgoing_tx.as_ref().and_then(|tx| tx.upgrade()) {
-                  tx.send(OutgoingMessage::LanGameJoined(LanGameJoined {
-                    lobby_name: self.info.lan_game_name_override.clone().unwrap_or_else(|| get_lan_game_name(&self.info.game.name, self.info.game.player_id)),
-                  })).await.ok();
-                }
+            if pkt.type_id() == ReqJoin::PACKET_TYPE_ID {
+              join_deadline.as_mut().reset((Instant::now() + self.join_timeout).into());
+              if !self.check_spectator_secret(&pkt)? {
+                tracing::warn!("observer presented a wrong spectator secret, closing connection");
+                return Ok(LobbyAction::Leave)
               }
-              if join_state.should_start() {
-                self.send_start().await?;
-                return Ok(LobbyAction::Start)
+            }
+
+            if let Err(err) = self.handle_packet(&mut join_state, base_t, pkt).await {
+              if matches!(err, Error::MapChecksumMismatch) {
+                self.report_map_mismatch(join_state.map_size, false).await;
+                self.send_map_download_url().await;
               }
+              return Err(err)
+            }
+            join_state.track_unk5(Instant::now());
+            if self.handle_join_progress(&join_state, &mut reported).await? {
+              return Ok(LobbyAction::Start)
             }
           } else {
             return Err(Error::StreamClosed)
           }
         }
+        _ = sleep_until_deadline(join_state.unk5_deadline()) => {
+          tracing::warn!("PlayerUnknown5 not received in {:?}, continuing without it", join_state.unk5_grace);
+          join_state.waive_unk5(Instant::now());
+          if self.handle_join_progress(&join_state, &mut reported).await? {
+            return Ok(LobbyAction::Start)
+          }
+        }
+        _ = &mut join_deadline, if join_state.joined && !join_state.is_ready() => {
+          tracing::error!("join packets not received in {:?}", self.join_timeout);
+          self.report_map_mismatch(join_state.map_size, true).await;
+          self.send_map_download_url().await;
+          return Ok(LobbyAction::Leave)
+        }
+        _ = &mut map_size_deadline, if !self.info.map_size_check.assume_ok && join_state.map_size.is_none() => {
+          return Err(Error::Timeout(anyhow::format_err!("map size not received")))
+        }
         _ = ping_interval.tick() => {
           self.stream.send(Packet::simple(PingFromHost::with_payload_since(base_t))?).await?;
         }
@@ -125,7 +230,7 @@ impl<'a> LobbyHandler<'a> {
               let next = self.status_rx.borrow().clone();
               match next {
                 Some(status) => {
-                  join_state.status = Some(status);
+                  join_state.status.apply(status);
                   if join_state.should_start() {
                     self.send_start().await?;
                     return Ok(LobbyAction::Start)
@@ -143,37 +248,147 @@ impl<'a> LobbyHandler<'a> {
     }
   }
 
-  async fn send_start(&mut self) -> Result<()> {
-    if self.starting {
-      return Ok(());
+  /// Reports the joined state once all join packets arrived,
+  /// returns `true` if the game was started
+  async fn handle_join_progress(
+    &mut self,
+    join_state: &JoinPacketRecvState,
+    reported: &mut bool,
+  ) -> Result<bool> {
+    if !join_state.is_ready() {
+      return Ok(false);
     }
-    self.starting = true;
+    // report to node that all players have joined
+    if !*reported {
+      tracing::debug!("all join packets received");
+      if let Some(node_stream) = self.node_stream.as_mut() {
+        node_stream
+          .report_slot_status(SlotClientStatus::Joined)
+          .await
+          .ok();
+      }
+      *reported = true;
+      if let Some(tx) = self.weak_outgoing_tx.as_ref().and_then(|tx| tx.upgrade()) {
+        tx.send(OutgoingMessage::LanGameJoined(LanGameJoined {
+          lobby_name: self
+            .info
+            .lan_game_name_override
+            .clone()
+            .unwrap_or_else(|| get_lan_game_name(&self.info.game.name, self.info.game.player_id)),
+          slot_layout: slot_layout(&self.info.slot_info, &self.left_players()),
+        }))
+        .await
+        .ok();
+      }
+    }
+    if join_state.should_start() {
+      self.send_start().await?;
+      return Ok(true);
+    }
+    Ok(false)
+  }
 
+  /// Observers must join with the entry key derived from `spectator_secret`,
+  /// players are not checked
+  fn check_spectator_secret(&self, pkt: &Packet) -> Result<bool> {
+    let secret = match self.info.spectator_secret {
+      Some(ref secret) => secret,
+      None => return Ok(true),
+    };
+    if !self.info.slot_info.is_observer() {
+      return Ok(true);
+    }
+    let req: ReqJoin = pkt.decode_simple()?;
+    Ok(req.entry_key == spectator_entry_key(secret))
+  }
+
+  async fn report_map_mismatch(&self, local_map_size: Option<u32>, timeout: bool) {
+    if let Some(tx) = self.weak_outgoing_tx.as_ref().and_then(|tx| tx.upgrade()) {
+      tx.send(OutgoingMessage::LanGameMapMismatch(LanGameMapMismatch {
+        game_id: self.info.game.game_id,
+        local_map_size,
+        expected_map_size: self.info.map_checksum.file_size as u32,
+        timeout,
+      }))
+      .await
+      .ok();
+    }
+  }
+
+  /// Warcraft can't download from the URL, the player has to install the map manually
+  async fn send_map_download_url(&mut self) {
+    let url = match self.info.map_download_url {
+      Some(ref url) => url,
+      None => return,
+    };
+    if let Some(sent) = self.map_download_url_sent {
+      if sent.swap(true, Ordering::SeqCst) {
+        return;
+      }
+    }
+    let message = format!(
+      "You don't have this map. Download it from {} and save it as {}, then rejoin.",
+      url, self.info.game.map_path
+    );
+    if let Err(err) = self.send_chat_to_self(message).await {
+      tracing::warn!("send map download url: {}", err);
+    }
+  }
+
+  async fn send_chat_to_self(&mut self, message: String) -> Result<()> {
+    let slot_player_id = self.info.slot_info.my_slot_player_id;
     self
       .stream
-      .send(Packet::simple(
-        self.info.slot_info.slot_info.clone() as flo_w3gs::protocol::slot::SlotInfo
-      )?)
+      .send(Packet::simple(ChatFromHost::lobby(
+        slot_player_id,
+        &[slot_player_id],
+        message,
+      ))?)
       .await?;
+    Ok(())
+  }
 
-    self.stream.send(Packet::simple(CountDownStart)?).await?;
-
-    sleep(Duration::from_secs(3)).await;
+  async fn send_start(&mut self) -> Result<()> {
+    if self.starting {
+      return Ok(());
+    }
+    self.starting = true;
 
-    // If we have a countdown notify, wait for it to be notified
-    // This is used to synchronize with the countdown state in Reforged client
-    // Without this, the game may start too early and cause instant-to-score-screen bug for slow computers
-    if let Some(ref notify) = self.lobby_countdown_notify {
-      tokio::select! {
-        _ = notify.notified() => {
-          tracing::debug!("lobby countdown notify received");
-        }
-        _ = sleep(Duration::from_secs(6)) => {
-          tracing::debug!("lobby countdown notify timeout");
+    let left_players = self.left_players();
+    let slot_info = self.info.slot_info.live(&left_players);
+    let delta = match self.sent_slot_info {
+      Some(ref prev) => slot_info.diff(prev),
+      None => Some(SlotInfoDelta::Full),
+    };
+    match delta {
+      // W3GS has no partial slot update, a changed layout is always sent in full
+      Some(delta) => {
+        if let SlotInfoDelta::Slot { index, .. } = delta {
+          tracing::debug!("-> slot info: slot {} changed", index);
         }
+        self
+          .stream
+          .send(Packet::simple(slot_info.slot_info.clone())?)
+          .await?;
+        self.sent_slot_info = Some(slot_info);
+      }
+      None => {
+        tracing::debug!("slot info unchanged, skipping");
       }
+    }
+
+    if cfg!(debug_assertions) && self.info.instant_start {
+      tracing::debug!("instant start, skipping countdown");
     } else {
+      self.stream.send(Packet::simple(CountDownStart)?).await?;
+
       sleep(Duration::from_secs(3)).await;
+
+      wait_countdown(
+        self.lobby_countdown_notify.as_deref(),
+        self.force_start_notify.as_deref(),
+      )
+      .await;
     }
 
     self.stream.send(Packet::simple(CountDownEnd)?).await?;
@@ -195,51 +410,64 @@ impl<'a> LobbyHandler<'a> {
 
     match pkt.type_id() {
       ReqJoin::PACKET_TYPE_ID => {
-        let num_players = slot_info.player_infos.len();
+        state.joined = true;
+        let pool = PacketBufferPool::shared();
+        let left_players = self.left_players();
+        let live_slot_info = slot_info.live_slot_info(&left_players);
+        let live_player_infos: Vec<_> = slot_info.live_player_infos(&left_players).collect();
+        let num_players = live_player_infos.len();
         let mut replies = Vec::with_capacity(num_players * 3);
 
+        // players who left don't send join packets
+        state.total_players = num_players
+          + if slot_info.stream_ob_slot.is_some() {
+            1
+          } else {
+            0
+          };
+
         // slot info
-        replies.push(Packet::simple(SlotInfoJoin {
-          slot_info: slot_info.slot_info.clone(),
+        replies.push(pool.simple(SlotInfoJoin {
+          slot_info: live_slot_info.clone(),
           player_id: slot_info.my_slot_player_id,
-          external_addr: SockAddr::from(match self.stream.local_addr() {
-            SocketAddr::V4(addr) => addr,
-            SocketAddr::V6(_) => return Err(flo_w3gs::error::Error::Ipv6NotSupported.into()),
-          }),
+          external_addr: SockAddr::from(select_external_addr(
+            self.stream.local_addr(),
+            self.info.bind_addr,
+          )?),
         })?);
         tracing::debug!(
           "-> slot info: slots = {}, players = {}, random_seed = {}",
-          slot_info.slot_info.slots().len(),
-          slot_info.slot_info.num_players,
-          slot_info.slot_info.random_seed
+          live_slot_info.slots().len(),
+          live_slot_info.num_players,
+          live_slot_info.random_seed
         );
 
-        replies.push(Packet::simple(
-          slot_info.slot_info.clone() as flo_w3gs::protocol::slot::SlotInfo
-        )?);
+        replies.push(pool.simple(live_slot_info.clone())?);
+        self.sent_slot_info = Some(LanSlotInfo {
+          slot_info: live_slot_info,
+          ..slot_info.clone()
+        });
 
         let mut player_info_packets = Vec::with_capacity(num_players);
         let mut player_skin_packets = Vec::with_capacity(num_players);
         let mut player_profile_packets = Vec::with_capacity(num_players);
 
-        for info in &slot_info.player_infos {
+        for info in live_player_infos {
           if info.slot_player_id != slot_info.my_slot_player_id {
             tracing::debug!(
               "-> PlayerInfo: player: id = {}, name = {}",
               info.slot_player_id,
               info.name
             );
-            player_info_packets.push(Packet::simple(PlayerInfo::new(
-              info.slot_player_id,
-              &info.name,
-            ))?);
+            player_info_packets
+              .push(pool.simple(PlayerInfo::new(info.slot_player_id, &info.name))?);
 
             tracing::debug!(
               "-> PlayerSkinsMessage: player: id = {}, name = {}",
               info.slot_player_id,
               info.name
             );
-            player_skin_packets.push(Packet::simple(ProtoBufPayload::new(PlayerSkinsMessage {
+            player_skin_packets.push(pool.simple(ProtoBufPayload::new(PlayerSkinsMessage {
               player_id: info.slot_player_id as u32,
               ..Default::default()
             }))?);
@@ -250,7 +478,7 @@ impl<'a> LobbyHandler<'a> {
             info.slot_player_id,
             info.name
           );
-          player_profile_packets.push(Packet::simple(ProtoBufPayload::new(
+          player_profile_packets.push(pool.simple(ProtoBufPayload::new(
             PlayerProfileMessage::new(info.slot_player_id, &info.name),
           ))?);
         }
@@ -258,16 +486,16 @@ impl<'a> LobbyHandler<'a> {
         if let Some(ob_slot) = self.info.slot_info.stream_ob_slot.clone() {
           let ob_player_id = index_to_player_id(ob_slot);
           tracing::debug!("-> PlayerInfo: stream ob: {}", ob_player_id);
-          player_info_packets.push(Packet::simple(PlayerInfo::new(ob_player_id, "FLO"))?);
+          player_info_packets.push(pool.simple(PlayerInfo::new(ob_player_id, "FLO"))?);
 
           tracing::debug!("-> PlayerSkinsMessage: stream ob: {}", ob_player_id);
-          player_skin_packets.push(Packet::simple(ProtoBufPayload::new(PlayerSkinsMessage {
+          player_skin_packets.push(pool.simple(ProtoBufPayload::new(PlayerSkinsMessage {
             player_id: ob_player_id as u32,
             ..Default::default()
           }))?);
 
           tracing::debug!("-> PlayerProfileMessage: obs: {}", ob_player_id);
-          player_profile_packets.push(Packet::simple(ProtoBufPayload::new(
+          player_profile_packets.push(pool.simple(ProtoBufPayload::new(
             PlayerProfileMessage::new(ob_player_id, "FLO"),
           ))?);
         }
@@ -277,22 +505,33 @@ impl<'a> LobbyHandler<'a> {
         replies.extend(player_profile_packets);
 
         // map check
-        replies.push(Packet::simple(MapCheck::new(
-          map_checksum.file_size as u32,
-          map_checksum.crc32,
-          &game_settings,
-        ))?);
+        let map_check = self
+          .info
+          .w3gs_profile
+          .map_check(map_checksum, game_settings);
         tracing::debug!(
-          "-> map check: file_size = {}, crc32 = {}",
-          map_checksum.file_size,
-          map_checksum.crc32
+          "-> map check: profile = {:?}, file_size = {}, crc32 = {}, xoro = {}",
+          self.info.w3gs_profile,
+          map_check.file_size,
+          map_check.file_crc,
+          map_check.map_xoro
         );
+        replies.push(pool.simple(map_check)?);
 
         self.stream.send_all(replies).await?;
       }
       MapSize::PACKET_TYPE_ID => {
         let payload: MapSize = pkt.decode_simple()?;
         tracing::debug!("<- map size: {:?}", payload);
+        state.map_size = Some(payload.map_size);
+        if payload.size_flag == 1 && payload.map_size != map_checksum.file_size as u32 {
+          tracing::error!(
+            "map size mismatch: local = {}, expected = {}",
+            payload.map_size,
+            map_checksum.file_size
+          );
+          return Err(Error::MapChecksumMismatch);
+        }
       }
       ChatToHost::PACKET_TYPE_ID => {
         self
@@ -306,7 +545,18 @@ impl<'a> LobbyHandler<'a> {
       }
       PongToHost::PACKET_TYPE_ID => {
         let payload: PongToHost = pkt.decode_simple()?;
-        let _ping = payload.elapsed_millis(base_t);
+        let ping = payload.elapsed_millis(base_t);
+        tracing::debug!(ping, "<- pong");
+        if let Some(ping) = self.ping_tracker.record(ping, Instant::now()) {
+          if let Some(tx) = self.weak_outgoing_tx.as_ref().and_then(|tx| tx.upgrade()) {
+            tx.send(OutgoingMessage::LobbyPing(LobbyPing {
+              player_id: self.info.game.player_id,
+              ping,
+            }))
+            .await
+            .ok();
+          }
+        }
       }
       ProtoBufPayload::PACKET_TYPE_ID => {
         let payload: ProtoBufPayload = pkt.decode_simple()?;
@@ -362,36 +612,682 @@ impl<'a> LobbyHandler<'a> {
   }
 }
 
+/// Waits between `CountDownStart` and `CountDownEnd`, after the initial 3s delay.
+///
+/// If we have a countdown notify, wait for it to be notified.
+/// This is used to synchronize with the countdown state in Reforged client.
+/// Without this, the game may start too early and cause instant-to-score-screen bug for slow computers.
+/// A force start notify completes the wait immediately.
+async fn wait_countdown(
+  lobby_countdown_notify: Option<&Notify>,
+  force_start_notify: Option<&Notify>,
+) {
+  let timeout = if lobby_countdown_notify.is_some() {
+    Duration::from_secs(6)
+  } else {
+    Duration::from_secs(3)
+  };
+  tokio::select! {
+    _ = notified(lobby_countdown_notify) => {
+      tracing::debug!("lobby countdown notify received");
+    }
+    _ = notified(force_start_notify) => {
+      tracing::debug!("lobby countdown force started");
+    }
+    _ = sleep(timeout) => {
+      if lobby_countdown_notify.is_some() {
+        tracing::debug!("lobby countdown notify timeout");
+      }
+    }
+  }
+}
+
+async fn notified(notify: Option<&Notify>) {
+  match notify {
+    Some(notify) => notify.notified().await,
+    None => futures::future::pending().await,
+  }
+}
+
+/// Entry key an observer has to send in `ReqJoin` when the game has a spectator secret
+pub fn spectator_entry_key(secret: &str) -> u32 {
+  let mut hasher = crc32fast::Hasher::new();
+  hasher.update(secret.as_bytes());
+  hasher.finalize()
+}
+
+/// Selects the address sent to the game client in `SlotInfoJoin`.
+///
+/// A configured bind address takes precedence over the local address of the stream,
+/// which could be an address the client can't reach on a multi-homed host.
+/// `SlotInfoJoin` only carries IPv4, a V6 loopback is sent as `127.0.0.1`.
+fn select_external_addr(
+  local_addr: SocketAddr,
+  bind_addr: Option<Ipv4Addr>,
+) -> Result<SocketAddrV4> {
+  match (local_addr, bind_addr) {
+    (local_addr, Some(ip)) => Ok(SocketAddrV4::new(ip, local_addr.port())),
+    (SocketAddr::V4(addr), None) => Ok(addr),
+    (SocketAddr::V6(addr), None) => {
+      let ip = addr.ip();
+      if ip.is_loopback() {
+        return Ok(SocketAddrV4::new(Ipv4Addr::LOCALHOST, addr.port()));
+      }
+      match ip.octets() {
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, a, b, c, d] => {
+          Ok(SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), addr.port()))
+        }
+        _ => Err(Error::LanIpv6AddrNotSupported(*ip)),
+      }
+    }
+  }
+}
+
+#[test]
+fn test_select_external_addr() {
+  let local_addr: SocketAddr = "127.0.0.1:6112".parse().unwrap();
+  assert_eq!(
+    select_external_addr(local_addr, None).unwrap(),
+    "127.0.0.1:6112".parse::<SocketAddrV4>().unwrap()
+  );
+  assert_eq!(
+    select_external_addr(local_addr, Some(Ipv4Addr::new(192, 168, 1, 10))).unwrap(),
+    "192.168.1.10:6112".parse::<SocketAddrV4>().unwrap()
+  );
+
+  let local_addr: SocketAddr = "[::1]:6112".parse().unwrap();
+  assert_eq!(
+    select_external_addr(local_addr, None).unwrap(),
+    "127.0.0.1:6112".parse::<SocketAddrV4>().unwrap()
+  );
+  let sock_addr = SockAddr::from(select_external_addr(local_addr, None).unwrap());
+  assert_eq!(sock_addr.family, 2);
+  assert_eq!(
+    sock_addr.addr_v4,
+    Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 6112))
+  );
+  assert_eq!(
+    select_external_addr(local_addr, Some(Ipv4Addr::new(10, 0, 0, 2))).unwrap(),
+    "10.0.0.2:6112".parse::<SocketAddrV4>().unwrap()
+  );
+
+  let local_addr: SocketAddr = "[::ffff:192.168.1.10]:6112".parse().unwrap();
+  assert_eq!(
+    select_external_addr(local_addr, None).unwrap(),
+    "192.168.1.10:6112".parse::<SocketAddrV4>().unwrap()
+  );
+
+  let local_addr: SocketAddr = "[2001:db8::1]:6112".parse().unwrap();
+  assert!(matches!(
+    select_external_addr(local_addr, None),
+    Err(Error::LanIpv6AddrNotSupported(_))
+  ));
+}
+
+/// Live slot layout reported to the UI
+fn slot_layout(slot_info: &LanSlotInfo, left_players: &BTreeSet<i32>) -> LanGameSlotLayout {
+  let slots = slot_info.slot_info.slots();
+  LanGameSlotLayout {
+    my_slot_player_id: slot_info.my_slot_player_id,
+    has_observer_slot: slot_info.stream_ob_slot.is_some(),
+    players: slot_info
+      .live_player_infos(left_players)
+      .filter_map(|info| {
+        let slot = slots.get(info.slot_index)?;
+        Some(LanGameSlotPlayer {
+          slot_index: info.slot_index,
+          slot_player_id: info.slot_player_id,
+          player_id: info.player_id,
+          name: info.name.clone(),
+          team: slot.team,
+          color: slot.color,
+          race: slot.race.bits(),
+        })
+      })
+      .collect(),
+  }
+}
+
 #[derive(Debug)]
 struct JoinPacketRecvState {
   total_players: usize,
   num_profile: usize,
   num_skins: usize,
   num_unk5: usize,
-  status: Option<NodeGameStatus>,
+  unk5_grace: Duration,
+  /// When all join packets except `PlayerUnknown5` were received
+  unk5_wait_since: Option<Instant>,
+  unk5_waived: bool,
+  map_size: Option<u32>,
+  map_size_check: MapSizeCheck,
+  status: GameStatusMachine,
+  /// `ReqJoin` received
+  joined: bool,
 }
 
 impl JoinPacketRecvState {
-  fn new(initial_game_state: Option<NodeGameStatus>, total_players: usize) -> Self {
+  fn new(
+    initial_game_state: Option<NodeGameStatus>,
+    map_size_check: MapSizeCheck,
+    total_players: usize,
+  ) -> Self {
     JoinPacketRecvState {
       total_players,
       num_profile: 0,
       num_skins: 0,
       num_unk5: 0,
-      status: initial_game_state,
+      unk5_grace: LOBBY_UNK5_GRACE,
+      unk5_wait_since: None,
+      unk5_waived: false,
+      map_size: None,
+      map_size_check,
+      status: GameStatusMachine::new(initial_game_state),
+      joined: false,
     }
   }
 
   fn is_ready(&self) -> bool {
-    self.num_profile == self.total_players && self.num_skins == 1 && self.num_unk5 == 1
+    self.is_ready_except_unk5() && (self.num_unk5 == 1 || self.unk5_waived)
+  }
+
+  fn is_ready_except_unk5(&self) -> bool {
+    self.num_profile == self.total_players
+      && self.num_skins == 1
+      && (self.map_size.is_some() || self.map_size_check.assume_ok)
+  }
+
+  /// Starts the `PlayerUnknown5` grace period if it's the only packet missing
+  fn track_unk5(&mut self, now: Instant) {
+    if self.unk5_wait_since.is_none() && self.num_unk5 == 0 && self.is_ready_except_unk5() {
+      self.unk5_wait_since = Some(now);
+    }
+  }
+
+  fn unk5_deadline(&self) -> Option<Instant> {
+    if self.unk5_waived || self.num_unk5 > 0 {
+      return None;
+    }
+    self.unk5_wait_since.map(|t| t + self.unk5_grace)
+  }
+
+  /// Stops waiting for `PlayerUnknown5` if the grace period ended at `now`
+  fn waive_unk5(&mut self, now: Instant) {
+    if self.unk5_deadline().map(|t| t <= now).unwrap_or(false) {
+      self.unk5_waived = true;
+    }
   }
 
   fn should_start(&self) -> bool {
-    self.is_ready()
-      && match self.status {
-        Some(NodeGameStatus::Loading) => true,
-        Some(NodeGameStatus::Running) => true,
-        _ => false,
+    self.is_ready() && self.status.is_started()
+  }
+}
+
+/// Smooths lobby pong samples and throttles the updates sent to the UI.
+#[derive(Debug)]
+struct LobbyPingTracker {
+  window: usize,
+  report_interval: Duration,
+  samples: VecDeque<u32>,
+  last_report: Option<Instant>,
+}
+
+impl LobbyPingTracker {
+  fn new(window: usize, report_interval: Duration) -> Self {
+    LobbyPingTracker {
+      window: window.max(1),
+      report_interval,
+      samples: VecDeque::with_capacity(window.max(1)),
+      last_report: None,
+    }
+  }
+
+  /// Records a sample and returns the moving average if an update is due.
+  fn record(&mut self, ping: u32, now: Instant) -> Option<u32> {
+    if self.samples.len() == self.window {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(ping);
+
+    if let Some(last) = self.last_report {
+      if now.saturating_duration_since(last) < self.report_interval {
+        return None;
+      }
+    }
+    self.last_report = Some(now);
+
+    let sum: u64 = self.samples.iter().map(|v| *v as u64).sum();
+    Some((sum / self.samples.len() as u64) as u32)
+  }
+}
+
+#[test]
+fn test_lobby_ping_tracker() {
+  let t = Instant::now();
+  let mut tracker = LobbyPingTracker::new(3, Duration::from_secs(5));
+
+  assert_eq!(tracker.record(30, t), Some(30));
+  assert_eq!(tracker.record(60, t + Duration::from_secs(1)), None);
+  assert_eq!(tracker.record(90, t + Duration::from_secs(2)), None);
+  assert_eq!(tracker.record(120, t + Duration::from_secs(5)), Some(90));
+  assert_eq!(tracker.record(0, t + Duration::from_secs(6)), None);
+  assert_eq!(tracker.record(0, t + Duration::from_secs(10)), Some(40));
+}
+
+#[test]
+fn test_join_state_without_map_size() {
+  let mut state = JoinPacketRecvState::new(None, MapSizeCheck::default(), 2);
+  state.num_profile = 2;
+  state.num_skins = 1;
+  state.num_unk5 = 1;
+  assert!(state.is_ready());
+
+  let mut state = JoinPacketRecvState::new(
+    None,
+    MapSizeCheck {
+      assume_ok: false,
+      ..Default::default()
+    },
+    2,
+  );
+  state.num_profile = 2;
+  state.num_skins = 1;
+  state.num_unk5 = 1;
+  assert!(!state.is_ready());
+  state.map_size = Some(127172);
+  assert!(state.is_ready());
+}
+
+#[test]
+fn test_join_state_without_unk5() {
+  let t = Instant::now();
+  let mut state = JoinPacketRecvState::new(Some(NodeGameStatus::Running), Default::default(), 2);
+  state.num_profile = 2;
+  state.track_unk5(t);
+  assert_eq!(state.unk5_deadline(), None);
+
+  state.num_skins = 1;
+  state.track_unk5(t);
+  assert_eq!(state.unk5_deadline(), Some(t + LOBBY_UNK5_GRACE));
+  assert!(!state.should_start());
+
+  // later packets don't extend the grace period
+  state.track_unk5(t + Duration::from_secs(1));
+  state.waive_unk5(t + LOBBY_UNK5_GRACE - Duration::from_millis(1));
+  assert!(!state.should_start());
+
+  state.waive_unk5(t + LOBBY_UNK5_GRACE);
+  assert!(state.should_start());
+  assert_eq!(state.unk5_deadline(), None);
+
+  // clients that send unk5 don't wait
+  let mut state = JoinPacketRecvState::new(Some(NodeGameStatus::Running), Default::default(), 2);
+  state.num_profile = 2;
+  state.num_skins = 1;
+  state.num_unk5 = 1;
+  state.track_unk5(t);
+  assert_eq!(state.unk5_deadline(), None);
+  assert!(state.should_start());
+}
+
+#[test]
+fn test_slot_layout() {
+  use crate::lan::game::slot::{build_player_slot_info, test_slots, SlotInfoOptions};
+
+  let slots = test_slots(24, &[0, 1]);
+  let info = build_player_slot_info(2, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  let layout = slot_layout(&info, &BTreeSet::new());
+  assert_eq!(layout.my_slot_player_id, info.my_slot_player_id);
+  assert!(layout.has_observer_slot);
+  assert_eq!(
+    layout
+      .players
+      .iter()
+      .map(|p| (p.player_id, p.name.as_str(), p.slot_index))
+      .collect::<Vec<_>>(),
+    vec![(1, "Player 1", 0), (2, "Player 2", 1)]
+  );
+  let me = layout
+    .players
+    .iter()
+    .find(|p| p.slot_player_id == layout.my_slot_player_id)
+    .unwrap();
+  assert_eq!(me.player_id, 2);
+
+  // players who left are not reported
+  let layout = slot_layout(&info, &vec![1].into_iter().collect());
+  assert_eq!(layout.players.len(), 1);
+  assert_eq!(layout.players[0].player_id, 2);
+}
+
+#[tokio::test]
+async fn test_wait_countdown_force_start() {
+  let countdown_notify = Notify::new();
+  let force_start_notify = Arc::new(Notify::new());
+  let t = Instant::now();
+  tokio::spawn({
+    let force_start_notify = force_start_notify.clone();
+    async move {
+      sleep(Duration::from_millis(50)).await;
+      force_start_notify.notify_one();
+    }
+  });
+  wait_countdown(Some(&countdown_notify), Some(&force_start_notify)).await;
+  assert!(t.elapsed() < Duration::from_secs(3));
+
+  // without a countdown notify, forcing also skips the fixed delay
+  let t = Instant::now();
+  force_start_notify.notify_one();
+  wait_countdown(None, Some(&force_start_notify)).await;
+  assert!(t.elapsed() < Duration::from_secs(3));
+}
+
+/// `LanGameInfo` of a 64x64 map with a 127172 bytes map file
+#[cfg(test)]
+fn test_lobby_info() -> LanGameInfo {
+  crate::lan::diag::test_lan_game_info(
+    "test",
+    "Maps\\test.w3x",
+    false,
+    64,
+    64,
+    flo_w3map::MapChecksum {
+      xoro: 0,
+      classic_xoro: 0,
+      crc32: 0,
+      sha1: [0; 20],
+      file_size: 127172,
+    },
+  )
+  .unwrap()
+}
+
+/// Connects a local game client running `client`, returns the lobby side of the connection
+#[cfg(test)]
+async fn test_lobby_connect<F, Fut>(client: F) -> (W3GSStream, tokio::task::JoinHandle<Fut::Output>)
+where
+  F: FnOnce(W3GSStream) -> Fut + Send + 'static,
+  Fut: std::future::Future + Send + 'static,
+  Fut::Output: Send + 'static,
+{
+  let mut listener = flo_w3gs::net::W3GSListener::bind().await.unwrap();
+  let port = listener.port();
+  let client = tokio::spawn(async move {
+    let stream = W3GSStream::connect(("127.0.0.1", port)).await.unwrap();
+    client(stream).await
+  });
+  let stream = listener.accept().await.unwrap().unwrap();
+  (stream, client)
+}
+
+#[tokio::test]
+async fn test_lobby_spectator_secret() {
+  use tokio::sync::watch;
+
+  // returns true if the lobby accepted the join request
+  async fn join(info: &LanGameInfo, entry_key: u32) -> bool {
+    let (mut stream, client) = test_lobby_connect(move |mut stream| async move {
+      stream
+        .send(Packet::simple(ReqJoin::new("Player 1", 1, entry_key)).unwrap())
+        .await
+        .unwrap();
+      matches!(stream.recv().await, Ok(Some(_)))
+    })
+    .await;
+    let (_status_tx, mut status_rx) = watch::channel(None);
+    let action = LobbyHandler::new(info, &mut stream, None, &mut status_rx, None, None)
+      .with_join_timeout(Duration::from_millis(100))
+      .run()
+      .await
+      .unwrap();
+    assert!(matches!(action, LobbyAction::Leave));
+    drop(stream);
+    client.await.unwrap()
+  }
+
+  let mut info = test_lobby_info();
+  info.spectator_secret = Some("secret".to_string());
+
+  // players are not checked
+  assert!(join(&info, 0).await);
+
+  info.slot_info.my_slot.team = 24;
+  assert!(!join(&info, 0).await);
+  assert!(!join(&info, spectator_entry_key("wrong")).await);
+  assert!(join(&info, spectator_entry_key("secret")).await);
+}
+
+#[tokio::test]
+async fn test_lobby_join_timeout() {
+  use tokio::sync::{mpsc, watch};
+
+  let info = test_lobby_info();
+  let (mut stream, client) = test_lobby_connect(|mut stream| async move {
+    stream
+      .send(Packet::simple(ReqJoin::new("Player 1", 1, 0)).unwrap())
+      .await
+      .unwrap();
+    // a client with a different map never sends the profile packets
+    while let Ok(Some(_)) = stream.recv().await {}
+  })
+  .await;
+
+  let (_status_tx, mut status_rx) = watch::channel(None);
+  let (tx, mut rx) = mpsc::channel(10);
+  let action = LobbyHandler::new(
+    &info,
+    &mut stream,
+    None,
+    &mut status_rx,
+    Some(tx.downgrade()),
+    None,
+  )
+  .with_join_timeout(Duration::from_millis(100))
+  .run()
+  .await
+  .unwrap();
+  assert!(matches!(action, LobbyAction::Leave));
+
+  match rx.recv().await.unwrap() {
+    OutgoingMessage::LanGameMapMismatch(msg) => {
+      assert!(msg.timeout);
+      assert_eq!(msg.local_map_size, None);
+      assert_eq!(msg.expected_map_size, 127172);
+    }
+    other => panic!("unexpected message: {:?}", other),
+  }
+
+  drop(stream);
+  client.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_lobby_map_size_mismatch() {
+  use tokio::sync::{mpsc, watch};
+
+  let info = test_lobby_info();
+  let (mut stream, client) = test_lobby_connect(|mut stream| async move {
+    stream
+      .send(Packet::simple(ReqJoin::new("Player 1", 1, 0)).unwrap())
+      .await
+      .unwrap();
+    stream
+      .send(Packet::simple(MapSize::new(1000)).unwrap())
+      .await
+      .unwrap();
+    while let Ok(Some(_)) = stream.recv().await {}
+  })
+  .await;
+
+  let (_status_tx, mut status_rx) = watch::channel(None);
+  let (tx, mut rx) = mpsc::channel(10);
+  let res = LobbyHandler::new(
+    &info,
+    &mut stream,
+    None,
+    &mut status_rx,
+    Some(tx.downgrade()),
+    None,
+  )
+  .run()
+  .await;
+  assert!(matches!(res, Err(Error::MapChecksumMismatch)));
+
+  match rx.recv().await.unwrap() {
+    OutgoingMessage::LanGameMapMismatch(msg) => {
+      assert!(!msg.timeout);
+      assert_eq!(msg.local_map_size, Some(1000));
+      assert_eq!(msg.expected_map_size, 127172);
+    }
+    other => panic!("unexpected message: {:?}", other),
+  }
+
+  drop(stream);
+  client.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_lobby_joined_slot_layout() {
+  use tokio::sync::{mpsc, watch};
+
+  let info = test_lobby_info();
+  let (mut stream, client) = test_lobby_connect(|mut stream| async move {
+    // stays in the lobby until the connection is dropped
+    while let Ok(Some(_)) = stream.recv().await {}
+  })
+  .await;
+
+  let (_status_tx, mut status_rx) = watch::channel(None);
+  let (tx, mut rx) = mpsc::channel(10);
+  let mut handler = LobbyHandler::new(
+    &info,
+    &mut stream,
+    None,
+    &mut status_rx,
+    Some(tx.downgrade()),
+    None,
+  );
+
+  let mut join_state = JoinPacketRecvState::new(None, MapSizeCheck::default(), 2);
+  let mut reported = false;
+  assert!(!handler
+    .handle_join_progress(&join_state, &mut reported)
+    .await
+    .unwrap());
+  assert!(rx.try_recv().is_err());
+
+  join_state.num_profile = 2;
+  join_state.num_skins = 1;
+  join_state.num_unk5 = 1;
+  assert!(!handler
+    .handle_join_progress(&join_state, &mut reported)
+    .await
+    .unwrap());
+  match rx.try_recv().unwrap() {
+    OutgoingMessage::LanGameJoined(msg) => {
+      assert_eq!(msg.lobby_name, "test");
+      assert_eq!(
+        msg.slot_layout,
+        slot_layout(&info.slot_info, &BTreeSet::new())
+      );
+      assert_eq!(msg.slot_layout.players[0].player_id, 1);
+    }
+    other => panic!("unexpected message: {:?}", other),
+  }
+
+  // reported once
+  handler
+    .handle_join_progress(&join_state, &mut reported)
+    .await
+    .unwrap();
+  assert!(rx.try_recv().is_err());
+
+  drop(stream);
+  client.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_lobby_map_download_url() {
+  use flo_w3gs::chat::ChatMessage;
+  use tokio::sync::watch;
+
+  const URL: &str = "https://maps.example.com/test.w3x";
+
+  // returns the lobby chat messages received by a client without the map
+  async fn join(info: &LanGameInfo, sent: &AtomicBool) -> Vec<String> {
+    let (mut stream, client) = test_lobby_connect(|mut stream| async move {
+      stream
+        .send(Packet::simple(ReqJoin::new("Player 1", 1, 0)).unwrap())
+        .await
+        .unwrap();
+      let mut messages = vec![];
+      while let Ok(Some(pkt)) = stream.recv().await {
+        if pkt.type_id() == ChatFromHost::PACKET_TYPE_ID {
+          let chat: ChatFromHost = pkt.decode_simple().unwrap();
+          if let ChatMessage::Chat(message) = chat.0.message {
+            messages.push(message.to_string_lossy().to_string());
+          }
+        }
       }
+      messages
+    })
+    .await;
+
+    let (_status_tx, mut status_rx) = watch::channel(None);
+    let action = LobbyHandler::new(info, &mut stream, None, &mut status_rx, None, None)
+      .with_join_timeout(Duration::from_millis(100))
+      .with_map_download_url_sent(sent)
+      .run()
+      .await
+      .unwrap();
+    assert!(matches!(action, LobbyAction::Leave));
+    drop(stream);
+    client.await.unwrap()
   }
+
+  let mut info = test_lobby_info();
+  let sent = AtomicBool::new(false);
+
+  // not configured
+  assert!(join(&info, &sent).await.is_empty());
+
+  info.map_download_url = Some(URL.to_string());
+  let messages = join(&info, &sent).await;
+  assert_eq!(messages.len(), 1);
+  assert!(messages[0].contains(URL));
+
+  // only once per player
+  assert!(join(&info, &sent).await.is_empty());
+}
+
+#[cfg(debug_assertions)]
+#[tokio::test]
+async fn test_lobby_instant_start() {
+  use flo_w3gs::protocol::slot::SlotInfo;
+  use tokio::sync::watch;
+
+  let mut info = test_lobby_info();
+  info.instant_start = true;
+
+  let (mut stream, client) = test_lobby_connect(|mut stream| async move {
+    let mut type_ids = vec![];
+    while let Ok(Some(pkt)) = stream.recv().await {
+      type_ids.push(pkt.type_id());
+    }
+    type_ids
+  })
+  .await;
+
+  let (_status_tx, mut status_rx) = watch::channel(None);
+  let t = Instant::now();
+  LobbyHandler::new(&info, &mut stream, None, &mut status_rx, None, None)
+    .send_start()
+    .await
+    .unwrap();
+  assert!(t.elapsed() < Duration::from_secs(3));
+  drop(stream);
+
+  assert_eq!(
+    client.await.unwrap(),
+    vec![SlotInfo::PACKET_TYPE_ID, CountDownEnd::PACKET_TYPE_ID]
+  );
 }
@@ -1,28 +1,48 @@
+mod capture;
+mod chat_log;
+mod delay;
+mod desync;
 mod game;
 mod lobby;
+mod observer_mux;
+mod profile;
 mod proxy;
 pub mod slot;
+mod stats;
+mod status;
 
-pub use self::lobby::{LobbyAction, LobbyHandler};
+pub use self::chat_log::ChatLogEntry;
+pub use self::lobby::{LobbyAction, LobbyHandler, MapSizeCheck};
+pub use self::profile::W3gsProfile;
 pub use self::proxy::GameEndReason;
+pub use self::stats::{ProxyStats, TrafficStats};
 use crate::controller::ControllerClient;
 use crate::error::*;
+use crate::lan::game::game::sleep_until_deadline;
 use crate::lan::game::proxy::PlayerEvent;
-use crate::lan::game::slot::LanSlotInfo;
+use crate::lan::game::slot::{LanSlotInfo, ObserverPlacement, SlotInfoOptions};
+use crate::lan::game::status::GameStatusMachine;
 use crate::lan::get_lan_game_name;
-use crate::node::stream::NodeConnectToken;
+use crate::node::stream::{NodeConnectToken, NodeReconnectPolicy};
 use crate::node::NodeInfo;
 use flo_lan::{GameInfo, MdnsPublisher};
-use flo_state::Addr;
+use flo_state::{async_trait, Addr};
 use flo_task::SpawnScope;
 use flo_types::game::LocalGameInfo;
 use flo_types::node::{NodeGameStatus, SlotClientStatus};
+use flo_w3gs::net::W3GSStream;
+use flo_w3gs::protocol::constants::GameSettingFlags;
 use flo_w3gs::protocol::game::GameSettings;
 use flo_w3map::MapChecksum;
+use parking_lot::Mutex;
 use proxy::LanProxy;
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Notify;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
 use tokio::time::sleep;
 use tracing_futures::Instrument;
 
@@ -31,6 +51,9 @@ pub struct LanGame {
   state: Arc<State>,
   proxy: LanProxy,
   mdns_shutdown_notify: Arc<Notify>,
+  advertised_tx: watch::Sender<bool>,
+  status: GameStatusSequencer,
+  player_status_map: HashMap<i32, SlotClientStatus>,
 }
 
 #[derive(Debug)]
@@ -40,6 +63,61 @@ pub struct LanGameInfo {
   pub(crate) map_checksum: MapChecksum,
   pub(crate) game_settings: GameSettings,
   pub(crate) lan_game_name_override: Option<String>,
+  /// Address advertised to the game client instead of the local address of the stream
+  pub(crate) bind_addr: Option<Ipv4Addr>,
+  /// Selects build specific packet contents, e.g. `MapCheck`
+  pub(crate) w3gs_profile: W3gsProfile,
+  /// Required from a client joining in an observer slot, see `spectator_entry_key`
+  pub(crate) spectator_secret: Option<String>,
+  /// Delays packets sent to the game if the local client joined in an observer slot
+  pub(crate) observer_delay: Option<Duration>,
+  /// Compares the game state checksums of the local client with the checksums agreed on
+  /// at the node and reports `DesyncDetected`
+  pub(crate) desync_monitor: bool,
+  /// Records relayed chat messages for moderation review, see `LanGame::chat_log`
+  pub(crate) chat_log: bool,
+  /// Skips `CountDownStart` and the countdown, for automated tests.
+  /// Ignored in release builds.
+  pub(crate) instant_start: bool,
+  /// Extra observer sockets that can watch the game, see `LanGame::attach_observer`
+  pub(crate) max_observers: usize,
+  /// Sent in lobby chat to a client that failed the map check
+  pub(crate) map_download_url: Option<String>,
+  pub(crate) map_size_check: MapSizeCheck,
+}
+
+/// Client settings applied to every hosted LAN game
+#[derive(Debug, Clone, Default)]
+pub struct LanGameOptions {
+  /// Only advertise LAN games on the interface with this address
+  pub bind_addr: Option<Ipv4Addr>,
+  /// Bind the LAN game proxy to the first free port in this range instead of an OS-assigned port
+  pub port_range: Option<RangeInclusive<u16>>,
+  pub map_size_check: MapSizeCheck,
+  /// Slot of the FLO stream observer in the LAN lobby
+  pub observer_placement: ObserverPlacement,
+  /// Observers joining the LAN lobby have to send the entry key derived from this secret
+  pub spectator_secret: Option<String>,
+  /// Records the W3GS packets of every game to `<game_id>.w3gs` in this directory
+  pub record_dir: Option<PathBuf>,
+  /// Delays the game packets if the local client joined in an observer slot
+  pub observer_delay: Option<Duration>,
+  /// Extra observer sockets that can watch the game, 0 disables `LanGame::attach_observer`
+  pub max_observers: usize,
+  /// Sent in lobby chat to a client that failed the map check
+  pub map_download_url: Option<String>,
+  /// Highest suffix appended to the LAN game name if it is already advertised on the network,
+  /// names are not changed if it is below 2
+  pub max_name_suffix: u32,
+  /// Reports `DesyncDetected` if the game state of the local client diverges from the other players
+  pub desync_monitor: bool,
+  /// Records the chat messages of every game, see `LanGame::chat_log`
+  pub chat_log: bool,
+  /// Reconnects the node session of a loading or running game after a transient disconnect
+  pub reconnect_policy: NodeReconnectPolicy,
+  /// Starts the game without the lobby countdown, for automated tests
+  #[cfg(debug_assertions)]
+  pub instant_start: bool,
 }
 
 impl LanGame {
@@ -54,32 +132,68 @@ impl LanGame {
     save_replay: bool,
     user_replay_path: String,
     lobby_countdown_notify: Option<Arc<Notify>>,
+    force_start_notify: Option<Arc<Notify>>,
+    mut network_change_rx: watch::Receiver<()>,
+    options: LanGameOptions,
   ) -> Result<Self> {
+    let bind_addr = options.bind_addr;
     let mdns_shutdown_notify = Arc::new(Notify::new());
 
     let game_id = game.game_id;
+    let map_sha1 = game.map_sha1;
+    let map_xoro = map_checksum.xoro;
     let game_name = get_lan_game_name(&game.name, my_player_id);
+    if !game_name.starts_with(game.name.as_str()) {
+      tracing::warn!(
+        "game name truncated to {} bytes: {}",
+        flo_lan::MAX_GAME_NAME_LEN,
+        game_name
+      );
+    }
+    let map_path = game.map_path.replace("\\", "/");
     let mut game_info = GameInfo::new(
       game.game_id,
       &game_name,
-      &game.map_path.replace("\\", "/"),
+      &map_path,
       game.map_sha1,
       map_checksum.xoro,
     )?;
+    let game_settings = lan_game_settings(&game, &map_path, map_xoro);
+    let max_name_suffix = options.max_name_suffix;
+    game_info.set_game_setting_flags(game_settings.game_setting_flags);
     let token = NodeConnectToken::from_vec(player_token).ok_or_else(|| Error::InvalidNodeToken)?;
 
+    let slot_info = crate::lan::game::slot::build_player_slot_info(
+      my_player_id,
+      game.random_seed,
+      &game.slots,
+      game.map_twelve_p,
+      SlotInfoOptions {
+        ob_placement: options.observer_placement,
+        ..Default::default()
+      },
+    )?;
+    slot_info.validate()?;
     let proxy = LanProxy::start(
       LanGameInfo {
-        slot_info: crate::lan::game::slot::build_player_slot_info(
-          my_player_id,
-          game.random_seed,
-          &game.slots,
-          game.map_twelve_p,
-        )?,
+        slot_info,
         game,
         map_checksum,
-        game_settings: game_info.data.settings.clone(),
+        game_settings,
         lan_game_name_override: None,
+        bind_addr,
+        w3gs_profile: W3gsProfile::from_game_version(&game_version),
+        spectator_secret: options.spectator_secret.clone(),
+        observer_delay: options.observer_delay,
+        desync_monitor: options.desync_monitor,
+        chat_log: options.chat_log,
+        #[cfg(debug_assertions)]
+        instant_start: options.instant_start,
+        #[cfg(not(debug_assertions))]
+        instant_start: false,
+        max_observers: options.max_observers,
+        map_download_url: options.map_download_url,
+        map_size_check: options.map_size_check,
       },
       node,
       token,
@@ -88,6 +202,13 @@ impl LanGame {
       save_replay,
       user_replay_path,
       lobby_countdown_notify,
+      force_start_notify,
+      options.reconnect_policy.clone(),
+      options
+        .record_dir
+        .as_ref()
+        .map(|dir| dir.join(format!("{}.w3gs", game_id))),
+      options.port_range.clone(),
     )
     .await?;
     game_info.set_port(proxy.port());
@@ -95,17 +216,65 @@ impl LanGame {
     let state = Arc::new(State {
       game_id,
       my_player_id,
+      map_sha1,
+      map_xoro,
+      status_history: Mutex::new(StatusHistory::new(STATUS_HISTORY_CAPACITY)),
     });
+    let (advertised_tx, mut advertised_rx) = watch::channel(true);
     tokio::spawn(
       {
         let mut scope = scope.handle();
         let mdns_shutdown_notify = mdns_shutdown_notify.clone();
-        let publisher = MdnsPublisher::start(game_version, game_info).await?;
+        let mut advertisement: Advertisement =
+          Advertisement::start(game_version, game_info, bind_addr, max_name_suffix).await?;
         async move {
-          let _publisher = publisher;
-          tokio::select! {
-            _ = scope.left() => {}
-            _ = mdns_shutdown_notify.notified() => {}
+          let mut network_change_closed = false;
+          let mut advertised_closed = false;
+          let mut retry_at = None;
+          loop {
+            tokio::select! {
+              _ = scope.left() => break,
+              _ = mdns_shutdown_notify.notified() => {
+                advertisement.hide().await;
+                break;
+              }
+              res = advertised_rx.changed(), if !advertised_closed => {
+                if res.is_err() {
+                  advertised_closed = true;
+                  continue;
+                }
+                let visible = *advertised_rx.borrow();
+                if let Err(err) = advertisement.set_visible(visible).await {
+                  tracing::error!("publish game info: {}", err);
+                  retry_at = Some(Instant::now() + REPUBLISH_RETRY_INTERVAL);
+                }
+              }
+              res = network_change_rx.changed(), if !network_change_closed => {
+                if res.is_err() {
+                  // network change source dropped, keep the current registration
+                  network_change_closed = true;
+                  continue;
+                }
+                // The proxy listener is bound to all interfaces, so the port stays the same
+                // after an interface switch, but the bonjour registration is tied to the
+                // interfaces present when it was created. Drop it before re-registering,
+                // otherwise the new registration collides with the stale service name.
+                // With `bind_addr` set, the address must also exist on the new interface.
+                tracing::debug!("network changed, re-publishing");
+                if let Err(err) = advertisement.republish().await {
+                  tracing::error!("re-publish game info: {}", err);
+                  retry_at = Some(Instant::now() + REPUBLISH_RETRY_INTERVAL);
+                }
+              }
+              _ = sleep_until_deadline(retry_at) => {
+                // e.g. the interface of `bind_addr` is still coming up
+                retry_at = None;
+                if let Err(err) = advertisement.republish().await {
+                  tracing::warn!("re-publish game info: {}", err);
+                  retry_at = Some(Instant::now() + REPUBLISH_RETRY_INTERVAL);
+                }
+              }
+            }
           }
 
           // sleep(Duration::from_secs(1)).await;
@@ -121,6 +290,9 @@ impl LanGame {
       proxy,
       state,
       mdns_shutdown_notify,
+      advertised_tx,
+      status: GameStatusSequencer::default(),
+      player_status_map: HashMap::new(),
     })
   }
 
@@ -128,11 +300,76 @@ impl LanGame {
     self.state.game_id
   }
 
+  pub fn proxy_stats(&self) -> ProxyStats {
+    self.proxy.stats()
+  }
+
+  /// Applied game status changes, oldest first.
+  pub fn status_history(&self) -> Vec<(Instant, NodeGameStatus)> {
+    self.state.status_history.lock().to_vec()
+  }
+
+  /// Chat messages relayed in this game, oldest first.
+  /// Empty unless the chat log was enabled.
+  pub fn chat_log(&self) -> Vec<ChatLogEntry> {
+    self.proxy.chat_log()
+  }
+
+  /// Forwards the game packets to `stream` until it is closed or detached,
+  /// delayed by `observer_delay` if set. Returns an id for `detach_observer`.
+  pub fn attach_observer(&self, stream: W3GSStream) -> Result<u64> {
+    self.proxy.attach_observer(stream)
+  }
+
+  pub fn detach_observer(&self, id: u64) -> bool {
+    self.proxy.detach_observer(id)
+  }
+
+  /// Hides the game from the LAN list or shows it again, the game itself keeps running.
+  /// Unlike `shutdown` this can be reverted.
+  /// Sends a chat message from the FLO observer slot to all players,
+  /// returns `false` if the game has no observer slot
+  pub async fn send_observer_chat(&self, message: &str) -> Result<bool> {
+    self.proxy.send_observer_chat(message).await
+  }
+
+  pub fn set_advertised(&self, visible: bool) {
+    self.advertised_tx.send(visible).ok();
+  }
+
   pub async fn update_game_status(&self, status: NodeGameStatus) {
+    // hold the guard until the status is dispatched so overlapping calls reach the proxy in order
+    let _guard = match self.status.begin(status).await {
+      Some(guard) => guard,
+      None => {
+        tracing::debug!("ignored stale game status: {:?}", status);
+        return;
+      }
+    };
+    self
+      .state
+      .status_history
+      .lock()
+      .push(Instant::now(), status);
+    if status == NodeGameStatus::Ended {
+      self.mdns_shutdown_notify.notify_one();
+    }
     self.proxy.dispatch_game_status_change(status).await;
   }
 
   pub async fn update_player_status(&mut self, player_id: i32, status: SlotClientStatus) {
+    if let Some(current) = self.player_status_map.get(&player_id).cloned() {
+      if !current.can_transition_to(status) {
+        tracing::warn!(
+          player_id,
+          "ignored illegal player status transition: {:?} -> {:?}",
+          current,
+          status
+        );
+        return;
+      }
+    }
+    self.player_status_map.insert(player_id, status);
     self
       .proxy
       .dispatch_player_event(PlayerEvent::PlayerStatusChange { player_id, status })
@@ -143,7 +380,21 @@ impl LanGame {
     self.state.game_id == game_id && self.state.my_player_id == my_player_id
   }
 
+  pub fn is_same_map(&self, sha1: &[u8; 20], checksum: u32) -> bool {
+    self.state.is_same_map(sha1, checksum)
+  }
+
   pub fn shutdown(self) {
+    let history: Vec<_> = self
+      .status_history()
+      .into_iter()
+      .map(|(time, status)| (status, time.elapsed()))
+      .collect();
+    tracing::debug!(
+      game_id = self.state.game_id,
+      "shutdown, status changes (status, time ago): {:?}",
+      history
+    );
     self.mdns_shutdown_notify.notify_one();
     tokio::spawn(async move {
       if let Err(_) =
@@ -155,7 +406,549 @@ impl LanGame {
   }
 }
 
+/// A game held by `LanGameSet`
+#[async_trait]
+pub trait HostedLanGame {
+  fn game_id(&self) -> i32;
+  async fn update_game_status(&mut self, status: NodeGameStatus);
+  fn shutdown(self);
+}
+
+#[async_trait]
+impl HostedLanGame for LanGame {
+  fn game_id(&self) -> i32 {
+    LanGame::game_id(self)
+  }
+
+  async fn update_game_status(&mut self, status: NodeGameStatus) {
+    LanGame::update_game_status(self, status).await
+  }
+
+  fn shutdown(self) {
+    LanGame::shutdown(self)
+  }
+}
+
+/// LAN games hosted by this client at the same time, keyed by game id.
+///
+/// Every game owns its own proxy listener and mDNS registration, so they show up
+/// as separate entries in the Warcraft LAN list.
+pub struct LanGameSet<G = LanGame> {
+  games: HashMap<i32, G>,
+}
+
+impl<G> Default for LanGameSet<G> {
+  fn default() -> Self {
+    Self {
+      games: HashMap::new(),
+    }
+  }
+}
+
+impl<G: HostedLanGame> LanGameSet<G> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a game, shutting down the game previously stored under the same id.
+  pub fn add(&mut self, game: G) {
+    if let Some(last_game) = self.games.insert(game.game_id(), game) {
+      last_game.shutdown();
+    }
+  }
+
+  pub fn remove(&mut self, game_id: i32) -> Option<G> {
+    self.games.remove(&game_id)
+  }
+
+  /// Shuts down the game with `game_id`, the other games keep running.
+  /// Returns false if there is no such game.
+  pub fn shutdown(&mut self, game_id: i32) -> bool {
+    match self.games.remove(&game_id) {
+      Some(game) => {
+        game.shutdown();
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn get(&self, game_id: i32) -> Option<&G> {
+    self.games.get(&game_id)
+  }
+
+  /// Sends `status` to every game
+  pub async fn update_game_status(&mut self, status: NodeGameStatus) {
+    for game in self.games.values_mut() {
+      game.update_game_status(status).await;
+    }
+  }
+
+  pub fn get_mut(&mut self, game_id: i32) -> Option<&mut G> {
+    self.games.get_mut(&game_id)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.games.is_empty()
+  }
+
+  /// Drops all games without waiting for the proxies to shut down
+  pub fn clear(&mut self) {
+    self.games.clear();
+  }
+}
+
+impl LanGameSet {
+  /// Returns true if `game` is already hosted for `my_player_id` with the same map.
+  /// A game whose map was swapped by the host must be advertised again.
+  pub fn contains_same_game(&self, game: &LocalGameInfo, my_player_id: i32) -> bool {
+    self
+      .games
+      .get(&game.game_id)
+      .map(|g| {
+        g.is_same_game(game.game_id, my_player_id)
+          && g.is_same_map(&game.map_sha1, game.map_checksum)
+      })
+      .unwrap_or_default()
+  }
+}
+
+/// Delay before registering again after a failed mDNS registration
+const REPUBLISH_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Service registration used by `Advertisement`
+#[async_trait]
+trait LanPublisher: Sized + Send {
+  async fn start(
+    game_version: String,
+    game_info: GameInfo,
+    bind_addr: Option<Ipv4Addr>,
+    max_name_suffix: u32,
+  ) -> Result<Self>;
+
+  async fn goodbye(&mut self) -> Result<()>;
+}
+
+#[async_trait]
+impl LanPublisher for MdnsPublisher {
+  async fn start(
+    game_version: String,
+    game_info: GameInfo,
+    bind_addr: Option<Ipv4Addr>,
+    max_name_suffix: u32,
+  ) -> Result<Self> {
+    MdnsPublisher::start(game_version, game_info, bind_addr, max_name_suffix)
+      .await
+      .map_err(Into::into)
+  }
+
+  async fn goodbye(&mut self) -> Result<()> {
+    MdnsPublisher::goodbye(self).await.map_err(Into::into)
+  }
+}
+
+/// mDNS registration of a LAN game.
+/// `publisher` is `None` while the game is hidden or the last registration failed.
+struct Advertisement<P = MdnsPublisher> {
+  game_version: String,
+  game_info: GameInfo,
+  bind_addr: Option<Ipv4Addr>,
+  max_name_suffix: u32,
+  visible: bool,
+  publisher: Option<P>,
+}
+
+impl<P: LanPublisher> Advertisement<P> {
+  async fn start(
+    game_version: String,
+    game_info: GameInfo,
+    bind_addr: Option<Ipv4Addr>,
+    max_name_suffix: u32,
+  ) -> Result<Self> {
+    let publisher = P::start(
+      game_version.clone(),
+      game_info.clone(),
+      bind_addr,
+      max_name_suffix,
+    )
+    .await?;
+    Ok(Self {
+      game_version,
+      game_info,
+      bind_addr,
+      max_name_suffix,
+      visible: true,
+      publisher: Some(publisher),
+    })
+  }
+
+  fn is_visible(&self) -> bool {
+    self.visible
+  }
+
+  async fn set_visible(&mut self, visible: bool) -> Result<()> {
+    if self.visible == visible {
+      return Ok(());
+    }
+    self.visible = visible;
+    if visible {
+      tracing::debug!("showing game");
+      self.publish().await?;
+    } else {
+      tracing::debug!("hiding game");
+      self.hide().await;
+    }
+    Ok(())
+  }
+
+  /// Registers again if the game is visible
+  async fn republish(&mut self) -> Result<()> {
+    if self.visible {
+      // drop the old registration first, the new one would collide with its service name
+      self.publisher.take();
+      self.publish().await?;
+    }
+    Ok(())
+  }
+
+  async fn publish(&mut self) -> Result<()> {
+    self.publisher = Some(
+      P::start(
+        self.game_version.clone(),
+        self.game_info.clone(),
+        self.bind_addr,
+        self.max_name_suffix,
+      )
+      .await?,
+    );
+    Ok(())
+  }
+
+  /// Withdraws the registration so the game disappears from the LAN list right away
+  async fn hide(&mut self) {
+    if let Some(mut publisher) = self.publisher.take() {
+      if let Err(err) = publisher.goodbye().await {
+        tracing::warn!("mdns goodbye: {}", err);
+      }
+    }
+  }
+}
+
+/// Serializes game status updates and drops updates that would move the game backwards,
+/// e.g. a late `Waiting` arriving after `Ended`.
+#[derive(Default)]
+struct GameStatusSequencer {
+  current: tokio::sync::Mutex<GameStatusMachine>,
+}
+
+impl GameStatusSequencer {
+  /// Records `status` as the current status, the returned guard blocks other updates until dropped.
+  /// Returns `None` if `status` is stale.
+  async fn begin(
+    &self,
+    status: NodeGameStatus,
+  ) -> Option<tokio::sync::MutexGuard<'_, GameStatusMachine>> {
+    let mut current = self.current.lock().await;
+    if current.apply(status).is_rejected() {
+      return None;
+    }
+    Some(current)
+  }
+}
+
 struct State {
   game_id: i32,
   my_player_id: i32,
+  map_sha1: [u8; 20],
+  map_xoro: u32,
+  status_history: Mutex<StatusHistory>,
+}
+
+const STATUS_HISTORY_CAPACITY: usize = 32;
+
+/// Keeps the last `capacity` game status changes.
+struct StatusHistory {
+  capacity: usize,
+  items: VecDeque<(Instant, NodeGameStatus)>,
+}
+
+impl StatusHistory {
+  fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      items: VecDeque::with_capacity(capacity),
+    }
+  }
+
+  fn push(&mut self, time: Instant, status: NodeGameStatus) {
+    if self.items.len() == self.capacity {
+      self.items.pop_front();
+    }
+    self.items.push_back((time, status));
+  }
+
+  fn to_vec(&self) -> Vec<(Instant, NodeGameStatus)> {
+    self.items.iter().cloned().collect()
+  }
+}
+
+/// Settings advertised over mDNS and sent in `MapCheck`, with the game's options applied
+fn lan_game_settings(game: &LocalGameInfo, map_path: &str, map_xoro: u32) -> GameSettings {
+  GameSettings::builder(map_path, game.map_sha1, map_xoro)
+    .flags(game.game_flags.apply(GameSettingFlags::default()))
+    .build()
+}
+
+impl State {
+  fn is_same_map(&self, sha1: &[u8; 20], checksum: u32) -> bool {
+    &self.map_sha1 == sha1 && self.map_xoro == checksum
+  }
+}
+
+#[test]
+fn test_is_same_map() {
+  let sha1 = [1_u8; 20];
+  let state = State {
+    game_id: 1,
+    my_player_id: 1,
+    map_sha1: sha1,
+    map_xoro: 0x7973_2A56,
+    status_history: Mutex::new(StatusHistory::new(STATUS_HISTORY_CAPACITY)),
+  };
+  assert!(state.is_same_map(&sha1, 0x7973_2A56));
+  assert!(!state.is_same_map(&sha1, 0x1234_5678));
+  assert!(!state.is_same_map(&[2_u8; 20], 0x7973_2A56));
+}
+
+#[test]
+fn test_lan_game_settings_game_flags() {
+  use flo_types::game::GameFlags;
+
+  let checksum = MapChecksum {
+    xoro: 1,
+    classic_xoro: 2,
+    crc32: 3,
+    sha1: [4; 20],
+    file_size: 127172,
+  };
+  let info =
+    crate::lan::diag::test_lan_game_info("test", "Maps/test.w3x", false, 64, 64, checksum.clone())
+      .unwrap();
+  let mut game = (*info.game).clone();
+
+  // no options keeps the defaults
+  let settings = lan_game_settings(&game, "Maps/test.w3x", checksum.xoro);
+  assert_eq!(settings.game_setting_flags, GameSettingFlags::default());
+
+  game.game_flags = GameFlags::TEAMS_LOCKED | GameFlags::ALLIES_SHARED;
+  let settings = lan_game_settings(&game, "Maps/test.w3x", checksum.xoro);
+  assert!(settings
+    .game_setting_flags
+    .contains(GameSettingFlags::TEAMS_FIXED | GameSettingFlags::SHARED_CONTROL));
+
+  let map_check = W3gsProfile::Reforged.map_check(&checksum, &settings);
+  assert_eq!(map_check.sha1, settings.map_sha1);
+  assert_eq!(map_check.map_xoro, checksum.xoro);
+
+  let mut game_info = GameInfo::new(1, "test", "Maps/test.w3x", [4; 20], checksum.xoro).unwrap();
+  game_info.set_game_setting_flags(settings.game_setting_flags);
+  assert_eq!(game_info.data.settings, settings);
+}
+
+#[test]
+fn test_status_history() {
+  use NodeGameStatus::*;
+  let now = Instant::now();
+  let mut history = StatusHistory::new(3);
+  history.push(now, Created);
+  history.push(now + Duration::from_secs(1), Waiting);
+  assert_eq!(
+    history.to_vec(),
+    vec![(now, Created), (now + Duration::from_secs(1), Waiting)]
+  );
+
+  history.push(now + Duration::from_secs(2), Loading);
+  history.push(now + Duration::from_secs(3), Running);
+  history.push(now + Duration::from_secs(4), Ended);
+  let statuses: Vec<_> = history.to_vec().into_iter().map(|(_, s)| s).collect();
+  assert_eq!(statuses, vec![Loading, Running, Ended]);
+}
+
+#[tokio::test]
+async fn test_game_status_sequencer_concurrent() {
+  use NodeGameStatus::*;
+  let sequencer = Arc::new(GameStatusSequencer::default());
+  let handles: Vec<_> = vec![Created, Ended, Waiting, Loading, Running, Waiting]
+    .into_iter()
+    .map(|status| {
+      let sequencer = sequencer.clone();
+      tokio::spawn(async move { sequencer.begin(status).await.is_some() })
+    })
+    .collect();
+  for handle in handles {
+    handle.await.unwrap();
+  }
+  assert_eq!(sequencer.current.lock().await.current(), Some(Ended));
+}
+
+/// Records the registrations of every game on the test thread
+#[cfg(test)]
+struct FakePublisher {
+  game_id: String,
+}
+
+#[cfg(test)]
+thread_local! {
+  static FAKE_PUBLISHER_EVENTS: std::cell::RefCell<Vec<(String, &'static str)>> =
+    std::cell::RefCell::new(vec![]);
+}
+
+#[cfg(test)]
+impl FakePublisher {
+  fn record(game_id: &str, event: &'static str) {
+    FAKE_PUBLISHER_EVENTS.with(|events| events.borrow_mut().push((game_id.to_string(), event)));
+  }
+
+  fn take_events() -> Vec<(String, &'static str)> {
+    FAKE_PUBLISHER_EVENTS.with(|events| events.borrow_mut().drain(..).collect())
+  }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl LanPublisher for FakePublisher {
+  async fn start(
+    _game_version: String,
+    game_info: GameInfo,
+    _bind_addr: Option<Ipv4Addr>,
+    _max_name_suffix: u32,
+  ) -> Result<Self> {
+    Self::record(&game_info.game_id, "start");
+    Ok(FakePublisher {
+      game_id: game_info.game_id,
+    })
+  }
+
+  async fn goodbye(&mut self) -> Result<()> {
+    Self::record(&self.game_id, "goodbye");
+    Ok(())
+  }
+}
+
+#[tokio::test]
+async fn test_advertisement_visibility() {
+  let take_events = || -> Vec<&'static str> {
+    FakePublisher::take_events()
+      .into_iter()
+      .map(|(_, event)| event)
+      .collect()
+  };
+
+  let game_info = GameInfo::new(1, "test", "maps/test.w3x", [0; 20], 0).unwrap();
+  let mut advertisement: Advertisement<FakePublisher> =
+    Advertisement::start("1.33.0.00000".to_string(), game_info, None, 0)
+      .await
+      .unwrap();
+  assert_eq!(take_events(), vec!["start"]);
+
+  for _ in 0..2 {
+    advertisement.set_visible(false).await.unwrap();
+    assert!(!advertisement.is_visible());
+    assert_eq!(take_events(), vec!["goodbye"]);
+    // hidden games are not registered again on network changes
+    advertisement.republish().await.unwrap();
+    assert_eq!(take_events(), Vec::<&str>::new());
+
+    advertisement.set_visible(true).await.unwrap();
+    assert!(advertisement.is_visible());
+    assert_eq!(take_events(), vec!["start"]);
+    advertisement.set_visible(true).await.unwrap();
+    assert_eq!(take_events(), Vec::<&str>::new());
+  }
+
+  // visible games are registered again, the old registration is dropped without a goodbye
+  advertisement.republish().await.unwrap();
+  assert_eq!(take_events(), vec!["start"]);
+}
+
+#[tokio::test]
+async fn test_lan_game_set_concurrent_games() {
+  use std::sync::Mutex as StdMutex;
+
+  /// Withdraws its advertisement once the game ended, like `LanGame`
+  struct FakeGame {
+    id: i32,
+    generation: u32,
+    advertisement: Advertisement<FakePublisher>,
+    stopped: Arc<StdMutex<Vec<(i32, u32)>>>,
+  }
+
+  #[async_trait]
+  impl HostedLanGame for FakeGame {
+    fn game_id(&self) -> i32 {
+      self.id
+    }
+
+    async fn update_game_status(&mut self, status: NodeGameStatus) {
+      if status == NodeGameStatus::Ended {
+        self.advertisement.hide().await;
+      }
+    }
+
+    fn shutdown(self) {
+      self
+        .stopped
+        .lock()
+        .unwrap()
+        .push((self.id, self.generation));
+    }
+  }
+
+  let stopped = Arc::new(StdMutex::new(vec![]));
+  let game = |id, generation| {
+    let stopped = stopped.clone();
+    async move {
+      let game_info = GameInfo::new(id, "test", "maps/test.w3x", [0; 20], 0).unwrap();
+      FakeGame {
+        id,
+        generation,
+        advertisement: Advertisement::start("1.33.0.00000".to_string(), game_info, None, 0)
+          .await
+          .unwrap(),
+        stopped,
+      }
+    }
+  };
+
+  let mut set = LanGameSet::new();
+  set.add(game(1, 0).await);
+  set.add(game(2, 0).await);
+  assert!(set.get(1).is_some());
+  assert!(set.get(2).is_some());
+  // every game has its own registration
+  assert_eq!(
+    FakePublisher::take_events(),
+    vec![("1".to_string(), "start"), ("2".to_string(), "start")]
+  );
+
+  // replacing a game only shuts down the game with the same id
+  set.add(game(1, 1).await);
+  assert_eq!(*stopped.lock().unwrap(), vec![(1, 0)]);
+  assert_eq!(set.get(1).map(|g| g.generation), Some(1));
+  assert_eq!(set.get(2).map(|g| g.generation), Some(0));
+  FakePublisher::take_events();
+
+  // the status reaches the publisher of every game
+  set.update_game_status(NodeGameStatus::Ended).await;
+  let mut events = FakePublisher::take_events();
+  events.sort();
+  assert_eq!(
+    events,
+    vec![("1".to_string(), "goodbye"), ("2".to_string(), "goodbye")]
+  );
+
+  assert!(set.shutdown(2));
+  assert!(!set.shutdown(2));
+  assert_eq!(*stopped.lock().unwrap(), vec![(1, 0), (2, 0)]);
+  assert!(set.get(1).is_some());
 }
@@ -0,0 +1,211 @@
+use crate::error::*;
+use crate::lan::game::delay::{DelayQueue, OBSERVER_DELAY_MAX_PACKETS};
+use flo_w3gs::net::W3GSStream;
+use flo_w3gs::protocol::packet::Packet;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{channel, error::TrySendError, Sender};
+use tracing_futures::Instrument;
+
+/// Packets buffered for an observer socket, an observer that falls further behind is dropped
+pub const OBSERVER_MUX_CHANNEL_SIZE: usize = 1024;
+
+/// Fans out the packets the game receives from the node to extra observer sockets, e.g. casters.
+///
+/// Observers join and leave independently. A slow or closed observer socket is removed
+/// without blocking the game or the other observers.
+#[derive(Debug)]
+pub struct ObserverMux {
+  max_observers: usize,
+  next_id: u64,
+  observers: BTreeMap<u64, MuxObserver>,
+}
+
+#[derive(Debug)]
+struct MuxObserver {
+  tx: Sender<Packet>,
+  delayed: Option<DelayQueue<Packet>>,
+}
+
+impl MuxObserver {
+  /// Returns false if the observer should be removed
+  fn send(&self, id: u64, pkt: Packet) -> bool {
+    match self.tx.try_send(pkt) {
+      Ok(_) => true,
+      Err(TrySendError::Full(_)) => {
+        tracing::warn!(id, "observer fell behind, dropping");
+        false
+      }
+      Err(TrySendError::Closed(_)) => {
+        tracing::debug!(id, "observer left");
+        false
+      }
+    }
+  }
+}
+
+impl ObserverMux {
+  pub fn new(max_observers: usize) -> Self {
+    Self {
+      max_observers,
+      next_id: 0,
+      observers: BTreeMap::new(),
+    }
+  }
+
+  /// Starts forwarding packets to `stream`, delayed by `delay` if set.
+  /// The observer leaves when its stream is closed or `leave` is called.
+  pub fn join(&mut self, stream: W3GSStream, delay: Option<Duration>) -> Result<u64> {
+    if self.observers.len() >= self.max_observers {
+      return Err(Error::ObserverLimitReached(self.max_observers));
+    }
+    let id = self.next_id;
+    self.next_id += 1;
+
+    let (tx, mut rx) = channel::<Packet>(OBSERVER_MUX_CHANNEL_SIZE);
+    tokio::spawn(
+      async move {
+        let mut stream = stream;
+        while let Some(pkt) = rx.recv().await {
+          if let Err(err) = stream.send(pkt).await {
+            tracing::debug!("observer stream: {}", err);
+            return;
+          }
+        }
+        stream.flush().await.ok();
+      }
+      .instrument(tracing::debug_span!("observer_mux", id)),
+    );
+
+    self.observers.insert(
+      id,
+      MuxObserver {
+        tx,
+        delayed: delay.map(|delay| DelayQueue::new(delay, OBSERVER_DELAY_MAX_PACKETS)),
+      },
+    );
+    Ok(id)
+  }
+
+  /// Returns false if the observer already left
+  pub fn leave(&mut self, id: u64) -> bool {
+    self.observers.remove(&id).is_some()
+  }
+
+  pub fn len(&self) -> usize {
+    self.observers.len()
+  }
+
+  /// Queues `pkt` received at `now` for every observer
+  pub fn dispatch(&mut self, now: Instant, pkt: &Packet) {
+    self
+      .observers
+      .retain(|id, observer| match observer.delayed.as_mut() {
+        Some(delayed) => {
+          if delayed.is_full() {
+            tracing::warn!(observer_id = *id, "observer delay queue full, detaching");
+            return false;
+          }
+          delayed.push(now, pkt.clone());
+          true
+        }
+        None => observer.send(*id, pkt.clone()),
+      });
+  }
+
+  /// Sends the delayed packets that are ready at `now`
+  pub fn release(&mut self, now: Instant) {
+    self.observers.retain(|id, observer| {
+      let ready: Vec<_> = match observer.delayed.as_mut() {
+        Some(delayed) => std::iter::from_fn(|| delayed.pop_ready(now)).collect(),
+        None => return true,
+      };
+      ready.into_iter().all(|pkt| observer.send(*id, pkt))
+    });
+  }
+
+  /// When the next delayed packet becomes ready
+  pub fn next_deadline(&self) -> Option<Instant> {
+    self
+      .observers
+      .values()
+      .filter_map(|observer| observer.delayed.as_ref()?.next_deadline())
+      .min()
+  }
+}
+
+#[tokio::test]
+async fn test_observer_mux() {
+  use flo_w3gs::net::W3GSListener;
+  use flo_w3gs::protocol::chat::ChatFromHost;
+
+  async fn connect(listener: &mut W3GSListener) -> (W3GSStream, W3GSStream) {
+    let port = listener.port();
+    let (client, server) = tokio::join!(W3GSStream::connect(("127.0.0.1", port)), async {
+      listener.accept().await.unwrap().unwrap()
+    });
+    (client.unwrap(), server)
+  }
+
+  let mut listener = W3GSListener::bind().await.unwrap();
+  let (mut a, a_server) = connect(&mut listener).await;
+  let (mut b, b_server) = connect(&mut listener).await;
+  let (_c, c_server) = connect(&mut listener).await;
+
+  let mut mux = ObserverMux::new(2);
+  let a_id = mux.join(a_server, None).unwrap();
+  mux.join(b_server, None).unwrap();
+  assert!(matches!(
+    mux.join(c_server, None),
+    Err(Error::ObserverLimitReached(2))
+  ));
+
+  let packets: Vec<Packet> = (1..=2)
+    .map(|i| Packet::simple(ChatFromHost::lobby(i, &[1], format!("packet {}", i))).unwrap())
+    .collect();
+  let now = Instant::now();
+  for pkt in &packets {
+    mux.dispatch(now, pkt);
+  }
+
+  for stream in vec![&mut a, &mut b] {
+    for pkt in &packets {
+      let received = stream.recv().await.unwrap().unwrap();
+      assert_eq!(received.payload, pkt.payload);
+    }
+  }
+
+  // leaving doesn't affect the other observer
+  assert!(mux.leave(a_id));
+  mux.dispatch(now, &packets[0]);
+  assert_eq!(b.recv().await.unwrap().unwrap().payload, packets[0].payload);
+  assert_eq!(a.recv().await.unwrap().map(|pkt| pkt.payload), None);
+  assert_eq!(mux.len(), 1);
+}
+
+#[tokio::test]
+async fn test_observer_mux_delay() {
+  use flo_w3gs::net::W3GSListener;
+  use flo_w3gs::protocol::leave::LeaveAck;
+  use flo_w3gs::protocol::packet::PacketPayload;
+
+  let mut listener = W3GSListener::bind().await.unwrap();
+  let port = listener.port();
+  let (client, server) = tokio::join!(W3GSStream::connect(("127.0.0.1", port)), async {
+    listener.accept().await.unwrap().unwrap()
+  });
+  let mut client = client.unwrap();
+
+  let mut mux = ObserverMux::new(1);
+  mux.join(server, Some(Duration::from_secs(10))).unwrap();
+  let t = Instant::now();
+  mux.dispatch(t, &Packet::simple(LeaveAck).unwrap());
+  assert_eq!(mux.next_deadline(), Some(t + Duration::from_secs(10)));
+
+  mux.release(t + Duration::from_secs(9));
+  assert_eq!(mux.next_deadline(), Some(t + Duration::from_secs(10)));
+  mux.release(t + Duration::from_secs(10));
+  assert_eq!(mux.next_deadline(), None);
+  let pkt = client.recv().await.unwrap().unwrap();
+  assert_eq!(pkt.type_id(), LeaveAck::PACKET_TYPE_ID);
+}
@@ -0,0 +1,92 @@
+use flo_w3gs::protocol::game::GameSettings;
+use flo_w3gs::protocol::map::MapCheck;
+use flo_w3map::MapChecksum;
+
+/// W3GS differences between game client builds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum W3gsProfile {
+  /// 1.31 and older
+  Classic,
+  /// 1.32 and newer
+  Reforged,
+}
+
+impl Default for W3gsProfile {
+  fn default() -> Self {
+    W3gsProfile::Reforged
+  }
+}
+
+impl W3gsProfile {
+  /// Selects the profile from a version string like `1.32.10.18067`,
+  /// unknown versions are treated as Reforged
+  pub fn from_game_version(version: &str) -> Self {
+    let mut parts = version.split('.').map(|v| v.trim().parse::<u32>());
+    match (parts.next(), parts.next()) {
+      (Some(Ok(major)), Some(Ok(minor))) if (major, minor) < (1, 32) => W3gsProfile::Classic,
+      _ => W3gsProfile::Reforged,
+    }
+  }
+
+  /// `MapCheck` accepted by clients of this profile
+  pub fn map_check(&self, checksum: &MapChecksum, game_settings: &GameSettings) -> MapCheck {
+    let mut map_check = MapCheck::new(checksum.file_size as u32, checksum.crc32, game_settings);
+    map_check.map_xoro = match *self {
+      W3gsProfile::Classic => checksum.classic_xoro,
+      W3gsProfile::Reforged => checksum.xoro,
+    };
+    map_check
+  }
+}
+
+#[test]
+fn test_w3gs_profile_from_game_version() {
+  assert_eq!(
+    W3gsProfile::from_game_version("1.26.0.6401"),
+    W3gsProfile::Classic
+  );
+  assert_eq!(
+    W3gsProfile::from_game_version("1.31.1.12164"),
+    W3gsProfile::Classic
+  );
+  assert_eq!(
+    W3gsProfile::from_game_version("1.32.10.18067"),
+    W3gsProfile::Reforged
+  );
+  assert_eq!(
+    W3gsProfile::from_game_version("2.0.0.22370"),
+    W3gsProfile::Reforged
+  );
+  assert_eq!(W3gsProfile::from_game_version(""), W3gsProfile::Reforged);
+}
+
+#[test]
+fn test_w3gs_profile_map_check() {
+  let info = crate::lan::diag::test_lan_game_info(
+    "test",
+    "Maps\\test.w3x",
+    false,
+    64,
+    64,
+    MapChecksum {
+      xoro: 1,
+      classic_xoro: 2,
+      crc32: 3,
+      sha1: [4; 20],
+      file_size: 127172,
+    },
+  )
+  .unwrap();
+
+  let reforged = W3gsProfile::Reforged.map_check(&info.map_checksum, &info.game_settings);
+  assert_eq!(reforged.file_size, 127172);
+  assert_eq!(reforged.file_crc, 3);
+  assert_eq!(reforged.map_xoro, 1);
+  assert_eq!(reforged.sha1, info.game_settings.map_sha1);
+
+  let classic = W3gsProfile::Classic.map_check(&info.map_checksum, &info.game_settings);
+  assert_eq!(classic.file_size, 127172);
+  assert_eq!(classic.file_crc, 3);
+  assert_eq!(classic.map_xoro, 2);
+  assert_eq!(classic.file_path, reforged.file_path);
+}
@@ -1,16 +1,23 @@
 use crate::controller::{ControllerClient, GetWeakOutgoingMessageSender};
 use crate::error::*;
+use crate::lan::game::capture::PacketRecorder;
+use crate::lan::game::chat_log::{ChatLog, ChatLogEntry, CHAT_LOG_MAX_BYTES};
 use crate::lan::game::game::GameHandler;
 use crate::lan::game::lobby::{LobbyAction, LobbyHandler};
-use crate::lan::game::slot::index_to_player_id;
+use crate::lan::game::observer_mux::ObserverMux;
+use crate::lan::game::slot::{index_to_player_id, LanSlotInfo};
+use crate::lan::game::stats::{ProxyCounters, ProxyStats};
 use crate::lan::game::LanGameInfo;
 use crate::lan::LanEvent;
 use crate::messages::OutgoingMessage;
-use crate::node::stream::{NodeConnectToken, NodeStream, NodeStreamSender};
+use crate::node::stream::{
+  NodeConnectToken, NodeReconnectPolicy, NodeStream, NodeStreamSender, TickChecksum,
+};
 use crate::node::NodeInfo;
 use flo_state::Addr;
 use flo_task::{SpawnScope, SpawnScopeHandle};
 use flo_types::node::{NodeGameStatus, SlotClientStatus};
+use flo_w3gs::chat::{ChatFromHost, ChatToHost, MessageScope};
 use flo_w3gs::constants::LeaveReason;
 use flo_w3gs::net::{W3GSListener, W3GSStream};
 use flo_w3gs::protocol::constants::PacketTypeId;
@@ -20,7 +27,10 @@ use flo_w3gs::protocol::packet::Packet;
 use flo_w3gs::protocol::packet::*;
 use flo_w3gs::protocol::ping::{PingFromHost, PongToHost};
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{channel, Receiver, Sender, WeakSender};
@@ -30,6 +40,7 @@ use tokio_stream::StreamExt;
 use tracing_futures::Instrument;
 
 const LOAD_SCREEN_PING_INTERVAL: Duration = Duration::from_secs(15);
+const TICK_CHECKSUM_CHANNEL_SIZE: usize = 64;
 
 #[derive(Debug, Clone)]
 pub enum GameEndReason {
@@ -43,6 +54,11 @@ pub struct LanProxy {
   port: u16,
   status_tx: watch::Sender<Option<NodeGameStatus>>,
   event_tx: Sender<PlayerEvent>,
+  counters: Arc<ProxyCounters>,
+  w3gs_tx: Sender<Packet>,
+  state: Arc<State>,
+  client: Addr<ControllerClient>,
+  load_tracker: LoadTracker,
 }
 
 impl LanProxy {
@@ -55,13 +71,26 @@ impl LanProxy {
     save_replay: bool,
     user_replay_path: String,
     lobby_countdown_notify: Option<Arc<Notify>>,
+    force_start_notify: Option<Arc<Notify>>,
+    reconnect_policy: NodeReconnectPolicy,
+    record_path: Option<PathBuf>,
+    port_range: Option<RangeInclusive<u16>>,
   ) -> Result<Self> {
     let scope = SpawnScope::new();
-    let listener = W3GSListener::bind().await?;
+    let listener = match port_range {
+      Some(range) => W3GSListener::bind_range(range).await?,
+      None => W3GSListener::bind().await?,
+    };
     let port = listener.port();
     let (status_tx, status_rx) = watch::channel(None);
     let (event_tx, event_rx) = channel(10);
     let (w3gs_tx, w3gs_rx) = channel(32);
+    let (checksum_tx, checksum_rx) = if info.desync_monitor {
+      let (tx, rx) = channel(TICK_CHECKSUM_CHANNEL_SIZE);
+      (Some(tx), Some(rx))
+    } else {
+      (None, None)
+    };
     let game_id = info.game.game_id;
 
     tracing::debug!("connecting to node: {}", node.client_socket_addr());
@@ -74,16 +103,39 @@ impl LanProxy {
       token,
       client.clone(),
       w3gs_tx.clone(),
+      checksum_tx,
       end_reason.clone(),
+      reconnect_policy,
     )
     .await?;
 
     tracing::debug!("listening on port {}", port);
 
+    let recorder = if let Some(path) = record_path {
+      tracing::info!("recording packets to {}", path.display());
+      Some(PacketRecorder::start(path)?)
+    } else {
+      None
+    };
+
+    let counters = Arc::new(ProxyCounters::default());
+    let load_tracker = LoadTracker::new(info.slot_info.player_infos.iter().map(|p| p.player_id));
+    let max_observers = info.max_observers;
+    let chat_log = if info.chat_log {
+      Some(Mutex::new(ChatLog::new(CHAT_LOG_MAX_BYTES)))
+    } else {
+      None
+    };
     let state = Arc::new(State {
       info,
       stream: node_stream.sender(),
       game_status_rx: status_rx,
+      counters: counters.clone(),
+      recorder,
+      left_players: Mutex::new(BTreeSet::new()),
+      map_download_url_sent: AtomicBool::new(false),
+      chat_log,
+      observer_mux: Mutex::new(ObserverMux::new(max_observers)),
     });
 
     tokio::spawn({
@@ -91,6 +143,7 @@ impl LanProxy {
       let scope = scope.handle();
       let node = node.clone();
       let client = client.clone();
+      let w3gs_tx = w3gs_tx.clone();
       async move {
         let res = state
           .serve(
@@ -98,6 +151,7 @@ impl LanProxy {
             event_rx,
             w3gs_tx,
             w3gs_rx,
+            checksum_rx,
             end_reason,
             scope,
             node,
@@ -106,6 +160,7 @@ impl LanProxy {
             save_replay,
             user_replay_path,
             lobby_countdown_notify,
+            force_start_notify,
           )
           .await;
 
@@ -128,6 +183,11 @@ impl LanProxy {
       port,
       status_tx,
       event_tx,
+      counters,
+      w3gs_tx,
+      state,
+      client,
+      load_tracker,
     })
   }
 
@@ -136,6 +196,10 @@ impl LanProxy {
   }
 
   pub async fn dispatch_player_event(&mut self, evt: PlayerEvent) {
+    self
+      .load_tracker
+      .dispatch(&self.client, self.state.info.game.game_id, &evt)
+      .await;
     self.event_tx.send(evt).await.ok();
   }
 
@@ -143,6 +207,45 @@ impl LanProxy {
     self.port
   }
 
+  pub fn stats(&self) -> ProxyStats {
+    self.counters.snapshot(self.node_stream.queue_len())
+  }
+
+  pub fn attach_observer(&self, stream: W3GSStream) -> Result<u64> {
+    let delay = self.state.info.observer_delay;
+    self.state.observer_mux.lock().join(stream, delay)
+  }
+
+  pub fn detach_observer(&self, id: u64) -> bool {
+    self.state.observer_mux.lock().leave(id)
+  }
+
+  pub fn chat_log(&self) -> Vec<ChatLogEntry> {
+    self
+      .state
+      .chat_log
+      .as_ref()
+      .map(|log| log.lock().to_vec())
+      .unwrap_or_default()
+  }
+
+  /// Sends a chat message from the FLO observer slot to all players, e.g. caster announcements.
+  /// Messages are delivered through the game stream once the game is running.
+  /// Returns `false` if the game has no observer slot.
+  pub async fn send_observer_chat(&self, message: &str) -> Result<bool> {
+    match observer_chat_packet(&self.state.info.slot_info, message)? {
+      Some(pkt) => {
+        self
+          .w3gs_tx
+          .send(pkt)
+          .await
+          .map_err(|_| Error::TaskCancelled(anyhow::format_err!("W3GS rx dropped")))?;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
   pub async fn shutdown(self) {
     self.node_stream.shutdown().await;
   }
@@ -153,6 +256,14 @@ struct State {
   info: LanGameInfo,
   stream: NodeStreamSender,
   game_status_rx: watch::Receiver<Option<NodeGameStatus>>,
+  counters: Arc<ProxyCounters>,
+  recorder: Option<PacketRecorder>,
+  /// Players reported as left while in the lobby
+  left_players: Mutex<BTreeSet<i32>>,
+  /// The download URL is sent once, not on every reconnect of the game client
+  map_download_url_sent: AtomicBool,
+  chat_log: Option<Mutex<ChatLog>>,
+  observer_mux: Mutex<ObserverMux>,
 }
 
 impl State {
@@ -162,6 +273,7 @@ impl State {
     event_rx: Receiver<PlayerEvent>,
     mut w3gs_tx: Sender<Packet>,
     mut w3gs_rx: Receiver<Packet>,
+    mut checksum_rx: Option<Receiver<TickChecksum>>,
     end_reason: Arc<Mutex<Option<GameEndReason>>>,
     mut scope: SpawnScopeHandle,
     node: Arc<NodeInfo>,
@@ -170,6 +282,7 @@ impl State {
     save_replay: bool,
     user_replay_path: String,
     lobby_countdown_notify: Option<Arc<Notify>>,
+    force_start_notify: Option<Arc<Notify>>,
   ) -> Result<()> {
     let mut node_stream = self.stream.clone();
     let mut status_rx = self.game_status_rx.clone();
@@ -215,6 +328,7 @@ impl State {
           &mut status_rx,
           weak_outgoing_tx,
           lobby_countdown_notify.clone(),
+          force_start_notify.clone(),
         );
         tokio::pin!(lobby);
 
@@ -283,8 +397,13 @@ impl State {
       &mut status_rx,
       &mut w3gs_tx,
       &mut w3gs_rx,
+      checksum_rx.as_mut(),
       &mut client,
       &end_reason,
+      &self.counters,
+      self.recorder.as_ref(),
+      self.chat_log.as_ref(),
+      &self.observer_mux,
       game_version_string,
       save_replay,
       user_replay_path,
@@ -339,6 +458,9 @@ impl State {
             Some(evt) => {
               match evt {
                 PlayerEvent::PlayerStatusChange { player_id, status } => {
+                  if status == SlotClientStatus::Left {
+                    self.left_players.lock().insert(player_id);
+                  }
                   map.insert(player_id, status);
                 },
               }
@@ -360,6 +482,7 @@ impl State {
     status_rx: &mut watch::Receiver<Option<NodeGameStatus>>,
     weak_outgoing_tx: Option<WeakSender<OutgoingMessage>>,
     lobby_countdown_notify: Option<Arc<Notify>>,
+    force_start_notify: Option<Arc<Notify>>,
   ) -> Result<LobbyAction> {
     let mut lobby_handler = LobbyHandler::new(
       &self.info,
@@ -368,7 +491,10 @@ impl State {
       status_rx,
       weak_outgoing_tx,
       lobby_countdown_notify,
-    );
+    )
+    .with_force_start_notify(force_start_notify)
+    .with_left_players(&self.left_players)
+    .with_map_download_url_sent(&self.map_download_url_sent);
     let action = lobby_handler.run().await?;
     Ok(action)
   }
@@ -582,3 +708,159 @@ pub enum PlayerEvent {
     status: SlotClientStatus,
   },
 }
+
+/// Reports once when every player of the game finished loading,
+/// players who left or disconnected are not waited for,
+/// but at least one player has to reach `Loaded`
+#[derive(Debug)]
+struct LoadTracker {
+  pending: BTreeSet<i32>,
+  any_loaded: bool,
+  done: bool,
+}
+
+impl LoadTracker {
+  fn new<I: IntoIterator<Item = i32>>(players: I) -> Self {
+    LoadTracker {
+      pending: players.into_iter().collect(),
+      any_loaded: false,
+      done: false,
+    }
+  }
+
+  /// Returns `true` the first time all players are loaded
+  fn update(&mut self, player_id: i32, status: SlotClientStatus) -> bool {
+    if self.done {
+      return false;
+    }
+    match status {
+      SlotClientStatus::Loaded => {
+        if self.pending.remove(&player_id) {
+          self.any_loaded = true;
+        }
+      }
+      SlotClientStatus::Left | SlotClientStatus::Disconnected => {
+        self.pending.remove(&player_id);
+      }
+      _ => return false,
+    }
+    self.done = self.any_loaded && self.pending.is_empty();
+    self.done
+  }
+
+  /// Notifies `LanEvent::GameLoaded` the first time all players are loaded
+  async fn dispatch(&mut self, client: &Addr<ControllerClient>, game_id: i32, evt: &PlayerEvent) {
+    let PlayerEvent::PlayerStatusChange { player_id, status } = evt;
+    if self.update(*player_id, *status) {
+      tracing::debug!(game_id, "all players loaded");
+      client.notify(LanEvent::GameLoaded { game_id }).await.ok();
+    }
+  }
+}
+
+/// Chat from the FLO observer slot to every player
+fn observer_chat_packet(slot_info: &LanSlotInfo, message: &str) -> Result<Option<Packet>> {
+  let ob_slot = match slot_info.stream_ob_slot {
+    Some(ob_slot) => ob_slot,
+    None => return Ok(None),
+  };
+  let to: Vec<u8> = slot_info
+    .player_infos
+    .iter()
+    .map(|info| info.slot_player_id)
+    .collect();
+  let chat = ChatToHost::in_game(MessageScope::All, index_to_player_id(ob_slot), &to, message);
+  Ok(Some(Packet::simple(ChatFromHost::from(chat))?))
+}
+
+#[test]
+fn test_observer_chat_packet() {
+  use crate::lan::game::slot::{
+    build_player_slot_info, test_slots, ObserverPlacement, SlotInfoOptions,
+  };
+
+  let options = || SlotInfoOptions {
+    ob_placement: ObserverPlacement::LastSlot,
+    ..Default::default()
+  };
+  let slots = test_slots(24, &[0, 1]);
+  let slot_info = build_player_slot_info(1, 0, &slots, false, options()).unwrap();
+  let pkt = observer_chat_packet(&slot_info, "gl hf").unwrap().unwrap();
+  let chat: ChatFromHost = pkt.decode_simple().unwrap();
+  assert_eq!(chat.from_player(), index_to_player_id(23));
+  assert_eq!(chat.0.to_players, vec![1, 2]);
+  assert_eq!(chat.0.chat_message(), Some(&b"gl hf"[..]));
+
+  // the observer slot is taken by a player
+  let slots = test_slots(24, &[0, 23]);
+  let slot_info = build_player_slot_info(1, 0, &slots, false, options()).unwrap();
+  assert!(observer_chat_packet(&slot_info, "gl hf").unwrap().is_none());
+}
+
+#[test]
+fn test_load_tracker() {
+  let mut tracker = LoadTracker::new(vec![1, 2, 3]);
+  assert!(!tracker.update(1, SlotClientStatus::Loading));
+  assert!(!tracker.update(1, SlotClientStatus::Loaded));
+  assert!(!tracker.update(2, SlotClientStatus::Loaded));
+  // unknown players are ignored
+  assert!(!tracker.update(4, SlotClientStatus::Loaded));
+  assert!(tracker.update(3, SlotClientStatus::Loaded));
+
+  // fires once
+  for player_id in 1..=3 {
+    assert!(!tracker.update(player_id, SlotClientStatus::Loaded));
+  }
+
+  // players who left don't hold back the event
+  let mut tracker = LoadTracker::new(vec![1, 2]);
+  assert!(!tracker.update(1, SlotClientStatus::Loaded));
+  assert!(tracker.update(2, SlotClientStatus::Left));
+
+  // a game nobody loaded into is not loaded
+  let mut tracker = LoadTracker::new(vec![1, 2]);
+  assert!(!tracker.update(1, SlotClientStatus::Left));
+  assert!(!tracker.update(2, SlotClientStatus::Disconnected));
+  assert!(!tracker.update(3, SlotClientStatus::Loaded));
+}
+
+#[tokio::test]
+async fn test_load_tracker_dispatch() {
+  use flo_state::mock::Mock;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static LOADED: AtomicUsize = AtomicUsize::new(0);
+
+  async fn handle_lan_event(evt: LanEvent) {
+    if let LanEvent::GameLoaded { game_id } = evt {
+      assert_eq!(game_id, 1);
+      LOADED.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  let client = Mock::<ControllerClient>::builder()
+    .handle(handle_lan_event)
+    .build();
+  let addr = client.addr();
+  let mut tracker = LoadTracker::new(vec![1, 2, 3]);
+
+  for status in vec![SlotClientStatus::Loading, SlotClientStatus::Loaded] {
+    for player_id in 1..=3 {
+      let evt = PlayerEvent::PlayerStatusChange { player_id, status };
+      tracker.dispatch(&addr, 1, &evt).await;
+    }
+  }
+  // a repeated status after every slot loaded
+  let evt = PlayerEvent::PlayerStatusChange {
+    player_id: 2,
+    status: SlotClientStatus::Loaded,
+  };
+  tracker.dispatch(&addr, 1, &evt).await;
+
+  // messages are handled in order, all notifications were handled once this returns
+  addr
+    .send(LanEvent::LanGameDisconnected { game_id: 1 })
+    .await
+    .unwrap();
+  assert_eq!(LOADED.load(Ordering::SeqCst), 1);
+}
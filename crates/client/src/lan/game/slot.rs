@@ -1,9 +1,11 @@
 use flo_w3gs::slot::{RacePref, SlotData, SlotInfo};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
 
 use crate::error::*;
 use flo_types::game::{LanGameSlot, SlotStatus};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LanSlotInfo {
   pub my_slot_player_id: u8,
   pub slot_info: SlotInfo,
@@ -12,12 +14,235 @@ pub struct LanSlotInfo {
   pub stream_ob_slot: Option<usize>,
 }
 
-#[derive(Debug)]
+impl LanSlotInfo {
+  /// Copy of this layout with `live_slot_info` applied
+  pub fn live(&self, left_players: &BTreeSet<i32>) -> LanSlotInfo {
+    LanSlotInfo {
+      slot_info: self.live_slot_info(left_players),
+      ..self.clone()
+    }
+  }
+
+  /// Changes of the slot layout since `prev`, `None` if it is unchanged
+  pub fn diff(&self, prev: &Self) -> Option<SlotInfoDelta> {
+    let (prev, next) = (&prev.slot_info, &self.slot_info);
+    if prev == next {
+      return None;
+    }
+
+    if prev.slots().len() != next.slots().len()
+      || prev.random_seed != next.random_seed
+      || prev.slot_layout != next.slot_layout
+      || prev.num_players != next.num_players
+    {
+      return Some(SlotInfoDelta::Full);
+    }
+
+    let mut changed = prev
+      .slots()
+      .iter()
+      .zip(next.slots())
+      .enumerate()
+      .filter(|(_, (a, b))| a != b);
+    let delta = match (changed.next(), changed.next()) {
+      (Some((index, (a, b))), None) => {
+        let settings_only = SlotData {
+          team: b.team,
+          color: b.color,
+          race: b.race,
+          ..a.clone()
+        } == *b;
+        if settings_only {
+          SlotInfoDelta::Slot { index }
+        } else {
+          SlotInfoDelta::Full
+        }
+      }
+      _ => SlotInfoDelta::Full,
+    };
+    Some(delta)
+  }
+
+  /// Slot layout with the slots of players who left opened,
+  /// sent to clients joining after the initial layout was built
+  pub fn live_slot_info(&self, left_players: &BTreeSet<i32>) -> SlotInfo {
+    let mut slot_info = self.slot_info.clone();
+    for info in self
+      .player_infos
+      .iter()
+      .filter(|info| !self.is_live(info, left_players))
+    {
+      let is_player = match slot_info.slot_mut(info.slot_index) {
+        Some(slot) => {
+          let is_player = slot.team != 24;
+          *slot = SlotData::default();
+          is_player
+        }
+        None => continue,
+      };
+      if is_player {
+        slot_info.num_players = slot_info.num_players.saturating_sub(1);
+      }
+    }
+    slot_info
+  }
+
+  /// The local client joins as an observer
+  pub fn is_observer(&self) -> bool {
+    self.my_slot.team == 24
+      || self.stream_ob_slot.map(index_to_player_id) == Some(self.my_slot_player_id)
+  }
+
+  /// Players who are still in the game
+  pub fn live_player_infos<'a>(
+    &'a self,
+    left_players: &'a BTreeSet<i32>,
+  ) -> impl Iterator<Item = &'a LanSlotPlayerInfo> {
+    self
+      .player_infos
+      .iter()
+      .filter(move |info| self.is_live(info, left_players))
+  }
+
+  fn is_live(&self, info: &LanSlotPlayerInfo, left_players: &BTreeSet<i32>) -> bool {
+    info.slot_player_id == self.my_slot_player_id || !left_players.contains(&info.player_id)
+  }
+
+  /// Checks that every player has their own slot and the local player is seated,
+  /// a malformed `game.slots` can otherwise put two players in the same slot
+  pub fn validate(&self) -> Result<()> {
+    let num_slots = self.slot_info.slots().len();
+    let mut slot_indices = BTreeSet::new();
+    let mut player_ids = BTreeSet::new();
+    for info in &self.player_infos {
+      if info.slot_index >= num_slots {
+        return Err(Error::InvalidSlotLayout(format!(
+          "player {} is in slot {}, the game has {} slots",
+          info.player_id, info.slot_index, num_slots
+        )));
+      }
+      if !slot_indices.insert(info.slot_index) {
+        return Err(Error::InvalidSlotLayout(format!(
+          "slot {} is assigned to more than one player",
+          info.slot_index
+        )));
+      }
+      if !player_ids.insert(info.player_id) {
+        return Err(Error::InvalidSlotLayout(format!(
+          "player {} is assigned to more than one slot",
+          info.player_id
+        )));
+      }
+    }
+
+    if let Some(ob_slot) = self.stream_ob_slot {
+      if slot_indices.contains(&ob_slot) {
+        return Err(Error::InvalidSlotLayout(format!(
+          "observer slot {} is assigned to a player",
+          ob_slot
+        )));
+      }
+    }
+
+    let seated = self
+      .player_infos
+      .iter()
+      .any(|info| info.slot_player_id == self.my_slot_player_id)
+      || self.stream_ob_slot.map(index_to_player_id) == Some(self.my_slot_player_id);
+    if !seated {
+      return Err(Error::InvalidSlotLayout(format!(
+        "local slot player id {} is not in the layout",
+        self.my_slot_player_id
+      )));
+    }
+
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SlotInfoDelta {
+  /// Only the race, color or team of a single slot changed
+  Slot { index: usize },
+  /// Anything else, the whole `SlotInfo` has to be resent
+  Full,
+}
+
+#[derive(Debug, Clone)]
 pub struct LanSlotPlayerInfo {
   pub slot_player_id: u8,
   pub slot_index: usize,
   pub player_id: i32,
   pub name: String,
+  /// Seated in an observer slot, doesn't play
+  pub observer: bool,
+}
+
+/// Where a player was seated and with which settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotAssignment {
+  pub player_id: i32,
+  pub slot_index: usize,
+  pub team: u8,
+  pub color: u8,
+  pub race: u8,
+  pub handicap: u8,
+  pub observer: bool,
+  /// The handicap from the slot settings was replaced by a handicap override
+  pub handicap_overridden: bool,
+}
+
+/// Slot assignments of all seated players, logged at debug level by `build_player_slot_info`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SlotAssignmentReport {
+  pub assignments: Vec<SlotAssignment>,
+}
+
+impl SlotAssignmentReport {
+  fn new(
+    slot_info: &SlotInfo,
+    player_infos: &[LanSlotPlayerInfo],
+    slots: &[LanGameSlot],
+    handicaps: Option<&BTreeMap<usize, u8>>,
+  ) -> Self {
+    let assignments = player_infos
+      .iter()
+      .filter_map(|info| {
+        let slot = slot_info.slots().get(info.slot_index)?;
+        let requested = slots.get(info.slot_index)?.settings.handicap as u8;
+        Some(SlotAssignment {
+          player_id: info.player_id,
+          slot_index: info.slot_index,
+          team: slot.team,
+          color: slot.color,
+          race: slot.race.bits(),
+          handicap: slot.handicap,
+          observer: info.observer,
+          handicap_overridden: handicaps
+            .and_then(|handicaps| handicaps.get(&info.slot_index))
+            .map(|handicap| *handicap != requested)
+            .unwrap_or(false),
+        })
+      })
+      .collect();
+    Self { assignments }
+  }
+
+  fn log(&self) {
+    for a in &self.assignments {
+      tracing::debug!(
+        player_id = a.player_id,
+        slot_index = a.slot_index,
+        team = a.team,
+        color = a.color,
+        race = a.race,
+        handicap = a.handicap,
+        observer = a.observer,
+        handicap_overridden = a.handicap_overridden,
+        "slot assigned"
+      );
+    }
+  }
 }
 
 pub enum SelfPlayer {
@@ -31,18 +256,89 @@ impl From<i32> for SelfPlayer {
   }
 }
 
+/// Where the FLO stream observer is placed in the slot layout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObserverPlacement {
+  /// The last slot of the layout: 12 or 24
+  LastSlot,
+  /// The first slot not taken by a player or computer
+  FirstOpen,
+  /// A specific slot index
+  Index(usize),
+}
+
+impl Default for ObserverPlacement {
+  fn default() -> Self {
+    ObserverPlacement::LastSlot
+  }
+}
+
+impl FromStr for ObserverPlacement {
+  type Err = String;
+
+  /// `last`, `first-open` or a slot index
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "last" => Ok(ObserverPlacement::LastSlot),
+      "first-open" => Ok(ObserverPlacement::FirstOpen),
+      index => index
+        .parse()
+        .map(ObserverPlacement::Index)
+        .map_err(|_| format!("invalid observer placement: {}", s)),
+    }
+  }
+}
+
+impl ObserverPlacement {
+  /// Resolves the slot index, returns `None` if the slot is taken or out of range
+  fn resolve(&self, num_slots: usize, occupied: &[usize]) -> Option<usize> {
+    let index = match *self {
+      ObserverPlacement::LastSlot => num_slots - 1,
+      ObserverPlacement::FirstOpen => (0..num_slots).find(|i| !occupied.contains(i))?,
+      ObserverPlacement::Index(index) => index,
+    };
+    if index >= num_slots || occupied.contains(&index) {
+      None
+    } else {
+      Some(index)
+    }
+  }
+}
+
+/// Handicaps the game accepts, in percent
+pub const HANDICAP_STEPS: [u8; 6] = [50, 60, 70, 80, 90, 100];
+
+/// Optional settings of `build_player_slot_info`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SlotInfoOptions<'a> {
+  pub ob_placement: ObserverPlacement,
+  /// Overrides the handicap of occupied slots by slot index,
+  /// other slots keep the handicap from their settings.
+  pub handicaps: Option<&'a BTreeMap<usize, u8>>,
+  /// Pins the color of player slots by slot index. A slot whose color is taken by
+  /// an override gets the lowest color not used by any player slot.
+  /// Colors range from 0 to the number of slots of the layout minus one.
+  pub colors: Option<&'a BTreeMap<usize, u8>>,
+}
+
 pub fn build_player_slot_info<'a, P, S>(
   self_player: P,
   random_seed: i32,
   slots: &'a [S],
-  map_twelve_p: bool
+  map_twelve_p: bool,
+  options: SlotInfoOptions,
 ) -> Result<LanSlotInfo>
 where
   P: Into<SelfPlayer>,
   S: 'a,
   &'a S: Into<LanGameSlot<'a>>,
 {
-  let flo_ob_slot: usize = if map_twelve_p {11} else {23};
+  let SlotInfoOptions {
+    ob_placement,
+    handicaps,
+    colors,
+  } = options;
+  let num_slots: usize = if map_twelve_p { 12 } else { 24 };
   let self_player: SelfPlayer = self_player.into();
   let slots: Vec<LanGameSlot> = slots.into_iter().map(Into::into).collect();
 
@@ -57,28 +353,49 @@ where
     return Err(Error::SlotNotResolved);
   }
 
-  let flo_ob_slot_occupied = occupied_slots
-    .iter()
-    .find(|(idx, _)| *idx == flo_ob_slot)
-    .is_some();
+  if let Some(handicaps) = handicaps {
+    for (slot_index, handicap) in handicaps {
+      if !HANDICAP_STEPS.contains(handicap) {
+        return Err(Error::InvalidHandicap {
+          slot_index: *slot_index,
+          handicap: *handicap,
+        });
+      }
+    }
+  }
 
-  let stream_ob_slot = if let SelfPlayer::StreamObserver = self_player {
-    if occupied_slots.len() > (if map_twelve_p {11} else {23}) {
-      return Err(Error::FloObserverSlotOccupied);
+  if let Some(colors) = colors {
+    let mut seen = BTreeSet::new();
+    for (slot_index, color) in colors {
+      if *color as usize >= num_slots || !seen.insert(*color) {
+        return Err(Error::InvalidSlotColor {
+          slot_index: *slot_index,
+          color: *color,
+        });
+      }
     }
-    Some(flo_ob_slot)
-  } else {
-    if flo_ob_slot_occupied {
-      None
-    } else {
-      Some(flo_ob_slot)
+  }
+
+  let flo_ob_slot = ob_placement.resolve(
+    num_slots,
+    &occupied_slots
+      .iter()
+      .map(|(idx, _)| *idx)
+      .collect::<Vec<_>>(),
+  );
+
+  // players still get an observer slot if one is free, the stream observer requires it
+  let stream_ob_slot = flo_ob_slot;
+  if let SelfPlayer::StreamObserver = self_player {
+    if stream_ob_slot.is_none() {
+      return Err(Error::FloObserverSlotOccupied);
     }
-  };
+  }
 
   let mut slot_info = {
     let mut b = SlotInfo::build();
     b.random_seed(random_seed)
-      .num_slots(if map_twelve_p {12} else {24})
+      .num_slots(num_slots)
       .num_players(
         occupied_slots
           .iter()
@@ -90,6 +407,9 @@ where
 
   for (i, player_slot) in &occupied_slots {
     use flo_w3gs::slot::SlotStatus;
+    let handicap = handicaps
+      .and_then(|handicaps| handicaps.get(i).cloned())
+      .unwrap_or(player_slot.settings.handicap as u8);
     let slot = slot_info.slot_mut(*i).expect("always has 24 slots");
 
     if player_slot.player.is_some() {
@@ -98,7 +418,7 @@ where
       slot.race = player_slot.settings.race.into();
       slot.color = player_slot.settings.color as u8;
       slot.team = player_slot.settings.team as u8;
-      slot.handicap = player_slot.settings.handicap as u8;
+      slot.handicap = handicap;
       slot.download_status = 100;
     } else {
       slot.computer = true;
@@ -107,11 +427,20 @@ where
       slot.race = player_slot.settings.race.into();
       slot.color = player_slot.settings.color as u8;
       slot.team = player_slot.settings.team as u8;
-      slot.handicap = player_slot.settings.handicap as u8;
+      slot.handicap = handicap;
       slot.download_status = 100;
     }
   }
 
+  if let Some(colors) = colors {
+    let player_slots: Vec<usize> = occupied_slots
+      .iter()
+      .filter(|(_, slot)| slot.settings.team != 24)
+      .map(|(i, _)| *i)
+      .collect();
+    apply_color_overrides(&mut slot_info, num_slots, &player_slots, colors);
+  }
+
   if let Some(ob_slot_idx) = stream_ob_slot.clone() {
     use flo_w3gs::slot::SlotStatus;
     let slot = slot_info
@@ -125,7 +454,7 @@ where
     slot.team = 24;
   };
 
-  let player_infos = occupied_slots
+  let player_infos: Vec<LanSlotPlayerInfo> = occupied_slots
     .into_iter()
     .filter_map(|(i, slot)| {
       if stream_ob_slot == Some(i) {
@@ -138,6 +467,7 @@ where
           slot_index: i,
           player_id: player.id,
           name: player.name.to_string(),
+          observer: slot.settings.team == 24,
         })
       } else {
         None
@@ -145,6 +475,10 @@ where
     })
     .collect();
 
+  if tracing::enabled!(tracing::Level::DEBUG) {
+    SlotAssignmentReport::new(&slot_info, &player_infos, &slots, handicaps).log();
+  }
+
   let my_slot_index = match self_player {
     SelfPlayer::Player(player_id) => slots
       .into_iter()
@@ -166,6 +500,508 @@ where
   })
 }
 
+fn apply_color_overrides(
+  slot_info: &mut SlotInfo,
+  num_slots: usize,
+  player_slots: &[usize],
+  colors: &BTreeMap<usize, u8>,
+) {
+  let mut overridden = BTreeSet::new();
+  for i in player_slots {
+    if let (Some(color), Some(slot)) = (colors.get(i), slot_info.slot_mut(*i)) {
+      slot.color = *color;
+      overridden.insert(*color);
+    }
+  }
+
+  let mut used: BTreeSet<u8> = player_slots
+    .iter()
+    .filter_map(|i| slot_info.slots().get(*i).map(|slot| slot.color))
+    .collect();
+  for i in player_slots.iter().filter(|i| !colors.contains_key(i)) {
+    let slot = match slot_info.slot_mut(*i) {
+      Some(slot) => slot,
+      None => continue,
+    };
+    if !overridden.contains(&slot.color) {
+      continue;
+    }
+    if let Some(color) = (0..num_slots as u8).find(|color| !used.contains(color)) {
+      slot.color = color;
+      used.insert(color);
+    }
+  }
+}
+
 pub fn index_to_player_id(index: usize) -> u8 {
   return (index + 1) as u8;
 }
+
+#[cfg(test)]
+pub(crate) fn test_slots(num_slots: usize, players: &[usize]) -> Vec<flo_types::game::Slot> {
+  use flo_types::game::{PlayerInfo, PlayerSource, Slot};
+  (0..num_slots)
+    .map(|i| {
+      let mut slot = Slot::default();
+      if players.contains(&i) {
+        slot.player = Some(PlayerInfo {
+          id: i as i32 + 1,
+          name: format!("Player {}", i + 1),
+          source: PlayerSource::Test,
+        });
+        slot.settings.status = SlotStatus::Occupied;
+      }
+      slot
+    })
+    .collect()
+}
+
+/// Encodes the SlotInfoJoin the game receives for `info` and decodes it back
+#[cfg(test)]
+fn roundtrip_slot_info_join(info: &LanSlotInfo) -> flo_w3gs::protocol::join::SlotInfoJoin {
+  use flo_util::binary::SockAddr;
+  use flo_w3gs::protocol::join::SlotInfoJoin;
+  use flo_w3gs::protocol::packet::Packet;
+
+  Packet::simple(SlotInfoJoin {
+    slot_info: info.slot_info.clone(),
+    player_id: info.my_slot_player_id,
+    external_addr: SockAddr::new_null(),
+  })
+  .unwrap()
+  .decode_simple()
+  .unwrap()
+}
+
+#[test]
+fn test_observer_placement_last_slot() {
+  let slots = test_slots(24, &[0, 1]);
+  let info = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  assert_eq!(info.stream_ob_slot, Some(23));
+  assert_eq!(info.player_infos.len(), 2);
+
+  let slots = test_slots(12, &[0, 1]);
+  let info = build_player_slot_info(
+    SelfPlayer::StreamObserver,
+    0,
+    &slots,
+    true,
+    SlotInfoOptions::default(),
+  )
+  .unwrap();
+  assert_eq!(info.stream_ob_slot, Some(11));
+  assert_eq!(info.my_slot_player_id, 12);
+
+  // the last slot is taken by a player
+  let slots = test_slots(24, &[0, 23]);
+  let info = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  assert_eq!(info.stream_ob_slot, None);
+  assert!(matches!(
+    build_player_slot_info(
+      SelfPlayer::StreamObserver,
+      0,
+      &slots,
+      false,
+      SlotInfoOptions::default()
+    ),
+    Err(Error::FloObserverSlotOccupied)
+  ));
+}
+
+#[test]
+fn test_observer_placement_first_open() {
+  let slots = test_slots(24, &[0, 1, 3]);
+  let info = build_player_slot_info(
+    1,
+    0,
+    &slots,
+    false,
+    SlotInfoOptions {
+      ob_placement: ObserverPlacement::FirstOpen,
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(info.stream_ob_slot, Some(2));
+  assert_eq!(info.slot_info.slots()[2].team, 24);
+  assert_eq!(info.player_infos.len(), 3);
+
+  let all: Vec<usize> = (0..12).collect();
+  let slots = test_slots(12, &all);
+  let info = build_player_slot_info(
+    1,
+    0,
+    &slots,
+    true,
+    SlotInfoOptions {
+      ob_placement: ObserverPlacement::FirstOpen,
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(info.stream_ob_slot, None);
+  assert!(matches!(
+    build_player_slot_info(
+      SelfPlayer::StreamObserver,
+      0,
+      &slots,
+      true,
+      SlotInfoOptions {
+        ob_placement: ObserverPlacement::FirstOpen,
+        ..Default::default()
+      }
+    ),
+    Err(Error::FloObserverSlotOccupied)
+  ));
+}
+
+#[test]
+fn test_observer_placement_index() {
+  let slots = test_slots(24, &[0, 1]);
+  let info = build_player_slot_info(
+    SelfPlayer::StreamObserver,
+    0,
+    &slots,
+    false,
+    SlotInfoOptions {
+      ob_placement: ObserverPlacement::Index(5),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(info.stream_ob_slot, Some(5));
+  assert_eq!(info.my_slot_player_id, 6);
+
+  // collides with a player
+  assert!(matches!(
+    build_player_slot_info(
+      SelfPlayer::StreamObserver,
+      0,
+      &slots,
+      false,
+      SlotInfoOptions {
+        ob_placement: ObserverPlacement::Index(1),
+        ..Default::default()
+      }
+    ),
+    Err(Error::FloObserverSlotOccupied)
+  ));
+  let info = build_player_slot_info(
+    2,
+    0,
+    &slots,
+    false,
+    SlotInfoOptions {
+      ob_placement: ObserverPlacement::Index(1),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(info.stream_ob_slot, None);
+  assert_eq!(info.player_infos.len(), 2);
+
+  // out of range for a 12 player map
+  let slots = test_slots(12, &[0, 1]);
+  assert!(matches!(
+    build_player_slot_info(
+      SelfPlayer::StreamObserver,
+      0,
+      &slots,
+      true,
+      SlotInfoOptions {
+        ob_placement: ObserverPlacement::Index(12),
+        ..Default::default()
+      }
+    ),
+    Err(Error::FloObserverSlotOccupied)
+  ));
+}
+
+#[test]
+fn test_observer_placement_from_str() {
+  assert_eq!("last".parse(), Ok(ObserverPlacement::LastSlot));
+  assert_eq!("first-open".parse(), Ok(ObserverPlacement::FirstOpen));
+  assert_eq!("5".parse(), Ok(ObserverPlacement::Index(5)));
+  assert!("first".parse::<ObserverPlacement>().is_err());
+  assert!("-1".parse::<ObserverPlacement>().is_err());
+}
+
+#[test]
+fn test_player_observer_slot() {
+  let mut slots = test_slots(24, &[0, 1, 2]);
+  slots[2].settings.team = 24;
+  let info = build_player_slot_info(3, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  let observers: Vec<bool> = info.player_infos.iter().map(|p| p.observer).collect();
+  assert_eq!(observers, vec![false, false, true]);
+  assert!(info.is_observer());
+  assert_eq!(info.slot_info.num_players, 2);
+
+  let info = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  assert!(!info.is_observer());
+}
+
+#[test]
+fn test_slot_assignment_report() {
+  let mut slots = test_slots(24, &[0, 1, 2, 4]);
+  slots[1].settings.team = 1;
+  slots[1].settings.color = 1;
+  slots[4].settings.team = 24;
+  let handicaps: BTreeMap<usize, u8> = vec![(0, 100), (2, 70)].into_iter().collect();
+  let info = build_player_slot_info(
+    1,
+    0,
+    &slots,
+    false,
+    SlotInfoOptions {
+      handicaps: Some(&handicaps),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  let lan_slots: Vec<LanGameSlot> = slots.iter().map(Into::into).collect();
+  let report = SlotAssignmentReport::new(
+    &info.slot_info,
+    &info.player_infos,
+    &lan_slots,
+    Some(&handicaps),
+  );
+
+  // the stream observer slot is not a seated player
+  let seated: Vec<(i32, usize)> = report
+    .assignments
+    .iter()
+    .map(|a| (a.player_id, a.slot_index))
+    .collect();
+  assert_eq!(seated, vec![(1, 0), (2, 1), (3, 2), (5, 4)]);
+
+  let a = &report.assignments[1];
+  assert_eq!((a.team, a.color, a.observer), (1, 1, false));
+  assert!(report.assignments[3].observer);
+
+  // an override equal to the slot settings doesn't count
+  let overridden: Vec<bool> = report
+    .assignments
+    .iter()
+    .map(|a| a.handicap_overridden)
+    .collect();
+  assert_eq!(overridden, vec![false, false, true, false]);
+  assert_eq!(report.assignments[2].handicap, 70);
+}
+
+#[test]
+fn test_handicap_override() {
+  let slots = test_slots(24, &[0, 1, 2]);
+  let handicaps: BTreeMap<usize, u8> = vec![(1, 60)].into_iter().collect();
+  let info = build_player_slot_info(
+    1,
+    0,
+    &slots,
+    false,
+    SlotInfoOptions {
+      handicaps: Some(&handicaps),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  let handicaps: Vec<u8> = info.slot_info.slots()[..3]
+    .iter()
+    .map(|slot| slot.handicap)
+    .collect();
+  assert_eq!(handicaps, vec![100, 60, 100]);
+
+  // sent to the game as is
+  let decoded = roundtrip_slot_info_join(&info);
+  assert_eq!(decoded.slot_info.slots()[1].handicap, 60);
+
+  let handicaps: BTreeMap<usize, u8> = vec![(1, 55)].into_iter().collect();
+  assert!(matches!(
+    build_player_slot_info(
+      1,
+      0,
+      &slots,
+      false,
+      SlotInfoOptions {
+        handicaps: Some(&handicaps),
+        ..Default::default()
+      }
+    ),
+    Err(Error::InvalidHandicap {
+      slot_index: 1,
+      handicap: 55
+    })
+  ));
+}
+
+#[test]
+fn test_live_slot_info_after_leave() {
+  let slots = test_slots(24, &[0, 1, 2]);
+  let info = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  assert_eq!(info.slot_info.num_players, 3);
+
+  let no_leavers = BTreeSet::new();
+  assert_eq!(info.live_slot_info(&no_leavers), info.slot_info);
+  assert_eq!(info.live_player_infos(&no_leavers).count(), 3);
+
+  // player 2 left, player 1 is reconnecting
+  let left: BTreeSet<i32> = vec![2].into_iter().collect();
+  let live = info.live_slot_info(&left);
+  assert_eq!(live.num_players, 2);
+  assert_eq!(live.slots()[1], SlotData::default());
+  assert_eq!(live.slots()[0], info.slot_info.slots()[0]);
+  assert_eq!(live.slots()[2], info.slot_info.slots()[2]);
+  assert_eq!(live.slots()[23], info.slot_info.slots()[23]);
+  let ids: Vec<i32> = info.live_player_infos(&left).map(|p| p.player_id).collect();
+  assert_eq!(ids, vec![1, 3]);
+  assert_eq!(info.live(&left).slot_info, live);
+  assert_eq!(info.live(&left).diff(&info), Some(SlotInfoDelta::Full));
+
+  // the local player is never removed
+  let left: BTreeSet<i32> = vec![1].into_iter().collect();
+  assert_eq!(info.live_slot_info(&left), info.slot_info);
+  assert_eq!(info.live_player_infos(&left).count(), 3);
+}
+
+#[test]
+fn test_slot_info_diff() {
+  let slots = test_slots(24, &[0, 1, 2]);
+  let prev = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  let mut next = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  assert_eq!(next.diff(&prev), None);
+
+  // single race change
+  next.slot_info.slot_mut(1).unwrap().race = RacePref::ORC;
+  assert_eq!(next.diff(&prev), Some(SlotInfoDelta::Slot { index: 1 }));
+
+  // two slots changed
+  next.slot_info.slot_mut(2).unwrap().color = 5;
+  assert_eq!(next.diff(&prev), Some(SlotInfoDelta::Full));
+
+  // a player left
+  let mut next = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  *next.slot_info.slot_mut(1).unwrap() = SlotData::default();
+  assert_eq!(next.diff(&prev), Some(SlotInfoDelta::Full));
+}
+
+#[test]
+fn test_random_seed_verbatim() {
+  // a controller seed override arrives as the u32 bits stored in an i32
+  let seed = 0xdead_beef_u32;
+  let slots = test_slots(24, &[0, 1]);
+  let info =
+    build_player_slot_info(1, seed as i32, &slots, false, SlotInfoOptions::default()).unwrap();
+  assert_eq!(info.slot_info.random_seed, seed);
+  assert_eq!(roundtrip_slot_info_join(&info).slot_info.random_seed, seed);
+}
+
+#[test]
+fn test_validate_slot_layout() {
+  let slots = test_slots(24, &[0, 1, 2]);
+  let mut info = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  info.validate().unwrap();
+
+  // a malformed layout with two players in slot 1
+  info.player_infos[2].slot_index = 1;
+  assert!(matches!(
+    info.validate(),
+    Err(Error::InvalidSlotLayout(msg)) if msg.contains("slot 1")
+  ));
+
+  let mut info = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  info.stream_ob_slot = Some(2);
+  assert!(matches!(info.validate(), Err(Error::InvalidSlotLayout(_))));
+
+  let mut info = build_player_slot_info(1, 0, &slots, false, SlotInfoOptions::default()).unwrap();
+  info.my_slot_player_id = index_to_player_id(10);
+  assert!(matches!(info.validate(), Err(Error::InvalidSlotLayout(_))));
+
+  // the stream observer sits in the observer slot
+  let info = build_player_slot_info(
+    SelfPlayer::StreamObserver,
+    0,
+    &slots,
+    false,
+    SlotInfoOptions::default(),
+  )
+  .unwrap();
+  info.validate().unwrap();
+}
+
+#[test]
+fn test_color_override() {
+  let mut slots = test_slots(24, &[0, 1, 2, 3]);
+  for (i, slot) in slots.iter_mut().enumerate().take(4) {
+    slot.settings.color = i as i32;
+  }
+  // slot 3 takes red from slot 0, slot 1 keeps its color
+  let colors: BTreeMap<usize, u8> = vec![(3, 0), (2, 5)].into_iter().collect();
+  let info = build_player_slot_info(
+    1,
+    0,
+    &slots,
+    false,
+    SlotInfoOptions {
+      colors: Some(&colors),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  let colors: Vec<u8> = info.slot_info.slots()[..4]
+    .iter()
+    .map(|slot| slot.color)
+    .collect();
+  assert_eq!(colors, vec![2, 1, 5, 0]);
+
+  for colors in vec![vec![(1, 24)], vec![(1, 3), (2, 3)]] {
+    let colors: BTreeMap<usize, u8> = colors.into_iter().collect();
+    assert!(matches!(
+      build_player_slot_info(
+        1,
+        0,
+        &slots,
+        false,
+        SlotInfoOptions {
+          colors: Some(&colors),
+          ..Default::default()
+        }
+      ),
+      Err(Error::InvalidSlotColor { .. })
+    ));
+  }
+
+  // 12 slot layouts only have colors 0 to 11
+  let players: Vec<usize> = (0..12).collect();
+  let mut slots = test_slots(12, &players);
+  for (i, slot) in slots.iter_mut().enumerate() {
+    slot.settings.color = i as i32;
+  }
+  let colors: BTreeMap<usize, u8> = vec![(1, 12)].into_iter().collect();
+  assert!(matches!(
+    build_player_slot_info(
+      1,
+      0,
+      &slots,
+      true,
+      SlotInfoOptions {
+        colors: Some(&colors),
+        ..Default::default()
+      }
+    ),
+    Err(Error::InvalidSlotColor { .. })
+  ));
+
+  // every color is taken, the displaced slot keeps its color
+  let colors: BTreeMap<usize, u8> = vec![(0, 11)].into_iter().collect();
+  let info = build_player_slot_info(
+    1,
+    0,
+    &slots,
+    true,
+    SlotInfoOptions {
+      colors: Some(&colors),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(info.slot_info.slots()[0].color, 11);
+  assert_eq!(info.slot_info.slots()[11].color, 11);
+}
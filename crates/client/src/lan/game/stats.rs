@@ -0,0 +1,121 @@
+use flo_w3gs::protocol::packet::Packet;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// W3GS packet header: 1 byte tag + 1 byte type id + 2 bytes length
+const PACKET_HEADER_LEN: u64 = 4;
+
+/// Traffic counters updated by the proxy forwarding loop.
+/// Counters only grow and are updated without locking.
+#[derive(Debug, Default)]
+pub struct ProxyCounters {
+  sent: DirectionCounters,
+  received: DirectionCounters,
+}
+
+impl ProxyCounters {
+  /// Records a packet forwarded from the game to the node
+  #[inline]
+  pub fn record_sent(&self, pkt: &Packet) {
+    self.sent.record(pkt)
+  }
+
+  /// Records a packet forwarded from the node to the game
+  #[inline]
+  pub fn record_received(&self, pkt: &Packet) {
+    self.received.record(pkt)
+  }
+
+  pub fn snapshot(&self, send_queue_depth: usize) -> ProxyStats {
+    ProxyStats {
+      sent: self.sent.snapshot(),
+      received: self.received.snapshot(),
+      send_queue_depth,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct DirectionCounters {
+  bytes: AtomicU64,
+  packets: AtomicU64,
+}
+
+impl DirectionCounters {
+  #[inline]
+  fn record(&self, pkt: &Packet) {
+    self.bytes.fetch_add(
+      PACKET_HEADER_LEN + pkt.payload.len() as u64,
+      Ordering::Relaxed,
+    );
+    self.packets.fetch_add(1, Ordering::Relaxed);
+  }
+
+  fn snapshot(&self) -> TrafficStats {
+    TrafficStats {
+      bytes: self.bytes.load(Ordering::Relaxed),
+      packets: self.packets.load(Ordering::Relaxed),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TrafficStats {
+  pub bytes: u64,
+  pub packets: u64,
+}
+
+/// Bandwidth statistics of a LAN proxy, `sent` is game -> node, `received` is node -> game
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ProxyStats {
+  pub sent: TrafficStats,
+  pub received: TrafficStats,
+  /// Number of packets waiting to be sent to the node
+  pub send_queue_depth: usize,
+}
+
+#[tokio::test]
+async fn test_proxy_counters_loopback() {
+  use flo_w3gs::net::{W3GSListener, W3GSStream};
+  use flo_w3gs::protocol::ping::PingFromHost;
+  use std::sync::Arc;
+
+  const N: u64 = 100;
+
+  let mut listener = W3GSListener::bind().await.unwrap();
+  let port = listener.port();
+  let counters = Arc::new(ProxyCounters::default());
+
+  // echo every packet back, recording both directions like the proxy does
+  let server = tokio::spawn({
+    let counters = counters.clone();
+    async move {
+      let mut stream = listener.accept().await.unwrap().unwrap();
+      while let Some(pkt) = stream.recv().await.unwrap() {
+        counters.record_sent(&pkt);
+        counters.record_received(&pkt);
+        stream.send(pkt).await.unwrap();
+      }
+    }
+  });
+
+  let mut client = W3GSStream::connect(("127.0.0.1", port)).await.unwrap();
+  let mut total_bytes = 0;
+  for i in 0..N {
+    let pkt = Packet::simple(PingFromHost::with_payload(i as u32)).unwrap();
+    total_bytes += PACKET_HEADER_LEN + pkt.payload.len() as u64;
+    client.send(pkt).await.unwrap();
+    client.recv().await.unwrap().unwrap();
+  }
+  drop(client);
+  server.await.unwrap();
+
+  let stats = counters.snapshot(0);
+  let expected = TrafficStats {
+    bytes: total_bytes,
+    packets: N,
+  };
+  assert_eq!(stats.sent, expected);
+  assert_eq!(stats.received, expected);
+  assert_eq!(total_bytes, N * 8);
+}
@@ -0,0 +1,144 @@
+use flo_types::node::NodeGameStatus;
+
+/// Result of feeding a node reported status into a `GameStatusMachine`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameStatusTransition {
+  Applied,
+  /// Same status reported again
+  Unchanged,
+  Rejected {
+    from: NodeGameStatus,
+    to: NodeGameStatus,
+  },
+}
+
+impl GameStatusTransition {
+  pub fn is_rejected(&self) -> bool {
+    matches!(self, GameStatusTransition::Rejected { .. })
+  }
+}
+
+/// Game lifecycle as reported by the node: `Created -> Waiting -> Loading -> Running -> Ended`.
+/// Reports can skip steps but never move backwards, out-of-order reports are rejected.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GameStatusMachine {
+  current: Option<NodeGameStatus>,
+}
+
+impl GameStatusMachine {
+  pub fn new(initial: Option<NodeGameStatus>) -> Self {
+    Self { current: initial }
+  }
+
+  pub fn current(&self) -> Option<NodeGameStatus> {
+    self.current
+  }
+
+  /// The node has started loading or running the game
+  pub fn is_started(&self) -> bool {
+    matches!(
+      self.current,
+      Some(NodeGameStatus::Loading) | Some(NodeGameStatus::Running)
+    )
+  }
+
+  pub fn check(current: Option<NodeGameStatus>, next: NodeGameStatus) -> GameStatusTransition {
+    use NodeGameStatus::*;
+    let current = match current {
+      Some(current) => current,
+      None => return GameStatusTransition::Applied,
+    };
+    if current == next {
+      return GameStatusTransition::Unchanged;
+    }
+    let valid = match (current, next) {
+      (Created, Waiting | Loading | Running | Ended) => true,
+      (Waiting, Loading | Running | Ended) => true,
+      (Loading, Running | Ended) => true,
+      (Running, Ended) => true,
+      _ => false,
+    };
+    if valid {
+      GameStatusTransition::Applied
+    } else {
+      GameStatusTransition::Rejected {
+        from: current,
+        to: next,
+      }
+    }
+  }
+
+  pub fn apply(&mut self, next: NodeGameStatus) -> GameStatusTransition {
+    let transition = Self::check(self.current, next);
+    match transition {
+      GameStatusTransition::Applied => {
+        self.current = Some(next);
+      }
+      GameStatusTransition::Unchanged => {}
+      GameStatusTransition::Rejected { from, to } => {
+        tracing::warn!("rejected game status transition: {:?} => {:?}", from, to);
+      }
+    }
+    transition
+  }
+}
+
+#[test]
+fn test_game_status_machine_valid_sequences() {
+  use NodeGameStatus::*;
+  let sequences: &[&[NodeGameStatus]] = &[
+    &[Created, Waiting, Loading, Running, Ended],
+    &[Waiting, Running, Ended],
+    &[Created, Ended],
+    &[Loading, Loading, Running],
+  ];
+  for seq in sequences {
+    let mut machine = GameStatusMachine::default();
+    for status in seq.iter().cloned() {
+      assert!(
+        !machine.apply(status).is_rejected(),
+        "{:?}: {:?}",
+        seq,
+        status
+      );
+      assert_eq!(machine.current(), Some(status));
+    }
+  }
+
+  let mut machine = GameStatusMachine::new(Some(Waiting));
+  assert!(!machine.is_started());
+  assert_eq!(machine.apply(Loading), GameStatusTransition::Applied);
+  assert!(machine.is_started());
+  assert_eq!(machine.apply(Loading), GameStatusTransition::Unchanged);
+  assert_eq!(machine.apply(Running), GameStatusTransition::Applied);
+  assert!(machine.is_started());
+  assert_eq!(machine.apply(Ended), GameStatusTransition::Applied);
+  assert!(!machine.is_started());
+}
+
+#[test]
+fn test_game_status_machine_invalid_sequences() {
+  use NodeGameStatus::*;
+  let mut machine = GameStatusMachine::new(Some(Running));
+  assert_eq!(
+    machine.apply(Waiting),
+    GameStatusTransition::Rejected {
+      from: Running,
+      to: Waiting
+    }
+  );
+  assert_eq!(machine.current(), Some(Running));
+  assert!(machine.apply(Loading).is_rejected());
+  assert!(machine.apply(Created).is_rejected());
+  assert_eq!(machine.current(), Some(Running));
+
+  let mut machine = GameStatusMachine::new(Some(Ended));
+  for status in [Created, Waiting, Loading, Running] {
+    assert!(machine.apply(status).is_rejected());
+  }
+  assert_eq!(machine.current(), Some(Ended));
+
+  let mut machine = GameStatusMachine::new(Some(Loading));
+  assert!(machine.apply(Waiting).is_rejected());
+  assert!(machine.is_started());
+}
@@ -1,11 +1,14 @@
 pub mod diag;
 pub mod game;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::net::Ipv4Addr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use game::LanGame;
-use tokio::sync::Notify;
+use game::{ChatLogEntry, LanGame, LanGameOptions, LanGameSet, ProxyStats};
+use tokio::sync::{watch, Notify};
+use tokio::time::interval;
 
 use crate::controller::ControllerClient;
 use crate::error::*;
@@ -22,10 +25,40 @@ use flo_types::node::{NodeGameStatus, SlotClientStatus};
 pub struct Lan {
   platform: Addr<Platform>,
   client: Deferred<ControllerClient, StartConfig>,
-  active_game: Option<LanGame>,
+  games: LanGameSet,
+  network_change_tx: watch::Sender<()>,
+  game_options: LanGameOptions,
 }
 
-impl Actor for Lan {}
+/// How often the local interface addresses are compared to detect network changes
+const NETWORK_CHANGE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[async_trait]
+impl Actor for Lan {
+  async fn started(&mut self, ctx: &mut Context<Self>) {
+    let addr = ctx.addr();
+    ctx.spawn(async move {
+      let mut watcher = NetworkWatcher::default();
+      let mut interval = interval(NETWORK_CHANGE_POLL_INTERVAL);
+      loop {
+        interval.tick().await;
+        let addrs = match flo_lan::get_local_ipv4_addrs() {
+          Ok(addrs) => addrs,
+          Err(err) => {
+            tracing::warn!("list local addresses: {}", err);
+            continue;
+          }
+        };
+        if watcher.update(addrs) {
+          tracing::info!("network interfaces changed");
+          if addr.send(NotifyNetworkChange).await.is_err() {
+            break;
+          }
+        }
+      }
+    });
+  }
+}
 
 #[async_trait]
 impl Service<StartConfig> for Lan {
@@ -36,7 +69,9 @@ impl Service<StartConfig> for Lan {
     Ok(Lan {
       platform,
       client: registry.deferred(),
-      active_game: None,
+      games: LanGameSet::new(),
+      network_change_tx: watch::channel(()).0,
+      game_options: registry.data().lan_game_options.clone(),
     })
   }
 }
@@ -47,6 +82,7 @@ pub struct ReplaceLanGame {
   pub player_token: Vec<u8>,
   pub game: Arc<LocalGameInfo>,
   pub lobby_countdown_notify: Option<Arc<Notify>>,
+  pub force_start_notify: Option<Arc<Notify>>,
 }
 
 impl Message for ReplaceLanGame {
@@ -64,15 +100,11 @@ impl Handler<ReplaceLanGame> for Lan {
       player_token,
       game,
       lobby_countdown_notify,
+      force_start_notify,
     }: ReplaceLanGame,
   ) -> <ReplaceLanGame as Message>::Result {
     let game_id = game.game_id;
-    if self
-      .active_game
-      .as_ref()
-      .map(|g| g.is_same_game(game_id, my_player_id))
-      == Some(true)
-    {
+    if self.games.contains_same_game(&game, my_player_id) {
       tracing::debug!("skip create: same game");
       return Ok(());
     }
@@ -85,9 +117,8 @@ impl Handler<ReplaceLanGame> for Lan {
       .await??;
 
     if checksum.sha1 == game.map_sha1 {
-      if let Some(last_game) = self.active_game.take() {
-        last_game.shutdown();
-      }
+      // other games hosted by this client keep running
+      self.games.shutdown(game_id);
 
       let client_info = self
         .platform
@@ -125,12 +156,15 @@ impl Handler<ReplaceLanGame> for Lan {
         save_replay,
         user_replay_path,
         lobby_countdown_notify,
+        force_start_notify,
+        self.network_change_tx.subscribe(),
+        self.game_options.clone(),
       )
       .await?;
       tracing::info!(player_id = my_player_id, game_id, "lan game created.");
-      self.active_game = Some(lan_game);
+      self.games.add(lan_game);
     } else {
-      self.active_game.take();
+      self.games.remove(game_id);
       return Err(Error::MapChecksumMismatch);
     }
     Ok(())
@@ -158,16 +192,16 @@ impl Handler<UpdateLanGameStatus> for Lan {
       updated_player_game_client_status_map,
     }: UpdateLanGameStatus,
   ) -> <UpdateLanGameStatus as Message>::Result {
-    let game = if let Some(game) = self.active_game.as_mut() {
-      game
-    } else {
+    if self.games.is_empty() {
       return Err(Error::NotInGame);
-    };
+    }
 
-    if game.game_id() != game_id {
+    let game = if let Some(game) = self.games.get_mut(game_id) {
+      game
+    } else {
       tracing::error!("UpdateLanGameStatus: game id mismatch");
       return Ok(());
-    }
+    };
 
     for (player_id, status) in updated_player_game_client_status_map {
       game.update_player_status(player_id, status).await;
@@ -199,16 +233,16 @@ impl Handler<UpdateLanGamePlayerStatus> for Lan {
       status,
     }: UpdateLanGamePlayerStatus,
   ) -> <UpdateLanGamePlayerStatus as Message>::Result {
-    let game = if let Some(game) = self.active_game.as_mut() {
-      game
-    } else {
+    if self.games.is_empty() {
       return Err(Error::NotInGame);
-    };
+    }
 
-    if game.game_id() != game_id {
+    let game = if let Some(game) = self.games.get_mut(game_id) {
+      game
+    } else {
       tracing::warn!("UpdateLanGamePlayerStatus: game id mismatch");
       return Ok(());
-    }
+    };
 
     game.update_player_status(player_id, status).await;
 
@@ -231,14 +265,132 @@ impl Handler<StopLanGame> for Lan {
     _: &mut Context<Self>,
     StopLanGame { game_id }: StopLanGame,
   ) -> <StopLanGame as Message>::Result {
-    if self.active_game.as_ref().map(|g| g.game_id()) == Some(game_id) {
-      if let Some(game) = self.active_game.take() {
-        game.shutdown();
+    self.games.shutdown(game_id);
+  }
+}
+
+pub struct GetLanGameProxyStats {
+  pub game_id: i32,
+}
+
+impl Message for GetLanGameProxyStats {
+  type Result = Option<ProxyStats>;
+}
+
+#[async_trait]
+impl Handler<GetLanGameProxyStats> for Lan {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    GetLanGameProxyStats { game_id }: GetLanGameProxyStats,
+  ) -> <GetLanGameProxyStats as Message>::Result {
+    self.games.get(game_id).map(LanGame::proxy_stats)
+  }
+}
+
+/// Hides a running game from the LAN game list or shows it again
+pub struct SetLanGameAdvertised {
+  pub game_id: i32,
+  pub visible: bool,
+}
+
+impl Message for SetLanGameAdvertised {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<SetLanGameAdvertised> for Lan {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    SetLanGameAdvertised { game_id, visible }: SetLanGameAdvertised,
+  ) -> <SetLanGameAdvertised as Message>::Result {
+    if let Some(game) = self.games.get(game_id) {
+      game.set_advertised(visible);
+    }
+  }
+}
+
+/// Broadcasts a chat message from the FLO observer slot, e.g. caster announcements
+pub struct SendLanGameObserverChat {
+  pub game_id: i32,
+  pub message: String,
+}
+
+impl Message for SendLanGameObserverChat {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<SendLanGameObserverChat> for Lan {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    SendLanGameObserverChat { game_id, message }: SendLanGameObserverChat,
+  ) -> <SendLanGameObserverChat as Message>::Result {
+    if let Some(game) = self.games.get(game_id) {
+      match game.send_observer_chat(&message).await {
+        Ok(true) => {}
+        Ok(false) => tracing::warn!(game_id, "observer chat: game has no observer slot"),
+        Err(err) => tracing::error!(game_id, "observer chat: {}", err),
       }
     }
   }
 }
 
+pub struct GetLanGameChatLog {
+  pub game_id: i32,
+}
+
+impl Message for GetLanGameChatLog {
+  type Result = Option<Vec<ChatLogEntry>>;
+}
+
+#[async_trait]
+impl Handler<GetLanGameChatLog> for Lan {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    GetLanGameChatLog { game_id }: GetLanGameChatLog,
+  ) -> <GetLanGameChatLog as Message>::Result {
+    self.games.get(game_id).map(LanGame::chat_log)
+  }
+}
+
+/// Re-publishes the active LAN game after the local network interfaces changed.
+pub struct NotifyNetworkChange;
+
+impl Message for NotifyNetworkChange {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<NotifyNetworkChange> for Lan {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    _: NotifyNetworkChange,
+  ) -> <NotifyNetworkChange as Message>::Result {
+    self.network_change_tx.send(()).ok();
+  }
+}
+
+/// Detects changes of the local interface addresses between polls
+#[derive(Debug, Default)]
+struct NetworkWatcher {
+  addrs: Option<BTreeSet<Ipv4Addr>>,
+}
+
+impl NetworkWatcher {
+  /// Returns true if `addrs` differs from the previous poll, the first poll only records them
+  fn update(&mut self, addrs: BTreeSet<Ipv4Addr>) -> bool {
+    match self.addrs.replace(addrs) {
+      Some(prev) => Some(&prev) != self.addrs.as_ref(),
+      None => false,
+    }
+  }
+}
+
 pub struct KillLanGame;
 
 impl Message for KillLanGame {
@@ -252,12 +404,21 @@ impl Handler<KillLanGame> for Lan {
     _: &mut Context<Self>,
     _: KillLanGame,
   ) -> <KillLanGame as Message>::Result {
-    self.active_game.take();
+    // withdraw the advertisements and end the proxies of every game
+    self.games.update_game_status(NodeGameStatus::Ended).await;
+    self.games.clear();
   }
 }
 
+/// Game name shown in the LAN game list, the name is truncated to keep the player id suffix visible
 pub fn get_lan_game_name(game_name: &str, player_id: i32) -> String {
-  format!("{}-{}", game_name, player_id)
+  let suffix = format!("-{}", player_id);
+  let max_len = flo_lan::MAX_GAME_NAME_LEN.saturating_sub(suffix.len());
+  format!(
+    "{}{}",
+    flo_lan::truncate_game_name(game_name, max_len),
+    suffix
+  )
 }
 
 #[derive(Debug)]
@@ -265,6 +426,10 @@ pub enum LanEvent {
   LanGameDisconnected {
     game_id: i32,
   },
+  /// Every player finished loading
+  GameLoaded {
+    game_id: i32,
+  },
   NodeStreamEvent {
     game_id: i32,
     inner: NodeStreamEvent,
@@ -274,3 +439,28 @@ pub enum LanEvent {
 impl Message for LanEvent {
   type Result = ();
 }
+
+#[test]
+fn test_get_lan_game_name() {
+  assert_eq!(get_lan_game_name("1v1", 42), "1v1-42");
+  let name = get_lan_game_name(&"x".repeat(40), 123456);
+  assert_eq!(name.len(), flo_lan::MAX_GAME_NAME_LEN);
+  assert!(name.ends_with("-123456"));
+  assert_eq!(name, format!("{}-123456", "x".repeat(24)));
+}
+
+#[test]
+fn test_network_watcher() {
+  let mut watcher = NetworkWatcher::default();
+  let wifi: BTreeSet<_> = vec![Ipv4Addr::new(192, 168, 1, 10)].into_iter().collect();
+  let vpn: BTreeSet<_> = vec![Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(10, 8, 0, 2)]
+    .into_iter()
+    .collect();
+
+  assert!(!watcher.update(wifi.clone()));
+  assert!(!watcher.update(wifi.clone()));
+  assert!(watcher.update(vpn.clone()));
+  assert!(!watcher.update(vpn));
+  assert!(watcher.update(wifi));
+  assert!(watcher.update(BTreeSet::new()));
+}
@@ -25,9 +25,15 @@ pub struct StartConfig {
   pub save_replay: bool, //Default value is false
   pub user_battlenet_client_id: Option<String>,
   pub lobby_countdown_notify: Option<Arc<Notify>>,
+  /// Completes the lobby countdown immediately when notified, e.g. by a tournament admin
+  pub force_start_notify: Option<Arc<Notify>>,
+  pub lan_game_options: LanGameOptions,
 }
 
+pub use crate::lan::game::slot::ObserverPlacement;
+pub use crate::lan::game::{LanGameOptions, MapSizeCheck};
 pub use crate::message::embed::{start_embed, FloEmbedClient, FloEmbedClientHandle};
+pub use crate::node::stream::NodeReconnectPolicy;
 pub use message::messages;
 
 #[cfg(feature = "ws")]
@@ -3,14 +3,17 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::borrow::Cow;
 use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use flo_net::proto::flo_connect::{
-  PacketGamePlayerLeave, PacketGamePlayerPingMapSnapshot, PacketGamePlayerPingMapSnapshotRequest,
-  PacketGameSelectNode, PacketGameSelectNodeRequest, PacketGameStartReject, PacketGameStartRequest,
-  PacketGameStarting, PacketPlayerPingMapUpdate,
+  PacketGameLobbyMessage, PacketGamePlayerLeave, PacketGamePlayerPingMapSnapshot,
+  PacketGamePlayerPingMapSnapshotRequest, PacketGameSelectNode, PacketGameSelectNodeRequest,
+  PacketGameStartReject, PacketGameStartRequest, PacketGameStarting, PacketPlayerPingMapUpdate,
+  PacketServerConfig,
 };
 
 use crate::error::{Error, Result};
+use crate::lan::game::{ChatLogEntry, ProxyStats};
 use crate::observer::WatchGame;
 use crate::ping::PingUpdate;
 use crate::platform::PlatformStateError;
@@ -39,6 +42,15 @@ pub enum IncomingMessage {
   ClearNodeAddrOverrides,
   WatchGame(WatchGame),
   WatchGameSetSpeed(WatchGameSetSpeed),
+  GetLanGameProxyStats(LanGameRef),
+  GetLanGameChatLog(LanGameRef),
+  /// Hides the LAN game from the game list or shows it again
+  SetLanGameAdvertised(LanGameAdvertised),
+  /// Sends a chat message to all players from the FLO observer slot
+  SendLanGameObserverChat(LanGameObserverChat),
+  /// Starts sending `Ping`, the UI has to answer each with `Pong`
+  EnableHeartbeat,
+  Pong(Heartbeat),
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -53,7 +65,7 @@ pub enum OutgoingMessage {
   ListMapsError(ErrorMessage),
   GetMapDetail(MapDetail),
   GetMapDetailError(ErrorMessage),
-  CurrentGameInfo(GameInfo),
+  CurrentGameInfo(CurrentGameInfo),
   GamePlayerEnter(GamePlayerEnter),
   GamePlayerLeave(PacketGamePlayerLeave),
   GameSlotUpdate(GameSlotUpdate),
@@ -66,6 +78,7 @@ pub enum OutgoingMessage {
   GameStartReject(PacketGameStartReject),
   GameStarting(PacketGameStarting),
   GameStarted(GameStarted),
+  GameLoaded(GameLoaded),
   GameStartError(ErrorMessage),
   GameSlotClientStatusUpdate(ClientUpdateSlotClientStatus),
   GameStatusUpdate(GameStatusUpdate),
@@ -75,6 +88,14 @@ pub enum OutgoingMessage {
   WatchGameError(ErrorMessage),
   WatchGameSetSpeedError(ErrorMessage),
   LanGameJoined(LanGameJoined),
+  LanGameMapMismatch(LanGameMapMismatch),
+  LobbyPing(LobbyPing),
+  ServerConfig(PacketServerConfig),
+  GameLobbyMessage(PacketGameLobbyMessage),
+  LanGameProxyStats(LanGameProxyStats),
+  LanGameChatLog(LanGameChatLog),
+  DesyncDetected(DesyncDetected),
+  Ping(Heartbeat),
 }
 
 impl FromStr for IncomingMessage {
@@ -211,4 +232,211 @@ pub struct StartTestGame {
 #[derive(Debug, Serialize, Clone)]
 pub struct LanGameJoined {
   pub lobby_name: String,
+  pub slot_layout: LanGameSlotLayout,
+}
+
+/// Slot layout sent to the game client when all join packets were received
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LanGameSlotLayout {
+  pub my_slot_player_id: u8,
+  pub has_observer_slot: bool,
+  pub players: Vec<LanGameSlotPlayer>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LanGameSlotPlayer {
+  pub slot_index: usize,
+  pub slot_player_id: u8,
+  pub player_id: i32,
+  pub name: String,
+  pub team: u8,
+  pub color: u8,
+  /// `RacePref` bits
+  pub race: u8,
+}
+
+/// The game client failed the map check or never finished joining the lobby
+#[derive(Debug, Serialize, Clone)]
+pub struct LanGameMapMismatch {
+  pub game_id: i32,
+  /// Map size reported by the game client, missing if it never answered the map check
+  pub local_map_size: Option<u32>,
+  pub expected_map_size: u32,
+  pub timeout: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LanGameRef {
+  pub game_id: i32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LanGameProxyStats {
+  pub game_id: i32,
+  pub stats: Option<ProxyStats>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LanGameAdvertised {
+  pub game_id: i32,
+  pub visible: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LanGameObserverChat {
+  pub game_id: i32,
+  pub message: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LanGameChatLog {
+  pub game_id: i32,
+  /// `None` if the game is not running, empty if the chat log is disabled
+  pub messages: Option<Vec<LanGameChatMessage>>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LanGameChatMessage {
+  /// Unix timestamp in milliseconds
+  pub time: u64,
+  pub player_id: i32,
+  pub message: String,
+}
+
+impl LanGameChatMessage {
+  /// Converts the time `entry` was logged to wall clock time, `now` is the current `Instant`
+  pub fn new(entry: ChatLogEntry, now: Instant) -> Self {
+    let time = SystemTime::now()
+      .checked_sub(now.saturating_duration_since(entry.time))
+      .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+      .map(|time| time.as_millis() as u64)
+      .unwrap_or_default();
+    Self {
+      time,
+      player_id: entry.player_id,
+      message: entry.message,
+    }
+  }
+}
+
+/// Every player finished loading and the game is live
+#[derive(Debug, Serialize, Clone)]
+pub struct GameLoaded {
+  pub game_id: i32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DesyncDetected {
+  pub game_id: i32,
+  pub tick: u32,
+  pub player_ids: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LobbyPing {
+  pub player_id: i32,
+  pub ping: u32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CurrentGameInfo {
+  #[serde(flatten)]
+  pub game: GameInfo,
+  /// Current ping to the game's node, `None` if not measured yet
+  pub node_ping: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Heartbeat {
+  pub seq: u32,
+}
+
+#[test]
+fn test_serialize_server_config() {
+  let msg = OutgoingMessage::ServerConfig(PacketServerConfig {
+    maintenance: true,
+    game_create_disabled: true,
+    message: "maintenance in 10 minutes".to_string(),
+  });
+  let value: Value = serde_json::from_str(&msg.serialize().unwrap()).unwrap();
+  assert_eq!(value["type"], "ServerConfig");
+  assert_eq!(value["maintenance"], true);
+  assert_eq!(value["game_create_disabled"], true);
+  assert_eq!(value["message"], "maintenance in 10 minutes");
+}
+
+#[test]
+fn test_serialize_game_lobby_message() {
+  let msg = OutgoingMessage::GameLobbyMessage(PacketGameLobbyMessage {
+    game_id: 1,
+    message: "high ping".to_string(),
+  });
+  let value: Value = serde_json::from_str(&msg.serialize().unwrap()).unwrap();
+  assert_eq!(value["type"], "GameLobbyMessage");
+  assert_eq!(value["game_id"], 1);
+  assert_eq!(value["message"], "high ping");
+}
+
+#[test]
+fn test_serialize_desync_detected() {
+  let msg = OutgoingMessage::DesyncDetected(DesyncDetected {
+    game_id: 1,
+    tick: 42,
+    player_ids: vec![2, 3],
+  });
+  let value: Value = serde_json::from_str(&msg.serialize().unwrap()).unwrap();
+  assert_eq!(value["type"], "DesyncDetected");
+  assert_eq!(value["tick"], 42);
+  assert_eq!(value["player_ids"], serde_json::json!([2, 3]));
+}
+
+#[test]
+fn test_serialize_game_loaded() {
+  let msg = OutgoingMessage::GameLoaded(GameLoaded { game_id: 1 });
+  let value: Value = serde_json::from_str(&msg.serialize().unwrap()).unwrap();
+  assert_eq!(value["type"], "GameLoaded");
+  assert_eq!(value["game_id"], 1);
+}
+
+#[test]
+fn test_serialize_lan_game_chat_log() {
+  use std::time::Duration;
+
+  let now = Instant::now();
+  let entry = ChatLogEntry {
+    time: now - Duration::from_secs(60),
+    player_id: 2,
+    message: "gg".to_string(),
+  };
+  let msg = OutgoingMessage::LanGameChatLog(LanGameChatLog {
+    game_id: 1,
+    messages: Some(vec![LanGameChatMessage::new(entry, now)]),
+  });
+  let value: Value = serde_json::from_str(&msg.serialize().unwrap()).unwrap();
+  assert_eq!(value["type"], "LanGameChatLog");
+  assert_eq!(value["game_id"], 1);
+  assert_eq!(value["messages"][0]["player_id"], 2);
+  assert_eq!(value["messages"][0]["message"], "gg");
+
+  // logged a minute ago
+  let elapsed = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_millis() as u64
+    - value["messages"][0]["time"].as_u64().unwrap();
+  assert!(elapsed >= 60_000 && elapsed < 70_000);
+}
+
+#[test]
+fn test_deserialize_set_lan_game_advertised() {
+  let msg: IncomingMessage = r#"{"type":"SetLanGameAdvertised","game_id":1,"visible":false}"#
+    .parse()
+    .unwrap();
+  assert!(matches!(
+    msg,
+    IncomingMessage::SetLanGameAdvertised(LanGameAdvertised {
+      game_id: 1,
+      visible: false
+    })
+  ));
 }
@@ -1,12 +1,15 @@
 use super::messages::{
-  ClientInfo, ErrorMessage, IncomingMessage, MapList, MapPath, OutgoingMessage, War3Info,
-  WatchGameInfo,
+  ClientInfo, ErrorMessage, Heartbeat, IncomingMessage, LanGameChatLog, LanGameChatMessage,
+  LanGameProxyStats, MapList, MapPath, OutgoingMessage, War3Info, WatchGameInfo,
 };
 use super::{ConnectController, MessageEvent};
 use crate::controller::{
   ClearNodeAddrOverrides, ControllerClient, SendFrame, SetNodeAddrOverrides,
 };
 use crate::error::{Error, Result};
+use crate::lan::{
+  GetLanGameChatLog, GetLanGameProxyStats, SendLanGameObserverChat, SetLanGameAdvertised,
+};
 use crate::message::stream::MessageStream;
 use crate::observer::{ObserverClient, ObserverHostShared};
 use crate::platform::{
@@ -24,9 +27,15 @@ use flo_task::{SpawnScope, SpawnScopeHandle};
 use parking_lot::Mutex;
 use s2_grpc_utils::S2ProtoPack;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{channel, Receiver, Sender, WeakSender};
 use tracing_futures::Instrument;
 
+/// How often the UI is pinged
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long the UI has to answer a ping before it's considered dead
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Debug)]
 pub struct Session {
   _scope: SpawnScope,
@@ -47,6 +56,7 @@ impl Session {
       controller_client,
       observer_client,
       current_observer_host: Mutex::new(None),
+      heartbeat: Mutex::new(None),
     });
     tokio::spawn(
       {
@@ -94,6 +104,8 @@ async fn serve_stream(
 
   let (reply_sender, mut receiver) = channel(3);
 
+  let mut heartbeat_interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
   loop {
     tokio::select! {
       _ = scope.left() => {
@@ -126,17 +138,89 @@ async fn serve_stream(
 
         state.handle_message(&reply_sender, msg).await?;
       }
+      _ = heartbeat_interval.tick() => {
+        if !send_heartbeat(stream.as_mut(), &state.heartbeat, Instant::now()).await? {
+          tracing::warn!("ui heartbeat timeout");
+          state.handle_disconnect().await?;
+          break;
+        }
+      }
     }
   }
 
   Ok(())
 }
 
+/// Sends a `Ping` if the UI enabled the heartbeat and none is pending,
+/// returns `false` if the pending one timed out
+async fn send_heartbeat(
+  stream: &mut dyn MessageStream,
+  tracker: &Mutex<Option<HeartbeatTracker>>,
+  now: Instant,
+) -> Result<bool> {
+  let tick = tracker.lock().as_mut().map(|tracker| tracker.tick(now));
+  match tick {
+    Some(HeartbeatTick::Ping(msg)) => {
+      stream.send(OutgoingMessage::Ping(msg)).await?;
+      Ok(true)
+    }
+    Some(HeartbeatTick::Wait) | None => Ok(true),
+    Some(HeartbeatTick::Dead) => Ok(false),
+  }
+}
+
+#[derive(Debug, PartialEq)]
+enum HeartbeatTick {
+  Ping(Heartbeat),
+  Wait,
+  Dead,
+}
+
+#[derive(Debug)]
+struct HeartbeatTracker {
+  timeout: Duration,
+  next_seq: u32,
+  pending: Option<(u32, Instant)>,
+}
+
+impl HeartbeatTracker {
+  fn new(timeout: Duration) -> Self {
+    Self {
+      timeout,
+      next_seq: 0,
+      pending: None,
+    }
+  }
+
+  fn tick(&mut self, now: Instant) -> HeartbeatTick {
+    match self.pending {
+      Some((_, sent_at)) if now.saturating_duration_since(sent_at) >= self.timeout => {
+        HeartbeatTick::Dead
+      }
+      Some(_) => HeartbeatTick::Wait,
+      None => {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.pending = Some((seq, now));
+        HeartbeatTick::Ping(Heartbeat { seq })
+      }
+    }
+  }
+
+  fn pong(&mut self, pong: &Heartbeat) {
+    if self.pending.map(|(seq, _)| seq) == Some(pong.seq) {
+      self.pending = None;
+    }
+  }
+}
+
 struct Worker {
   platform: Addr<Platform>,
   controller_client: Addr<ControllerClient>,
   observer_client: Addr<ObserverClient>,
   current_observer_host: Mutex<Option<ObserverHostShared>>,
+  /// Set once the UI sent `EnableHeartbeat`
+  heartbeat: Mutex<Option<HeartbeatTracker>>,
 }
 
 impl Worker {
@@ -256,6 +340,69 @@ impl Worker {
         };
         reply_sender.send(reply).await?;
       }
+      IncomingMessage::EnableHeartbeat => {
+        self
+          .heartbeat
+          .lock()
+          .get_or_insert_with(|| HeartbeatTracker::new(HEARTBEAT_TIMEOUT));
+      }
+      IncomingMessage::Pong(pong) => {
+        if let Some(tracker) = self.heartbeat.lock().as_mut() {
+          tracker.pong(&pong);
+        }
+      }
+      IncomingMessage::GetLanGameProxyStats(req) => {
+        let stats = self
+          .controller_client
+          .send(GetLanGameProxyStats {
+            game_id: req.game_id,
+          })
+          .await?;
+        reply_sender
+          .send(OutgoingMessage::LanGameProxyStats(LanGameProxyStats {
+            game_id: req.game_id,
+            stats,
+          }))
+          .await?;
+      }
+      IncomingMessage::SetLanGameAdvertised(req) => {
+        self
+          .controller_client
+          .send(SetLanGameAdvertised {
+            game_id: req.game_id,
+            visible: req.visible,
+          })
+          .await?;
+      }
+      IncomingMessage::SendLanGameObserverChat(req) => {
+        self
+          .controller_client
+          .send(SendLanGameObserverChat {
+            game_id: req.game_id,
+            message: req.message,
+          })
+          .await?;
+      }
+      IncomingMessage::GetLanGameChatLog(req) => {
+        let entries = self
+          .controller_client
+          .send(GetLanGameChatLog {
+            game_id: req.game_id,
+          })
+          .await?;
+        let now = Instant::now();
+        reply_sender
+          .send(OutgoingMessage::LanGameChatLog(LanGameChatLog {
+            game_id: req.game_id,
+            messages: entries.map(|entries| {
+              entries
+                .into_iter()
+                .map(|entry| LanGameChatMessage::new(entry, now))
+                .collect()
+            }),
+          }))
+          .await?;
+      }
     }
     Ok(())
   }
@@ -371,3 +518,71 @@ impl From<tokio::sync::mpsc::error::SendError<OutgoingMessage>> for Error {
     Error::TaskCancelled(anyhow::format_err!("websocket message dropped"))
   }
 }
+
+#[tokio::test]
+async fn test_heartbeat_dead_ui() {
+  use flo_state::async_trait;
+
+  struct SilentStream(Vec<OutgoingMessage>);
+
+  #[async_trait]
+  impl MessageStream for SilentStream {
+    async fn send(&mut self, msg: OutgoingMessage) -> Result<()> {
+      self.0.push(msg);
+      Ok(())
+    }
+
+    async fn recv(&mut self) -> Option<IncomingMessage> {
+      futures::future::pending().await
+    }
+
+    async fn flush(&mut self) {}
+  }
+
+  let mut stream = SilentStream(vec![]);
+  let tracker = Mutex::new(None);
+  let t = Instant::now();
+
+  // not enabled by the UI
+  assert!(send_heartbeat(&mut stream, &tracker, t).await.unwrap());
+  assert!(send_heartbeat(&mut stream, &tracker, t + HEARTBEAT_TIMEOUT)
+    .await
+    .unwrap());
+  assert!(stream.0.is_empty());
+
+  *tracker.lock() = Some(HeartbeatTracker::new(HEARTBEAT_TIMEOUT));
+  assert!(send_heartbeat(&mut stream, &tracker, t).await.unwrap());
+  assert!(matches!(
+    stream.0[..],
+    [OutgoingMessage::Ping(Heartbeat { seq: 0 })]
+  ));
+
+  assert!(
+    send_heartbeat(&mut stream, &tracker, t + HEARTBEAT_INTERVAL)
+      .await
+      .unwrap()
+  );
+  assert_eq!(stream.0.len(), 1);
+
+  assert!(
+    !send_heartbeat(&mut stream, &tracker, t + HEARTBEAT_TIMEOUT)
+      .await
+      .unwrap()
+  );
+}
+
+#[test]
+fn test_heartbeat_pong() {
+  let mut tracker = HeartbeatTracker::new(HEARTBEAT_TIMEOUT);
+  let t = Instant::now();
+
+  assert_eq!(tracker.tick(t), HeartbeatTick::Ping(Heartbeat { seq: 0 }));
+  tracker.pong(&Heartbeat { seq: 1 });
+  assert_eq!(tracker.tick(t + HEARTBEAT_TIMEOUT), HeartbeatTick::Dead);
+
+  tracker.pong(&Heartbeat { seq: 0 });
+  assert_eq!(
+    tracker.tick(t + HEARTBEAT_TIMEOUT),
+    HeartbeatTick::Ping(Heartbeat { seq: 1 })
+  );
+}
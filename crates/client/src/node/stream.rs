@@ -11,13 +11,14 @@ use flo_net::stream::FloStream;
 use flo_net::w3gs::{W3GSAckQueue, W3GSFrameExt, W3GSMetadata, W3GSPacket, W3GSPacketTypeId};
 use flo_state::Addr;
 use flo_types::game::GameStatusUpdate;
-use flo_types::node::NodeGameStatusSnapshot;
 use flo_types::node::SlotClientStatus;
+use flo_types::node::{NodeGameStatus, NodeGameStatusSnapshot};
 use flo_w3gs::action::IncomingAction;
 use flo_w3gs::protocol::chat::ChatFromHost;
-use futures::FutureExt;
+use futures::{Future, FutureExt};
 use parking_lot::Mutex;
 use s2_grpc_utils::{S2ProtoEnum, S2ProtoUnpack};
+use std::collections::VecDeque;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -28,6 +29,53 @@ use tokio::time::{sleep, Sleep};
 use tokio_util::sync::CancellationToken;
 use tracing_futures::Instrument;
 
+const SEND_CHANNEL_SIZE: usize = 10;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Controls how the node connection is re-established after a transient disconnect.
+/// Only games that are loading or running are reconnected, otherwise the session ends.
+#[derive(Debug, Clone)]
+pub struct NodeReconnectPolicy {
+  /// Time spent reconnecting before giving up, counted from the last stable connection.
+  /// Zero disables reconnecting.
+  pub max_elapsed: Duration,
+  pub initial_interval: Duration,
+  pub max_interval: Duration,
+  /// Outgoing messages buffered while the node is unreachable,
+  /// the game loop is blocked once the buffer is full
+  pub max_buffered: usize,
+}
+
+impl Default for NodeReconnectPolicy {
+  fn default() -> Self {
+    Self {
+      max_elapsed: Duration::from_secs(60),
+      initial_interval: Duration::from_secs(1),
+      max_interval: Duration::from_secs(5),
+      max_buffered: 512,
+    }
+  }
+}
+
+impl NodeReconnectPolicy {
+  fn allows_reconnect(&self, status: Option<NodeGameStatus>) -> bool {
+    self.max_elapsed > Duration::from_secs(0)
+      && matches!(
+        status,
+        Some(NodeGameStatus::Loading) | Some(NodeGameStatus::Running)
+      )
+  }
+
+  fn backoff(&self) -> ExponentialBackoff {
+    ExponentialBackoff {
+      initial_interval: self.initial_interval,
+      max_interval: self.max_interval,
+      max_elapsed_time: None,
+      ..Default::default()
+    }
+  }
+}
+
 pub struct NodeStream {
   tx: NodeStreamSender,
   ct: CancellationToken,
@@ -54,11 +102,13 @@ impl NodeStream {
     token: NodeConnectToken,
     client: Addr<ControllerClient>,
     game_tx: Sender<W3GSPacket>,
+    checksum_tx: Option<Sender<TickChecksum>>,
     end_reason: Arc<Mutex<Option<GameEndReason>>>,
+    reconnect_policy: NodeReconnectPolicy,
   ) -> Result<Self> {
     let ct = CancellationToken::new();
     let shutdown_notify = Arc::new(Notify::new());
-    let (tx, rx) = channel(10);
+    let (tx, rx) = channel(SEND_CHANNEL_SIZE);
 
     let session = Session {
       game_id: game.game.game_id,
@@ -68,6 +118,7 @@ impl NodeStream {
       token,
       client,
       game_tx,
+      checksum_tx,
       rx,
       ct: ct.clone(),
       ack_q: W3GSAckQueue::new(),
@@ -75,7 +126,9 @@ impl NodeStream {
       ack: 0,
       time: 0,
       last_connected_at: None,
+      game_status: None,
       end_reason,
+      reconnect_policy,
     };
 
     tokio::spawn(
@@ -100,6 +153,10 @@ impl NodeStream {
   pub fn sender(&self) -> NodeStreamSender {
     self.tx.clone()
   }
+
+  pub fn queue_len(&self) -> usize {
+    self.tx.queue_len()
+  }
 }
 
 struct Session {
@@ -111,6 +168,8 @@ struct Session {
   token: NodeConnectToken,
   client: Addr<ControllerClient>,
   game_tx: Sender<W3GSPacket>,
+  /// Receives the tick checksums agreed on at the node, requested if set
+  checksum_tx: Option<Sender<TickChecksum>>,
   rx: Receiver<WorkerMsg>,
   ct: CancellationToken,
   ack_q: W3GSAckQueue,
@@ -118,17 +177,17 @@ struct Session {
   time: u32,
   ack: u32,
   last_connected_at: Option<Instant>,
+  game_status: Option<NodeGameStatus>,
   end_reason: Arc<Mutex<Option<GameEndReason>>>,
+  reconnect_policy: NodeReconnectPolicy,
 }
 
 impl Session {
   async fn run(mut self) {
-    let mut reconnect_backoff = ExponentialBackoff {
-      initial_interval: Duration::from_secs(1),
-      max_interval: Duration::from_secs(5),
-      max_elapsed_time: Some(Duration::from_secs(60)),
-      ..Default::default()
-    };
+    let policy = self.reconnect_policy.clone();
+    let mut reconnect_backoff = policy.backoff();
+    let mut pending = VecDeque::new();
+    let started_at = Instant::now();
     let ct = self.ct.clone();
     let mut leave_ack_received = false;
 
@@ -150,31 +209,59 @@ impl Session {
               .ok();
           }
 
-          tokio::select! {
-            _ = ct.cancelled() => {
+          let connect = connect(
+            self.addr,
+            self.token.clone(),
+            self.client.clone(),
+            self.game_id,
+            self.checksum_tx.is_some(),
+            self.pending_ack_frames(),
+          );
+          let res = buffer_worker_msgs(&mut self.rx, &mut pending, policy.max_buffered, async {
+            tokio::select! {
+              _ = ct.cancelled() => None,
+              res = connect => Some(res),
+            }
+          })
+          .await;
+
+          match res {
+            None => {
               tracing::info!("session cancelled");
               break 'main None;
-            },
-            res = self.connect() => {
-              match res {
-                Ok(pair) => {
-                  break pair;
+            }
+            Some(Ok(pair)) => {
+              break pair;
+            }
+            Some(Err(err)) => {
+              tracing::error!("connect node: {}", err);
+              use flo_net::proto::flo_node::ClientConnectRejectReason;
+              match err {
+                Error::NodeConnectionRejected(reason, _)
+                  if reason != ClientConnectRejectReason::Multi =>
+                {
+                  break 'main None;
                 }
-                Err(err) => {
-                  tracing::error!("connect node: {}", err);
-                  use flo_net::proto::flo_node::ClientConnectRejectReason;
-                  match err {
-                    Error::NodeConnectionRejected(reason, _) if reason != ClientConnectRejectReason::Multi => {
+                _ => {
+                  let retry = if self.last_connected_at.is_some() {
+                    reconnect_backoff.get_elapsed_time() < policy.max_elapsed
+                  } else {
+                    started_at.elapsed() < CONNECT_TIMEOUT
+                  };
+                  match reconnect_backoff.next_backoff().filter(|_| retry) {
+                    Some(delay) => {
+                      tracing::error!("connect node error: {:?}", err);
+                      buffer_worker_msgs(
+                        &mut self.rx,
+                        &mut pending,
+                        policy.max_buffered,
+                        sleep(delay),
+                      )
+                      .await;
+                    }
+                    None => {
+                      tracing::error!("connect node: retry limit reached");
                       break 'main None;
-                    },
-                    _ => {
-                      if let Some(delay) = reconnect_backoff.next_backoff() {
-                        tracing::error!("connect node error: {:?}", err);
-                        sleep(delay).await;
-                      } else {
-                        tracing::error!("connect node: timeout");
-                        break 'main None;
-                      }
                     }
                   }
                 }
@@ -185,8 +272,24 @@ impl Session {
       };
 
       self.last_connected_at.replace(Instant::now());
+      self.game_status.replace(conn.game_status);
       tracing::info!("node connected");
 
+      if !pending.is_empty() {
+        tracing::info!("sending {} buffered messages", pending.len());
+        let mut frames = Vec::with_capacity(pending.len());
+        for msg in pending.drain(..) {
+          match self.encode_worker_msg(msg) {
+            Ok(frame) => frames.push(frame),
+            Err(err) => tracing::error!("encode worker msg: {}", err),
+          }
+        }
+        // W3GS frames stay in the ack queue and are resent if the connection drops again
+        if let Err(err) = stream.send_frames(frames).await {
+          tracing::error!("send buffered messages: {}", err);
+        }
+      }
+
       let res = conn.run(&mut stream, &mut self).await;
       match res {
         Ok(res) => match res {
@@ -200,8 +303,18 @@ impl Session {
             } else {
               tracing::error!("node disconnected unexpectedly");
             }
+            if !policy.allows_reconnect(self.game_status) {
+              tracing::info!("reconnect not allowed: {:?}", self.game_status);
+              break 'main Some(stream);
+            }
             if let Some(delay) = reconnect_backoff.next_backoff() {
-              sleep(delay).await;
+              buffer_worker_msgs(
+                &mut self.rx,
+                &mut pending,
+                policy.max_buffered,
+                sleep(delay),
+              )
+              .await;
             }
           }
           ConnectionRunResult::NodeLeft => {
@@ -226,7 +339,11 @@ impl Session {
 
         let mut flush_frames = vec![];
         self.rx.close();
+        let mut flush_msgs: Vec<_> = pending.drain(..).collect();
         while let Some(msg) = self.rx.recv().await {
+          flush_msgs.push(msg);
+        }
+        for msg in flush_msgs {
           match self.encode_worker_msg(msg) {
             Ok(frame) => {
               tracing::info!("flush frame: {:?}", frame);
@@ -295,73 +412,14 @@ impl Session {
     self.notify_disconnected().await;
   }
 
-  async fn connect(&self) -> Result<(FloStream, Connection)> {
-    let mut stream = FloStream::connect_no_delay(self.addr).await?;
-
-    stream
-      .send(proto::PacketClientConnect {
-        version: Some(crate::version::FLO_VERSION.into()),
-        token: self.token.to_vec(),
-        ..Default::default()
-      })
-      .await?;
-
-    let frame = stream.recv_frame().await?;
-
-    let (player_id, status_snapshot): (i32, NodeGameStatusSnapshot) = flo_net::try_flo_packet! {
-      frame => {
-        p: proto::PacketClientConnectAccept => {
-          let game_id = p.game_id;
-          let player_id = p.player_id;
-          tracing::debug!(
-            game_id,
-            player_id,
-            "node connected: version = {:?}, game_status = {:?}",
-            p.version,
-            p.game_status,
-          );
-          let status = NodeGameStatusSnapshot::unpack(p)?;
-          (player_id, status)
-        }
-        p: proto::PacketClientConnectReject => {
-          return Err(Error::NodeConnectionRejected(p.reason(), p.message))
-        }
-      }
-    };
-
-    if !self.ack_q.pending_ack_queue().is_empty() {
-      let frames = self
-        .ack_q
-        .pending_ack_queue()
-        .iter()
-        .cloned()
-        .map(|(meta, packet)| Frame::from_w3gs(meta, packet));
-      stream.send_frames(frames).await?;
-    }
-
-    let game_id = status_snapshot.game_id;
-
-    if self
-      .client
-      .notify(LanEvent::NodeStreamEvent {
-        game_id: self.game_id,
-        inner: NodeStreamEvent::GameStatusSnapshot(status_snapshot),
-      })
-      .await
-      .is_err()
-    {
-      return Err(Error::TaskCancelled(anyhow::format_err!(
-        "controller connection gone"
-      )));
-    }
-
-    Ok((
-      stream,
-      Connection {
-        game_id,
-        _player_id: player_id,
-      },
-    ))
+  fn pending_ack_frames(&self) -> Vec<Frame> {
+    self
+      .ack_q
+      .pending_ack_queue()
+      .iter()
+      .cloned()
+      .map(|(meta, packet)| Frame::from_w3gs(meta, packet))
+      .collect()
   }
 
   async fn retry_shutdown(&self) -> Result<()> {
@@ -380,6 +438,7 @@ impl Session {
         token: self.token.to_vec(),
         retry_shutdown: true,
         leave_reason,
+        ..Default::default()
       })
       .await?;
 
@@ -485,9 +544,106 @@ impl Session {
   }
 }
 
+/// Connects to the node and resends `pending_ack_frames` on the new connection
+async fn connect(
+  addr: SocketAddr,
+  token: NodeConnectToken,
+  client: Addr<ControllerClient>,
+  session_game_id: i32,
+  tick_checksums: bool,
+  pending_ack_frames: Vec<Frame>,
+) -> Result<(FloStream, Connection)> {
+  let mut stream = FloStream::connect_no_delay(addr).await?;
+
+  stream
+    .send(proto::PacketClientConnect {
+      version: Some(crate::version::FLO_VERSION.into()),
+      token: token.to_vec(),
+      tick_checksums,
+      ..Default::default()
+    })
+    .await?;
+
+  let frame = stream.recv_frame().await?;
+
+  let (player_id, status_snapshot): (i32, NodeGameStatusSnapshot) = flo_net::try_flo_packet! {
+    frame => {
+      p: proto::PacketClientConnectAccept => {
+        let game_id = p.game_id;
+        let player_id = p.player_id;
+        tracing::debug!(
+          game_id,
+          player_id,
+          "node connected: version = {:?}, game_status = {:?}",
+          p.version,
+          p.game_status,
+        );
+        let status = NodeGameStatusSnapshot::unpack(p)?;
+        (player_id, status)
+      }
+      p: proto::PacketClientConnectReject => {
+        return Err(Error::NodeConnectionRejected(p.reason(), p.message))
+      }
+    }
+  };
+
+  if !pending_ack_frames.is_empty() {
+    stream.send_frames(pending_ack_frames).await?;
+  }
+
+  let game_id = status_snapshot.game_id;
+  let game_status = status_snapshot.game_status;
+
+  if client
+    .notify(LanEvent::NodeStreamEvent {
+      game_id: session_game_id,
+      inner: NodeStreamEvent::GameStatusSnapshot(status_snapshot),
+    })
+    .await
+    .is_err()
+  {
+    return Err(Error::TaskCancelled(anyhow::format_err!(
+      "controller connection gone"
+    )));
+  }
+
+  Ok((
+    stream,
+    Connection {
+      game_id,
+      _player_id: player_id,
+      game_status,
+    },
+  ))
+}
+
+/// Drives `fut` to completion, moving worker messages into `pending` meanwhile
+/// so the game loop isn't blocked while the node is unreachable
+async fn buffer_worker_msgs<F: Future>(
+  rx: &mut Receiver<WorkerMsg>,
+  pending: &mut VecDeque<WorkerMsg>,
+  max_buffered: usize,
+  fut: F,
+) -> F::Output {
+  tokio::pin!(fut);
+  let mut closed = false;
+  loop {
+    tokio::select! {
+      res = &mut fut => return res,
+      next = rx.recv(), if !closed && pending.len() < max_buffered => {
+        match next {
+          Some(msg) => pending.push_back(msg),
+          None => closed = true,
+        }
+      }
+    }
+  }
+}
+
 struct Connection {
   game_id: i32,
   _player_id: i32,
+  game_status: NodeGameStatus,
 }
 
 impl Connection {
@@ -634,13 +790,25 @@ impl Connection {
             }).await
           );
         }
+        p: proto::PacketClientTickChecksum => {
+          if let Some(tx) = session.checksum_tx.as_ref() {
+            // the desync monitor skips ticks it missed
+            tx.try_send(TickChecksum {
+              tick: p.tick,
+              checksum: p.checksum,
+            })
+            .ok();
+          }
+        }
         p: flo_net::proto::flo_node::PacketNodeGameStatusUpdate => {
           tracing::debug!(game_id = p.game_id, "update game status: {:?}", p);
+          let update = GameStatusUpdate::from(p);
+          session.game_status.replace(update.status);
           flo_log::result_ok!(
             "send NodeStreamEvent::GameStatusUpdate",
             client.notify(LanEvent::NodeStreamEvent {
               game_id,
-              inner: NodeStreamEvent::GameStatusUpdate(p.into())
+              inner: NodeStreamEvent::GameStatusUpdate(update)
             }).await
           );
         }
@@ -663,6 +831,11 @@ impl NodeStreamSender {
     Ok(())
   }
 
+  /// Number of messages waiting for the node stream worker
+  pub fn queue_len(&self) -> usize {
+    SEND_CHANNEL_SIZE.saturating_sub(self.tx.capacity())
+  }
+
   #[inline]
   pub async fn send_w3gs(&mut self, pkt: W3GSPacket) -> Result<()> {
     let type_id = pkt.type_id();
@@ -711,6 +884,13 @@ pub enum NodeStreamEvent {
   Disconnected,
 }
 
+/// Game state checksum of a tick all players agreed on at the node
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickChecksum {
+  pub tick: u32,
+  pub checksum: u32,
+}
+
 #[derive(Debug, S2ProtoUnpack, serde::Serialize, Clone)]
 #[s2_grpc(message_type(
   flo_net::proto::flo_connect::PacketGameSlotClientStatusUpdate,
@@ -722,3 +902,131 @@ pub struct SlotClientStatusUpdate {
   #[s2_grpc(proto_enum)]
   pub status: SlotClientStatus,
 }
+
+#[tokio::test]
+async fn test_session_buffers_msgs_while_reconnecting() {
+  use flo_net::listener::FloListener;
+  use flo_state::mock::Mock;
+  use flo_w3gs::protocol::ping::PingFromHost;
+  use futures::StreamExt;
+  use tokio::sync::oneshot;
+
+  const N: u32 = SEND_CHANNEL_SIZE as u32 * 3;
+
+  async fn handle_lan_event(_: LanEvent) {}
+
+  let client = Mock::<ControllerClient>::builder()
+    .handle(handle_lan_event)
+    .build();
+
+  let mut listener = FloListener::bind_v4(0).await.unwrap();
+  let addr: SocketAddr = ([127, 0, 0, 1], listener.port()).into();
+  let (all_sent_tx, all_sent_rx) = oneshot::channel::<()>();
+
+  // stub node: accepts and closes the first connection, then accepts the second one
+  // only after the client has queued all packets
+  let node = tokio::spawn(async move {
+    async fn accept(stream: &mut FloStream) {
+      stream.recv::<proto::PacketClientConnect>().await.unwrap();
+      stream
+        .send(proto::PacketClientConnectAccept {
+          game_id: 1,
+          player_id: 1,
+          game_status: proto::NodeGameStatus::Running.into(),
+          ..Default::default()
+        })
+        .await
+        .unwrap();
+    }
+
+    let mut incoming = listener.incoming();
+    let mut stream = incoming.next().await.unwrap().unwrap();
+    accept(&mut stream).await;
+    drop(stream);
+
+    let mut stream = incoming.next().await.unwrap().unwrap();
+    all_sent_rx.await.unwrap();
+    accept(&mut stream).await;
+
+    let mut payloads = vec![];
+    while payloads.len() < N as usize {
+      let frame = stream.recv_frame().await.unwrap();
+      if frame.type_id == PacketTypeId::W3GS {
+        let (_, pkt) = frame.try_into_w3gs().unwrap();
+        payloads.push(pkt.payload);
+      }
+    }
+    payloads
+  });
+
+  let (game_tx, _game_rx) = channel(1);
+  let (tx, rx) = channel(SEND_CHANNEL_SIZE);
+  let ct = CancellationToken::new();
+  let session = Session {
+    game_id: 1,
+    player_id: 1,
+    slot_player_id: 1,
+    addr,
+    token: NodeConnectToken([0; 16]),
+    client: client.addr(),
+    game_tx,
+    checksum_tx: None,
+    rx,
+    ct: ct.clone(),
+    ack_q: W3GSAckQueue::new(),
+    tick: 0,
+    ack: 0,
+    time: 0,
+    last_connected_at: None,
+    game_status: None,
+    end_reason: Arc::new(Mutex::new(None)),
+    // the default policy reconnects
+    reconnect_policy: NodeReconnectPolicy {
+      initial_interval: Duration::from_millis(10),
+      max_interval: Duration::from_millis(10),
+      max_buffered: N as usize,
+      ..Default::default()
+    },
+  };
+  let worker = tokio::spawn(session.run());
+
+  // the node is unreachable, sending must not block
+  let mut sender = NodeStreamSender { tx };
+  let mut expected = vec![];
+  for i in 0..N {
+    let pkt = W3GSPacket::simple(PingFromHost::with_payload(i)).unwrap();
+    expected.push(pkt.payload.clone());
+    tokio::time::timeout(Duration::from_secs(5), sender.send_w3gs(pkt))
+      .await
+      .unwrap()
+      .unwrap();
+  }
+  all_sent_tx.send(()).unwrap();
+
+  let payloads = tokio::time::timeout(Duration::from_secs(5), node)
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(payloads, expected);
+
+  ct.cancel();
+  worker.abort();
+}
+
+#[test]
+fn test_reconnect_policy_allows_reconnect() {
+  use NodeGameStatus::*;
+  let policy = NodeReconnectPolicy::default();
+  assert_eq!(policy.max_elapsed, Duration::from_secs(60));
+  assert!(policy.allows_reconnect(Some(Loading)));
+  assert!(policy.allows_reconnect(Some(Running)));
+  for status in [None, Some(Created), Some(Waiting), Some(Ended)] {
+    assert!(!policy.allows_reconnect(status), "{:?}", status);
+  }
+
+  let policy = NodeReconnectPolicy {
+    max_elapsed: Duration::from_secs(0),
+    ..Default::default()
+  };
+  assert!(!policy.allows_reconnect(Some(Running)));
+}
@@ -1,6 +1,6 @@
 use super::send_queue::SendQueue;
 use crate::error::{Error, Result};
-use crate::lan::game::slot::{LanSlotInfo, SelfPlayer};
+use crate::lan::game::slot::{LanSlotInfo, ObserverPlacement, SelfPlayer, SlotInfoOptions};
 use crate::platform::{GetClientPlatformInfo, OpenMap, Platform};
 use flo_lan::MdnsPublisher;
 use flo_observer::record::GameRecordData;
@@ -45,6 +45,7 @@ pub struct ObserverGameHost<S> {
   source: S,
   shared: ObserverHostShared,
   game_version: String,
+  observer_placement: ObserverPlacement,
 }
 
 impl<S> ObserverGameHost<S>
@@ -56,6 +57,7 @@ where
     delay_secs: Option<i64>,
     source: S,
     platform: Addr<Platform>,
+    observer_placement: ObserverPlacement,
   ) -> Result<Self> {
     let client_info = platform
       .send(GetClientPlatformInfo::default())
@@ -107,6 +109,7 @@ where
       source,
       shared: ObserverHostShared::new(game_id, delay_secs),
       game_version: client_info.version,
+      observer_placement,
     })
   }
 
@@ -124,12 +127,22 @@ where
       game_info
     };
 
-    let _p = MdnsPublisher::start(self.game_version.clone(), lan_game_info).await?;
+    let _p = MdnsPublisher::start(
+      self.game_version.clone(),
+      lan_game_info,
+      None,
+      flo_lan::DEFAULT_MAX_NAME_SUFFIX,
+    )
+    .await?;
     let slot_info = crate::lan::game::slot::build_player_slot_info(
       SelfPlayer::StreamObserver,
       self.info.random_seed,
       &self.info.slots,
-      self.info.map.twelve_p
+      self.info.map.twelve_p,
+      SlotInfoOptions {
+        ob_placement: self.observer_placement,
+        ..Default::default()
+      },
     )?;
 
     let mut stream: W3GSStream = loop {
@@ -827,7 +840,8 @@ async fn test_archive() -> crate::error::Result<()> {
 
   let platform = Platform::new(&Default::default()).await.unwrap().start();
 
-  let host = ObserverGameHost::new(game, None, s, platform.addr()).await?;
+  let host =
+    ObserverGameHost::new(game, None, s, platform.addr(), ObserverPlacement::default()).await?;
   host.play().await?;
 
   Ok(())
@@ -1,4 +1,5 @@
 use crate::error::{Error, Result};
+use crate::lan::game::slot::ObserverPlacement;
 use crate::observer::game::ObserverGameHost;
 pub use crate::observer::game::ObserverHostShared;
 use crate::observer::source::NetworkSource;
@@ -14,13 +15,15 @@ pub mod source;
 
 pub struct ObserverClient {
   platform: Addr<Platform>,
+  observer_placement: ObserverPlacement,
   playing: Option<Playing>,
 }
 
 impl ObserverClient {
-  pub fn new(platform: Addr<Platform>) -> Self {
+  pub fn new(platform: Addr<Platform>, observer_placement: ObserverPlacement) -> Self {
     Self {
       platform,
+      observer_placement,
       playing: None,
     }
   }
@@ -34,7 +37,8 @@ impl Service<StartConfig> for ObserverClient {
 
   async fn create(registry: &mut RegistryRef<StartConfig>) -> Result<Self, Self::Error> {
     let platform = registry.resolve::<Platform>().await?;
-    Ok(ObserverClient::new(platform))
+    let observer_placement = registry.data().lan_game_options.observer_placement;
+    Ok(ObserverClient::new(platform, observer_placement))
   }
 }
 
@@ -66,8 +70,14 @@ impl Handler<WatchGame> for ObserverClient {
       token,
     )
     .await?;
-    let host =
-      ObserverGameHost::new(game, source.delay_secs(), source, self.platform.clone()).await?;
+    let host = ObserverGameHost::new(
+      game,
+      source.delay_secs(),
+      source,
+      self.platform.clone(),
+      self.observer_placement,
+    )
+    .await?;
     let shared = host.shared();
     let ct = CancellationToken::new();
     self.playing.replace(Playing { ct: ct.clone() });
@@ -11,7 +11,13 @@ pub async fn handle_handshake(stream: &mut FloStream) -> Result<ConnectState> {
   let req: PacketClientConnect = stream.recv().await?;
   let client_version = req.connect_version.extract()?;
 
-  tracing::debug!("client version = {}", client_version);
+  let capabilities = Capabilities::SUPPORTED.negotiate(req.capabilities);
+
+  tracing::debug!(
+    "client version = {}, capabilities = {:?}",
+    client_version,
+    capabilities
+  );
 
   let token = validate_player_token(&req.token)?;
 
@@ -25,6 +31,7 @@ pub async fn handle_handshake(stream: &mut FloStream) -> Result<ConnectState> {
       minor: client_version.minor,
       patch: client_version.patch,
     },
+    capabilities,
   })
 }
 
@@ -33,4 +40,6 @@ pub struct ConnectState {
   pub player_id: i32,
   pub joined_game: Option<Game>,
   pub client_version: Version,
+  /// Features supported by both sides
+  pub capabilities: Capabilities,
 }
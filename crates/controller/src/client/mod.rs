@@ -1,4 +1,5 @@
 use flo_net::connect;
+use flo_net::connect::Capabilities;
 use flo_net::listener::FloListener;
 use flo_net::packet::FloPacket;
 use flo_net::packet::OptionalFieldExt;
@@ -6,8 +7,10 @@ use flo_net::proto;
 use flo_net::stream::FloStream;
 use s2_grpc_utils::{S2ProtoPack, S2ProtoUnpack};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::time::Duration;
 
+use crate::config::GetServerConfig;
 use crate::error::*;
 use crate::state::{ActorMapExt, ControllerStateRef};
 
@@ -50,7 +53,8 @@ pub async fn serve(state: ControllerStateRef) -> Result<()> {
 
     let state = state.clone();
     tokio::spawn(async move {
-      tracing::debug!("connected: {}", stream.peer_addr()?);
+      let peer_addr = stream.peer_addr()?;
+      tracing::debug!("connected: {}", peer_addr);
 
       let accepted = match handshake::handle_handshake(&mut stream).await {
         Ok(accepted) => accepted,
@@ -74,7 +78,32 @@ pub async fn serve(state: ControllerStateRef) -> Result<()> {
         return Ok(());
       }
 
-      if let Err(err) = handle_stream(state.clone(), player_id, stream).await {
+      let ip = peer_addr.ip();
+      let ip_ban = state
+        .db
+        .exec(move |conn| crate::player::db::find_ip_ban(conn, player_id, ip))
+        .await?;
+      if let Some((ip_ban_id, network)) = ip_ban {
+        tracing::info!(
+          player_id,
+          ip_ban_id,
+          "rejected: ip {} banned by {}",
+          ip,
+          network
+        );
+        stream
+          .send(proto::flo_connect::PacketClientConnectReject {
+            lobby_version: Some(From::from(crate::version::FLO_LOBBY_VERSION)),
+            reason: proto::flo_connect::ClientConnectRejectReason::Banned.into(),
+          })
+          .await?;
+        stream.shutdown().await?;
+        return Ok(());
+      }
+
+      if let Err(err) =
+        handle_stream(state.clone(), player_id, ip, accepted.capabilities, stream).await
+      {
         tracing::debug!("stream error: {}", err);
       }
 
@@ -93,11 +122,13 @@ pub async fn serve(state: ControllerStateRef) -> Result<()> {
 async fn handle_stream(
   state: ControllerStateRef,
   player_id: i32,
+  ip: IpAddr,
+  capabilities: Capabilities,
   mut stream: FloStream,
 ) -> Result<()> {
-  let (sender, mut receiver) = PlayerSender::new(player_id);
+  let (sender, mut receiver) = PlayerSender::new(player_id, capabilities);
 
-  send_initial_state(state.clone(), &mut stream, sender).await?;
+  send_initial_state(state.clone(), &mut stream, sender, ip, capabilities).await?;
 
   let mut ping = PingStream::interval(PING_INTERVAL, PING_TIMEOUT);
   ping.start();
@@ -188,6 +219,8 @@ async fn send_initial_state(
   state: ControllerStateRef,
   stream: &mut FloStream,
   sender: PlayerSender,
+  ip: IpAddr,
+  capabilities: Capabilities,
 ) -> Result<()> {
   let player_id = sender.player_id();
 
@@ -208,6 +241,7 @@ async fn send_initial_state(
     .notify(Connect {
       game_id: game_id.clone(),
       sender,
+      ip,
     })
     .await?;
 
@@ -226,11 +260,22 @@ async fn send_initial_state(
       }
     }),
     nodes: state.nodes.send(ListNode).await?.pack()?,
+    capabilities: Some(Capabilities::SUPPORTED.bits()),
   }
   .encode_as_frame()?;
 
   let mut frames = vec![frame_accept];
 
+  if capabilities.contains(Capabilities::SERVER_CONFIG) {
+    let frame_server_config = state
+      .config
+      .send(GetServerConfig)
+      .await?
+      .to_packet()
+      .encode_as_frame()?;
+    frames.push(frame_server_config);
+  }
+
   if let Some(game_id) = game_id {
     let (mut game, node_player_token) = state
       .db
@@ -345,8 +390,9 @@ async fn handle_player_ping_map_update_request(
 
   state
     .player_packet_sender
-    .broadcast(
+    .broadcast_if_supported(
       targets,
+      Capabilities::PING_MAP,
       proto::flo_connect::PacketPlayerPingMapUpdate {
         player_id,
         ping_map: packet.ping_map,
@@ -383,8 +429,9 @@ async fn handle_game_player_ping_map_snapshot_request(
 
   state
     .player_packet_sender
-    .send(
+    .send_if_supported(
       player_id,
+      Capabilities::PING_MAP,
       PacketGamePlayerPingMapSnapshot {
         game_id,
         node_ping_map,
@@ -408,6 +455,7 @@ async fn handle_game_select_node_request(
       SelectNode {
         node_id: packet.node_id.clone(),
         player_id,
+        preferred_node_ids: vec![],
       },
     )
     .await?;
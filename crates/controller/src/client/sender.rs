@@ -1,3 +1,4 @@
+use flo_net::connect::Capabilities;
 use flo_net::packet::*;
 use flo_net::proto::flo_connect::*;
 use std::time::Duration;
@@ -14,23 +15,40 @@ pub enum PlayerSenderMessage {
 #[derive(Debug, Clone)]
 pub struct PlayerSender {
   player_id: i32,
+  capabilities: Capabilities,
   sender: Sender<PlayerSenderMessage>,
 }
 
 impl PlayerSender {
-  pub fn new(player_id: i32) -> (Self, PlayerReceiver) {
+  pub fn new(player_id: i32, capabilities: Capabilities) -> (Self, PlayerReceiver) {
     let (sender, receiver) = channel(8);
-    (PlayerSender { player_id, sender }, receiver)
+    (
+      PlayerSender {
+        player_id,
+        capabilities,
+        sender,
+      },
+      receiver,
+    )
   }
 
   pub fn player_id(&self) -> i32 {
     self.player_id
   }
 
+  /// Features negotiated with the player's client
+  pub fn capabilities(&self) -> Capabilities {
+    self.capabilities
+  }
+
   pub async fn disconnect_multi(&mut self) {
     self.disconnect(ClientDisconnectReason::Multi).await;
   }
 
+  pub async fn disconnect_maintenance(&mut self) {
+    self.disconnect(ClientDisconnectReason::Maintenance).await;
+  }
+
   #[tracing::instrument]
   async fn disconnect(&mut self, reason: ClientDisconnectReason) {
     self
@@ -3,33 +3,144 @@ use bs_diesel_utils::{DbConn, ExecutorRef};
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use once_cell::sync::Lazy;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
 use tonic::{metadata::MetadataValue, service::Interceptor, Request, Status};
 
 use crate::error::*;
 
+use crate::player::state::conn::DisconnectIdle;
+use crate::player::state::PlayerRegistry;
 use crate::player::PlayerSource;
-use crate::schema::{api_client, player};
+use crate::rate_limit::{GrpcMethod, RateLimit, RateLimitClass, RateLimiter};
+use crate::schema::{api_client, player, server_maintenance};
 use crate::state::{Data, Reload};
-use flo_state::{async_trait, Actor, Context, Handler, Message, RegistryRef, Service};
+use flo_state::{async_trait, Actor, Addr, Context, Handler, Message, RegistryRef, Service};
 
 pub static JWT_SECRET_BASE64: Lazy<String> =
   Lazy::new(|| env::var("JWT_SECRET_BASE64").expect("env `JWT_SECRET_BASE64`"));
 
-#[derive(Debug, Queryable)]
+/// API clients allowed to call admin RPCs, e.g. `FLO_ADMIN_API_CLIENT_IDS=1,2`
+pub static ADMIN_API_CLIENT_IDS: Lazy<Vec<i32>> = Lazy::new(|| {
+  env::var("FLO_ADMIN_API_CLIENT_IDS")
+    .ok()
+    .map(|v| parse_id_list(&v))
+    .unwrap_or_default()
+});
+
+/// Node region used when an API client doesn't pick a node, matched against the node country,
+/// e.g. `FLO_API_CLIENT_DEFAULT_REGIONS=1:DE,2:US`
+pub static API_CLIENT_DEFAULT_REGIONS: Lazy<BTreeMap<i32, String>> = Lazy::new(|| {
+  env::var("FLO_API_CLIENT_DEFAULT_REGIONS")
+    .ok()
+    .map(|v| parse_region_map(&v))
+    .unwrap_or_default()
+});
+
+/// Reject joins from a player whose source account already occupies a slot in the game,
+/// enabled unless `FLO_UNIQUE_PLAYER_SOURCE_PER_GAME=0`
+pub static UNIQUE_PLAYER_SOURCE_PER_GAME: Lazy<bool> = Lazy::new(|| {
+  env::var("FLO_UNIQUE_PLAYER_SOURCE_PER_GAME")
+    .ok()
+    .map(|v| v != "0" && !v.eq_ignore_ascii_case("false"))
+    .unwrap_or(true)
+});
+
+/// Maps games can be created with, by sha1 hex, e.g. `FLO_MAP_ALLOWLIST=<sha1>,<sha1>`.
+/// Every map is allowed if unset.
+pub static MAP_ALLOWLIST: Lazy<Option<BTreeSet<String>>> = Lazy::new(|| {
+  env::var("FLO_MAP_ALLOWLIST")
+    .ok()
+    .map(|v| parse_sha1_list(&v))
+});
+
+fn parse_sha1_list(value: &str) -> BTreeSet<String> {
+  value
+    .split(',')
+    .map(|v| v.trim().to_ascii_lowercase())
+    .filter(|v| !v.is_empty())
+    .collect()
+}
+
+fn parse_region_map(value: &str) -> BTreeMap<i32, String> {
+  value
+    .split(',')
+    .filter_map(|item| {
+      let mut parts = item.splitn(2, ':');
+      let id = parts.next()?.trim().parse().ok()?;
+      let region = parts.next()?.trim();
+      if region.is_empty() {
+        None
+      } else {
+        Some((id, region.to_string()))
+      }
+    })
+    .collect()
+}
+
+fn parse_id_list(value: &str) -> Vec<i32> {
+  value
+    .split(',')
+    .filter_map(|v| v.trim().parse().ok())
+    .collect()
+}
+
+#[derive(Debug)]
 pub struct ApiClient {
   id: i32,
   _name: String,
   secret_key: String,
   _created_at: DateTime<Utc>,
+  rate_limit_per_minute: Option<i32>,
   player_id: i32,
+  /// From `FLO_API_CLIENT_DEFAULT_REGIONS`, not stored in the database
+  default_region: Option<String>,
+}
+
+/// Operational flags pushed to connected clients
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+  pub maintenance: bool,
+  pub game_create_disabled: bool,
+  pub message: String,
+}
+
+impl ServerConfig {
+  fn from_env() -> Self {
+    ServerConfig {
+      maintenance: env::var("FLO_MAINTENANCE").ok().as_deref() == Some("1"),
+      game_create_disabled: env::var("FLO_GAME_CREATE_DISABLED").ok().as_deref() == Some("1"),
+      message: env::var("FLO_SERVER_MESSAGE").unwrap_or_default(),
+    }
+  }
+
+  pub fn to_packet(&self) -> flo_net::proto::flo_connect::PacketServerConfig {
+    flo_net::proto::flo_connect::PacketServerConfig {
+      maintenance: self.maintenance,
+      game_create_disabled: self.game_create_disabled,
+      message: self.message.clone(),
+    }
+  }
+
+  /// New games and joins are refused during maintenance
+  pub fn check_accepting_games(&self) -> Result<()> {
+    if self.maintenance {
+      return Err(Error::ServerMaintenance);
+    }
+    Ok(())
+  }
 }
 
 pub struct ConfigStorage {
   db: ExecutorRef,
   api_client_map: Arc<ArcSwap<BTreeMap<Vec<u8>, ApiClient>>>,
+  rate_limiter: Arc<RateLimiter>,
+  server_config: ServerConfig,
+  players: Addr<PlayerRegistry>,
+  drain: Option<JoinHandle<()>>,
 }
 
 impl Actor for ConfigStorage {}
@@ -41,12 +152,25 @@ impl Service<Data> for ConfigStorage {
   async fn create(registry: &mut RegistryRef<Data>) -> Result<Self, Self::Error> {
     let db = registry.data().db.clone();
     let map = ConfigStorage::load_map(&db).await?;
+    let maintenance = db.exec(get_maintenance).await?;
 
-    let storage = ConfigStorage {
+    let mut storage = ConfigStorage {
       db,
       api_client_map: Arc::new(ArcSwap::new(Arc::new(map))),
+      rate_limiter: Arc::new(RateLimiter::default()),
+      server_config: ServerConfig::from_env(),
+      players: registry.resolve().await?,
+      drain: None,
     };
 
+    if let Some(Maintenance { drain_at }) = maintenance {
+      tracing::info!(?drain_at, "resuming maintenance");
+      storage.server_config.maintenance = true;
+      if let Some(drain_at) = drain_at {
+        storage.schedule_drain(drain_at);
+      }
+    }
+
     Ok(storage)
   }
 }
@@ -60,6 +184,90 @@ impl Handler<Reload> for ConfigStorage {
   }
 }
 
+pub struct GetServerConfig;
+impl Message for GetServerConfig {
+  type Result = ServerConfig;
+}
+
+#[async_trait]
+impl Handler<GetServerConfig> for ConfigStorage {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    _: GetServerConfig,
+  ) -> <GetServerConfig as Message>::Result {
+    self.server_config.clone()
+  }
+}
+
+/// Turning maintenance on or off here is the same as `EnterMaintenance` without
+/// a drain or `ExitMaintenance`
+pub struct UpdateServerConfig(pub ServerConfig);
+impl Message for UpdateServerConfig {
+  type Result = Result<ServerConfig>;
+}
+
+#[async_trait]
+impl Handler<UpdateServerConfig> for ConfigStorage {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    UpdateServerConfig(config): UpdateServerConfig,
+  ) -> <UpdateServerConfig as Message>::Result {
+    if config.maintenance != self.server_config.maintenance {
+      if config.maintenance {
+        self.enter_maintenance(None).await?;
+      } else {
+        self.exit_maintenance().await?;
+      }
+    }
+    self.server_config.game_create_disabled = config.game_create_disabled;
+    self.server_config.message = config.message;
+    Ok(self.server_config.clone())
+  }
+}
+
+/// Refuses new games and joins, idle lobby connections are disconnected
+/// once `drain_timeout` elapses. Persisted across restarts.
+pub struct EnterMaintenance {
+  pub drain_timeout: Duration,
+}
+
+impl Message for EnterMaintenance {
+  type Result = Result<ServerConfig>;
+}
+
+#[async_trait]
+impl Handler<EnterMaintenance> for ConfigStorage {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    EnterMaintenance { drain_timeout }: EnterMaintenance,
+  ) -> <EnterMaintenance as Message>::Result {
+    let drain_at = Utc::now() + chrono::Duration::seconds(drain_timeout.as_secs() as i64);
+    self.enter_maintenance(Some(drain_at)).await?;
+    Ok(self.server_config.clone())
+  }
+}
+
+pub struct ExitMaintenance;
+
+impl Message for ExitMaintenance {
+  type Result = Result<ServerConfig>;
+}
+
+#[async_trait]
+impl Handler<ExitMaintenance> for ConfigStorage {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    _: ExitMaintenance,
+  ) -> <ExitMaintenance as Message>::Result {
+    self.exit_maintenance().await?;
+    Ok(self.server_config.clone())
+  }
+}
+
 pub struct GetInterceptor;
 impl Message for GetInterceptor {
   type Result = FloGrpcInterceptor;
@@ -74,6 +282,7 @@ impl Handler<GetInterceptor> for ConfigStorage {
   ) -> <GetInterceptor as Message>::Result {
     FloGrpcInterceptor {
       api_client_map: self.api_client_map.clone(),
+      rate_limiter: self.rate_limiter.clone(),
     }
   }
 }
@@ -81,18 +290,42 @@ impl Handler<GetInterceptor> for ConfigStorage {
 pub const REQUEST_META_SECRET: &str = "x-flo-secret";
 pub const REQUEST_META_API_CLIENT_ID: &str = "x-flo-api-client-id-bin";
 pub const REQUEST_META_API_PLAYER_ID: &str = "x-flo-api-player-id-bin";
+pub const REQUEST_META_API_CLIENT_REGION: &str = "x-flo-api-client-region";
 
 #[derive(Clone)]
 pub struct FloGrpcInterceptor {
   api_client_map: Arc<ArcSwap<BTreeMap<Vec<u8>, ApiClient>>>,
+  rate_limiter: Arc<RateLimiter>,
 }
 
 impl Interceptor for FloGrpcInterceptor {
   fn call(&mut self, mut req: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+    if req
+      .extensions()
+      .get::<GrpcMethod>()
+      .map(GrpcMethod::is_public)
+      .unwrap_or(false)
+    {
+      return Ok(req);
+    }
+
     let secret = req.metadata().get(REQUEST_META_SECRET);
     match secret {
       Some(secret) => match self.api_client_map.load().get(secret.as_bytes()) {
         Some(client) => {
+          let class = req
+            .extensions()
+            .get::<GrpcMethod>()
+            .map(GrpcMethod::class)
+            .unwrap_or(RateLimitClass::Write);
+          if let Some(limit) = RateLimit::for_class(client.rate_limit_per_minute, class) {
+            if !self
+              .rate_limiter
+              .check(client.id, class, limit, Instant::now())
+            {
+              return Err(Status::resource_exhausted("rate limit exceeded"));
+            }
+          }
           let meta = req.metadata_mut();
           meta.insert_bin(
             REQUEST_META_API_CLIENT_ID,
@@ -102,6 +335,15 @@ impl Interceptor for FloGrpcInterceptor {
             REQUEST_META_API_PLAYER_ID,
             MetadataValue::from_bytes(&client.player_id.to_le_bytes()),
           );
+          // the region is only set by the server
+          meta.remove(REQUEST_META_API_CLIENT_REGION);
+          if let Some(region) = client
+            .default_region
+            .as_ref()
+            .and_then(|v| MetadataValue::from_str(v).ok())
+          {
+            meta.insert(REQUEST_META_API_CLIENT_REGION, region);
+          }
           Ok(req)
         }
         None => Err(Status::unauthenticated("invalid secret")),
@@ -114,6 +356,47 @@ impl Interceptor for FloGrpcInterceptor {
 }
 
 impl ConfigStorage {
+  /// Persists the maintenance state, replaces any pending drain
+  async fn enter_maintenance(&mut self, drain_at: Option<DateTime<Utc>>) -> Result<()> {
+    self
+      .db
+      .exec(move |conn| set_maintenance(conn, drain_at))
+      .await?;
+    self.server_config.maintenance = true;
+    match drain_at {
+      Some(drain_at) => self.schedule_drain(drain_at),
+      None => self.cancel_drain(),
+    }
+    Ok(())
+  }
+
+  async fn exit_maintenance(&mut self) -> Result<()> {
+    self.db.exec(clear_maintenance).await?;
+    self.cancel_drain();
+    self.server_config.maintenance = false;
+    Ok(())
+  }
+
+  fn cancel_drain(&mut self) {
+    if let Some(drain) = self.drain.take() {
+      drain.abort();
+    }
+  }
+
+  /// Replaces any pending drain
+  fn schedule_drain(&mut self, drain_at: DateTime<Utc>) {
+    self.cancel_drain();
+    let delay = (drain_at - Utc::now()).to_std().unwrap_or_default();
+    let players = self.players.clone();
+    self.drain = Some(tokio::spawn(async move {
+      tokio::time::sleep(delay).await;
+      match players.send(DisconnectIdle).await {
+        Ok(count) => tracing::info!(count, "maintenance: idle players disconnected"),
+        Err(err) => tracing::error!("maintenance: disconnect idle players: {}", err),
+      }
+    }));
+  }
+
   async fn load_map(db: &ExecutorRef) -> Result<BTreeMap<Vec<u8>, ApiClient>> {
     let mut map = BTreeMap::new();
 
@@ -138,20 +421,29 @@ impl ConfigStorage {
             api_client::name,
             api_client::secret_key,
             api_client::created_at,
-            diesel::dsl::sql::<diesel::sql_types::Integer>("0"),
+            api_client::rate_limit_per_minute,
           ))
-          .load::<ApiClient>(conn)?;
+          .load::<(i32, String, String, DateTime<Utc>, Option<i32>)>(conn)?;
         Ok((api_player_map, items))
       })
       .await?;
 
-    for mut item in items {
-      item.player_id = if let Some(player_id) = api_player_map.get(&item.id).cloned() {
+    for (id, name, secret_key, created_at, rate_limit_per_minute) in items {
+      let player_id = if let Some(player_id) = api_player_map.get(&id).cloned() {
         player_id
       } else {
-        tracing::error!(id = item.id, "api player not found");
+        tracing::error!(id, "api player not found");
         continue;
       };
+      let item = ApiClient {
+        id,
+        _name: name,
+        secret_key,
+        _created_at: created_at,
+        rate_limit_per_minute,
+        player_id,
+        default_region: API_CLIENT_DEFAULT_REGIONS.get(&id).cloned(),
+      };
       map.insert(item.secret_key.as_bytes().to_vec(), item);
     }
 
@@ -159,6 +451,36 @@ impl ConfigStorage {
   }
 }
 
+/// The persisted maintenance state, the only source of `ServerConfig::maintenance`
+/// besides `FLO_MAINTENANCE` at startup
+#[derive(Debug, Queryable)]
+struct Maintenance {
+  drain_at: Option<DateTime<Utc>>,
+}
+
+fn get_maintenance(conn: &DbConn) -> Result<Option<Maintenance>> {
+  server_maintenance::table
+    .select((server_maintenance::drain_at,))
+    .first(conn)
+    .optional()
+    .map_err(Into::into)
+}
+
+fn set_maintenance(conn: &DbConn, drain_at: Option<DateTime<Utc>>) -> Result<()> {
+  diesel::insert_into(server_maintenance::table)
+    .values(server_maintenance::drain_at.eq(drain_at))
+    .on_conflict(server_maintenance::id)
+    .do_update()
+    .set(server_maintenance::drain_at.eq(drain_at))
+    .execute(conn)?;
+  Ok(())
+}
+
+pub(crate) fn clear_maintenance(conn: &DbConn) -> Result<()> {
+  diesel::delete(server_maintenance::table).execute(conn)?;
+  Ok(())
+}
+
 // Create API players if not exist
 //
 // every api client has a special player which
@@ -184,6 +506,10 @@ fn create_api_players(conn: &DbConn) -> Result<()> {
 pub trait ApiRequestExt {
   fn get_api_client_id(&self) -> i32;
   fn get_api_player_id(&self) -> i32;
+  fn is_admin_api_client(&self) -> bool {
+    ADMIN_API_CLIENT_IDS.contains(&self.get_api_client_id())
+  }
+  fn get_api_client_default_region(&self) -> Option<String>;
 }
 
 impl<T> ApiRequestExt for Request<T> {
@@ -206,4 +532,149 @@ impl<T> ApiRequestExt for Request<T> {
       .unwrap();
     i32::from_le_bytes([value[0], value[1], value[2], value[3]])
   }
+
+  fn get_api_client_default_region(&self) -> Option<String> {
+    self
+      .metadata()
+      .get(REQUEST_META_API_CLIENT_REGION)
+      .and_then(|v| v.to_str().ok())
+      .map(ToString::to_string)
+  }
+}
+
+#[test]
+fn test_parse_region_map() {
+  let map = parse_region_map("1:DE, 2 : us,3:,x:FR,4");
+  assert_eq!(map.len(), 2);
+  assert_eq!(map.get(&1).map(String::as_str), Some("DE"));
+  assert_eq!(map.get(&2).map(String::as_str), Some("us"));
+}
+
+#[test]
+fn test_parse_id_list() {
+  assert_eq!(parse_id_list("1, 2,x,,3"), vec![1, 2, 3]);
+  assert!(parse_id_list("").is_empty());
+}
+
+#[test]
+fn test_check_accepting_games() {
+  let config = ServerConfig::default();
+  assert!(config.check_accepting_games().is_ok());
+
+  let config = ServerConfig {
+    maintenance: true,
+    ..Default::default()
+  };
+  assert!(matches!(
+    config.check_accepting_games(),
+    Err(Error::ServerMaintenance)
+  ));
+}
+
+#[test]
+fn test_interceptor_public_methods() {
+  let mut interceptor = FloGrpcInterceptor {
+    api_client_map: Default::default(),
+    rate_limiter: Default::default(),
+  };
+  let call = |interceptor: &mut FloGrpcInterceptor, path: &str| {
+    let mut req = Request::new(());
+    req.extensions_mut().insert(GrpcMethod(format!(
+      "/flo_controller.FloController/{}",
+      path
+    )));
+    interceptor.call(req).map_err(|status| status.code())
+  };
+
+  assert!(call(&mut interceptor, "HealthCheck").is_ok());
+  assert_eq!(
+    call(&mut interceptor, "GetGame").err(),
+    Some(tonic::Code::Unauthenticated)
+  );
+  assert_eq!(
+    interceptor.call(Request::new(())).err().map(|s| s.code()),
+    Some(tonic::Code::Unauthenticated)
+  );
+}
+
+#[test]
+fn test_interceptor_health_check_quota() {
+  let client = ApiClient {
+    id: 1,
+    _name: "test".to_string(),
+    secret_key: "secret".to_string(),
+    _created_at: Utc::now(),
+    rate_limit_per_minute: Some(1),
+    player_id: 1,
+    default_region: None,
+  };
+  let mut api_client_map = BTreeMap::new();
+  api_client_map.insert(client.secret_key.as_bytes().to_vec(), client);
+  let mut interceptor = FloGrpcInterceptor {
+    api_client_map: Arc::new(ArcSwap::new(Arc::new(api_client_map))),
+    rate_limiter: Default::default(),
+  };
+  let call = |interceptor: &mut FloGrpcInterceptor, path: &str| {
+    let mut req = Request::new(());
+    req.extensions_mut().insert(GrpcMethod(format!(
+      "/flo_controller.FloController/{}",
+      path
+    )));
+    req
+      .metadata_mut()
+      .insert(REQUEST_META_SECRET, MetadataValue::from_static("secret"));
+    interceptor.call(req).map_err(|status| status.code())
+  };
+
+  // health checks with a secret don't use the client's quota
+  for _ in 0..3 {
+    assert!(call(&mut interceptor, "HealthCheck").is_ok());
+  }
+  assert!(call(&mut interceptor, "CreateGame").is_ok());
+  assert_eq!(
+    call(&mut interceptor, "CreateGame").err(),
+    Some(tonic::Code::ResourceExhausted)
+  );
+  assert!(call(&mut interceptor, "HealthCheck").is_ok());
+}
+
+#[test]
+fn test_interceptor_replaces_region() {
+  let client = |id: i32, default_region: Option<&str>| ApiClient {
+    id,
+    _name: "test".to_string(),
+    secret_key: format!("secret{}", id),
+    _created_at: Utc::now(),
+    rate_limit_per_minute: None,
+    player_id: id,
+    default_region: default_region.map(ToString::to_string),
+  };
+  let api_client_map: BTreeMap<_, _> = vec![client(1, None), client(2, Some("DE"))]
+    .into_iter()
+    .map(|client| (client.secret_key.as_bytes().to_vec(), client))
+    .collect();
+  let mut interceptor = FloGrpcInterceptor {
+    api_client_map: Arc::new(ArcSwap::new(Arc::new(api_client_map))),
+    rate_limiter: Default::default(),
+  };
+  let call = |interceptor: &mut FloGrpcInterceptor, secret: &'static str| {
+    let mut req = Request::new(());
+    req.extensions_mut().insert(GrpcMethod(
+      "/flo_controller.FloController/GetGame".to_string(),
+    ));
+    let meta = req.metadata_mut();
+    meta.insert(REQUEST_META_SECRET, MetadataValue::from_static(secret));
+    meta.insert(
+      REQUEST_META_API_CLIENT_REGION,
+      MetadataValue::from_static("US"),
+    );
+    interceptor
+      .call(req)
+      .unwrap()
+      .get_api_client_default_region()
+  };
+
+  // a region sent by the client is never used
+  assert_eq!(call(&mut interceptor, "secret1"), None);
+  assert_eq!(call(&mut interceptor, "secret2"), Some("DE".to_string()));
 }
@@ -1 +1,85 @@
 pub use bs_diesel_utils::{lock::transaction_with_advisory_lock, DbConn, Executor, ExecutorRef};
+
+/// Database helpers for tests that need a real database.
+///
+/// Tests connect to `DATABASE_URL` (`.env` is loaded) and are skipped if it is not set.
+#[cfg(test)]
+pub mod test {
+  use super::DbConn;
+  use crate::error::*;
+  use crate::player::db::UpsertPlayer;
+  use crate::player::{Player, PlayerSource};
+  use crate::schema::api_client;
+  use diesel::prelude::*;
+  use diesel::r2d2::{ConnectionManager, Pool};
+
+  pub type TestPool = Pool<ConnectionManager<PgConnection>>;
+
+  fn pool(max_size: u32) -> Option<TestPool> {
+    dotenv::dotenv().ok();
+    let url = match std::env::var("DATABASE_URL") {
+      Ok(url) => url,
+      Err(_) => {
+        eprintln!("DATABASE_URL not set, skipped");
+        return None;
+      }
+    };
+    let pool = Pool::builder()
+      .max_size(max_size)
+      .build(ConnectionManager::<PgConnection>::new(url))
+      .unwrap();
+    crate::migration::run(&pool.get().unwrap()).unwrap();
+    Some(pool)
+  }
+
+  /// Runs `f` in a transaction that is always rolled back.
+  /// Returns `None` without running `f` if `DATABASE_URL` is not set.
+  pub fn with_transaction<T, F>(f: F) -> Option<T>
+  where
+    F: FnOnce(&DbConn) -> Result<T>,
+  {
+    let pool = pool(1)?;
+    let conn = pool.get().unwrap();
+    Some(conn.test_transaction(|| f(&conn)))
+  }
+
+  /// Runs `f` with a pool of `max_size` connections, for tests of concurrent transactions.
+  /// Changes are committed, `f` has to remove the rows it inserted.
+  /// Returns `None` without running `f` if `DATABASE_URL` is not set.
+  pub fn with_pool<T, F>(max_size: u32, f: F) -> Option<T>
+  where
+    F: FnOnce(&TestPool) -> Result<T>,
+  {
+    let pool = pool(max_size)?;
+    Some(f(&pool).unwrap())
+  }
+
+  /// Inserts an api client named `name`, returns its id
+  pub fn create_api_client(conn: &DbConn, name: &str) -> Result<i32> {
+    diesel::insert_into(api_client::table)
+      .values((api_client::name.eq(name), api_client::secret_key.eq(name)))
+      .returning(api_client::id)
+      .get_result(conn)
+      .map_err(Into::into)
+  }
+
+  /// Inserts an api client and a player of it named `name`
+  pub fn create_player(conn: &DbConn, name: &str) -> Result<Player> {
+    let api_client_id = create_api_client(conn, name)?;
+    create_api_player(conn, api_client_id, name)
+  }
+
+  pub fn create_api_player(conn: &DbConn, api_client_id: i32, name: &str) -> Result<Player> {
+    crate::player::db::upsert(
+      conn,
+      &UpsertPlayer {
+        api_client_id,
+        name: name.to_string(),
+        source: PlayerSource::Test,
+        source_id: name.to_string(),
+        source_state: None,
+        realm: None,
+      },
+    )
+  }
+}
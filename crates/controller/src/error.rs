@@ -11,6 +11,8 @@ pub enum Error {
   NodeNotFound,
   #[error("Node not ready")]
   NodeNotReady,
+  #[error("No node has ping data for the players in this game")]
+  NodePingUnavailable,
   #[error("Node rejected connection: {addr:?}: {reason:?}")]
   NodeConnectionRejected {
     addr: std::net::SocketAddrV4,
@@ -36,6 +38,8 @@ pub enum Error {
   PlayerNotHost,
   #[error("Player not found")]
   PlayerNotFound,
+  #[error("Search query must be at least {0} characters")]
+  PlayerSearchQueryTooShort(usize),
   #[error("Game not found")]
   GameNotFound,
   #[error("Only games with `Preparing` or `Created` status are cancellable")]
@@ -44,6 +48,8 @@ pub enum Error {
   GameDataInvalid,
   #[error("The game you are trying to join is full")]
   GameFull,
+  #[error("The game you are trying to join has no free observer slot")]
+  GameObserverSlotFull,
   #[error("Create game request already exists")]
   GameCreating,
   #[error("Create game request rejected: {0:?}")]
@@ -52,18 +58,34 @@ pub enum Error {
   GameLeaveRejected(flo_net::proto::flo_node::UpdateSlotClientStatusRejectReason),
   #[error("Game node not selected")]
   GameNodeNotSelected,
+  #[error("Node is hosting the maximum number of games")]
+  NodeAtCapacity,
+  #[error("No node can host another game")]
+  NodeUnavailable,
   #[error("Slot update denied")]
   GameSlotUpdateDenied,
   #[error("Game already started")]
   GameStarted,
   #[error("Game not in starting state")]
   GameNotStarting,
+  #[error("Only games with `Created` or `Running` status can be rejoined")]
+  GameNotRejoinable,
+  #[error("Only games with `Ended` or `Terminated` status accept results")]
+  GameNotEnded,
+  #[error("Game result already reported")]
+  GameResultAlreadyReported,
+  #[error("Invalid game result: {0}")]
+  GameResultInvalid(String),
+  #[error("Invalid force overrides: {0}")]
+  MapForcesInvalid(crate::map::MapValidationError),
   #[error("This map has no player slot")]
   MapHasNoPlayer,
   #[error("Player not in game")]
   PlayerNotInGame,
   #[error("Player already in game")]
   PlayerAlreadyInGame,
+  #[error("Player source account already in game")]
+  PlayerSourceAlreadyInGame,
   #[error("Player slot not found")]
   PlayerSlotNotFound,
   #[error("Send to player channel timeout")]
@@ -72,6 +94,8 @@ pub enum Error {
   PlayerChannelClosed,
   #[error("Player source id is invalid")]
   PlayerSourceIdInvalid,
+  #[error("Player name is invalid: {0}")]
+  PlayerNameInvalid(&'static str),
   #[error("Invalid player source state")]
   InvalidPlayerSourceState,
   #[error("Actor not found")]
@@ -84,8 +108,30 @@ pub enum Error {
   PlayerColorConflict,
   #[error("Invalid player team value")]
   PlayerTeamInvalid,
+  #[error("Too many game tags or tag too long")]
+  GameTagsInvalid,
+  #[error("Invalid game flags combination")]
+  GameFlagsInvalid,
+  #[error("Idempotency key is empty or too long")]
+  IdempotencyKeyInvalid,
+  #[error("Server is in maintenance")]
+  ServerMaintenance,
+  #[error("Map is not allowed on this server")]
+  MapNotAllowed,
+  #[error("Invalid map sha1 hex string: {0}")]
+  MapSha1HexInvalid(String),
+  #[error("Map file exceeds {0} bytes")]
+  MapFileTooLarge(usize),
+  #[error("Invalid map file: {0}")]
+  MapFileInvalid(String),
   #[error("Player not belongs to the current API client")]
   PlayerOwnerCheckFailed,
+  #[error("Invalid IP network: {0}")]
+  IpNetworkInvalid(String),
+  #[error("IP ban not belongs to the current API client")]
+  IpBanOwnerCheckFailed,
+  #[error("Game not belongs to the current API client")]
+  GameOwnerCheckFailed,
   #[error("Operation timeout: {0}")]
   Timeout(anyhow::Error),
   #[error("net: {0}")]
@@ -123,10 +169,33 @@ impl From<Error> for Status {
     match e {
       e @ Error::GameNotFound
       | e @ Error::PlayerNotFound
+      | e @ Error::PlayerSearchQueryTooShort(_)
+      | e @ Error::GameResultInvalid(_)
+      | e @ Error::MapForcesInvalid(_)
       | e @ Error::MapHasNoPlayer
+      | e @ Error::MapNotAllowed
       | e @ Error::GameFull
+      | e @ Error::GameObserverSlotFull
       | e @ Error::GameNotCancellable
+      | e @ Error::GameTagsInvalid
+      | e @ Error::GameFlagsInvalid
+      | e @ Error::IdempotencyKeyInvalid
+      | e @ Error::NodePingUnavailable
+      | e @ Error::MapSha1HexInvalid(_)
+      | e @ Error::MapFileTooLarge(_)
+      | e @ Error::MapFileInvalid(_)
+      | e @ Error::PlayerNameInvalid(_)
+      | e @ Error::IpNetworkInvalid(_)
       | e @ Error::JoinTokenExpired => Status::invalid_argument(e.to_string()),
+      e @ Error::PlayerSourceAlreadyInGame
+      | e @ Error::GameNotRejoinable
+      | e @ Error::GameNotEnded
+      | e @ Error::GameResultAlreadyReported => Status::failed_precondition(e.to_string()),
+      e @ Error::PlayerNotHost => Status::permission_denied(e.to_string()),
+      e @ Error::NodeAtCapacity | e @ Error::NodeUnavailable => {
+        Status::resource_exhausted(e.to_string())
+      }
+      e @ Error::ServerMaintenance => Status::unavailable(e.to_string()),
       e @ Error::PlayerTokenExpired => Status::unauthenticated(e.to_string()),
       Error::JsonWebToken(e) => Status::unauthenticated(e.to_string()),
       e => Status::internal(e.to_string()),
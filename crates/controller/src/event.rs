@@ -0,0 +1,117 @@
+use crate::player::PlayerBanType;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+const CONTROLLER_EVENT_CAPACITY: usize = 1024;
+
+/// Domain events streamed to `subscribe_events` subscribers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum ControllerEvent {
+  GameCreated {
+    game_id: i32,
+    created_by: i32,
+  },
+  /// The game actor was removed: the game ended, was cancelled or expired
+  GameEnded {
+    game_id: i32,
+  },
+  PlayerBanCreated {
+    player_id: i32,
+    ban_type: PlayerBanType,
+  },
+  PlayerBanRemoved {
+    ban_id: i32,
+  },
+  NodesReloaded,
+}
+
+impl ControllerEvent {
+  pub fn name(&self) -> &'static str {
+    match *self {
+      ControllerEvent::GameCreated { .. } => "GameCreated",
+      ControllerEvent::GameEnded { .. } => "GameEnded",
+      ControllerEvent::PlayerBanCreated { .. } => "PlayerBanCreated",
+      ControllerEvent::PlayerBanRemoved { .. } => "PlayerBanRemoved",
+      ControllerEvent::NodesReloaded => "NodesReloaded",
+    }
+  }
+}
+
+/// Fans out controller events to external sinks.
+/// Publishing never blocks, subscribers that fall more than
+/// `CONTROLLER_EVENT_CAPACITY` events behind skip the oldest ones.
+#[derive(Debug, Clone)]
+pub struct ControllerEventBus {
+  tx: broadcast::Sender<ControllerEvent>,
+}
+
+impl Default for ControllerEventBus {
+  fn default() -> Self {
+    Self::with_capacity(CONTROLLER_EVENT_CAPACITY)
+  }
+}
+
+impl ControllerEventBus {
+  pub fn with_capacity(capacity: usize) -> Self {
+    let (tx, _) = broadcast::channel(capacity);
+    Self { tx }
+  }
+
+  pub fn publish(&self, event: ControllerEvent) {
+    if self.tx.receiver_count() > 0 {
+      self.tx.send(event).ok();
+    }
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<ControllerEvent> {
+    self.tx.subscribe()
+  }
+}
+
+#[test]
+fn test_controller_event_bus() {
+  use broadcast::error::TryRecvError;
+
+  let bus = ControllerEventBus::with_capacity(2);
+  bus.publish(ControllerEvent::NodesReloaded);
+
+  let mut rx = bus.subscribe();
+  assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+
+  // creating a game
+  bus.publish(ControllerEvent::GameCreated {
+    game_id: 1,
+    created_by: 2,
+  });
+  let event = rx.try_recv().unwrap();
+  assert_eq!(
+    event,
+    ControllerEvent::GameCreated {
+      game_id: 1,
+      created_by: 2
+    }
+  );
+  assert_eq!(event.name(), "GameCreated");
+  assert_eq!(
+    serde_json::to_value(&event).unwrap(),
+    serde_json::json!({ "type": "GameCreated", "game_id": 1, "created_by": 2 })
+  );
+
+  // slow subscriber
+  for game_id in 0..3 {
+    bus.publish(ControllerEvent::GameEnded { game_id });
+  }
+  assert!(matches!(rx.try_recv(), Err(TryRecvError::Lagged(1))));
+  assert_eq!(
+    rx.try_recv().unwrap(),
+    ControllerEvent::GameEnded { game_id: 1 }
+  );
+  assert_eq!(
+    rx.try_recv().unwrap(),
+    ControllerEvent::GameEnded { game_id: 2 }
+  );
+
+  drop(bus);
+  assert!(matches!(rx.try_recv(), Err(TryRecvError::Closed)));
+}
@@ -4,21 +4,23 @@ use diesel::prelude::*;
 use s2_grpc_utils::{S2ProtoEnum, S2ProtoPack, S2ProtoUnpack};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
 
 use crate::db::DbConn;
 use crate::error::*;
 use crate::game::slots::{UsedSlot, UsedSlotInfo};
 use crate::game::state::GameStatusUpdate;
 use crate::game::{
-  Computer, CreateGameSlot, Game, GameEntry, GameStatus, Race, Slot, SlotClientStatus,
-  SlotSettings, SlotStatus, Slots,
+  Computer, CreateGameSlot, Game, GameEntry, GameOutcome, GameStatus, PlayerResult, Race, Slot,
+  SlotClientStatus, SlotSettings, SlotStatus, Slots,
 };
-use crate::map::Map;
+use crate::map::{Map, MapForce};
 use crate::node::{NodeRef, NodeRefColumns, PlayerToken};
 use crate::player::{PlayerRef, PlayerRefColumns};
-use crate::schema::{game, game_used_slot, node, player};
+use crate::schema::{game, game_results, game_used_slot, node, player};
 use diesel::pg::expression::dsl::{all, any};
+use flo_types::game::GameFlags;
 
 pub fn get(conn: &DbConn, id: i32) -> Result<GameRowWithRelated> {
   let row = game::table
@@ -42,6 +44,28 @@ pub struct QueryGameParams {
   pub take: Option<i64>,
   pub since_id: Option<i32>,
   pub player_id: Option<i32>,
+  pub created_by: Option<i32>,
+  pub tag: Option<String>,
+  #[serde(default)]
+  pub page_size: i64,
+  pub next_id: Option<i32>,
+}
+
+const QUERY_GAME_DEFAULT_PAGE_SIZE: i64 = 30;
+const QUERY_GAME_MAX_PAGE_SIZE: i64 = 100;
+
+impl QueryGameParams {
+  fn page_size(&self) -> i64 {
+    let size = if self.page_size > 0 {
+      self.page_size
+    } else {
+      self
+        .take
+        .filter(|v| *v > 0)
+        .unwrap_or(QUERY_GAME_DEFAULT_PAGE_SIZE)
+    };
+    std::cmp::min(QUERY_GAME_MAX_PAGE_SIZE, size)
+  }
 }
 
 #[derive(Debug, S2ProtoPack)]
@@ -49,6 +73,7 @@ pub struct QueryGameParams {
 pub struct QueryGame {
   pub games: Vec<GameEntry>,
   pub has_more: bool,
+  pub next_id: Option<i32>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, S2ProtoEnum)]
@@ -85,7 +110,7 @@ pub fn get_entry(conn: &DbConn, id: i32) -> Result<GameEntry> {
 pub fn query(conn: &DbConn, params: &QueryGameParams) -> Result<QueryGame> {
   use game::dsl;
 
-  let take = std::cmp::min(100, params.take.clone().unwrap_or(30));
+  let take = params.page_size();
 
   let mut q = game::table
     .left_outer_join(node::table)
@@ -127,6 +152,10 @@ pub fn query(conn: &DbConn, params: &QueryGameParams) -> Result<QueryGame> {
     q = q.filter(dsl::id.lt(id))
   }
 
+  if let Some(id) = params.next_id.clone() {
+    q = q.filter(dsl::id.le(id))
+  }
+
   if let Some(player_id) = params.player_id.clone() {
     let subq = game_used_slot::table
       .select(game_used_slot::dsl::game_id)
@@ -134,14 +163,37 @@ pub fn query(conn: &DbConn, params: &QueryGameParams) -> Result<QueryGame> {
     q = q.filter(dsl::id.eq(any(subq)));
   }
 
-  let mut games: Vec<GameEntry> = q.load(conn)?;
+  if let Some(created_by) = params.created_by {
+    q = q.filter(dsl::created_by.eq(created_by));
+  }
 
-  let has_more = games.len() > take as usize;
-  if has_more {
-    games.truncate(take as usize);
+  if let Some(ref tag) = params.tag {
+    q = q.filter(dsl::tags.contains(vec![tag.clone()]));
   }
 
-  Ok(QueryGame { games, has_more })
+  let mut games: Vec<GameEntry> = q.load(conn)?;
+
+  let next_id = split_page(&mut games, take as usize, |game| game.id);
+
+  Ok(QueryGame {
+    games,
+    has_more: next_id.is_some(),
+    next_id,
+  })
+}
+
+/// Truncates `rows` to `page_size` and returns the id of the first row of the next page
+pub(crate) fn split_page<T, F>(rows: &mut Vec<T>, page_size: usize, get_id: F) -> Option<i32>
+where
+  F: Fn(&T) -> i32,
+{
+  if rows.len() > page_size {
+    let next_id = rows.get(page_size).map(get_id);
+    rows.truncate(page_size);
+    next_id
+  } else {
+    None
+  }
 }
 
 pub fn cancel(conn: &DbConn, game_id: i32, created_by: Option<i32>) -> Result<()> {
@@ -165,6 +217,30 @@ pub fn cancel(conn: &DbConn, game_id: i32, created_by: Option<i32>) -> Result<()
   Ok(())
 }
 
+pub fn update_host(
+  conn: &DbConn,
+  game_id: i32,
+  from_player_id: i32,
+  to_player_id: i32,
+) -> Result<()> {
+  conn.transaction(|| -> Result<()> {
+    let GetSlots {
+      host_player_id,
+      slots,
+    } = get_slots(conn, game_id)?;
+    crate::game::state::host::check_transfer_host(
+      host_player_id,
+      &slots.get_player_ids(),
+      from_player_id,
+      to_player_id,
+    )?;
+    diesel::update(game::table.find(game_id))
+      .set(game::created_by.eq(to_player_id))
+      .execute(conn)?;
+    Ok(())
+  })
+}
+
 #[derive(Debug, Deserialize, S2ProtoUnpack)]
 #[s2_grpc(message_type = "flo_grpc::controller::CreateGameRequest")]
 pub struct CreateGameParams {
@@ -173,23 +249,178 @@ pub struct CreateGameParams {
   pub map: Map,
   pub is_private: bool,
   pub is_live: bool,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// Cancels the game if it doesn't start within this many seconds
+  #[serde(default)]
+  pub lobby_timeout_secs: Option<u32>,
+  /// Replaces the map's forces, players are seated in the team of their slot's force
+  #[serde(default)]
+  pub force_overrides: Vec<MapForce>,
+  /// Pins the game's random seed, for replay and desync testing only
+  #[serde(default)]
+  pub random_seed_override: Option<u32>,
+  /// Repeated requests with the same key return the game created by the first one
+  #[serde(default)]
+  pub idempotency_key: Option<String>,
+  /// Kicks lobby players whose ping to the selected node stays above this
+  #[serde(default)]
+  pub max_ping_ms: Option<u32>,
+  /// `flo_types::game::GameFlags` bits
+  #[serde(default)]
+  pub game_flags: u32,
 }
 
-/// Creates a game, make the creator as the first player
-pub fn create(conn: &DbConn, params: CreateGameParams) -> Result<Game> {
+impl CreateGameParams {
+  pub fn random_seed(&self) -> i32 {
+    random_seed(self.random_seed_override)
+  }
+
+  pub fn lobby_timeout(&self) -> Option<Duration> {
+    lobby_timeout(self.lobby_timeout_secs)
+  }
+}
+
+fn lobby_timeout(secs: Option<u32>) -> Option<Duration> {
+  secs
+    .filter(|secs| *secs > 0)
+    .map(|secs| Duration::from_secs(secs as u64))
+}
+
+/// When a game created now with `timeout` gets cancelled if it hasn't started
+fn lobby_timeout_at(timeout: Option<Duration>) -> Option<DateTime<Utc>> {
+  timeout.map(|timeout| Utc::now() + chrono::Duration::seconds(timeout.as_secs() as i64))
+}
+
+/// The override reinterpreted as the stored `i32`, or a fresh random seed
+fn random_seed(seed_override: Option<u32>) -> i32 {
+  match seed_override {
+    Some(seed) => {
+      tracing::warn!(
+        seed,
+        "RANDOM SEED OVERRIDE IN EFFECT, games are deterministic: not for production use"
+      );
+      seed as i32
+    }
+    None => rand::random(),
+  }
+}
+
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
+
+fn check_idempotency_key(key: &str) -> Result<()> {
+  if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+    return Err(Error::IdempotencyKeyInvalid);
+  }
+  Ok(())
+}
+
+pub const MAX_GAME_TAGS: usize = 8;
+pub const MAX_GAME_TAG_LEN: usize = 32;
+
+fn check_tags(tags: &[String]) -> Result<()> {
+  if tags.len() > MAX_GAME_TAGS
+    || tags
+      .iter()
+      .any(|tag| tag.is_empty() || tag.chars().count() > MAX_GAME_TAG_LEN)
+  {
+    return Err(Error::GameTagsInvalid);
+  }
+  Ok(())
+}
+
+fn check_game_flags(bits: u32) -> Result<()> {
+  GameFlags::validate(bits)
+    .map(|_| ())
+    .ok_or_else(|| Error::GameFlagsInvalid)
+}
+
+fn check_create_params(params: &CreateGameParams) -> Result<()> {
   let max_players = params.map.players.len();
 
   if max_players == 0 {
     return Err(Error::MapHasNoPlayer);
   }
 
-  let player = crate::player::db::get_ref(conn, params.player_id)?;
+  if max_players > 24 {
+    return Err(Error::TooManyPlayers);
+  }
+
+  // maps in the pre-1.29 format only have 12 slots
+  if !params.map.twelve_p && max_players > 12 {
+    return Err(Error::TooManyPlayers);
+  }
+
+  check_tags(&params.tags)?;
+  check_game_flags(params.game_flags)?;
+
+  if let Some(key) = params.idempotency_key.as_deref() {
+    check_idempotency_key(key)?;
+  }
+
+  params
+    .map
+    .validate_forces(&params.force_overrides)
+    .map_err(Error::MapForcesInvalid)?;
+
+  check_map_allowed(&params.map, crate::config::MAP_ALLOWLIST.as_ref())?;
+
+  Ok(())
+}
+
+/// Every map is allowed without an allowlist
+fn check_map_allowed(map: &Map, allowlist: Option<&BTreeSet<String>>) -> Result<()> {
+  match allowlist {
+    Some(allowlist) if !allowlist.contains(&map.sha1.to_hex()) => Err(Error::MapNotAllowed),
+    _ => Ok(()),
+  }
+}
+
+/// Runs the checks of `create` without inserting the game, returns the creator
+pub fn validate_create(conn: &DbConn, params: &CreateGameParams) -> Result<PlayerRef> {
+  check_create_params(params)?;
+  crate::player::db::get_ref(conn, params.player_id)
+}
+
+/// Errors returned by `validate_create` because of the request itself, other errors
+/// are server side failures
+pub fn is_create_validation_error(err: &Error) -> bool {
+  matches!(
+    err,
+    Error::MapHasNoPlayer
+      | Error::TooManyPlayers
+      | Error::PlayerNotFound
+      | Error::GameTagsInvalid
+      | Error::GameFlagsInvalid
+      | Error::IdempotencyKeyInvalid
+      | Error::MapForcesInvalid(_)
+      | Error::MapNotAllowed
+  )
+}
+
+/// Creates a game, make the creator as the first player
+pub fn create(conn: &DbConn, params: CreateGameParams) -> Result<Game> {
+  let player = validate_create(conn, &params)?;
+  let max_players = params.map.players.len();
+  let custom_forces = !params.force_overrides.is_empty();
+  let mut map = params.map;
+  if custom_forces {
+    map.forces = params.force_overrides;
+  }
+
   let mut slots = Slots::new(max_players);
   slots.join(&player);
+  if let Some(race) = crate::player::db::get_preferred_race(conn, params.player_id)? {
+    slots.apply_preferred_race(params.player_id, race, &map.players);
+  }
+  if custom_forces {
+    slots.apply_forces(params.player_id, &map.forces);
+  }
 
   let meta = Meta {
-    map: params.map,
+    map,
     created_by: player.into(),
+    custom_forces,
   };
 
   let meta_value = serde_json::to_value(&meta)?;
@@ -202,13 +433,16 @@ pub fn create(conn: &DbConn, params: CreateGameParams) -> Result<Game> {
     max_players: max_players as i32,
     created_by: Some(params.player_id),
     meta: meta_value,
-    random_seed: rand::random(),
+    random_seed: params.random_seed(),
     locked: false,
     node_id: None,
     mask_player_names: false,
     enable_ping_equalizer: false,
     flo_tv_delay_override_secs: None,
     map_twelve_p: meta.map.twelve_p,
+    tags: &params.tags,
+    game_flags: params.game_flags as i32,
+    lobby_timeout_at: lobby_timeout_at(params.lobby_timeout()),
   };
 
   let row = conn.transaction(|| -> Result<_> {
@@ -235,6 +469,23 @@ pub struct CreateGameAsBotParams {
   pub mask_player_names: bool,
   pub enable_ping_equalizer: bool,
   pub flo_tv_delay_override_secs: Option<i32>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// Pins the game's random seed, for replay and desync testing only
+  #[serde(default)]
+  pub random_seed_override: Option<u32>,
+  /// `flo_types::game::GameFlags` bits
+  #[serde(default)]
+  pub game_flags: u32,
+  /// Cancels the game if it doesn't start within this many seconds
+  #[serde(default)]
+  pub lobby_timeout_secs: Option<u32>,
+}
+
+impl CreateGameAsBotParams {
+  pub fn lobby_timeout(&self) -> Option<Duration> {
+    lobby_timeout(self.lobby_timeout_secs)
+  }
 }
 
 /// Creates a full game and lock it
@@ -244,7 +495,7 @@ pub fn create_as_bot(
   api_player_id: i32,
   params: CreateGameAsBotParams,
 ) -> Result<Game> {
-  use std::collections::{BTreeMap, BTreeSet};
+  use std::collections::BTreeMap;
   let max_players = params.map.players.len();
 
   if max_players == 0 {
@@ -255,6 +506,9 @@ pub fn create_as_bot(
     return Err(Error::TooManyPlayers);
   }
 
+  check_tags(&params.tags)?;
+  check_game_flags(params.game_flags)?;
+
   let (player_slots, referee_slots): (Vec<_>, Vec<_>) = params
     .slots
     .iter()
@@ -335,6 +589,7 @@ pub fn create_as_bot(
       .remove(&api_player_id)
       .ok_or_else(|| Error::PlayerNotFound)?
       .into(),
+    custom_forces: false,
   };
 
   let meta_value = serde_json::to_value(&meta)?;
@@ -347,13 +602,16 @@ pub fn create_as_bot(
     max_players: max_players as i32,
     created_by: Some(api_player_id),
     meta: meta_value,
-    random_seed: rand::random(),
+    random_seed: random_seed(params.random_seed_override),
     locked: true,
     node_id: Some(params.node_id),
     mask_player_names: params.mask_player_names,
     enable_ping_equalizer: params.enable_ping_equalizer,
     flo_tv_delay_override_secs: params.flo_tv_delay_override_secs,
     map_twelve_p: meta.map.twelve_p,
+    tags: &params.tags,
+    game_flags: params.game_flags as i32,
+    lobby_timeout_at: lobby_timeout_at(params.lobby_timeout()),
   };
 
   let row = conn.transaction(|| -> Result<_> {
@@ -371,7 +629,32 @@ pub fn create_as_bot(
 
 /// Adds a player into a game
 pub fn add_player(conn: &DbConn, game_id: i32, player_id: i32) -> Result<Vec<Slot>> {
-  let InspectId { status, locked } = inspect_id(conn, game_id)?;
+  add_player_to_slot(conn, game_id, player_id, false)
+}
+
+/// Adds a player into a free observer slot of a game
+pub fn add_observer(conn: &DbConn, game_id: i32, player_id: i32) -> Result<Vec<Slot>> {
+  add_player_to_slot(conn, game_id, player_id, true)
+}
+
+fn add_player_to_slot(
+  conn: &DbConn,
+  game_id: i32,
+  player_id: i32,
+  observer: bool,
+) -> Result<Vec<Slot>> {
+  // the game row stays locked until the slots are written,
+  // so concurrent joins can't both pass the player source check
+  conn.transaction(|| add_player_to_locked_slot(conn, game_id, player_id, observer))
+}
+
+fn add_player_to_locked_slot(
+  conn: &DbConn,
+  game_id: i32,
+  player_id: i32,
+  observer: bool,
+) -> Result<Vec<Slot>> {
+  let InspectId { status, locked } = inspect_id_for_update(conn, game_id)?;
 
   if locked {
     return Err(Error::GameSlotUpdateDenied);
@@ -387,14 +670,40 @@ pub fn add_player(conn: &DbConn, game_id: i32, player_id: i32) -> Result<Vec<Slo
     return Err(Error::PlayerAlreadyInGame);
   }
 
-  if slots.is_full() {
+  if !observer && slots.is_full() {
     return Err(Error::GameFull);
   }
 
+  if *crate::config::UNIQUE_PLAYER_SOURCE_PER_GAME {
+    let mut ids = slots.get_player_ids();
+    ids.push(player_id);
+    let identities = crate::player::db::get_source_identities(conn, &ids)?;
+    if crate::player::db::find_source_conflict(player_id, &identities).is_some() {
+      return Err(Error::PlayerSourceAlreadyInGame);
+    }
+  }
+
   let player = crate::player::db::get_ref(conn, player_id)?;
+  let meta = get_meta(conn, game_id)?;
+
+  if observer {
+    let num_slots = if meta.map.twelve_p { 12 } else { 24 };
+    slots
+      .join_observer(&player, num_slots)
+      .ok_or_else(|| Error::GameObserverSlotFull)?;
+    upsert_used_slots(conn, game_id, slots.as_used())?;
+    return Ok(slots.into_inner());
+  }
 
   slots.join(&player);
 
+  if let Some(race) = crate::player::db::get_preferred_race(conn, player_id)? {
+    slots.apply_preferred_race(player_id, race, &meta.map.players);
+  }
+  if meta.custom_forces {
+    slots.apply_forces(player_id, &meta.map.forces);
+  }
+
   upsert_used_slots(conn, game_id, slots.as_used())?;
 
   Ok(slots.into_inner())
@@ -469,6 +778,19 @@ fn inspect_id(conn: &DbConn, game_id: i32) -> Result<InspectId> {
   )
 }
 
+/// Like `inspect_id`, locks the game row until the transaction ends
+fn inspect_id_for_update(conn: &DbConn, game_id: i32) -> Result<InspectId> {
+  Ok(
+    game::table
+      .find(game_id)
+      .select((game::status, game::locked))
+      .for_update()
+      .first(conn)
+      .optional()?
+      .ok_or_else(|| Error::GameNotFound)?,
+  )
+}
+
 pub fn leave_node(conn: &DbConn, game_id: i32, player_id: i32) -> Result<()> {
   use game_used_slot::dsl;
   diesel::update(
@@ -550,13 +872,26 @@ pub fn update_slot_settings(
   }
 
   let mut slots = get_slots(conn, game_id)?.slots;
-  let mut updated_indexes = vec![];
-  if let Some(slots) = slots.update_slot_at(slot_index, &settings) {
-    for (index, slot) in slots {
-      sync_slot_at(conn, game_id, index as i32, &slot)?;
-      updated_indexes.push(index);
+  let updated_indexes: Vec<i32> = slots
+    .update_slot_at(slot_index, &settings)
+    .map(|updated| updated.into_iter().map(|(index, _)| index).collect())
+    .unwrap_or_default();
+
+  if !updated_indexes.is_empty() {
+    // a player moved to another slot takes the team of that slot's force
+    let meta = get_meta(conn, game_id)?;
+    if meta.custom_forces {
+      for index in &updated_indexes {
+        if let Some(player_id) = slots[*index as usize].player.as_ref().map(|p| p.id) {
+          slots.apply_forces(player_id, &meta.map.forces);
+        }
+      }
     }
   }
+
+  for index in &updated_indexes {
+    sync_slot_at(conn, game_id, *index, &slots[*index as usize])?;
+  }
   Ok(UpdateSlotSettings {
     slots: slots.into_inner(),
     updated_indexes,
@@ -861,6 +1196,25 @@ pub fn get_all_active_game_state(conn: &DbConn) -> Result<Vec<GameStateFromDb>>
   Ok(games)
 }
 
+/// `(game_id, node_id)` of active games with a selected node
+pub fn get_active_game_nodes(conn: &DbConn) -> Result<Vec<(i32, i32)>> {
+  use game::dsl;
+  let rows: Vec<(i32, Option<i32>)> = game::table
+    .filter(
+      dsl::status
+        .eq(any(GameStatus::active_variants()))
+        .and(dsl::node_id.is_not_null()),
+    )
+    .select((dsl::id, dsl::node_id))
+    .load(conn)?;
+  Ok(
+    rows
+      .into_iter()
+      .filter_map(|(game_id, node_id)| Some((game_id, node_id?)))
+      .collect(),
+  )
+}
+
 pub fn get_expired_games(conn: &DbConn) -> Result<Vec<i32>> {
   let t = Utc::now() - chrono::Duration::minutes(30);
   game::table
@@ -871,6 +1225,22 @@ pub fn get_expired_games(conn: &DbConn) -> Result<Vec<i32>> {
     .map_err(Into::into)
 }
 
+/// `(game_id, lobby_timeout_at)` of games that haven't started and have a lobby timeout,
+/// used after server restart to reschedule the timeouts
+pub fn get_lobby_timeouts(conn: &DbConn) -> Result<Vec<(i32, DateTime<Utc>)>> {
+  let rows: Vec<(i32, Option<DateTime<Utc>>)> = game::table
+    .select((game::id, game::lobby_timeout_at))
+    .filter(game::status.eq_any(&[GameStatus::Preparing, GameStatus::Created]))
+    .filter(game::lobby_timeout_at.is_not_null())
+    .load(conn)?;
+  Ok(
+    rows
+      .into_iter()
+      .filter_map(|(id, at)| at.map(|at| (id, at)))
+      .collect(),
+  )
+}
+
 pub fn select_node(conn: &DbConn, id: i32, player_id: i32, node_id: Option<i32>) -> Result<()> {
   use game::dsl;
 
@@ -944,6 +1314,21 @@ pub fn update_created(
   })
 }
 
+/// Replaces a player's node token after the node issued a new one
+pub fn update_node_token(conn: &DbConn, game_id: i32, token: &PlayerToken) -> Result<()> {
+  use game_used_slot::dsl as gus;
+  diesel::update(
+    game_used_slot::table.filter(
+      gus::game_id
+        .eq(game_id)
+        .and(gus::player_id.eq(token.player_id)),
+    ),
+  )
+  .set(gus::node_token.eq(token.as_slice()))
+  .execute(conn)?;
+  Ok(())
+}
+
 /// Created -> Preparing
 pub fn update_reset_created(conn: &DbConn, id: i32) -> Result<()> {
   use game::dsl;
@@ -977,6 +1362,150 @@ pub fn reset_instance_state(conn: &DbConn) -> Result<()> {
   })
 }
 
+/// Names and statuses of the active games in `ids`
+pub fn get_active_name_and_status(
+  conn: &DbConn,
+  ids: &[i32],
+) -> Result<Vec<(i32, String, GameStatus)>> {
+  use game::dsl;
+  game::table
+    .filter(
+      dsl::id
+        .eq(any(ids))
+        .and(dsl::status.eq(any(GameStatus::active_variants()))),
+    )
+    .order(dsl::id)
+    .select((dsl::id, dsl::name, dsl::status))
+    .load(conn)
+    .map_err(Into::into)
+}
+
+pub fn get_active_max_players_and_status(
+  conn: &DbConn,
+  ids: &[i32],
+) -> Result<Vec<(i32, i32, GameStatus)>> {
+  use game::dsl;
+  game::table
+    .filter(
+      dsl::id
+        .eq(any(ids))
+        .and(dsl::status.eq(any(GameStatus::active_variants()))),
+    )
+    .order(dsl::id)
+    .select((dsl::id, dsl::max_players, dsl::status))
+    .load(conn)
+    .map_err(Into::into)
+}
+
+/// Checks that the game was created by a player of the API client
+pub fn check_game_api_client_id(conn: &DbConn, api_client_id: i32, game_id: i32) -> Result<()> {
+  let n = game::table
+    .inner_join(player::table)
+    .filter(
+      game::id
+        .eq(game_id)
+        .and(player::api_client_id.eq(api_client_id)),
+    )
+    .count()
+    .get_result::<i64>(conn)?;
+  if n == 0 {
+    return Err(Error::GameOwnerCheckFailed);
+  }
+  Ok(())
+}
+
+/// Records the outcome of an ended game, replacing a previous report only if `overwrite` is set
+pub fn report_result(
+  conn: &DbConn,
+  game_id: i32,
+  results: &[PlayerResult],
+  overwrite: bool,
+) -> Result<()> {
+  use game_results::dsl as gr;
+  use game_used_slot::dsl as gus;
+
+  conn.transaction(|| -> Result<_> {
+    let status: GameStatus = game::table
+      .find(game_id)
+      .select(game::dsl::status)
+      .first(conn)
+      .optional()?
+      .ok_or_else(|| Error::GameNotFound)?;
+    let player_ids: Vec<Option<i32>> = game_used_slot::table
+      .filter(gus::game_id.eq(game_id))
+      .select(gus::player_id)
+      .load(conn)?;
+    let player_ids: Vec<i32> = player_ids.into_iter().flatten().collect();
+    let reported = game_results::table
+      .filter(gr::game_id.eq(game_id))
+      .count()
+      .get_result::<i64>(conn)?
+      > 0;
+
+    validate_results(status, &player_ids, results, reported, overwrite)?;
+
+    if reported {
+      diesel::delete(game_results::table.filter(gr::game_id.eq(game_id))).execute(conn)?;
+    }
+
+    let rows: Vec<_> = results
+      .iter()
+      .map(|result| GameResultInsert {
+        game_id,
+        player_id: result.player_id,
+        outcome: result.outcome,
+      })
+      .collect();
+    diesel::insert_into(game_results::table)
+      .values(&rows)
+      .execute(conn)?;
+    Ok(())
+  })
+}
+
+fn validate_results(
+  status: GameStatus,
+  player_ids: &[i32],
+  results: &[PlayerResult],
+  reported: bool,
+  overwrite: bool,
+) -> Result<()> {
+  if !status.is_terminal() {
+    return Err(Error::GameNotEnded);
+  }
+  if reported && !overwrite {
+    return Err(Error::GameResultAlreadyReported);
+  }
+  if results.is_empty() {
+    return Err(Error::GameResultInvalid("no player result".to_string()));
+  }
+  let mut seen = Vec::with_capacity(results.len());
+  for result in results {
+    if !player_ids.contains(&result.player_id) {
+      return Err(Error::GameResultInvalid(format!(
+        "player {} is not in the game",
+        result.player_id
+      )));
+    }
+    if seen.contains(&result.player_id) {
+      return Err(Error::GameResultInvalid(format!(
+        "player {} reported more than once",
+        result.player_id
+      )));
+    }
+    seen.push(result.player_id);
+  }
+  Ok(())
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "game_results"]
+struct GameResultInsert {
+  game_id: i32,
+  player_id: i32,
+  outcome: GameOutcome,
+}
+
 pub fn get_node_active_game_ids(conn: &DbConn, node_id: i32) -> Result<Vec<i32>> {
   use game::dsl as g;
 
@@ -996,6 +1525,19 @@ pub fn get_node_active_game_ids(conn: &DbConn, node_id: i32) -> Result<Vec<i32>>
 pub struct Meta {
   pub map: Map,
   pub created_by: Option<PlayerRef>,
+  /// `map.forces` were overridden at creation and decide the players' teams
+  #[serde(default)]
+  pub custom_forces: bool,
+}
+
+fn get_meta(conn: &DbConn, game_id: i32) -> Result<Meta> {
+  let value: Value = game::table
+    .find(game_id)
+    .select(game::dsl::meta)
+    .first(conn)
+    .optional()?
+    .ok_or_else(|| Error::GameNotFound)?;
+  Ok(serde_json::from_value(value)?)
 }
 
 #[derive(Debug, Queryable)]
@@ -1021,6 +1563,8 @@ pub struct GameRowWithRelated {
   pub enable_ping_equalizer: bool,
   pub flo_tv_delay_override_secs: Option<i32>,
   pub map_twelve_p: bool,
+  pub tags: Vec<String>,
+  pub game_flags: i32,
 }
 
 pub(crate) type GameRowWithRelatedColumns = (
@@ -1045,6 +1589,8 @@ pub(crate) type GameRowWithRelatedColumns = (
   game::dsl::enable_ping_equalizer,
   game::dsl::flo_tv_delay_override_secs,
   game::dsl::map_twelve_p,
+  game::dsl::tags,
+  game::dsl::game_flags,
 );
 
 impl GameRowWithRelated {
@@ -1071,6 +1617,8 @@ impl GameRowWithRelated {
       game::dsl::enable_ping_equalizer,
       game::dsl::flo_tv_delay_override_secs,
       game::dsl::map_twelve_p,
+      game::dsl::tags,
+      game::dsl::game_flags,
     )
   }
 
@@ -1098,6 +1646,8 @@ impl GameRowWithRelated {
       game_version: self.game_version,
       enable_ping_equalizer: self.enable_ping_equalizer,
       flo_tv_delay_override_secs: self.flo_tv_delay_override_secs,
+      tags: self.tags,
+      game_flags: self.game_flags as u32,
     })
   }
 }
@@ -1119,6 +1669,9 @@ pub struct GameInsert<'a> {
   pub enable_ping_equalizer: bool,
   pub flo_tv_delay_override_secs: Option<i32>,
   pub map_twelve_p: bool,
+  pub tags: &'a [String],
+  pub game_flags: i32,
+  pub lobby_timeout_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Insertable)]
@@ -1181,3 +1734,454 @@ impl UsedSlotUpdate {
     }
   }
 }
+
+#[cfg(test)]
+pub(crate) fn test_create_params(player_id: i32, players: usize) -> CreateGameParams {
+  let mut map = crate::map::test_map(players, 0);
+  map.forces.clear();
+  CreateGameParams {
+    player_id,
+    name: "test".to_string(),
+    map,
+    is_private: false,
+    is_live: false,
+    tags: vec![],
+    lobby_timeout_secs: None,
+    force_overrides: vec![],
+    random_seed_override: None,
+    idempotency_key: None,
+    max_ping_ms: None,
+    game_flags: 0,
+  }
+}
+
+#[test]
+fn test_check_create_params() {
+  let params = |players: usize| test_create_params(1, players);
+
+  // every failure of the checks is reported by `validate_create_game` instead of failing the RPC
+  for p in vec![params(0), params(13), params(25)] {
+    assert!(is_create_validation_error(
+      &check_create_params(&p).unwrap_err()
+    ));
+  }
+
+  assert!(check_create_params(&params(2)).is_ok());
+  assert!(matches!(
+    check_create_params(&params(0)),
+    Err(Error::MapHasNoPlayer)
+  ));
+  assert!(matches!(
+    check_create_params(&params(25)),
+    Err(Error::TooManyPlayers)
+  ));
+  // 13 players only fit into the 24 slots of a twelve_p map
+  assert!(matches!(
+    check_create_params(&params(13)),
+    Err(Error::TooManyPlayers)
+  ));
+  let mut p = params(13);
+  p.map.twelve_p = true;
+  assert!(check_create_params(&p).is_ok());
+
+  let mut p = params(2);
+  p.game_flags = (GameFlags::FFA | GameFlags::TEAMS_LOCKED).bits();
+  assert!(matches!(
+    check_create_params(&p),
+    Err(Error::GameFlagsInvalid)
+  ));
+  p.game_flags = GameFlags::TEAMS_LOCKED.bits();
+  assert!(check_create_params(&p).is_ok());
+
+  let mut p = params(2);
+  assert_eq!(p.lobby_timeout(), None);
+  p.lobby_timeout_secs = Some(0);
+  assert_eq!(p.lobby_timeout(), None);
+  p.lobby_timeout_secs = Some(90);
+  assert_eq!(p.lobby_timeout(), Some(Duration::from_secs(90)));
+
+  let force = |player_set| MapForce {
+    name: "Force".to_string(),
+    flags: 0,
+    player_set,
+  };
+  let mut p = params(4);
+  p.force_overrides = vec![force(0b0011), force(0b1100)];
+  assert!(check_create_params(&p).is_ok());
+  p.force_overrides = vec![force(0b0011), force(0b11100)];
+  assert!(matches!(
+    check_create_params(&p),
+    Err(Error::MapForcesInvalid(_))
+  ));
+
+  let mut p = params(2);
+  p.random_seed_override = Some(0x1234_5678);
+  assert_eq!(p.random_seed(), 0x1234_5678);
+  p.random_seed_override = Some(u32::MAX);
+  assert_eq!(p.random_seed(), -1);
+
+  let mut p = params(2);
+  p.idempotency_key = Some("retry-1".to_string());
+  assert!(check_create_params(&p).is_ok());
+  for key in &["".to_string(), "k".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1)] {
+    p.idempotency_key = Some(key.clone());
+    assert!(matches!(
+      check_create_params(&p),
+      Err(Error::IdempotencyKeyInvalid)
+    ));
+  }
+}
+
+#[test]
+fn test_check_map_allowed() {
+  let map = test_create_params(1, 2).map;
+  assert!(check_map_allowed(&map, None).is_ok());
+
+  let mut allowlist = BTreeSet::new();
+  allowlist.insert("f".repeat(40));
+  let err = check_map_allowed(&map, Some(&allowlist)).unwrap_err();
+  assert!(matches!(err, Error::MapNotAllowed));
+  assert!(is_create_validation_error(&err));
+
+  allowlist.insert(map.sha1.to_hex());
+  assert!(check_map_allowed(&map, Some(&allowlist)).is_ok());
+}
+
+#[test]
+fn test_check_tags() {
+  assert!(check_tags(&[]).is_ok());
+  assert!(check_tags(&["tournament:1".to_string(), "round:2".to_string()]).is_ok());
+  assert!(matches!(
+    check_tags(&["".to_string()]),
+    Err(Error::GameTagsInvalid)
+  ));
+  assert!(matches!(
+    check_tags(&["x".repeat(MAX_GAME_TAG_LEN + 1)]),
+    Err(Error::GameTagsInvalid)
+  ));
+  assert!(matches!(
+    check_tags(&vec!["x".to_string(); MAX_GAME_TAGS + 1]),
+    Err(Error::GameTagsInvalid)
+  ));
+}
+
+#[test]
+fn test_query_by_tag() {
+  crate::db::test::with_transaction(|conn| {
+    let player = crate::db::test::create_player(conn, "tag_test")?;
+    let create_tagged = |tags: &[&str]| {
+      let mut params = test_create_params(player.id, 2);
+      params.tags = tags.iter().map(|tag| tag.to_string()).collect();
+      create(conn, params)
+    };
+    let a = create_tagged(&["tournament:1", "round:1"])?;
+    let b = create_tagged(&["tournament:1", "round:2"])?;
+    create_tagged(&[])?;
+
+    let query_tag = |tag: &str| -> Result<Vec<i32>> {
+      let params = QueryGameParams {
+        player_id: Some(player.id),
+        tag: Some(tag.to_string()),
+        ..Default::default()
+      };
+      Ok(
+        query(conn, &params)?
+          .games
+          .into_iter()
+          .map(|game| game.id)
+          .collect(),
+      )
+    };
+    assert_eq!(query_tag("tournament:1")?, vec![b.id, a.id]);
+    assert_eq!(query_tag("round:2")?, vec![b.id]);
+    assert_eq!(query_tag("round")?, Vec::<i32>::new());
+    Ok(())
+  });
+}
+
+#[test]
+fn test_get_lobby_timeouts() {
+  crate::db::test::with_transaction(|conn| {
+    let player = crate::db::test::create_player(conn, "lobby_timeout")?;
+    let mut params = test_create_params(player.id, 2);
+    params.lobby_timeout_secs = Some(60);
+    let game = create(conn, params)?;
+    let other = create(conn, test_create_params(player.id, 2))?;
+
+    let timeouts = get_lobby_timeouts(conn)?;
+    let timeout_at = timeouts
+      .iter()
+      .find(|(id, _)| *id == game.id)
+      .map(|(_, at)| *at)
+      .unwrap();
+    let remaining = timeout_at - Utc::now();
+    assert!(remaining > chrono::Duration::seconds(50));
+    assert!(remaining <= chrono::Duration::seconds(60));
+    assert!(!timeouts.iter().any(|(id, _)| *id == other.id));
+
+    // started games are not cancelled
+    diesel::update(game::table.find(game.id))
+      .set(game::status.eq(GameStatus::Running))
+      .execute(conn)?;
+    assert!(!get_lobby_timeouts(conn)?
+      .iter()
+      .any(|(id, _)| *id == game.id));
+    Ok(())
+  });
+}
+
+#[test]
+fn test_check_game_api_client_id() {
+  use crate::db::test::{create_api_client, create_api_player};
+
+  crate::db::test::with_transaction(|conn| {
+    let api_client_id = create_api_client(conn, "result_owner")?;
+    let host = create_api_player(conn, api_client_id, "result_owner")?;
+    let game = create(conn, test_create_params(host.id, 2))?;
+    let other_api_client_id = create_api_client(conn, "result_other")?;
+
+    check_game_api_client_id(conn, api_client_id, game.id)?;
+    assert!(matches!(
+      check_game_api_client_id(conn, other_api_client_id, game.id),
+      Err(Error::GameOwnerCheckFailed)
+    ));
+    Ok(())
+  });
+}
+
+#[test]
+fn test_add_player_source_conflict() {
+  use crate::db::test::{create_api_client, create_api_player, create_player};
+
+  crate::db::test::with_transaction(|conn| {
+    let host = create_player(conn, "source_host")?;
+    let game = create(conn, test_create_params(host.id, 3))?;
+    // the same account registered through two API clients
+    let a = create_api_player(conn, create_api_client(conn, "source_a")?, "account")?;
+    let b = create_api_player(conn, create_api_client(conn, "source_b")?, "account")?;
+    add_player(conn, game.id, a.id)?;
+    assert!(matches!(
+      add_player(conn, game.id, b.id),
+      Err(Error::PlayerSourceAlreadyInGame)
+    ));
+    assert_eq!(get_slots(conn, game.id)?.slots.get_player_ids().len(), 2);
+    Ok(())
+  });
+}
+
+#[test]
+fn test_add_player_source_conflict_concurrent() {
+  use crate::db::test::{create_api_client, create_api_player};
+  use crate::schema::api_client;
+  use std::sync::{Arc, Barrier};
+
+  crate::db::test::with_pool(3, |pool| {
+    let conn = pool.get().unwrap();
+    let suffix = Utc::now().timestamp_nanos();
+    let name = |prefix: &str| format!("{}_{}", prefix, suffix);
+    let api_client_ids = vec![
+      create_api_client(&conn, &name("concurrent_a"))?,
+      create_api_client(&conn, &name("concurrent_b"))?,
+    ];
+    let host = create_api_player(&conn, api_client_ids[0], &name("concurrent_host"))?;
+    let game = create(&conn, test_create_params(host.id, 3))?;
+    // the same account registered through two API clients
+    let account = name("concurrent_account");
+    let a = create_api_player(&conn, api_client_ids[0], &account)?;
+    let b = create_api_player(&conn, api_client_ids[1], &account)?;
+
+    let game_id = game.id;
+    let barrier = Arc::new(Barrier::new(2));
+    let joins: Vec<_> = vec![a.id, b.id]
+      .into_iter()
+      .map(|player_id| {
+        let pool = pool.clone();
+        let barrier = barrier.clone();
+        std::thread::spawn(move || {
+          let conn = pool.get().unwrap();
+          barrier.wait();
+          add_player(&conn, game_id, player_id)
+        })
+      })
+      .collect();
+    let results: Vec<_> = joins.into_iter().map(|t| t.join().unwrap()).collect();
+    let player_ids = get_slots(&conn, game_id)?.slots.get_player_ids();
+    diesel::delete(game::table.find(game_id)).execute(&conn)?;
+    diesel::delete(player::table.filter(player::id.eq_any(vec![host.id, a.id, b.id])))
+      .execute(&conn)?;
+    diesel::delete(api_client::table.filter(api_client::id.eq_any(api_client_ids)))
+      .execute(&conn)?;
+
+    assert_eq!(results.iter().filter(|res| res.is_ok()).count(), 1);
+    assert!(results
+      .iter()
+      .any(|res| matches!(res, Err(Error::PlayerSourceAlreadyInGame))));
+    assert_eq!(player_ids.len(), 2);
+    Ok(())
+  });
+}
+
+#[test]
+fn test_update_slot_settings_custom_forces() {
+  use crate::db::test::create_player;
+
+  crate::db::test::with_transaction(|conn| {
+    let host = create_player(conn, "forces_host")?;
+    let guest = create_player(conn, "forces_guest")?;
+    let force = |player_set| MapForce {
+      name: "Force".to_string(),
+      flags: 0,
+      player_set,
+    };
+    let mut params = test_create_params(host.id, 4);
+    params.force_overrides = vec![force(0b0011), force(0b1100)];
+    let game = create(conn, params)?;
+    add_player(conn, game.id, guest.id)?;
+    let team_of = |player_id| -> Result<i32> {
+      Ok(
+        get_slots(conn, game.id)?
+          .slots
+          .find_player_slot(player_id)
+          .unwrap()
+          .settings
+          .team,
+      )
+    };
+    let update = |slot_index, team, status| {
+      update_slot_settings(
+        conn,
+        game.id,
+        slot_index,
+        SlotSettings {
+          team,
+          status,
+          ..Default::default()
+        },
+      )
+    };
+    assert_eq!(team_of(guest.id)?, 0);
+
+    // the team follows the force of the slot
+    update(1, 1, SlotStatus::Occupied)?;
+    assert_eq!(team_of(guest.id)?, 0);
+
+    // moving back from the referees seats the guest in slot 2
+    update(1, 24, SlotStatus::Occupied)?;
+    update(1, 0, SlotStatus::Closed)?;
+    let referee_index = get_slots(conn, game.id)?
+      .slots
+      .iter()
+      .position(|s| s.player.as_ref().map(|p| p.id) == Some(guest.id))
+      .unwrap();
+    update(referee_index as i32, 0, SlotStatus::Occupied)?;
+    assert_eq!(team_of(guest.id)?, 1);
+    Ok(())
+  });
+}
+
+#[test]
+fn test_query_game_page_size() {
+  let mut params = QueryGameParams::default();
+  assert_eq!(params.page_size(), QUERY_GAME_DEFAULT_PAGE_SIZE);
+  params.take = Some(10);
+  assert_eq!(params.page_size(), 10);
+  params.page_size = 20;
+  assert_eq!(params.page_size(), 20);
+  params.page_size = 1000;
+  assert_eq!(params.page_size(), QUERY_GAME_MAX_PAGE_SIZE);
+}
+
+#[test]
+fn test_split_page() {
+  // rows are ordered by id desc, each page loads `page_size + 1` rows
+  let ids: Vec<i32> = (1..=5).rev().collect();
+  let load = |next_id: Option<i32>| -> Vec<i32> {
+    ids
+      .iter()
+      .cloned()
+      .filter(|id| next_id.map(|next_id| *id <= next_id).unwrap_or(true))
+      .take(4)
+      .collect()
+  };
+
+  let mut page = load(None);
+  let next_id = split_page(&mut page, 3, |id| *id);
+  assert_eq!(page, vec![5, 4, 3]);
+  assert_eq!(next_id, Some(2));
+
+  let mut page = load(next_id);
+  let next_id = split_page(&mut page, 3, |id| *id);
+  assert_eq!(page, vec![2, 1]);
+  assert_eq!(next_id, None);
+}
+
+#[test]
+fn test_validate_results() {
+  let result = |player_id, outcome| PlayerResult { player_id, outcome };
+  let players = [1, 2];
+  let results = vec![result(1, GameOutcome::Won), result(2, GameOutcome::Lost)];
+
+  assert!(validate_results(GameStatus::Ended, &players, &results, false, false).is_ok());
+  assert!(validate_results(GameStatus::Terminated, &players, &results, false, false).is_ok());
+  assert!(matches!(
+    validate_results(GameStatus::Running, &players, &results, false, false),
+    Err(Error::GameNotEnded)
+  ));
+
+  // duplicate reports need `overwrite`
+  assert!(matches!(
+    validate_results(GameStatus::Ended, &players, &results, true, false),
+    Err(Error::GameResultAlreadyReported)
+  ));
+  assert!(validate_results(GameStatus::Ended, &players, &results, true, true).is_ok());
+
+  assert!(matches!(
+    validate_results(GameStatus::Ended, &players, &[], false, false),
+    Err(Error::GameResultInvalid(_))
+  ));
+  assert!(matches!(
+    validate_results(
+      GameStatus::Ended,
+      &players,
+      &[result(3, GameOutcome::Won)],
+      false,
+      false
+    ),
+    Err(Error::GameResultInvalid(_))
+  ));
+  assert!(matches!(
+    validate_results(
+      GameStatus::Ended,
+      &players,
+      &[result(1, GameOutcome::Won), result(1, GameOutcome::Draw)],
+      false,
+      false
+    ),
+    Err(Error::GameResultInvalid(_))
+  ));
+}
+
+#[test]
+fn test_update_node_token() {
+  use crate::db::test::create_player;
+
+  crate::db::test::with_transaction(|conn| {
+    let host = create_player(conn, "token_host")?;
+    let game = create(conn, test_create_params(host.id, 2))?;
+    let token = |byte| PlayerToken {
+      player_id: host.id,
+      bytes: [byte; 16],
+    };
+    update_created(
+      conn,
+      game.id,
+      None,
+      vec![(host.id, token(1))].into_iter().collect(),
+    )?;
+
+    update_node_token(conn, game.id, &token(2))?;
+    let (_, stored) = get_full_and_node_token(conn, game.id, host.id)?;
+    assert_eq!(stored.map(|t| t.bytes), Some([2; 16]));
+    Ok(())
+  });
+}
@@ -12,7 +12,8 @@ pub mod messages {
   pub use super::state::node::SelectNode;
   pub use super::state::player::GetGamePlayers;
   pub use super::state::registry::{
-    AddGamePlayer, Register, Remove, RemoveGamePlayer, ResolveGamePlayerPingBroadcastTargets,
+    AddGamePlayer, GetGamesPlayerCount, GetNodeGames, Register, Remove, RemoveGamePlayer,
+    ResolveGamePlayerPingBroadcastTargets,
   };
   pub use super::state::slot::UpdateSlot;
   pub use super::state::start::{StartGameCheck, StartGamePlayerAck};
@@ -3,11 +3,15 @@ use diesel::prelude::*;
 use std::collections::HashMap;
 
 use crate::game::{
-  Computer, Slot, SlotClientStatus, SlotSettings, SlotSettingsColumns, SlotStatus,
+  Computer, Race, Slot, SlotClientStatus, SlotSettings, SlotSettingsColumns, SlotStatus,
 };
+use crate::map::{MapForce, MapPlayer};
 use crate::player::{PlayerRef, PlayerRefColumns};
 use crate::schema::game_used_slot;
 
+/// `MapPlayer::race` of a slot whose race can be chosen in the lobby
+const MAP_PLAYER_RACE_SELECTABLE: u32 = 0;
+
 #[derive(Debug)]
 pub struct Slots {
   inner: Vec<Slot>,
@@ -117,6 +121,75 @@ impl Slots {
     })
   }
 
+  /// Seats the player in the first open slot after the map's player slots as a referee.
+  /// `num_slots` is the slot count of the map, 12 or 24.
+  pub fn join_observer(&mut self, player: &PlayerRef, num_slots: usize) -> Option<&mut Slot> {
+    let start = self.map_players.min(num_slots);
+    let slot = self.inner[start..num_slots.min(24)]
+      .iter_mut()
+      .find(|s| s.settings.status == SlotStatus::Open)?;
+    slot.player = Some(player.clone());
+    slot.settings.team = 24;
+    slot.settings.color = 0;
+    slot.settings.status = SlotStatus::Occupied;
+    slot.settings.computer = Computer::Easy;
+    Some(slot)
+  }
+
+  /// Seats the player with `race` if the player occupies a player slot whose race is selectable.
+  /// Maps fix the race of a slot by setting a race on the matching map player.
+  pub fn apply_preferred_race(
+    &mut self,
+    player_id: i32,
+    race: Race,
+    map_players: &[MapPlayer],
+  ) -> bool {
+    let index = match self
+      .inner
+      .iter()
+      .position(|s| s.player.as_ref().map(|p| p.id) == Some(player_id))
+    {
+      Some(index) => index,
+      None => return false,
+    };
+    let selectable = map_players
+      .get(index)
+      .map(|p| p.race == MAP_PLAYER_RACE_SELECTABLE)
+      .unwrap_or(false);
+    let slot = &mut self.inner[index];
+    if !selectable || slot.settings.team == 24 {
+      return false;
+    }
+    slot.settings.race = race;
+    true
+  }
+
+  /// Moves the player to the team of the force that contains its slot.
+  /// Only used for forces overridden at creation, maps' own forces are ignored.
+  pub fn apply_forces(&mut self, player_id: i32, forces: &[MapForce]) -> bool {
+    let index = match self
+      .inner
+      .iter()
+      .position(|s| s.player.as_ref().map(|p| p.id) == Some(player_id))
+    {
+      Some(index) => index,
+      None => return false,
+    };
+    let team = match forces
+      .iter()
+      .position(|force| index < 32 && force.player_set & (1 << index) != 0)
+    {
+      Some(team) => team,
+      None => return false,
+    };
+    let slot = &mut self.inner[index];
+    if slot.settings.team == 24 {
+      return false;
+    }
+    slot.settings.team = team as i32;
+    true
+  }
+
   pub fn find_player_slot(&self, player_id: i32) -> Option<&Slot> {
     self
       .inner
@@ -412,3 +485,106 @@ impl UsedSlotInfo {
     )
   }
 }
+
+#[test]
+fn test_apply_preferred_race() {
+  use crate::player::PlayerSource;
+
+  let player = |id: i32| PlayerRef {
+    id,
+    name: format!("Player {}", id),
+    source: PlayerSource::Test,
+    realm: None,
+  };
+  let map_player = |race: u32| MapPlayer {
+    name: "Player".to_string(),
+    r#type: 1,
+    race,
+    flags: 0,
+  };
+  // the second slot has a race fixed by the map
+  let map_players = vec![map_player(0), map_player(2)];
+
+  let mut slots = Slots::new(2);
+  slots.join(&player(1));
+  slots.join(&player(2));
+  slots.join(&player(3));
+
+  assert!(slots.apply_preferred_race(1, Race::Orc, &map_players));
+  assert_eq!(slots.find_player_slot(1).unwrap().settings.race, Race::Orc);
+
+  assert!(!slots.apply_preferred_race(2, Race::Undead, &map_players));
+  assert_eq!(
+    slots.find_player_slot(2).unwrap().settings.race,
+    Race::Human
+  );
+
+  // observer
+  assert!(!slots.apply_preferred_race(3, Race::Undead, &map_players));
+  assert!(!slots.apply_preferred_race(4, Race::Undead, &map_players));
+}
+
+#[test]
+fn test_apply_forces() {
+  use crate::player::PlayerSource;
+  let player = |id| PlayerRef {
+    id,
+    name: format!("Player {}", id),
+    source: PlayerSource::Test,
+    realm: None,
+  };
+  let force = |player_set| MapForce {
+    name: "Force".to_string(),
+    flags: 0,
+    player_set,
+  };
+  // a free for all map played as 2v2
+  let forces = vec![force(0b0011), force(0b1100)];
+
+  let mut slots = Slots::new(4);
+  for id in 1..=5 {
+    slots.join(&player(id));
+    slots.apply_forces(id, &forces);
+  }
+
+  let teams: Vec<i32> = (1..=5)
+    .map(|id| slots.find_player_slot(id).unwrap().settings.team)
+    .collect();
+  assert_eq!(teams, vec![0, 0, 1, 1, 24]);
+  assert!(!slots.apply_forces(6, &forces));
+}
+
+#[test]
+fn test_join_observer() {
+  use crate::player::PlayerSource;
+  let player = |id| PlayerRef {
+    id,
+    name: format!("Player {}", id),
+    source: PlayerSource::Test,
+    realm: None,
+  };
+
+  let mut slots = Slots::new(2);
+  slots.join(&player(1));
+  let slot = slots.join_observer(&player(2), 24).unwrap();
+  assert_eq!(slot.settings.team, 24);
+  assert_eq!(slot.settings.status, SlotStatus::Occupied);
+  // player slots are left open
+  assert_eq!(
+    slots
+      .iter()
+      .position(|s| s.player.as_ref().map(|p| p.id) == Some(2)),
+    Some(2)
+  );
+  assert_eq!(slots[1].settings.status, SlotStatus::Open);
+
+  // a 12 player map with all referee slots taken
+  let mut slots = Slots::new(10);
+  assert!(slots.join_observer(&player(1), 12).is_some());
+  assert!(slots.join_observer(&player(2), 12).is_some());
+  assert!(slots.join_observer(&player(3), 12).is_none());
+
+  // maps using all slots have none
+  let mut slots = Slots::new(24);
+  assert!(slots.join_observer(&player(1), 24).is_none());
+}
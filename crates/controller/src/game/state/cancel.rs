@@ -1,14 +1,38 @@
 use crate::error::*;
-use crate::game::state::GameActor;
+use crate::game::db::get_lobby_timeouts;
+use crate::game::state::registry::Remove;
+use crate::game::state::{GameActor, GameRegistry};
 
 use crate::player::state::sender::PlayerFrames;
 
 use flo_net::packet::FloPacket;
 
+use chrono::Utc;
 use flo_state::{async_trait, Context, Handler, Message};
+use std::time::Duration;
+use tokio::time::sleep;
 
 pub struct CancelGame {
   pub player_id: Option<i32>,
+  pub reason: CancelReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CancelReason {
+  /// Cancelled by the host or an API client, or expired
+  Cancelled,
+  /// Didn't start within the lobby timeout of the game
+  LobbyTimeout,
+}
+
+impl CancelReason {
+  fn leave_reason(self) -> flo_net::proto::flo_connect::PlayerLeaveReason {
+    use flo_net::proto::flo_connect::PlayerLeaveReason;
+    match self {
+      CancelReason::Cancelled => PlayerLeaveReason::GameCancelled,
+      CancelReason::LobbyTimeout => PlayerLeaveReason::LobbyTimeout,
+    }
+  }
 }
 
 impl Message for CancelGame {
@@ -20,7 +44,7 @@ impl Handler<CancelGame> for GameActor {
   async fn handle(
     &mut self,
     _: &mut Context<Self>,
-    CancelGame { player_id }: CancelGame,
+    CancelGame { player_id, reason }: CancelGame,
   ) -> Result<()> {
     let game_id = self.game_id;
 
@@ -44,7 +68,7 @@ impl Handler<CancelGame> for GameActor {
         let frame_left = PacketGamePlayerLeave {
           game_id,
           player_id,
-          reason: PlayerLeaveReason::GameCancelled.into(),
+          reason: reason.leave_reason().into(),
         }
         .encode_as_frame()?;
 
@@ -58,3 +82,147 @@ impl Handler<CancelGame> for GameActor {
     Ok(())
   }
 }
+
+/// Sent by `schedule_lobby_timeout` when the lobby timeout of a game elapsed
+pub struct LobbyTimeout {
+  pub game_id: i32,
+}
+
+impl Message for LobbyTimeout {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<LobbyTimeout> for GameRegistry {
+  async fn handle(&mut self, ctx: &mut Context<Self>, LobbyTimeout { game_id }: LobbyTimeout) {
+    let game = match self.map.get_mut(&game_id) {
+      Some(game) => game,
+      None => return,
+    };
+    let cancel = CancelGame {
+      player_id: None,
+      reason: CancelReason::LobbyTimeout,
+    };
+    match game.send(cancel).await {
+      Ok(Ok(())) => {
+        tracing::debug!(game_id, "shutting down: reason: LobbyTimeout");
+        let addr = ctx.addr();
+        ctx.spawn(async move {
+          if let Err(err) = addr.send(Remove { game_id }).await {
+            tracing::error!(game_id, "remove timed out game: {}", err);
+          }
+        });
+      }
+      // already started
+      Ok(Err(Error::GameNotCancellable)) => {}
+      Ok(Err(err)) => {
+        tracing::error!(game_id, "cancel timed out game: {}", err);
+      }
+      Err(err) => {
+        tracing::error!(game_id, "cancel timed out game: {}", err);
+      }
+    }
+  }
+}
+
+impl GameRegistry {
+  /// Cancels the game after `timeout` unless it has started
+  pub(crate) fn schedule_lobby_timeout(
+    &self,
+    ctx: &mut Context<Self>,
+    game_id: i32,
+    timeout: Duration,
+  ) {
+    let addr = ctx.addr();
+    ctx.spawn(async move {
+      sleep(timeout).await;
+      addr.notify(LobbyTimeout { game_id }).await.ok();
+    });
+  }
+
+  /// Reschedules the lobby timeouts of the games loaded from the database,
+  /// a deadline that passed while the server was down fires immediately
+  pub(crate) async fn restore_lobby_timeouts(&self, ctx: &mut Context<Self>) -> Result<()> {
+    let timeouts = self.db.exec(|conn| get_lobby_timeouts(conn)).await?;
+    let now = Utc::now();
+    for (game_id, timeout_at) in timeouts {
+      let timeout = (timeout_at - now).to_std().unwrap_or_default();
+      self.schedule_lobby_timeout(ctx, game_id, timeout);
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn test_cancel_leave_reason() {
+  use flo_net::proto::flo_connect::PlayerLeaveReason;
+  assert_eq!(
+    CancelReason::Cancelled.leave_reason(),
+    PlayerLeaveReason::GameCancelled
+  );
+  assert_eq!(
+    CancelReason::LobbyTimeout.leave_reason(),
+    PlayerLeaveReason::LobbyTimeout
+  );
+}
+
+#[tokio::test]
+async fn test_lobby_timeout_removes_game() {
+  use crate::db::test::{create_api_client, create_api_player};
+  use crate::event::ControllerEvent;
+  use crate::game::state::create::CreateGame;
+  use crate::state::ControllerState;
+  use bs_diesel_utils::Executor;
+
+  dotenv::dotenv().ok();
+  if std::env::var("DATABASE_URL").is_err() {
+    eprintln!("DATABASE_URL not set, skipped");
+    return;
+  }
+
+  // the game registry commits, use a name no other run has taken
+  let name = format!(
+    "lt{}",
+    chrono::Utc::now().timestamp_millis() % 1_000_000_000
+  );
+  let db = Executor::env().into_ref();
+  // created before the server starts, its timeout is restored from the database
+  let (api_client_id, player_id, restored_id) = db
+    .exec(move |conn| {
+      crate::migration::run(conn)?;
+      let api_client_id = create_api_client(conn, &name)?;
+      let player = create_api_player(conn, api_client_id, &name)?;
+      let mut params = crate::game::db::test_create_params(player.id, 2);
+      params.lobby_timeout_secs = Some(2);
+      let game = crate::game::db::create(conn, params)?;
+      Ok::<_, Error>((api_client_id, player.id, game.id))
+    })
+    .await
+    .unwrap();
+
+  let state = ControllerState::init().await.unwrap();
+  let mut rx = state.events.subscribe();
+
+  let mut params = crate::game::db::test_create_params(player_id, 2);
+  params.lobby_timeout_secs = Some(1);
+  let game = state
+    .games
+    .send(CreateGame {
+      api_client_id,
+      params,
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+  let mut pending = vec![game.id, restored_id];
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while !pending.is_empty() {
+      if let ControllerEvent::GameEnded { game_id } = rx.recv().await.unwrap() {
+        pending.retain(|id| *id != game_id);
+      }
+    }
+  })
+  .await
+  .unwrap();
+}
@@ -1,11 +1,44 @@
 use crate::error::{Error, Result};
+use crate::event::ControllerEvent;
 use crate::game::db::{CreateGameAsBotParams, CreateGameParams};
+use crate::game::state::lobby_ping::watch_lobby_ping;
 use crate::game::state::registry::Register;
 use crate::game::state::GameRegistry;
 use crate::game::{Game, GameStatus};
 use flo_state::{async_trait, Context, Handler, Message};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long `create_game` idempotency keys are remembered
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(3600);
+
+/// Games created with an idempotency key, by API client and key
+#[derive(Debug, Default)]
+pub struct IdempotencyKeys {
+  map: HashMap<(i32, String), (i32, Instant)>,
+}
+
+impl IdempotencyKeys {
+  /// The game created with this key, if it hasn't expired at `now`
+  pub fn get(&self, api_client_id: i32, key: &str, now: Instant) -> Option<i32> {
+    self
+      .map
+      .get(&(api_client_id, key.to_string()))
+      .filter(|(_, created_at)| now.saturating_duration_since(*created_at) < IDEMPOTENCY_KEY_TTL)
+      .map(|(game_id, _)| *game_id)
+  }
+
+  /// Records the game created with this key and forgets expired keys
+  pub fn insert(&mut self, api_client_id: i32, key: String, game_id: i32, now: Instant) {
+    self.map.retain(|_, (_, created_at)| {
+      now.saturating_duration_since(*created_at) < IDEMPOTENCY_KEY_TTL
+    });
+    self.map.insert((api_client_id, key), (game_id, now));
+  }
+}
 
 pub struct CreateGame {
+  pub api_client_id: i32,
   pub params: CreateGameParams,
 }
 
@@ -17,15 +50,46 @@ impl Message for CreateGame {
 impl Handler<CreateGame> for GameRegistry {
   async fn handle(
     &mut self,
-    _: &mut Context<Self>,
-    CreateGame { params }: CreateGame,
+    ctx: &mut Context<Self>,
+    CreateGame {
+      api_client_id,
+      params,
+    }: CreateGame,
   ) -> <CreateGame as Message>::Result {
     let player_id = params.player_id;
+    let lobby_timeout = params.lobby_timeout();
+    let max_ping = params.max_ping_ms.filter(|ms| *ms > 0);
+    // the registry handles one message at a time, nothing can create
+    // a game with the same key between this check and the insert below
+    let idempotency_key = params.idempotency_key.clone();
+    if let Some(key) = idempotency_key.as_deref() {
+      if let Some(game_id) = self
+        .idempotency_keys
+        .get(api_client_id, key, Instant::now())
+      {
+        tracing::debug!(
+          game_id,
+          api_client_id,
+          "create game: idempotency key reused"
+        );
+        return self
+          .db
+          .exec(move |conn| crate::game::db::get_full(conn, game_id))
+          .await;
+      }
+    }
+
     let game = self
       .db
       .exec(move |conn| crate::game::db::create(conn, params))
       .await?;
 
+    if let Some(key) = idempotency_key {
+      self
+        .idempotency_keys
+        .insert(api_client_id, key, game.id, Instant::now());
+    }
+
     self.register(Register {
       id: game.id,
       status: GameStatus::Preparing,
@@ -34,6 +98,25 @@ impl Handler<CreateGame> for GameRegistry {
       node_id: None,
     });
 
+    if let Some(timeout) = lobby_timeout {
+      self.schedule_lobby_timeout(ctx, game.id, timeout);
+    }
+
+    if let (Some(max_ping), Some(owner)) = (max_ping, self.map.get(&game.id)) {
+      ctx.spawn(watch_lobby_ping(
+        game.id,
+        max_ping,
+        owner.addr(),
+        ctx.addr(),
+        self.players.clone(),
+      ));
+    }
+
+    self.events.publish(ControllerEvent::GameCreated {
+      game_id: game.id,
+      created_by: game.created_by.id,
+    });
+
     self
       .players
       .player_replace_game(player_id, game.clone(), vec![])
@@ -57,13 +140,14 @@ impl Message for CreateGameAsBot {
 impl Handler<CreateGameAsBot> for GameRegistry {
   async fn handle(
     &mut self,
-    _: &mut Context<Self>,
+    ctx: &mut Context<Self>,
     CreateGameAsBot {
       api_client_id,
       api_player_id,
       params,
     }: CreateGameAsBot,
   ) -> <CreateGameAsBot as Message>::Result {
+    let lobby_timeout = params.lobby_timeout();
     let (mut game, player_ids, mute_list_map) = self
       .db
       .exec(move |conn| {
@@ -82,6 +166,15 @@ impl Handler<CreateGameAsBot> for GameRegistry {
       node_id: game.node.as_ref().map(|v| v.id),
     });
 
+    if let Some(timeout) = lobby_timeout {
+      self.schedule_lobby_timeout(ctx, game.id, timeout);
+    }
+
+    self.events.publish(ControllerEvent::GameCreated {
+      game_id: game.id,
+      created_by: game.created_by.id,
+    });
+
     self
       .players
       .players_replace_game(player_ids, game.clone(), mute_list_map)
@@ -90,3 +183,80 @@ impl Handler<CreateGameAsBot> for GameRegistry {
     Ok(game)
   }
 }
+
+#[test]
+fn test_idempotency_keys() {
+  let t = Instant::now();
+  let mut keys = IdempotencyKeys::default();
+  assert_eq!(keys.get(1, "a", t), None);
+
+  // the second request with the same key gets the first game
+  keys.insert(1, "a".to_string(), 100, t);
+  assert_eq!(keys.get(1, "a", t + Duration::from_secs(1)), Some(100));
+  assert_eq!(keys.get(1, "b", t), None);
+  assert_eq!(keys.get(2, "a", t), None);
+
+  assert_eq!(keys.get(1, "a", t + IDEMPOTENCY_KEY_TTL), None);
+  keys.insert(2, "a".to_string(), 101, t + IDEMPOTENCY_KEY_TTL);
+  assert_eq!(keys.map.len(), 1);
+  assert_eq!(keys.get(2, "a", t + IDEMPOTENCY_KEY_TTL), Some(101));
+}
+
+#[tokio::test]
+async fn test_create_game_publishes_event() {
+  use crate::db::test::{create_api_client, create_api_player};
+  use crate::event::ControllerEvent;
+  use crate::state::ControllerState;
+
+  dotenv::dotenv().ok();
+  if std::env::var("DATABASE_URL").is_err() {
+    eprintln!("DATABASE_URL not set, skipped");
+    return;
+  }
+
+  let state = ControllerState::init().await.unwrap();
+  let mut rx = state.events.subscribe();
+
+  // the game registry commits, use a name no other run has taken
+  let name = format!(
+    "ev{}",
+    chrono::Utc::now().timestamp_millis() % 1_000_000_000
+  );
+  let (api_client_id, player) = state
+    .db
+    .exec(move |conn| {
+      let api_client_id = create_api_client(conn, &name)?;
+      let player = create_api_player(conn, api_client_id, &name)?;
+      Ok::<_, Error>((api_client_id, player))
+    })
+    .await
+    .unwrap();
+
+  let game = state
+    .games
+    .send(CreateGame {
+      api_client_id,
+      params: crate::game::db::test_create_params(player.id, 2),
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+  let expected = ControllerEvent::GameCreated {
+    game_id: game.id,
+    created_by: player.id,
+  };
+  let event = tokio::time::timeout(Duration::from_secs(5), async {
+    loop {
+      match rx.recv().await.unwrap() {
+        event @ ControllerEvent::GameCreated { .. } => return event,
+        // expired games removed at startup
+        ControllerEvent::GameEnded { .. } => {}
+        other => panic!("unexpected event: {:?}", other),
+      }
+    }
+  })
+  .await
+  .unwrap();
+  assert_eq!(event, expected);
+}
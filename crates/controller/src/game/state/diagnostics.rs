@@ -0,0 +1,204 @@
+use crate::error::*;
+use crate::game::state::GameActor;
+use crate::game::{Game, GameStatus, SlotClientStatus};
+use chrono::{DateTime, Utc};
+use flo_net::packet::PacketTypeId;
+use flo_state::{async_trait, Context, Handler, Message};
+use flo_types::ping::PingStats;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+const HISTORY_LIMIT: usize = 32;
+
+/// Bounded in-memory log of game state transitions, kept for diagnostics
+#[derive(Debug, Default)]
+pub struct GameHistory {
+  status: VecDeque<HistoryEntry<GameStatus>>,
+  node: VecDeque<HistoryEntry<Option<i32>>>,
+  packets: HashMap<PacketTypeId, u64>,
+  reconnects: BTreeMap<i32, u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry<T> {
+  pub time: DateTime<Utc>,
+  pub value: T,
+}
+
+impl GameHistory {
+  pub fn record_status(&mut self, status: GameStatus) {
+    push_bounded(&mut self.status, status)
+  }
+
+  pub fn record_node(&mut self, node_id: Option<i32>) {
+    push_bounded(&mut self.node, node_id)
+  }
+
+  pub fn record_packet(&mut self, type_id: PacketTypeId) {
+    *self.packets.entry(type_id).or_default() += 1;
+  }
+
+  /// Counts a reconnect if the player comes back from the disconnected state
+  pub fn record_client_status(
+    &mut self,
+    player_id: i32,
+    prev: Option<SlotClientStatus>,
+    next: SlotClientStatus,
+  ) {
+    if prev == Some(SlotClientStatus::Disconnected)
+      && next != SlotClientStatus::Disconnected
+      && next.still_in_game()
+    {
+      *self.reconnects.entry(player_id).or_default() += 1;
+    }
+  }
+
+  fn packet_counts(&self) -> BTreeMap<String, u64> {
+    self
+      .packets
+      .iter()
+      .map(|(type_id, count)| (format!("{:?}", type_id), *count))
+      .collect()
+  }
+}
+
+fn push_bounded<T>(list: &mut VecDeque<HistoryEntry<T>>, value: T) {
+  if list.len() == HISTORY_LIMIT {
+    list.pop_front();
+  }
+  list.push_back(HistoryEntry {
+    time: Utc::now(),
+    value,
+  })
+}
+
+/// Runtime state of a game actor that isn't persisted in the database
+#[derive(Debug, Serialize)]
+pub struct GameRuntimeDiagnostics {
+  pub status: GameStatus,
+  pub started: bool,
+  pub host_player: i32,
+  pub players: Vec<i32>,
+  pub selected_node_id: Option<i32>,
+  pub player_client_status_map: BTreeMap<i32, SlotClientStatus>,
+  pub status_history: Vec<HistoryEntry<GameStatus>>,
+  pub node_history: Vec<HistoryEntry<Option<i32>>>,
+  pub packet_counts: BTreeMap<String, u64>,
+  pub player_reconnect_counts: BTreeMap<i32, u32>,
+}
+
+/// Everything operators need to attach to a bug report about a game
+#[derive(Debug, Serialize)]
+pub struct GameDiagnostics {
+  pub game: Game,
+  pub runtime: GameRuntimeDiagnostics,
+  pub ping: BTreeMap<i32, BTreeMap<i32, PingStats>>,
+}
+
+pub struct GetGameDiagnostics;
+
+impl Message for GetGameDiagnostics {
+  type Result = Result<GameRuntimeDiagnostics>;
+}
+
+#[async_trait]
+impl Handler<GetGameDiagnostics> for GameActor {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    _: GetGameDiagnostics,
+  ) -> Result<GameRuntimeDiagnostics> {
+    Ok(GameRuntimeDiagnostics {
+      status: self.status,
+      started: self.started(),
+      host_player: self.host_player,
+      players: self.players.clone(),
+      selected_node_id: self.selected_node_id,
+      player_client_status_map: self
+        .player_client_status_map
+        .iter()
+        .map(|(k, v)| (*k, *v))
+        .collect(),
+      status_history: self.history.status.iter().cloned().collect(),
+      node_history: self.history.node.iter().cloned().collect(),
+      packet_counts: self.history.packet_counts(),
+      player_reconnect_counts: self.history.reconnects.clone(),
+    })
+  }
+}
+
+#[test]
+fn test_game_history() {
+  let mut history = GameHistory::default();
+  for _ in 0..(HISTORY_LIMIT + 3) {
+    history.record_node(Some(1));
+  }
+  history.record_node(None);
+  assert_eq!(history.node.len(), HISTORY_LIMIT);
+  assert_eq!(history.node.back().unwrap().value, None);
+
+  history.record_status(GameStatus::Running);
+  history.record_packet(PacketTypeId::NodeGameStatusUpdate);
+  history.record_packet(PacketTypeId::NodeGameStatusUpdate);
+  history.record_packet(PacketTypeId::GameSelectNode);
+  assert_eq!(history.status.len(), 1);
+  assert_eq!(
+    history.packet_counts().get("NodeGameStatusUpdate"),
+    Some(&2)
+  );
+  assert_eq!(history.packet_counts().get("GameSelectNode"), Some(&1));
+}
+
+#[test]
+fn test_game_history_reconnects() {
+  use SlotClientStatus::*;
+  let mut history = GameHistory::default();
+  history.record_client_status(1, None, Connected);
+  history.record_client_status(1, Some(Connected), Disconnected);
+  history.record_client_status(1, Some(Disconnected), Loaded);
+  history.record_client_status(1, Some(Loaded), Disconnected);
+  history.record_client_status(1, Some(Disconnected), Connected);
+  history.record_client_status(2, Some(Disconnected), Left);
+  history.record_client_status(2, Some(Disconnected), Disconnected);
+  assert_eq!(history.reconnects.get(&1), Some(&2));
+  assert_eq!(history.reconnects.get(&2), None);
+}
+
+#[test]
+fn test_runtime_diagnostics_sections() {
+  let mut history = GameHistory::default();
+  history.record_status(GameStatus::Running);
+  history.record_node(Some(1));
+  history.record_packet(PacketTypeId::NodeGameStatusUpdate);
+
+  let runtime = GameRuntimeDiagnostics {
+    status: GameStatus::Running,
+    started: true,
+    host_player: 1,
+    players: vec![1, 2],
+    selected_node_id: Some(1),
+    player_client_status_map: vec![(1, SlotClientStatus::Loading)].into_iter().collect(),
+    status_history: history.status.iter().cloned().collect(),
+    node_history: history.node.iter().cloned().collect(),
+    packet_counts: history.packet_counts(),
+    player_reconnect_counts: history.reconnects.clone(),
+  };
+
+  let value = serde_json::to_value(&runtime).unwrap();
+  for section in &[
+    "status",
+    "player_client_status_map",
+    "status_history",
+    "node_history",
+    "packet_counts",
+    "player_reconnect_counts",
+  ] {
+    assert!(
+      value.get(section).is_some(),
+      "missing section `{}`",
+      section
+    );
+  }
+  assert_eq!(value["status_history"].as_array().unwrap().len(), 1);
+  assert_eq!(value["packet_counts"]["NodeGameStatusUpdate"], 1);
+}
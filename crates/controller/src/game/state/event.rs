@@ -0,0 +1,68 @@
+use crate::error::*;
+use crate::game::state::GameActor;
+use flo_net::packet::Frame;
+use flo_state::{async_trait, Context, Handler, Message};
+use tokio::sync::broadcast;
+
+const GAME_EVENT_CAPACITY: usize = 64;
+
+/// Fans out lobby frames dispatched by a game actor to `watch_game` subscribers.
+/// Subscribers are closed when the game actor is removed.
+#[derive(Debug)]
+pub struct GameEventBus {
+  tx: broadcast::Sender<Frame>,
+}
+
+impl Default for GameEventBus {
+  fn default() -> Self {
+    let (tx, _) = broadcast::channel(GAME_EVENT_CAPACITY);
+    Self { tx }
+  }
+}
+
+impl GameEventBus {
+  pub fn publish(&self, frame: &Frame) {
+    if self.tx.receiver_count() > 0 {
+      self.tx.send(frame.clone()).ok();
+    }
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<Frame> {
+    self.tx.subscribe()
+  }
+}
+
+pub struct SubscribeGameEvents;
+
+impl Message for SubscribeGameEvents {
+  type Result = Result<broadcast::Receiver<Frame>>;
+}
+
+#[async_trait]
+impl Handler<SubscribeGameEvents> for GameActor {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    _: SubscribeGameEvents,
+  ) -> Result<broadcast::Receiver<Frame>> {
+    Ok(self.events.subscribe())
+  }
+}
+
+#[test]
+fn test_game_event_bus() {
+  use broadcast::error::TryRecvError;
+  use flo_net::packet::PacketTypeId;
+
+  let bus = GameEventBus::default();
+  bus.publish(&Frame::new_empty(PacketTypeId::GameSelectNode));
+
+  let mut rx = bus.subscribe();
+  assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+
+  bus.publish(&Frame::new_empty(PacketTypeId::GameSlotUpdate));
+  assert_eq!(rx.try_recv().unwrap().type_id, PacketTypeId::GameSlotUpdate);
+
+  drop(bus);
+  assert!(matches!(rx.try_recv(), Err(TryRecvError::Closed)));
+}
@@ -0,0 +1,86 @@
+use crate::error::*;
+use crate::game::state::GameActor;
+use flo_state::{async_trait, Context, Handler, Message};
+
+pub struct TransferHost {
+  pub from_player_id: i32,
+  pub to_player_id: i32,
+}
+
+impl Message for TransferHost {
+  type Result = Result<()>;
+}
+
+#[async_trait]
+impl Handler<TransferHost> for GameActor {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    TransferHost {
+      from_player_id,
+      to_player_id,
+    }: TransferHost,
+  ) -> Result<()> {
+    check_transfer_host(
+      self.host_player,
+      &self.players,
+      from_player_id,
+      to_player_id,
+    )?;
+
+    let game_id = self.game_id;
+    self
+      .db
+      .exec(move |conn| crate::game::db::update_host(conn, game_id, from_player_id, to_player_id))
+      .await?;
+
+    self.host_player = to_player_id;
+    tracing::debug!(
+      game_id,
+      "host transferred: {} => {}",
+      from_player_id,
+      to_player_id
+    );
+
+    Ok(())
+  }
+}
+
+pub fn check_transfer_host(
+  host_player: i32,
+  players: &[i32],
+  from_player_id: i32,
+  to_player_id: i32,
+) -> Result<()> {
+  if from_player_id != host_player {
+    return Err(Error::PlayerNotHost);
+  }
+  if to_player_id == from_player_id || !players.contains(&to_player_id) {
+    return Err(Error::PlayerNotInGame);
+  }
+  Ok(())
+}
+
+#[test]
+fn test_check_transfer_host() {
+  let players = [1, 2, 3];
+  assert!(check_transfer_host(1, &players, 1, 2).is_ok());
+  assert!(matches!(
+    check_transfer_host(1, &players, 2, 3),
+    Err(Error::PlayerNotHost)
+  ));
+  assert!(matches!(
+    check_transfer_host(1, &players, 1, 4),
+    Err(Error::PlayerNotInGame)
+  ));
+  assert!(matches!(
+    check_transfer_host(1, &players, 1, 1),
+    Err(Error::PlayerNotInGame)
+  ));
+
+  // rejections reach the gRPC caller as permission denied
+  let status = tonic::Status::from(check_transfer_host(1, &players, 2, 3).unwrap_err());
+  assert_eq!(status.code(), tonic::Code::PermissionDenied);
+  let status = tonic::Status::from(check_transfer_host(1, &players, 1, 4).unwrap_err());
+  assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+}
@@ -5,11 +5,16 @@ use diesel::prelude::*;
 use flo_net::packet::FloPacket;
 use flo_net::proto;
 use flo_state::{async_trait, Context, Handler, Message};
+use futures::Future;
+use std::time::Duration;
+use tokio::time::sleep;
 
-use s2_grpc_utils::S2ProtoPack;
+use s2_grpc_utils::{S2ProtoEnum, S2ProtoPack};
 
 pub struct PlayerJoin {
   pub player_id: i32,
+  /// Seats the player in an observer slot
+  pub observer: bool,
 }
 
 impl Message for PlayerJoin {
@@ -21,14 +26,21 @@ impl Handler<PlayerJoin> for GameActor {
   async fn handle(
     &mut self,
     _: &mut Context<Self>,
-    PlayerJoin { player_id }: PlayerJoin,
+    PlayerJoin {
+      player_id,
+      observer,
+    }: PlayerJoin,
   ) -> Result<Game> {
     let game_id = self.game_id;
     let (game, mute_list) = self
       .db
       .exec(move |conn| {
         conn.transaction(|| {
-          crate::game::db::add_player(conn, game_id, player_id)?;
+          if observer {
+            crate::game::db::add_observer(conn, game_id, player_id)?;
+          } else {
+            crate::game::db::add_player(conn, game_id, player_id)?;
+          }
           let game = crate::game::db::get_full(conn, game_id)?;
           let mut mute_list_map =
             crate::player::db::get_mute_list_map(conn, &game.get_player_ids())?;
@@ -68,9 +80,209 @@ impl Handler<PlayerJoin> for GameActor {
         }
       }
       .encode_as_frame()?;
+      self.events.publish(&frame);
       self.player_reg.broadcast(players, frame).await?;
     }
 
     Ok(game)
   }
 }
+
+/// Retry policy for registering a player in the game registry after `PlayerJoin` succeeded
+#[derive(Debug, Clone)]
+pub struct AddGamePlayerRetry {
+  pub max_attempts: usize,
+  pub interval: Duration,
+}
+
+impl Default for AddGamePlayerRetry {
+  fn default() -> Self {
+    Self {
+      max_attempts: 3,
+      interval: Duration::from_millis(100),
+    }
+  }
+}
+
+/// Runs `register` until it succeeds or `retry.max_attempts` is reached, backing off linearly.
+/// If all attempts failed, `rollback` is called to undo the join so the player
+/// is not left half-joined.
+pub async fn register_joined_player<R, RF, B, BF>(
+  retry: &AddGamePlayerRetry,
+  mut register: R,
+  rollback: B,
+) -> Result<()>
+where
+  R: FnMut() -> RF,
+  RF: Future<Output = Result<()>>,
+  B: FnOnce() -> BF,
+  BF: Future<Output = Result<()>>,
+{
+  let mut attempt = 1;
+  loop {
+    match register().await {
+      Ok(()) => return Ok(()),
+      Err(err) if attempt < retry.max_attempts => {
+        tracing::warn!(attempt, "add game player: {}", err);
+        sleep(retry.interval * attempt as u32).await;
+        attempt += 1;
+      }
+      Err(err) => {
+        tracing::error!(attempt, "add game player: {}, rolling back", err);
+        if let Err(err) = rollback().await {
+          tracing::error!("roll back player join: {}", err);
+        }
+        return Err(err);
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, S2ProtoEnum)]
+#[repr(i32)]
+#[s2_grpc(proto_enum_type(flo_grpc::controller::JoinGameFailureReason))]
+pub enum JoinGameFailureReason {
+  Other = 0,
+  AlreadyInGame = 1,
+  GameFull = 2,
+  SourceAlreadyInGame = 3,
+  GameStarted = 4,
+}
+
+impl JoinGameFailureReason {
+  fn from_error(err: &Error) -> Self {
+    match *err {
+      Error::PlayerAlreadyInGame => JoinGameFailureReason::AlreadyInGame,
+      Error::GameFull => JoinGameFailureReason::GameFull,
+      Error::PlayerSourceAlreadyInGame => JoinGameFailureReason::SourceAlreadyInGame,
+      Error::GameStarted => JoinGameFailureReason::GameStarted,
+      _ => JoinGameFailureReason::Other,
+    }
+  }
+}
+
+#[derive(Debug, S2ProtoPack)]
+#[s2_grpc(message_type(flo_grpc::controller::JoinGameFailure))]
+pub struct JoinGameFailure {
+  pub player_id: i32,
+  #[s2_grpc(proto_enum)]
+  pub reason: JoinGameFailureReason,
+  pub message: String,
+}
+
+/// Runs `join` for each player in order, a failed join doesn't stop the remaining ones.
+/// Returns the failed joins.
+pub async fn join_each<J, JF>(player_ids: Vec<i32>, mut join: J) -> Vec<JoinGameFailure>
+where
+  J: FnMut(i32) -> JF,
+  JF: Future<Output = Result<()>>,
+{
+  let mut failures = vec![];
+  for player_id in player_ids {
+    if let Err(err) = join(player_id).await {
+      tracing::debug!(player_id, "batch join: {}", err);
+      failures.push(JoinGameFailure {
+        player_id,
+        reason: JoinGameFailureReason::from_error(&err),
+        message: err.to_string(),
+      });
+    }
+  }
+  failures
+}
+
+#[tokio::test]
+async fn test_register_joined_player() {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+
+  let retry = AddGamePlayerRetry {
+    max_attempts: 3,
+    interval: Duration::from_millis(1),
+  };
+
+  // succeeds after a transient failure, no rollback
+  let attempts = Arc::new(AtomicUsize::new(0));
+  let rolled_back = Arc::new(AtomicUsize::new(0));
+  register_joined_player(
+    &retry,
+    || {
+      let attempts = attempts.clone();
+      async move {
+        if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+          Err(Error::ActorNotFound)
+        } else {
+          Ok(())
+        }
+      }
+    },
+    || {
+      let rolled_back = rolled_back.clone();
+      async move {
+        rolled_back.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+      }
+    },
+  )
+  .await
+  .unwrap();
+  assert_eq!(attempts.load(Ordering::SeqCst), 2);
+  assert_eq!(rolled_back.load(Ordering::SeqCst), 0);
+
+  // keeps failing, the join is rolled back
+  let attempts = Arc::new(AtomicUsize::new(0));
+  let rolled_back = Arc::new(AtomicUsize::new(0));
+  let res = register_joined_player(
+    &retry,
+    || {
+      let attempts = attempts.clone();
+      async move {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        Err(Error::ActorNotFound)
+      }
+    },
+    || {
+      let rolled_back = rolled_back.clone();
+      async move {
+        rolled_back.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+      }
+    },
+  )
+  .await;
+  assert!(matches!(res, Err(Error::ActorNotFound)));
+  assert_eq!(attempts.load(Ordering::SeqCst), 3);
+  assert_eq!(rolled_back.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_join_each() {
+  let mut joined = vec![];
+  let failures = join_each(vec![1, 2, 3, 4, 1], |player_id| {
+    let res = match player_id {
+      2 => Err(Error::GameFull),
+      3 => Err(Error::ActorNotFound),
+      _ if joined.contains(&player_id) => Err(Error::PlayerAlreadyInGame),
+      _ => {
+        joined.push(player_id);
+        Ok(())
+      }
+    };
+    async move { res }
+  })
+  .await;
+
+  assert_eq!(joined, vec![1, 4]);
+  assert_eq!(
+    failures
+      .iter()
+      .map(|f| (f.player_id, f.reason))
+      .collect::<Vec<_>>(),
+    vec![
+      (2, JoinGameFailureReason::GameFull),
+      (3, JoinGameFailureReason::Other),
+      (1, JoinGameFailureReason::AlreadyInGame),
+    ]
+  );
+  assert_eq!(failures[0].message, Error::GameFull.to_string());
+}
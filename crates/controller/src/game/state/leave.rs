@@ -65,6 +65,57 @@ impl Handler<PlayerLeave> for GameActor {
   }
 }
 
+pub struct KickPlayer {
+  pub operator_player_id: i32,
+  pub target_player_id: i32,
+}
+
+impl Message for KickPlayer {
+  type Result = Result<PlayerLeaveResult>;
+}
+
+#[async_trait]
+impl Handler<KickPlayer> for GameActor {
+  async fn handle(
+    &mut self,
+    ctx: &mut Context<Self>,
+    KickPlayer {
+      operator_player_id,
+      target_player_id,
+    }: KickPlayer,
+  ) -> Result<PlayerLeaveResult> {
+    check_kick(
+      self.host_player,
+      &self.players,
+      operator_player_id,
+      target_player_id,
+    )?;
+    self
+      .handle(
+        ctx,
+        PlayerLeave {
+          player_id: target_player_id,
+        },
+      )
+      .await
+  }
+}
+
+fn check_kick(
+  host_player: i32,
+  players: &[i32],
+  operator_player_id: i32,
+  target_player_id: i32,
+) -> Result<()> {
+  if operator_player_id != host_player {
+    return Err(Error::PlayerNotHost);
+  }
+  if !players.contains(&target_player_id) {
+    return Err(Error::PlayerNotInGame);
+  }
+  Ok(())
+}
+
 #[tracing::instrument(skip(state))]
 async fn leave_game_lobby(
   state: &mut GameActor,
@@ -169,6 +220,7 @@ async fn leave_game_abort(
     pkt
   }
   .encode_as_frame()?;
+  state.events.publish(&frame);
   state
     .player_reg
     .broadcast(active_player_ids.clone(), frame)
@@ -214,6 +266,7 @@ async fn broadcast(
       reason: proto::flo_connect::PlayerLeaveReason::Left.into(),
     }
     .encode_as_frame()?;
+    state.events.publish(&frame_player_leave);
 
     for id in recipient_players {
       frame_map.insert(*id, frame_player_leave.clone().into());
@@ -223,3 +276,17 @@ async fn broadcast(
   }
   Ok(())
 }
+
+#[test]
+fn test_check_kick() {
+  let players = [1, 2, 3];
+  assert!(matches!(
+    check_kick(1, &players, 2, 3),
+    Err(Error::PlayerNotHost)
+  ));
+  assert!(matches!(
+    check_kick(1, &players, 1, 4),
+    Err(Error::PlayerNotInGame)
+  ));
+  assert!(check_kick(1, &players, 1, 3).is_ok());
+}
@@ -0,0 +1,249 @@
+use crate::error::*;
+use crate::game::state::leave::KickPlayer;
+use crate::game::state::registry::{Remove, RemoveGamePlayer};
+use crate::game::state::{GameActor, GameRegistry};
+use crate::game::GameStatus;
+use crate::player::state::sender::PlayerRegistryHandle;
+use flo_net::connect::Capabilities;
+use flo_net::packet::FloPacket;
+use flo_net::proto;
+use flo_state::{async_trait, Addr, Context, Handler, Message};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+pub const LOBBY_PING_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a player's ping has to stay above the limit before they are warned
+pub const HIGH_PING_WARN_AFTER: Duration = Duration::from_secs(10);
+/// How long a player's ping has to stay above the limit before they are kicked
+pub const HIGH_PING_KICK_AFTER: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default, PartialEq)]
+pub struct HighPingActions {
+  pub warn: Vec<i32>,
+  pub kick: Vec<i32>,
+}
+
+/// Tracks for how long each lobby player's ping to the selected node has been above `max_ping`
+#[derive(Debug)]
+pub struct HighPingTracker {
+  max_ping: u32,
+  since: HashMap<i32, Instant>,
+  warned: HashSet<i32>,
+}
+
+impl HighPingTracker {
+  pub fn new(max_ping: u32) -> Self {
+    HighPingTracker {
+      max_ping,
+      since: HashMap::new(),
+      warned: HashSet::new(),
+    }
+  }
+
+  /// `pings` is keyed by player id, players without a ping sample are never flagged.
+  /// The host is exempt.
+  pub fn update(
+    &mut self,
+    now: Instant,
+    host_player: i32,
+    pings: &BTreeMap<i32, Option<u32>>,
+  ) -> HighPingActions {
+    let mut actions = HighPingActions::default();
+    let max_ping = self.max_ping;
+    self.since.retain(|player_id, _| {
+      *player_id != host_player
+        && pings
+          .get(player_id)
+          .cloned()
+          .flatten()
+          .map(|ping| ping > max_ping)
+          .unwrap_or(false)
+    });
+    let since = &self.since;
+    self
+      .warned
+      .retain(|player_id| since.contains_key(player_id));
+
+    for (player_id, ping) in pings {
+      if *player_id == host_player || !ping.map(|ping| ping > max_ping).unwrap_or(false) {
+        continue;
+      }
+      let elapsed = now.saturating_duration_since(*self.since.entry(*player_id).or_insert(now));
+      if elapsed >= HIGH_PING_KICK_AFTER {
+        self.since.remove(player_id);
+        self.warned.remove(player_id);
+        actions.kick.push(*player_id);
+      } else if elapsed >= HIGH_PING_WARN_AFTER && self.warned.insert(*player_id) {
+        actions.warn.push(*player_id);
+      }
+    }
+
+    actions
+  }
+}
+
+pub struct LobbyPingInfo {
+  pub host_player: i32,
+  pub players: Vec<i32>,
+  pub node_id: Option<i32>,
+}
+
+/// `None` once the game left the lobby
+pub struct GetLobbyPingInfo;
+
+impl Message for GetLobbyPingInfo {
+  type Result = Option<LobbyPingInfo>;
+}
+
+#[async_trait]
+impl Handler<GetLobbyPingInfo> for GameActor {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    _: GetLobbyPingInfo,
+  ) -> <GetLobbyPingInfo as Message>::Result {
+    if self.status != GameStatus::Preparing || self.started() {
+      return None;
+    }
+    Some(LobbyPingInfo {
+      host_player: self.host_player,
+      players: self.players.clone(),
+      node_id: self.selected_node_id,
+    })
+  }
+}
+
+/// Kicks players whose ping to the selected node stays above `max_ping`,
+/// runs until the game starts or is removed
+pub async fn watch_lobby_ping(
+  game_id: i32,
+  max_ping: u32,
+  game: Addr<GameActor>,
+  registry: Addr<GameRegistry>,
+  players: PlayerRegistryHandle,
+) {
+  let mut tracker = HighPingTracker::new(max_ping);
+  loop {
+    sleep(LOBBY_PING_CHECK_INTERVAL).await;
+    match check_lobby_ping(game_id, &mut tracker, &game, &registry, &players).await {
+      Ok(true) => {}
+      Ok(false) => break,
+      Err(err) => {
+        tracing::error!(game_id, "lobby ping check: {}", err);
+        break;
+      }
+    }
+  }
+}
+
+async fn check_lobby_ping(
+  game_id: i32,
+  tracker: &mut HighPingTracker,
+  game: &Addr<GameActor>,
+  registry: &Addr<GameRegistry>,
+  players: &PlayerRegistryHandle,
+) -> Result<bool> {
+  let info = match game.send(GetLobbyPingInfo).await {
+    Ok(Some(info)) => info,
+    // started or removed
+    Ok(None) | Err(_) => return Ok(false),
+  };
+  let node_id = match info.node_id {
+    Some(node_id) => node_id,
+    None => return Ok(true),
+  };
+
+  let snapshot = players.players_ping_snapshot(info.players.clone()).await?;
+  let pings: BTreeMap<i32, Option<u32>> = info
+    .players
+    .iter()
+    .map(|player_id| {
+      let ping = snapshot
+        .map
+        .get(player_id)
+        .and_then(|map| map.get(&node_id))
+        .and_then(|stats| stats.avg.or(stats.current));
+      (*player_id, ping)
+    })
+    .collect();
+
+  let actions = tracker.update(Instant::now(), info.host_player, &pings);
+
+  for player_id in actions.warn {
+    let frame = proto::flo_connect::PacketGameLobbyMessage {
+      game_id,
+      message: format!(
+        "Your ping to the node is above {} ms, you will be kicked if it stays that high.",
+        tracker.max_ping
+      ),
+    }
+    .encode_as_frame()?;
+    players
+      .send_if_supported(player_id, Capabilities::LOBBY_MESSAGE, frame)
+      .await?;
+  }
+
+  for player_id in actions.kick {
+    tracing::info!(game_id, player_id, "kicked: high ping");
+    let res = game
+      .send(KickPlayer {
+        operator_player_id: info.host_player,
+        target_player_id: player_id,
+      })
+      .await??;
+    if res.game_ended {
+      registry.send(Remove { game_id }).await?;
+      return Ok(false);
+    }
+    registry
+      .send(RemoveGamePlayer { game_id, player_id })
+      .await?;
+  }
+
+  Ok(true)
+}
+
+#[test]
+fn test_high_ping_tracker() {
+  let t = Instant::now();
+  let mut tracker = HighPingTracker::new(150);
+  let pings: BTreeMap<i32, Option<u32>> =
+    vec![(1, Some(300)), (2, Some(250)), (3, Some(40)), (4, None)]
+      .into_iter()
+      .collect();
+
+  // the host (1) is exempt, the stubbed high ping player (2) is flagged
+  assert_eq!(tracker.update(t, 1, &pings), HighPingActions::default());
+  assert_eq!(
+    tracker.update(t + HIGH_PING_WARN_AFTER, 1, &pings),
+    HighPingActions {
+      warn: vec![2],
+      kick: vec![],
+    }
+  );
+  // warned once
+  assert_eq!(
+    tracker.update(t + HIGH_PING_WARN_AFTER, 1, &pings),
+    HighPingActions::default()
+  );
+  assert_eq!(
+    tracker.update(t + HIGH_PING_KICK_AFTER, 1, &pings),
+    HighPingActions {
+      warn: vec![],
+      kick: vec![2],
+    }
+  );
+
+  // the window restarts once the ping recovers
+  let mut tracker = HighPingTracker::new(150);
+  let mut pings = pings;
+  tracker.update(t, 1, &pings);
+  pings.insert(2, Some(100));
+  tracker.update(t + HIGH_PING_WARN_AFTER, 1, &pings);
+  pings.insert(2, Some(250));
+  assert_eq!(
+    tracker.update(t + HIGH_PING_KICK_AFTER, 1, &pings),
+    HighPingActions::default()
+  );
+}
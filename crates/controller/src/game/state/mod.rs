@@ -1,10 +1,15 @@
 pub mod cancel;
 pub mod create;
+pub mod diagnostics;
+pub mod event;
+pub mod host;
 pub mod join;
 pub mod leave;
+pub mod lobby_ping;
 pub mod node;
 pub mod player;
 pub mod registry;
+pub mod rejoin;
 pub mod slot;
 pub mod start;
 pub mod status;
@@ -12,17 +17,22 @@ pub mod status;
 pub use status::{GameSlotClientStatusUpdate, GameStatusUpdate};
 
 use crate::error::*;
+use crate::event::ControllerEventBus;
 use crate::game::db::{get_all_active_game_state, get_expired_games};
 use crate::game::{GameStatus, SlotClientStatus};
 use crate::node::{NodeRegistry, PlayerToken};
 use crate::player::state::sender::PlayerRegistryHandle;
 
-use crate::game::state::cancel::CancelGame;
+use crate::game::state::cancel::{CancelGame, CancelReason};
+use crate::game::state::diagnostics::GameHistory;
+use crate::game::state::event::GameEventBus;
 use crate::game::state::registry::Remove;
 use crate::player::state::PlayerRegistry;
 use crate::state::{Data, GetActorEntry};
 use bs_diesel_utils::ExecutorRef;
+use create::IdempotencyKeys;
 use flo_state::*;
+use node::NodeFailover;
 use start::StartGameState;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
@@ -39,6 +49,8 @@ pub struct GameRegistry {
   player_games_map: BTreeMap<i32, Vec<i32>>,
   game_players_map: BTreeMap<i32, Vec<i32>>,
   game_node_map: BTreeMap<i32, i32>,
+  idempotency_keys: IdempotencyKeys,
+  events: ControllerEventBus,
 }
 
 impl GameRegistry {
@@ -46,6 +58,7 @@ impl GameRegistry {
     db: ExecutorRef,
     player_packet_sender: PlayerRegistryHandle,
     nodes: Addr<NodeRegistry>,
+    events: ControllerEventBus,
   ) -> Result<GameRegistry> {
     let games = db.exec(|conn| get_all_active_game_state(conn)).await?;
     let mut map = BTreeMap::new();
@@ -87,6 +100,9 @@ impl GameRegistry {
           start_state: None,
           player_tokens,
           player_client_status_map: Default::default(),
+          history: GameHistory::default(),
+          events: GameEventBus::default(),
+          node_failover: NodeFailover::default(),
         }),
       );
     }
@@ -99,6 +115,8 @@ impl GameRegistry {
       player_games_map,
       game_players_map,
       game_node_map,
+      idempotency_keys: IdempotencyKeys::default(),
+      events,
     };
 
     Ok(state)
@@ -110,7 +128,11 @@ impl GameRegistry {
     let mut cancelled = vec![];
     for id in ids {
       if let Some(c) = self.map.get_mut(&id) {
-        if let Err(err) = c.send(CancelGame { player_id: None }).await {
+        let cancel = CancelGame {
+          player_id: None,
+          reason: CancelReason::Cancelled,
+        };
+        if let Err(err) = c.send(cancel).await {
           tracing::error!(game_id = id, "cancel expired game: {}", err);
         } else {
           cancelled.push(id)
@@ -137,6 +159,9 @@ impl GameRegistry {
 impl Actor for GameRegistry {
   async fn started(&mut self, ctx: &mut Context<Self>) {
     self.handle(ctx, RemoveExpiredGames).await;
+    if let Err(err) = self.restore_lobby_timeouts(ctx).await {
+      tracing::error!("restore lobby timeouts: {}", err);
+    }
   }
 }
 
@@ -147,7 +172,8 @@ impl Service<Data> for GameRegistry {
   async fn create(registry: &mut RegistryRef<Data>) -> Result<Self, Self::Error> {
     let players = registry.resolve::<PlayerRegistry>().await?;
     let nodes = registry.resolve::<NodeRegistry>().await?;
-    Self::init(registry.data().db.clone(), players.into(), nodes).await
+    let data = registry.data();
+    Self::init(data.db.clone(), players.into(), nodes, data.events.clone()).await
   }
 }
 
@@ -198,6 +224,9 @@ pub struct GameActor {
   pub start_state: Option<Owner<StartGameState>>,
   pub player_tokens: HashMap<i32, [u8; 16]>,
   pub player_client_status_map: HashMap<i32, SlotClientStatus>,
+  pub history: GameHistory,
+  pub events: GameEventBus,
+  pub node_failover: NodeFailover,
 }
 
 impl Actor for GameActor {}
@@ -1,13 +1,21 @@
 use crate::error::*;
 use crate::game::state::GameActor;
+use crate::game::GameStatus;
+use crate::node::messages::{GetNodeHealth, ReserveNodeGame, SetNodeGame};
+use crate::node::Node;
 
+use chrono::Utc;
 use flo_net::packet::FloPacket;
 use flo_net::proto;
 use flo_state::{async_trait, Context, Handler, Message};
+use flo_types::ping::PingStats;
+use std::collections::{BTreeMap, VecDeque};
 
 pub struct SelectNode {
   pub node_id: Option<i32>,
   pub player_id: i32,
+  /// Nodes to fail over to, in order, if the selected node goes down before the game starts
+  pub preferred_node_ids: Vec<i32>,
 }
 
 impl Message for SelectNode {
@@ -19,22 +27,55 @@ impl Handler<SelectNode> for GameActor {
   async fn handle(
     &mut self,
     _: &mut Context<Self>,
-    SelectNode { node_id, player_id }: SelectNode,
+    SelectNode {
+      node_id,
+      player_id,
+      preferred_node_ids,
+    }: SelectNode,
   ) -> Result<()> {
+    self.select_node(node_id, player_id).await?;
+    self.node_failover = NodeFailover::new(node_id, preferred_node_ids);
+    Ok(())
+  }
+}
+
+impl GameActor {
+  async fn select_node(&mut self, node_id: Option<i32>, player_id: i32) -> Result<()> {
     let game_id = self.game_id;
 
     if self.started() {
       return Err(Error::GameStarted);
     }
 
-    self
+    // checked and assigned by the node registry in one step,
+    // so concurrent selections can't oversubscribe a node
+    let prev_node_id = self
+      .nodes
+      .send(ReserveNodeGame { game_id, node_id })
+      .await??;
+
+    if let Err(err) = self
       .db
       .exec(move |conn| crate::game::db::select_node(conn, game_id, player_id, node_id))
-      .await?;
+      .await
+    {
+      self
+        .nodes
+        .notify(SetNodeGame {
+          game_id,
+          node_id: prev_node_id,
+        })
+        .await
+        .ok();
+      return Err(err.into());
+    }
 
     self.selected_node_id = node_id;
+    self.history.record_node(node_id);
 
     let frame = proto::flo_connect::PacketGameSelectNode { game_id, node_id }.encode_as_frame()?;
+    self.history.record_packet(frame.type_id);
+    self.events.publish(&frame);
     self
       .player_reg
       .broadcast(self.players.clone(), frame)
@@ -43,3 +84,249 @@ impl Handler<SelectNode> for GameActor {
     Ok(())
   }
 }
+
+/// Remaining nodes a game can fail over to
+#[derive(Debug, Default)]
+pub struct NodeFailover {
+  node_ids: VecDeque<i32>,
+}
+
+impl NodeFailover {
+  pub fn new(selected_node_id: Option<i32>, preferred_node_ids: Vec<i32>) -> Self {
+    let mut node_ids = VecDeque::with_capacity(preferred_node_ids.len());
+    for node_id in preferred_node_ids {
+      if Some(node_id) != selected_node_id && !node_ids.contains(&node_id) {
+        node_ids.push_back(node_id);
+      }
+    }
+    NodeFailover { node_ids }
+  }
+
+  /// Takes the next reachable node, unreachable nodes are skipped and dropped
+  pub fn next<F: Fn(i32) -> bool>(&mut self, is_reachable: F) -> Option<i32> {
+    while let Some(node_id) = self.node_ids.pop_front() {
+      if is_reachable(node_id) {
+        return Some(node_id);
+      }
+    }
+    None
+  }
+}
+
+/// Sent by the game registry when the controller lost the connection to a node
+pub struct NodeConnectionLost {
+  pub node_id: i32,
+}
+
+impl Message for NodeConnectionLost {
+  /// The node the game moved to
+  type Result = Result<Option<i32>>;
+}
+
+#[async_trait]
+impl Handler<NodeConnectionLost> for GameActor {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    NodeConnectionLost { node_id }: NodeConnectionLost,
+  ) -> <NodeConnectionLost as Message>::Result {
+    // only before the game starts loading
+    if self.selected_node_id != Some(node_id)
+      || self.status != GameStatus::Preparing
+      || self.started()
+    {
+      return Ok(None);
+    }
+
+    let now = Utc::now();
+    let healthy: Vec<i32> = self
+      .nodes
+      .send(GetNodeHealth)
+      .await?
+      .into_iter()
+      .filter(|health| health.is_healthy(now))
+      .map(|health| health.node_id)
+      .collect();
+
+    while let Some(next_node_id) = self.node_failover.next(|id| healthy.contains(&id)) {
+      match self.select_node(Some(next_node_id), self.host_player).await {
+        Ok(()) => {
+          tracing::info!(
+            game_id = self.game_id,
+            node_id,
+            next_node_id,
+            "node connection lost: failed over"
+          );
+          return Ok(Some(next_node_id));
+        }
+        Err(err) => {
+          tracing::warn!(
+            game_id = self.game_id,
+            next_node_id,
+            "node failover: {}",
+            err
+          );
+        }
+      }
+    }
+
+    Ok(None)
+  }
+}
+
+/// Picks the node that minimizes the highest ping among the players.
+///
+/// `ping_map` is keyed by player id, then node id. Nodes without ping data from any
+/// player are skipped, ties are broken by the lower node id.
+pub fn pick_lowest_ping_node(
+  node_ids: &[i32],
+  ping_map: &BTreeMap<i32, BTreeMap<i32, PingStats>>,
+) -> Option<i32> {
+  let mut best: Option<(u32, i32)> = None;
+  for node_id in node_ids {
+    let max_ping = ping_map
+      .values()
+      .filter_map(|map| map.get(node_id))
+      .filter_map(|stats| stats.avg.or(stats.current))
+      .max();
+    if let Some(max_ping) = max_ping {
+      let candidate = (max_ping, *node_id);
+      if best.map(|best| candidate < best).unwrap_or(true) {
+        best = Some(candidate);
+      }
+    }
+  }
+  best.map(|(_, node_id)| node_id)
+}
+
+/// Ids of the nodes in `region`, all nodes if `region` is not set or has no nodes
+pub fn region_node_ids(nodes: &[Node], region: Option<&str>) -> Vec<i32> {
+  let all = || nodes.iter().map(|node| node.id).collect();
+  let region = match region {
+    Some(region) => region,
+    None => return all(),
+  };
+  let ids: Vec<i32> = nodes
+    .iter()
+    .filter(|node| node.country_id.eq_ignore_ascii_case(region))
+    .map(|node| node.id)
+    .collect();
+  if ids.is_empty() {
+    tracing::warn!(region, "no node found in region");
+    all()
+  } else {
+    ids
+  }
+}
+
+/// Node of a game created by an API client.
+///
+/// A node specified by the request always wins, otherwise the first node in the
+/// default region of the client is used. `0` means no node was specified.
+pub fn resolve_api_game_node(
+  requested_node_id: i32,
+  nodes: &[Node],
+  default_region: Option<&str>,
+) -> Option<i32> {
+  if requested_node_id != 0 {
+    return Some(requested_node_id);
+  }
+  if default_region.is_none() {
+    return None;
+  }
+  region_node_ids(nodes, default_region).first().cloned()
+}
+
+#[test]
+fn test_pick_lowest_ping_node() {
+  fn stats(avg: u32) -> PingStats {
+    PingStats {
+      avg: Some(avg),
+      ..Default::default()
+    }
+  }
+
+  let mut ping_map = BTreeMap::new();
+  ping_map.insert(
+    1,
+    vec![(10, stats(30)), (20, stats(80)), (30, stats(50))]
+      .into_iter()
+      .collect(),
+  );
+  ping_map.insert(
+    2,
+    vec![(10, stats(120)), (20, stats(90)), (30, stats(50))]
+      .into_iter()
+      .collect(),
+  );
+
+  // node 30 has the lowest max ping
+  assert_eq!(pick_lowest_ping_node(&[10, 20, 30], &ping_map), Some(30));
+  // ties are broken by node id
+  ping_map.get_mut(&1).unwrap().insert(40, stats(50));
+  ping_map.get_mut(&2).unwrap().insert(40, stats(40));
+  assert_eq!(pick_lowest_ping_node(&[40, 30], &ping_map), Some(30));
+  // nodes without ping data are skipped
+  assert_eq!(pick_lowest_ping_node(&[50, 20], &ping_map), Some(20));
+  assert_eq!(pick_lowest_ping_node(&[50], &ping_map), None);
+  assert_eq!(pick_lowest_ping_node(&[], &ping_map), None);
+}
+
+#[test]
+fn test_resolve_api_game_node_by_region() {
+  use chrono::Utc;
+
+  fn node(id: i32, country_id: &str) -> Node {
+    Node {
+      id,
+      name: format!("node-{}", id),
+      location: String::new(),
+      secret: String::new(),
+      ip_addr: String::new(),
+      created_at: Utc::now(),
+      updated_at: Utc::now(),
+      country_id: country_id.to_string(),
+      disabled: false,
+      max_games: None,
+    }
+  }
+
+  let nodes = vec![node(1, "US"), node(2, "DE"), node(3, "DE"), node(4, "US")];
+  let regions: BTreeMap<i32, &str> = vec![(10, "de"), (20, "US")].into_iter().collect();
+  let place = |api_client_id: i32, requested_node_id: i32| {
+    resolve_api_game_node(
+      requested_node_id,
+      &nodes,
+      regions.get(&api_client_id).cloned(),
+    )
+  };
+
+  // clients with different default regions
+  assert_eq!(place(10, 0), Some(2));
+  assert_eq!(place(20, 0), Some(1));
+  // the request overrides the default region
+  assert_eq!(place(10, 4), Some(4));
+  // no default region, no node
+  assert_eq!(place(30, 0), None);
+
+  assert_eq!(region_node_ids(&nodes, Some("DE")), vec![2, 3]);
+  assert_eq!(region_node_ids(&nodes, Some("FR")), vec![1, 2, 3, 4]);
+  assert_eq!(region_node_ids(&nodes, None), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn test_node_failover() {
+  // the first node drops, the game moves to the second
+  let mut failover = NodeFailover::new(Some(1), vec![1, 2, 3]);
+  assert_eq!(failover.next(|_| true), Some(2));
+  assert_eq!(failover.next(|_| true), Some(3));
+  assert_eq!(failover.next(|_| true), None);
+
+  // unreachable nodes are skipped
+  let mut failover = NodeFailover::new(Some(1), vec![2, 3, 2, 4]);
+  assert_eq!(failover.next(|id| id != 2), Some(3));
+  assert_eq!(failover.next(|_| true), Some(4));
+  assert_eq!(failover.next(|_| true), None);
+
+  assert_eq!(NodeFailover::default().next(|_| true), None);
+}
@@ -1,8 +1,12 @@
 use crate::error::*;
+use crate::event::ControllerEvent;
+use crate::game::state::node::NodeConnectionLost;
 use crate::game::state::{GameActor, GameRegistry};
 use crate::game::GameStatus;
+use crate::node::messages::SetNodeGame;
 use flo_state::{async_trait, Context, Handler, Message, Owner};
 use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
 
 #[derive(Debug)]
 pub struct Register {
@@ -52,6 +56,9 @@ impl GameRegistry {
         start_state: None,
         player_tokens: Default::default(),
         player_client_status_map: Default::default(),
+        history: Default::default(),
+        events: Default::default(),
+        node_failover: Default::default(),
       }),
     );
   }
@@ -72,6 +79,17 @@ impl Handler<Remove> for GameRegistry {
     if let Some(owner) = self.map.remove(&id) {
       self.game_players_map.remove(&id);
       self.game_node_map.remove(&id);
+      self
+        .nodes
+        .notify(SetNodeGame {
+          game_id: id,
+          node_id: None,
+        })
+        .await
+        .ok();
+      self
+        .events
+        .publish(ControllerEvent::GameEnded { game_id: id });
 
       let addr = ctx.addr();
       ctx.spawn(async move {
@@ -165,6 +183,144 @@ impl Handler<UpdateGameNodeCache> for GameRegistry {
   }
 }
 
+/// Sent by a node connection when it was lost, moves the games on the node
+/// that haven't started to the next node of their preference list
+pub struct NodeDisconnected {
+  pub node_id: i32,
+}
+
+impl Message for NodeDisconnected {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<NodeDisconnected> for GameRegistry {
+  async fn handle(
+    &mut self,
+    ctx: &mut Context<Self>,
+    NodeDisconnected { node_id }: NodeDisconnected,
+  ) {
+    let games: Vec<_> = self
+      .game_node_map
+      .iter()
+      .filter(|(_, id)| **id == node_id)
+      .filter_map(|(game_id, _)| self.map.get(game_id).map(|game| (*game_id, game.addr())))
+      .collect();
+
+    let addr = ctx.addr();
+    for (game_id, game) in games {
+      let addr = addr.clone();
+      ctx.spawn(async move {
+        match game.send(NodeConnectionLost { node_id }).await {
+          Ok(Ok(Some(next_node_id))) => {
+            addr
+              .notify(UpdateGameNodeCache {
+                game_id,
+                node_id: Some(next_node_id),
+              })
+              .await
+              .ok();
+          }
+          Ok(Ok(None)) => {}
+          Ok(Err(err)) => {
+            tracing::error!(game_id, node_id, "node failover: {}", err);
+          }
+          Err(err) => {
+            tracing::error!(game_id, node_id, "node failover: {}", err);
+          }
+        }
+      });
+    }
+  }
+}
+
+/// Games selected the node, with their number of players
+pub struct GetNodeGames {
+  pub node_id: i32,
+}
+
+impl Message for GetNodeGames {
+  type Result = Vec<(i32, i32)>;
+}
+
+#[async_trait]
+impl Handler<GetNodeGames> for GameRegistry {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    GetNodeGames { node_id }: GetNodeGames,
+  ) -> Vec<(i32, i32)> {
+    node_games(&self.game_node_map, &self.game_players_map, node_id)
+  }
+}
+
+fn node_games(
+  game_node_map: &BTreeMap<i32, i32>,
+  game_players_map: &BTreeMap<i32, Vec<i32>>,
+  node_id: i32,
+) -> Vec<(i32, i32)> {
+  game_node_map
+    .iter()
+    .filter(|(_, v)| **v == node_id)
+    .map(|(game_id, _)| {
+      let num_players = game_players_map
+        .get(game_id)
+        .map(|players| players.len() as i32)
+        .unwrap_or(0);
+      (*game_id, num_players)
+    })
+    .collect()
+}
+
+/// Number of players of each game in `game_ids`, games not in the registry are skipped
+pub struct GetGamesPlayerCount {
+  pub game_ids: Vec<i32>,
+}
+
+impl Message for GetGamesPlayerCount {
+  type Result = Vec<(i32, i32)>;
+}
+
+#[async_trait]
+impl Handler<GetGamesPlayerCount> for GameRegistry {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    GetGamesPlayerCount { game_ids }: GetGamesPlayerCount,
+  ) -> Vec<(i32, i32)> {
+    let map = &self.map;
+    games_player_count(
+      &game_ids,
+      |game_id| map.contains_key(&game_id),
+      &self.game_players_map,
+    )
+  }
+}
+
+fn games_player_count<F>(
+  game_ids: &[i32],
+  is_registered: F,
+  game_players_map: &BTreeMap<i32, Vec<i32>>,
+) -> Vec<(i32, i32)>
+where
+  F: Fn(i32) -> bool,
+{
+  let mut game_ids = game_ids.to_vec();
+  game_ids.sort();
+  game_ids.dedup();
+  game_ids
+    .into_iter()
+    .filter(|game_id| is_registered(*game_id))
+    .map(|game_id| {
+      let num_players = game_players_map
+        .get(&game_id)
+        .map(|players| players.len() as i32)
+        .unwrap_or(0);
+      (game_id, num_players)
+    })
+    .collect()
+}
+
 pub struct ResolveGamePlayerPingBroadcastTargets {
   pub player_id: i32,
   pub node_ids: Vec<i32>,
@@ -245,3 +401,45 @@ impl GameRegistry {
     }
   }
 }
+
+#[test]
+fn test_node_games() {
+  let game_node_map = vec![(1, 10), (2, 20)].into_iter().collect();
+  let game_players_map = vec![(1, vec![100, 101]), (2, vec![200])]
+    .into_iter()
+    .collect();
+  assert_eq!(
+    node_games(&game_node_map, &game_players_map, 10),
+    vec![(1, 2)]
+  );
+  assert_eq!(
+    node_games(&game_node_map, &game_players_map, 20),
+    vec![(2, 1)]
+  );
+  assert_eq!(node_games(&game_node_map, &game_players_map, 30), vec![]);
+}
+
+#[test]
+fn test_games_player_count() {
+  let game_players_map = vec![(1, vec![100, 101]), (2, vec![200])]
+    .into_iter()
+    .collect();
+  // game 3 has no players left, 4 is unknown
+  let registered = [1, 2, 3];
+  assert_eq!(
+    games_player_count(
+      &[4, 2, 1, 3, 2],
+      |game_id| registered.contains(&game_id),
+      &game_players_map
+    ),
+    vec![(1, 2), (2, 1), (3, 0)]
+  );
+  assert_eq!(
+    games_player_count(
+      &[4],
+      |game_id| registered.contains(&game_id),
+      &game_players_map
+    ),
+    vec![]
+  );
+}
@@ -0,0 +1,86 @@
+use crate::error::*;
+use crate::game::state::GameActor;
+use crate::game::GameStatus;
+use crate::node::messages::NodeRenewPlayerToken;
+use crate::state::ActorMapExt;
+use flo_state::{async_trait, Context, Handler, Message};
+use std::collections::HashMap;
+
+/// Issues a new node token to a player that dropped from a running game,
+/// the node keeps the slot reserved for it until the game ends and the
+/// previous token stops working
+pub struct RejoinGame {
+  pub player_id: i32,
+}
+
+impl Message for RejoinGame {
+  type Result = Result<[u8; 16]>;
+}
+
+#[async_trait]
+impl Handler<RejoinGame> for GameActor {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    RejoinGame { player_id }: RejoinGame,
+  ) -> Result<[u8; 16]> {
+    check_rejoin(self.status, &self.player_tokens, player_id)?;
+    let game_id = self.game_id;
+    let node_id = self
+      .selected_node_id
+      .ok_or_else(|| Error::GameNodeNotSelected)?;
+
+    let token = self
+      .nodes
+      .send_to(node_id, NodeRenewPlayerToken { game_id, player_id })
+      .await?
+      .await
+      .or_cancelled()?;
+    let bytes = token.bytes;
+    self
+      .db
+      .exec(move |conn| crate::game::db::update_node_token(conn, game_id, &token))
+      .await?;
+    self.player_tokens.insert(player_id, bytes);
+
+    tracing::debug!(game_id, player_id, "rejoin");
+    Ok(bytes)
+  }
+}
+
+pub fn check_rejoin(
+  status: GameStatus,
+  player_tokens: &HashMap<i32, [u8; 16]>,
+  player_id: i32,
+) -> Result<()> {
+  match status {
+    GameStatus::Created | GameStatus::Running => {}
+    _ => return Err(Error::GameNotRejoinable),
+  }
+  if !player_tokens.contains_key(&player_id) {
+    return Err(Error::PlayerNotInGame);
+  }
+  Ok(())
+}
+
+#[test]
+fn test_check_rejoin() {
+  let tokens: HashMap<i32, [u8; 16]> = vec![(1, [1; 16]), (2, [2; 16])].into_iter().collect();
+
+  check_rejoin(GameStatus::Running, &tokens, 1).unwrap();
+  check_rejoin(GameStatus::Created, &tokens, 2).unwrap();
+  assert!(matches!(
+    check_rejoin(GameStatus::Running, &tokens, 3),
+    Err(Error::PlayerNotInGame)
+  ));
+  for status in &[
+    GameStatus::Preparing,
+    GameStatus::Ended,
+    GameStatus::Terminated,
+  ] {
+    assert!(matches!(
+      check_rejoin(*status, &tokens, 1),
+      Err(Error::GameNotRejoinable)
+    ));
+  }
+}
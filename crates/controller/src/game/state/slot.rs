@@ -59,6 +59,7 @@ impl Handler<UpdateSlot> for GameActor {
         player: slot.player.clone().map(|p| p.pack()).transpose()?,
       }
       .encode_as_frame()?;
+      self.events.publish(&frame);
       frames_slot_update.push(frame);
     }
 
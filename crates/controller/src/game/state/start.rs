@@ -1,13 +1,15 @@
 use crate::error::*;
 use crate::game::state::GameActor;
 use crate::game::{GameStatus, SlotClientStatus};
+use crate::map::MapSha1;
 use crate::node::messages::NodeCreateGame;
 use crate::player::state::sender::PlayerFrames;
 use crate::state::ActorMapExt;
 use flo_net::packet::FloPacket;
 use flo_net::proto;
 use flo_state::{async_trait, Actor, Addr, Context, Handler, Message};
-use std::collections::HashMap;
+use s2_grpc_utils::{S2ProtoEnum, S2ProtoUnpack};
+use std::collections::{BTreeMap, HashMap};
 use std::time::Duration;
 use tokio::sync::oneshot;
 
@@ -221,6 +223,7 @@ impl GameActor {
       .exec(move |conn| crate::game::db::update_created(conn, game_id, agreed_version, token_map))
       .await?;
     self.status = GameStatus::Created;
+    self.history.record_status(self.status);
 
     Ok(Ok(()))
   }
@@ -586,3 +589,165 @@ impl Handler<StartGameCheckAsBot> for GameActor {
     Ok(())
   }
 }
+
+/// Why a player's start game check ack blocked the start
+#[derive(Debug, Clone, Copy, PartialEq, S2ProtoEnum)]
+#[repr(i32)]
+#[s2_grpc(proto_enum_type = "flo_grpc::controller::StartGamePlayerAckReason")]
+pub enum StartGamePlayerAckReason {
+  Ok = 0,
+  NotReady = 1,
+  War3VersionMismatch = 2,
+  MapMismatch = 3,
+}
+
+/// Result of checking a player's start game check ack
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerAckCheck {
+  pub reason: StartGamePlayerAckReason,
+  /// Only set on `MapMismatch`: the sha1 reported by most players, in hex
+  pub expected_map_sha1: Option<String>,
+  /// Only set on `MapMismatch`: the sha1 reported by this player, in hex
+  pub actual_map_sha1: Option<String>,
+}
+
+/// Compares each ack with the version and map reported by most players
+pub fn get_player_ack_reasons(
+  map: &HashMap<i32, proto::flo_connect::PacketGameStartPlayerClientInfoRequest>,
+) -> HashMap<i32, StartGamePlayerAckReason> {
+  get_player_ack_checks(map)
+    .into_iter()
+    .map(|(player_id, check)| (player_id, check.reason))
+    .collect()
+}
+
+/// Like `get_player_ack_reasons`, with both map sha1s attached to map mismatches
+pub fn get_player_ack_checks(
+  map: &HashMap<i32, proto::flo_connect::PacketGameStartPlayerClientInfoRequest>,
+) -> HashMap<i32, PlayerAckCheck> {
+  fn most_common<'a, I: Iterator<Item = &'a [u8]>>(values: I) -> Option<&'a [u8]> {
+    let mut counts = BTreeMap::new();
+    for value in values.filter(|v| !v.is_empty()) {
+      *counts.entry(value).or_insert(0) += 1;
+    }
+    let max = counts.values().max().cloned()?;
+    counts
+      .into_iter()
+      .find(|(_, count)| *count == max)
+      .map(|(value, _)| value)
+  }
+
+  fn sha1_hex(bytes: &[u8]) -> Option<String> {
+    MapSha1::unpack(bytes.to_vec())
+      .ok()
+      .map(|sha1| sha1.to_hex())
+  }
+
+  let expected_version = most_common(map.values().map(|ack| ack.war3_version.as_bytes()));
+  let expected_sha1 = most_common(map.values().map(|ack| &ack.map_sha1 as &[u8]));
+
+  map
+    .iter()
+    .map(|(player_id, ack)| {
+      let mut check = PlayerAckCheck {
+        reason: StartGamePlayerAckReason::Ok,
+        expected_map_sha1: None,
+        actual_map_sha1: None,
+      };
+      if ack.war3_version.is_empty() || ack.map_sha1.is_empty() {
+        check.reason = StartGamePlayerAckReason::NotReady;
+      } else if Some(ack.war3_version.as_bytes()) != expected_version {
+        check.reason = StartGamePlayerAckReason::War3VersionMismatch;
+      } else if Some(&ack.map_sha1 as &[u8]) != expected_sha1 {
+        check.reason = StartGamePlayerAckReason::MapMismatch;
+        check.expected_map_sha1 = expected_sha1.and_then(sha1_hex);
+        check.actual_map_sha1 = sha1_hex(&ack.map_sha1);
+      }
+      (*player_id, check)
+    })
+    .collect()
+}
+
+#[test]
+fn test_get_player_ack_reasons() {
+  use proto::flo_connect::PacketGameStartPlayerClientInfoRequest;
+
+  fn ack(version: &str, sha1: &[u8]) -> PacketGameStartPlayerClientInfoRequest {
+    PacketGameStartPlayerClientInfoRequest {
+      war3_version: version.to_string(),
+      map_sha1: sha1.to_vec(),
+      ..Default::default()
+    }
+  }
+
+  let map: HashMap<_, _> = vec![
+    (1, ack("1.32.10", &[1; 20])),
+    (2, ack("1.32.10", &[1; 20])),
+    (3, ack("1.32.10", &[1; 20])),
+    (4, ack("1.32.9", &[1; 20])),
+    (5, ack("1.32.10", &[2; 20])),
+    (6, ack("1.32.9", &[2; 20])),
+    (7, ack("", &[1; 20])),
+    (8, ack("1.32.10", &[])),
+  ]
+  .into_iter()
+  .collect();
+
+  let reasons = get_player_ack_reasons(&map);
+  let expected: HashMap<_, _> = vec![
+    (1, StartGamePlayerAckReason::Ok),
+    (2, StartGamePlayerAckReason::Ok),
+    (3, StartGamePlayerAckReason::Ok),
+    (4, StartGamePlayerAckReason::War3VersionMismatch),
+    (5, StartGamePlayerAckReason::MapMismatch),
+    (6, StartGamePlayerAckReason::War3VersionMismatch),
+    (7, StartGamePlayerAckReason::NotReady),
+    (8, StartGamePlayerAckReason::NotReady),
+  ]
+  .into_iter()
+  .collect();
+  assert_eq!(reasons, expected);
+
+  assert!(get_player_ack_reasons(&HashMap::new()).is_empty());
+}
+
+#[test]
+fn test_get_player_ack_checks_map_mismatch() {
+  use proto::flo_connect::PacketGameStartPlayerClientInfoRequest;
+
+  fn ack(sha1: &[u8]) -> PacketGameStartPlayerClientInfoRequest {
+    PacketGameStartPlayerClientInfoRequest {
+      war3_version: "1.32.10".to_string(),
+      map_sha1: sha1.to_vec(),
+      ..Default::default()
+    }
+  }
+
+  let map: HashMap<_, _> = vec![
+    (1, ack(&[0xab; 20])),
+    (2, ack(&[0xab; 20])),
+    (3, ack(&[0x01; 20])),
+  ]
+  .into_iter()
+  .collect();
+
+  let checks = get_player_ack_checks(&map);
+  for player_id in &[1, 2] {
+    assert_eq!(
+      checks[player_id],
+      PlayerAckCheck {
+        reason: StartGamePlayerAckReason::Ok,
+        expected_map_sha1: None,
+        actual_map_sha1: None,
+      }
+    );
+  }
+  assert_eq!(
+    checks[&3],
+    PlayerAckCheck {
+      reason: StartGamePlayerAckReason::MapMismatch,
+      expected_map_sha1: Some("ab".repeat(20)),
+      actual_map_sha1: Some("01".repeat(20)),
+    }
+  );
+}
@@ -51,12 +51,16 @@ impl Handler<GameSlotClientStatusUpdate> for GameActor {
     };
     pkt.set_status(status.into_proto_enum());
 
+    let frame = pkt.encode_as_frame()?;
+    self.history.record_packet(frame.type_id);
+    self.events.publish(&frame);
     self
       .player_reg
-      .broadcast(self.players.clone(), pkt.encode_as_frame()?)
+      .broadcast(self.players.clone(), frame)
       .await?;
 
-    self.player_client_status_map.insert(player_id, status);
+    let prev = self.player_client_status_map.insert(player_id, status);
+    self.history.record_client_status(player_id, prev, status);
 
     Ok(())
   }
@@ -93,6 +97,9 @@ impl Handler<GameStatusUpdate> for GameActor {
 
     let frame_game_status = message.to_packet().encode_as_frame()?;
     self.status = GameStatus::from(message.status);
+    self.history.record_status(self.status);
+    self.history.record_packet(frame_game_status.type_id);
+    self.events.publish(&frame_game_status);
 
     let ended = match self.status {
       GameStatus::Ended | GameStatus::Terminated => true,
@@ -33,6 +33,11 @@ pub struct Game {
   pub game_version: Option<String>,
   pub enable_ping_equalizer: bool,
   pub flo_tv_delay_override_secs: Option<i32>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// `flo_types::game::GameFlags` bits
+  #[serde(default)]
+  pub game_flags: u32,
 }
 
 impl S2ProtoPack<flo_net::proto::flo_connect::GameInfo> for Game {
@@ -56,6 +61,7 @@ impl S2ProtoPack<flo_net::proto::flo_connect::GameInfo> for Game {
       random_seed: self.random_seed,
       created_by: self.created_by.pack()?,
       mask_player_names: self.mask_player_names,
+      game_flags: self.game_flags,
     })
   }
 }
@@ -119,6 +125,30 @@ pub struct GameEntry {
   pub created_by: Option<PlayerRef>,
 }
 
+/// Lightweight summary of a game running on a node
+#[derive(Debug, S2ProtoPack)]
+#[s2_grpc(message_type(flo_grpc::game::NodeGameSummary))]
+pub struct NodeGameSummary {
+  pub id: i32,
+  pub name: String,
+  #[s2_grpc(proto_enum)]
+  pub status: GameStatus,
+  pub num_players: i32,
+}
+
+/// How full a game is, see `GetGamesPlayerCount`
+#[derive(Debug, S2ProtoPack)]
+#[s2_grpc(message_type(flo_grpc::game::GameOccupancy))]
+pub struct GameOccupancy {
+  pub game_id: i32,
+  /// Players in the game, observers included
+  pub occupied_slots: i32,
+  /// Player slots of the map
+  pub total_slots: i32,
+  #[s2_grpc(proto_enum)]
+  pub status: GameStatus,
+}
+
 pub(crate) type GameEntryColumns = (
   game::dsl::id,
   game::dsl::name,
@@ -174,6 +204,11 @@ impl GameStatus {
     Self::active_variants().contains(self)
   }
 
+  /// The game is over and won't change status again
+  pub fn is_terminal(&self) -> bool {
+    matches!(self, Self::Ended | Self::Terminated)
+  }
+
   pub fn active_variants() -> &'static [GameStatus] {
     &[Self::Preparing, Self::Created, Self::Running]
   }
@@ -303,6 +338,24 @@ pub enum Race {
   Random = 4,
 }
 
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, S2ProtoEnum, BSDieselEnum)]
+#[repr(i32)]
+#[s2_grpc(proto_enum_type(flo_grpc::game::GameOutcome))]
+pub enum GameOutcome {
+  Won = 0,
+  Lost = 1,
+  Left = 2,
+  Draw = 3,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, S2ProtoUnpack)]
+#[s2_grpc(message_type(flo_grpc::game::PlayerResult))]
+pub struct PlayerResult {
+  pub player_id: i32,
+  #[s2_grpc(proto_enum)]
+  pub outcome: GameOutcome,
+}
+
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, S2ProtoEnum, BSDieselEnum)]
 #[repr(i32)]
 #[s2_grpc(proto_enum_type(flo_grpc::game::Computer, flo_net::proto::flo_connect::Computer))]
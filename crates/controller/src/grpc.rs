@@ -1,25 +1,49 @@
-use crate::config::{ApiRequestExt, GetInterceptor};
+use crate::config::{
+  ApiRequestExt, EnterMaintenance, ExitMaintenance, GetInterceptor, GetServerConfig, ServerConfig,
+  UpdateServerConfig,
+};
 use crate::error::{Error, Result};
+use crate::event::ControllerEvent as DomainEvent;
 use crate::game::db::{CreateGameAsBotParams, CreateGameParams};
 use crate::game::messages::{CreateGame, PlayerJoin, PlayerLeave};
-use crate::game::state::cancel::CancelGame;
+use crate::game::state::cancel::{CancelGame, CancelReason};
 use crate::game::state::create::CreateGameAsBot;
-use crate::game::state::node::SelectNode;
-use crate::game::state::registry::{AddGamePlayer, Remove, RemoveGamePlayer, UpdateGameNodeCache};
-use crate::game::state::start::{StartGameCheckAsBot, StartGameCheckAsBotResult};
-use crate::node::messages::ListNode;
+use crate::game::state::diagnostics::{GameDiagnostics, GetGameDiagnostics};
+use crate::game::state::event::SubscribeGameEvents;
+use crate::game::state::host::TransferHost;
+use crate::game::state::join::{join_each, register_joined_player, AddGamePlayerRetry};
+use crate::game::state::leave::KickPlayer;
+use crate::game::state::node::{
+  pick_lowest_ping_node, region_node_ids, resolve_api_game_node, SelectNode,
+};
+use crate::game::state::player::GetGamePlayers;
+use crate::game::state::registry::{
+  AddGamePlayer, GetGamesPlayerCount, GetNodeGames, Remove, RemoveGamePlayer, UpdateGameNodeCache,
+};
+use crate::game::state::rejoin::RejoinGame;
+use crate::game::state::start::{
+  get_player_ack_checks, StartGameCheckAsBot, StartGameCheckAsBotResult,
+};
+use crate::game::{Game, GameOccupancy, NodeGameSummary, PlayerResult};
+use crate::node::messages::{GetFullNodeIds, GetNodeHealth, ListNode};
+use crate::player::state::conn::GetPlayerIp;
 use crate::player::state::ping::GetPlayersPingSnapshot;
-use crate::player::{PlayerBanType, PlayerSource, SourceState};
+use crate::player::{IpNetwork, PlayerBanType, PlayerSource, SourceState};
+use crate::rate_limit::GrpcMethod;
 use crate::state::{ActorMapExt, ControllerStateRef};
 use bs_diesel_utils::executor::ExecutorError;
 use chrono::{DateTime, Utc};
 use flo_grpc::controller::flo_controller_server::*;
 use flo_grpc::controller::*;
+use flo_net::connect::Capabilities;
+use flo_net::packet::FramePayload;
+use futures::Stream;
 use s2_grpc_utils::{S2ProtoEnum, S2ProtoPack, S2ProtoUnpack};
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::pin::Pin;
 use std::time::Duration;
 use tonic::transport::Server;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 use tower_http::trace::TraceLayer;
 use tracing::Span;
 
@@ -30,6 +54,7 @@ pub async fn serve(state: ControllerStateRef) -> Result<()> {
   let interceptor = state.config.send(GetInterceptor).await?;
   let server = FloControllerServer::with_interceptor(server_impl, interceptor);
   let layer = tower::ServiceBuilder::new()
+    .map_request(GrpcMethod::tag)
     .layer(
       TraceLayer::new_for_grpc()
         .on_request(())
@@ -58,6 +83,75 @@ impl FloControllerService {
   pub fn new(state: ControllerStateRef) -> Self {
     FloControllerService { state }
   }
+
+  async fn check_accepting_games(&self) -> Result<()> {
+    self
+      .state
+      .config
+      .send(GetServerConfig)
+      .await?
+      .check_accepting_games()
+  }
+
+  /// A game picks its node when it starts, `validate_create_game` reports if none has room
+  async fn check_node_available(&self) -> Result<()> {
+    let nodes = self.state.nodes.send(ListNode).await?;
+    let full_node_ids = self.state.nodes.send(GetFullNodeIds).await?;
+    check_node_available(nodes.iter().map(|node| node.id), &full_node_ids)
+  }
+
+  /// Pushes the server config to all connected players
+  async fn broadcast_server_config(&self, config: &ServerConfig) -> Result<()> {
+    use flo_net::packet::FloPacket;
+    let frame = config.to_packet().encode_as_frame()?;
+    self
+      .state
+      .player_packet_sender
+      .broadcast_to_all_if_supported(Capabilities::SERVER_CONFIG, frame)
+      .await
+  }
+
+  async fn join_game_player(&self, game_id: i32, player_id: i32, observer: bool) -> Result<Game> {
+    self.check_accepting_games().await?;
+    let game = self
+      .state
+      .games
+      .send_to(
+        game_id,
+        PlayerJoin {
+          player_id,
+          observer,
+        },
+      )
+      .await?;
+
+    register_joined_player(
+      &AddGamePlayerRetry::default(),
+      || {
+        let games = self.state.games.clone();
+        async move {
+          games
+            .send(AddGamePlayer { game_id, player_id })
+            .await
+            .map_err(Error::from)
+        }
+      },
+      || async move {
+        let res = self
+          .state
+          .games
+          .send_to(game_id, PlayerLeave { player_id })
+          .await?;
+        if res.game_ended {
+          self.state.games.send(Remove { game_id }).await?;
+        }
+        Ok(())
+      },
+    )
+    .await?;
+
+    Ok(game)
+  }
 }
 
 #[tonic::async_trait]
@@ -78,6 +172,22 @@ impl FloController for FloControllerService {
     }))
   }
 
+  async fn get_players(
+    &self,
+    request: Request<GetPlayersRequest>,
+  ) -> Result<Response<GetPlayersReply>, Status> {
+    let player_ids = request.into_inner().player_ids;
+    let players = self
+      .state
+      .db
+      .exec(move |conn| crate::player::db::get_many(conn, &player_ids))
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(GetPlayersReply {
+      players: players.pack().map_err(Status::internal)?,
+    }))
+  }
+
   async fn get_player_by_token(
     &self,
     request: Request<GetPlayerByTokenRequest>,
@@ -138,6 +248,91 @@ impl FloController for FloControllerService {
     }))
   }
 
+  async fn list_node_games(
+    &self,
+    request: Request<ListNodeGamesRequest>,
+  ) -> Result<Response<ListNodeGamesReply>, Status> {
+    use std::collections::BTreeMap;
+
+    let node_id = request.into_inner().node_id;
+    let node_games: BTreeMap<i32, i32> = self
+      .state
+      .games
+      .send(GetNodeGames { node_id })
+      .await
+      .map_err(Error::from)?
+      .into_iter()
+      .collect();
+    if node_games.is_empty() {
+      return Ok(Response::new(ListNodeGamesReply { games: vec![] }));
+    }
+
+    let ids: Vec<i32> = node_games.keys().cloned().collect();
+    let rows = self
+      .state
+      .db
+      .exec(move |conn| crate::game::db::get_active_name_and_status(conn, &ids))
+      .await
+      .map_err(Error::from)?;
+    let games: Vec<_> = rows
+      .into_iter()
+      .map(|(id, name, status)| NodeGameSummary {
+        id,
+        name,
+        status,
+        num_players: node_games.get(&id).cloned().unwrap_or(0),
+      })
+      .collect();
+    Ok(Response::new(ListNodeGamesReply {
+      games: games.pack().map_err(Error::from)?,
+    }))
+  }
+
+  async fn health_check(&self, _request: Request<()>) -> Result<Response<HealthReply>, Status> {
+    const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+    let db_check = self.state.db.exec(|conn| {
+      use diesel::RunQueryDsl;
+      diesel::sql_query("SELECT 1").execute(conn)
+    });
+    let database_healthy = match tokio::time::timeout(DB_CHECK_TIMEOUT, db_check).await {
+      Ok(Ok(_)) => true,
+      Ok(Err(err)) => {
+        tracing::error!("health check: db: {}", err);
+        false
+      }
+      Err(_) => {
+        tracing::error!("health check: db: timeout");
+        false
+      }
+    };
+
+    let now = Utc::now();
+    let nodes = self
+      .state
+      .nodes
+      .send(GetNodeHealth)
+      .await
+      .map_err(Error::from)?;
+    let node_statuses = nodes
+      .into_iter()
+      .map(|node| {
+        Ok(NodeHealthStatus {
+          node_id: node.node_id,
+          connected: node.connected,
+          healthy: node.is_healthy(now),
+          last_seen_at: node.last_seen.map(|t| t.pack()).transpose()?,
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    Ok(Response::new(HealthReply {
+      healthy: database_healthy,
+      database_healthy,
+      node_statuses,
+    }))
+  }
+
   async fn list_games(
     &self,
     request: Request<ListGamesRequest>,
@@ -173,14 +368,183 @@ impl FloController for FloControllerService {
     }))
   }
 
+  async fn get_game_occupancy(
+    &self,
+    request: Request<GetGameOccupancyRequest>,
+  ) -> Result<Response<GetGameOccupancyReply>, Status> {
+    use std::collections::BTreeMap;
+
+    let game_ids = request.into_inner().game_ids;
+    let counts: BTreeMap<i32, i32> = self
+      .state
+      .games
+      .send(GetGamesPlayerCount { game_ids })
+      .await
+      .map_err(Error::from)?
+      .into_iter()
+      .collect();
+    if counts.is_empty() {
+      return Ok(Response::new(GetGameOccupancyReply { games: vec![] }));
+    }
+
+    let ids: Vec<i32> = counts.keys().cloned().collect();
+    let rows = self
+      .state
+      .db
+      .exec(move |conn| crate::game::db::get_active_max_players_and_status(conn, &ids))
+      .await
+      .map_err(Error::from)?;
+    let games: Vec<_> = rows
+      .into_iter()
+      .map(|(game_id, max_players, status)| GameOccupancy {
+        game_id,
+        occupied_slots: counts.get(&game_id).cloned().unwrap_or(0),
+        total_slots: max_players,
+        status,
+      })
+      .collect();
+    Ok(Response::new(GetGameOccupancyReply {
+      games: games.pack().map_err(Error::from)?,
+    }))
+  }
+
+  async fn get_game_diagnostics(
+    &self,
+    request: Request<GetGameDiagnosticsRequest>,
+  ) -> Result<Response<GetGameDiagnosticsReply>, Status> {
+    if !request.is_admin_api_client() {
+      return Err(Status::permission_denied("admin api client required"));
+    }
+    let game_id = request.into_inner().game_id;
+    let game = self
+      .state
+      .db
+      .exec(move |conn| crate::game::db::get_full(conn, game_id))
+      .await
+      .map_err(Error::from)?;
+    let runtime = self
+      .state
+      .games
+      .send_to(game_id, GetGameDiagnostics)
+      .await
+      .map_err(|e| match e {
+        Error::ActorNotFound => Error::GameNotFound,
+        e => e,
+      })?;
+    let ping = self
+      .state
+      .players
+      .send(GetPlayersPingSnapshot {
+        players: runtime.players.clone(),
+      })
+      .await
+      .map_err(Error::from)?
+      .map;
+    let diagnostics = GameDiagnostics {
+      game,
+      runtime,
+      ping,
+    };
+    Ok(Response::new(GetGameDiagnosticsReply {
+      json: serde_json::to_string(&diagnostics).map_err(Error::from)?,
+    }))
+  }
+
+  type WatchGameStream = Pin<Box<dyn Stream<Item = Result<GameEvent, Status>> + Send>>;
+
+  async fn watch_game(
+    &self,
+    request: Request<WatchGameRequest>,
+  ) -> Result<Response<Self::WatchGameStream>, Status> {
+    use tokio::sync::broadcast::error::RecvError;
+
+    let game_id = request.into_inner().game_id;
+    let rx = self
+      .state
+      .games
+      .send_to(game_id, SubscribeGameEvents)
+      .await
+      .map_err(|e| match e {
+        Error::ActorNotFound => Status::not_found(Error::GameNotFound.to_string()),
+        e => e.into(),
+      })?;
+
+    let stream = futures::stream::unfold(rx, move |mut rx| async move {
+      loop {
+        match rx.recv().await {
+          Ok(frame) => {
+            if let FramePayload::Bytes(payload) = frame.payload {
+              let event = GameEvent {
+                game_id,
+                type_id: u8::from(frame.type_id) as i32,
+                payload: payload.to_vec(),
+              };
+              return Some((Ok(event), rx));
+            }
+          }
+          Err(RecvError::Lagged(n)) => {
+            tracing::warn!(game_id, "watch game: skipped {} events", n);
+          }
+          Err(RecvError::Closed) => return None,
+        }
+      }
+    });
+
+    Ok(Response::new(Box::pin(stream)))
+  }
+
+  type SubscribeEventsStream = Pin<Box<dyn Stream<Item = Result<ControllerEvent, Status>> + Send>>;
+
+  async fn subscribe_events(
+    &self,
+    request: Request<SubscribeEventsRequest>,
+  ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+    use tokio::sync::broadcast::error::RecvError;
+
+    if !request.is_admin_api_client() {
+      return Err(Status::permission_denied("admin api client required"));
+    }
+
+    let rx = self.state.events.subscribe();
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+      loop {
+        match rx.recv().await {
+          Ok(event) => {
+            let payload = match serde_json::to_string(&event) {
+              Ok(payload) => payload,
+              Err(err) => {
+                tracing::error!("subscribe events: serialize {:?}: {}", event, err);
+                continue;
+              }
+            };
+            let event = ControllerEvent {
+              event_type: event.name().to_string(),
+              payload_json: payload,
+            };
+            return Some((Ok(event), rx));
+          }
+          Err(RecvError::Lagged(n)) => {
+            tracing::warn!("subscribe events: skipped {} events", n);
+          }
+          Err(RecvError::Closed) => return None,
+        }
+      }
+    });
+
+    Ok(Response::new(Box::pin(stream)))
+  }
+
   async fn create_game(
     &self,
     request: Request<CreateGameRequest>,
   ) -> Result<Response<CreateGameReply>, Status> {
+    let api_client_id = request.get_api_client_id();
+    self.check_accepting_games().await?;
     let game = self
       .state
       .games
       .send(CreateGame {
+        api_client_id,
         params: CreateGameParams::unpack(request.into_inner()).map_err(Error::from)?,
       })
       .await
@@ -191,6 +555,37 @@ impl FloController for FloControllerService {
     }))
   }
 
+  async fn validate_create_game(
+    &self,
+    request: Request<CreateGameRequest>,
+  ) -> Result<Response<ValidateCreateGameReply>, Status> {
+    let params = CreateGameParams::unpack(request.into_inner()).map_err(Error::from)?;
+    let mut errors = vec![];
+    // same order as `create_game`
+    if let Err(status) = self.check_accepting_games().await {
+      errors.push(status.message().to_string());
+    }
+    if let Err(err) = self.check_node_available().await {
+      errors.push(err.to_string());
+    }
+    let res = self
+      .state
+      .db
+      .exec(move |conn| crate::game::db::validate_create(conn, &params))
+      .await
+      .map_err(Error::from);
+    match res {
+      Ok(_) => {}
+      Err(e) if crate::game::db::is_create_validation_error(&e) => errors.push(e.to_string()),
+      Err(e) => return Err(e.into()),
+    }
+
+    Ok(Response::new(ValidateCreateGameReply {
+      valid: errors.is_empty(),
+      errors,
+    }))
+  }
+
   async fn join_game(
     &self,
     request: Request<JoinGameRequest>,
@@ -198,28 +593,55 @@ impl FloController for FloControllerService {
     let params = request.into_inner();
 
     let game = self
-      .state
-      .games
-      .send_to(
-        params.game_id,
-        PlayerJoin {
-          player_id: params.player_id,
-        },
-      )
+      .join_game_player(params.game_id, params.player_id, false)
       .await?;
 
-    self
+    Ok(Response::new(JoinGameReply {
+      game: game.pack().map_err(Error::from)?,
+    }))
+  }
+
+  async fn join_game_as_observer(
+    &self,
+    request: Request<JoinGameAsObserverRequest>,
+  ) -> Result<Response<JoinGameAsObserverReply>, Status> {
+    let params = request.into_inner();
+
+    let game = self
+      .join_game_player(params.game_id, params.player_id, true)
+      .await?;
+
+    Ok(Response::new(JoinGameAsObserverReply {
+      game: game.pack().map_err(Error::from)?,
+    }))
+  }
+
+  async fn join_game_batch(
+    &self,
+    request: Request<JoinGameBatchRequest>,
+  ) -> Result<Response<JoinGameBatchReply>, Status> {
+    let params = request.into_inner();
+    let game_id = params.game_id;
+
+    // joins run one at a time so slots are assigned in request order
+    let failures = join_each(params.player_ids, |player_id| async move {
+      self
+        .join_game_player(game_id, player_id, false)
+        .await
+        .map(|_| ())
+    })
+    .await;
+
+    let game = self
       .state
-      .games
-      .send(AddGamePlayer {
-        game_id: params.game_id,
-        player_id: params.player_id,
-      })
+      .db
+      .exec(move |conn| crate::game::db::get_full(conn, game_id))
       .await
       .map_err(Error::from)?;
 
-    Ok(Response::new(JoinGameReply {
+    Ok(Response::new(JoinGameBatchReply {
       game: game.pack().map_err(Error::from)?,
+      failures: failures.pack().map_err(Error::from)?,
     }))
   }
 
@@ -254,32 +676,111 @@ impl FloController for FloControllerService {
     let join_token = crate::game::token::validate_join_token(&params.token)?;
 
     let game = self
+      .join_game_player(join_token.game_id, params.player_id, false)
+      .await?;
+
+    Ok(Response::new(JoinGameReply {
+      game: game.pack().map_err(Error::from)?,
+    }))
+  }
+
+  async fn leave_game(&self, request: Request<LeaveGameRequest>) -> Result<Response<()>, Status> {
+    let params = request.into_inner();
+
+    let res = self
       .state
       .games
       .send_to(
-        join_token.game_id,
-        PlayerJoin {
+        params.game_id,
+        PlayerLeave {
           player_id: params.player_id,
         },
       )
-      .await?;
+      .await
+      .map_err(Error::from)?;
+
+    if res.game_ended {
+      tracing::debug!(
+        game_id = params.game_id,
+        "shutting down: reason: PlayerLeave"
+      );
+      self
+        .state
+        .games
+        .send(Remove {
+          game_id: params.game_id,
+        })
+        .await
+        .map_err(Error::from)?;
+    } else {
+      self
+        .state
+        .games
+        .send(RemoveGamePlayer {
+          game_id: params.game_id,
+          player_id: params.player_id,
+        })
+        .await
+        .map_err(Error::from)?;
+    }
+
+    Ok(Response::new(()))
+  }
+
+  async fn transfer_host(
+    &self,
+    request: Request<TransferHostRequest>,
+  ) -> Result<Response<()>, Status> {
+    let params = request.into_inner();
 
     self
       .state
       .games
-      .send(AddGamePlayer {
-        game_id: join_token.game_id,
-        player_id: params.player_id,
-      })
+      .send_to(
+        params.game_id,
+        TransferHost {
+          from_player_id: params.from_player_id,
+          to_player_id: params.to_player_id,
+        },
+      )
       .await
-      .map_err(Error::from)?;
+      .map_err(|e| match e {
+        Error::ActorNotFound => Status::not_found(Error::GameNotFound.to_string()),
+        e @ Error::PlayerNotInGame => Status::failed_precondition(e.to_string()),
+        e => e.into(),
+      })?;
 
-    Ok(Response::new(JoinGameReply {
-      game: game.pack().map_err(Error::from)?,
+    Ok(Response::new(()))
+  }
+
+  async fn rejoin_game(
+    &self,
+    request: Request<RejoinGameRequest>,
+  ) -> Result<Response<RejoinGameReply>, Status> {
+    let params = request.into_inner();
+
+    let token = self
+      .state
+      .games
+      .send_to(
+        params.game_id,
+        RejoinGame {
+          player_id: params.player_id,
+        },
+      )
+      .await
+      .map_err(|e| match e {
+        Error::ActorNotFound => Status::not_found(Error::GameNotFound.to_string()),
+        e @ Error::PlayerNotInGame => Status::failed_precondition(e.to_string()),
+        e => e.into(),
+      })?;
+
+    Ok(Response::new(RejoinGameReply {
+      token: token.to_vec(),
     }))
   }
 
-  async fn leave_game(&self, request: Request<LeaveGameRequest>) -> Result<Response<()>, Status> {
+  async fn kick_player(&self, request: Request<KickPlayerRequest>) -> Result<Response<()>, Status> {
     let params = request.into_inner();
 
     let res = self
@@ -287,8 +788,9 @@ impl FloController for FloControllerService {
       .games
       .send_to(
         params.game_id,
-        PlayerLeave {
-          player_id: params.player_id,
+        KickPlayer {
+          operator_player_id: params.operator_player_id,
+          target_player_id: params.target_player_id,
         },
       )
       .await
@@ -297,7 +799,7 @@ impl FloController for FloControllerService {
     if res.game_ended {
       tracing::debug!(
         game_id = params.game_id,
-        "shutting down: reason: PlayerLeave"
+        "shutting down: reason: KickPlayer"
       );
       self
         .state
@@ -313,7 +815,7 @@ impl FloController for FloControllerService {
         .games
         .send(RemoveGamePlayer {
           game_id: params.game_id,
-          player_id: params.player_id,
+          player_id: params.target_player_id,
         })
         .await
         .map_err(Error::from)?;
@@ -322,15 +824,66 @@ impl FloController for FloControllerService {
     Ok(Response::new(()))
   }
 
-  async fn select_game_node(
+  async fn select_game_node(
+    &self,
+    request: Request<SelectGameNodeRequest>,
+  ) -> Result<Response<()>, Status> {
+    let SelectGameNodeRequest {
+      game_id,
+      player_id,
+      node_id,
+      preferred_node_ids,
+    } = request.into_inner();
+
+    self
+      .state
+      .games
+      .send_to(
+        game_id,
+        SelectNode {
+          player_id,
+          node_id: node_id.clone(),
+          preferred_node_ids,
+        },
+      )
+      .await?;
+
+    self
+      .state
+      .games
+      .notify(UpdateGameNodeCache { game_id, node_id })
+      .await
+      .map_err(Error::from)?;
+
+    Ok(Response::new(()))
+  }
+
+  async fn auto_select_game_node(
     &self,
-    request: Request<SelectGameNodeRequest>,
-  ) -> Result<Response<()>, Status> {
-    let SelectGameNodeRequest {
-      game_id,
-      player_id,
-      node_id,
-    } = request.into_inner();
+    request: Request<AutoSelectGameNodeRequest>,
+  ) -> Result<Response<AutoSelectGameNodeReply>, Status> {
+    let default_region = request.get_api_client_default_region();
+    let AutoSelectGameNodeRequest { game_id, player_id } = request.into_inner();
+
+    let players = self.state.games.send_to(game_id, GetGamePlayers).await?;
+    let nodes = self.state.nodes.send(ListNode).await.map_err(Error::from)?;
+    let full_node_ids = self
+      .state
+      .nodes
+      .send(GetFullNodeIds)
+      .await
+      .map_err(Error::from)?;
+    let mut node_ids = region_node_ids(&nodes, default_region.as_deref());
+    node_ids.retain(|id| !full_node_ids.contains(id));
+    let snapshot = self
+      .state
+      .players
+      .send(GetPlayersPingSnapshot { players })
+      .await
+      .map_err(Error::from)?;
+
+    let node_id =
+      pick_lowest_ping_node(&node_ids, &snapshot.map).ok_or_else(|| Error::NodePingUnavailable)?;
 
     self
       .state
@@ -339,7 +892,8 @@ impl FloController for FloControllerService {
         game_id,
         SelectNode {
           player_id,
-          node_id: node_id.clone(),
+          node_id: Some(node_id),
+          preferred_node_ids: vec![],
         },
       )
       .await?;
@@ -347,11 +901,14 @@ impl FloController for FloControllerService {
     self
       .state
       .games
-      .notify(UpdateGameNodeCache { game_id, node_id })
+      .notify(UpdateGameNodeCache {
+        game_id,
+        node_id: Some(node_id),
+      })
       .await
       .map_err(Error::from)?;
 
-    Ok(Response::new(()))
+    Ok(Response::new(AutoSelectGameNodeReply { node_id }))
   }
 
   async fn cancel_game(&self, request: Request<CancelGameRequest>) -> Result<Response<()>, Status> {
@@ -366,6 +923,7 @@ impl FloController for FloControllerService {
         game_id,
         CancelGame {
           player_id: Some(player_id),
+          reason: CancelReason::Cancelled,
         },
       )
       .await?;
@@ -381,20 +939,66 @@ impl FloController for FloControllerService {
     Ok(Response::new(()))
   }
 
+  async fn report_game_result(
+    &self,
+    request: Request<ReportGameResultRequest>,
+  ) -> Result<Response<()>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let req = request.into_inner();
+    let game_id = req.game_id;
+    let overwrite = req.overwrite;
+    let results: Vec<PlayerResult> = S2ProtoUnpack::unpack(req.results).map_err(Error::from)?;
+    self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::game::db::check_game_api_client_id(conn, api_client_id, game_id)?;
+        crate::game::db::report_result(conn, game_id, &results, overwrite)
+      })
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(()))
+  }
+
   async fn import_map_checksums(
     &self,
     request: Request<ImportMapChecksumsRequest>,
   ) -> Result<Response<ImportMapChecksumsReply>, Status> {
     let items =
       Vec::<crate::map::db::ImportItem>::unpack(request.into_inner().items).map_err(Error::from)?;
-    let updated = self
+    let result = self
       .state
       .db
       .exec(move |conn| crate::map::db::import(conn, items))
       .await
       .map_err(Error::from)?;
     Ok(Response::new(ImportMapChecksumsReply {
-      updated: updated as u32,
+      updated: result.updated as u32,
+      skipped: result.skipped.len() as u32,
+      skipped_items: result.skipped.pack().map_err(Error::from)?,
+    }))
+  }
+
+  async fn compute_map_checksum(
+    &self,
+    request: Request<Streaming<ComputeMapChecksumRequest>>,
+  ) -> Result<Response<ComputeMapChecksumReply>, Status> {
+    use crate::map::checksum::{append_chunk, compute, MAX_MAP_FILE_SIZE};
+
+    let mut stream = request.into_inner();
+    let mut bytes = vec![];
+    while let Some(req) = stream.message().await? {
+      append_chunk(&mut bytes, &req.map_bytes, MAX_MAP_FILE_SIZE)?;
+    }
+
+    let checksum = tokio::task::spawn_blocking(move || compute(&bytes))
+      .await
+      .map_err(|err| Status::internal(err.to_string()))??;
+
+    Ok(Response::new(ComputeMapChecksumReply {
+      sha1: checksum.sha1.to_vec(),
+      checksum: checksum.xoro,
+      file_size: checksum.file_size as u64,
     }))
   }
 
@@ -412,6 +1016,22 @@ impl FloController for FloControllerService {
     Ok(Response::new(SearchMapChecksumReply { checksum }))
   }
 
+  async fn get_map_by_sha1(
+    &self,
+    request: Request<GetMapBySha1Request>,
+  ) -> Result<Response<GetMapBySha1Reply>, Status> {
+    let sha1 = request.into_inner().sha1;
+    let map = self
+      .state
+      .db
+      .exec(move |conn| crate::map::db::get_by_sha1(conn, sha1))
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(GetMapBySha1Reply {
+      map: map.map(|map| map.pack()).transpose().map_err(Error::from)?,
+    }))
+  }
+
   async fn get_players_by_source_ids(
     &self,
     request: Request<GetPlayersBySourceIdsRequest>,
@@ -431,6 +1051,35 @@ impl FloController for FloControllerService {
     }))
   }
 
+  async fn get_player_ping_summary(
+    &self,
+    request: Request<GetPlayerPingSummaryRequest>,
+  ) -> Result<Response<GetPlayerPingSummaryReply>, Status> {
+    use flo_grpc::player::PlayerPingSummary;
+
+    let ids = request.into_inner().ids;
+    let snapshot = self
+      .state
+      .players
+      .send(GetPlayersPingSnapshot { players: ids })
+      .await
+      .map_err(Error::from)?;
+
+    Ok(Response::new(GetPlayerPingSummaryReply {
+      summaries: snapshot
+        .summaries()
+        .into_iter()
+        .map(|summary| PlayerPingSummary {
+          player_id: summary.player_id,
+          min: summary.min,
+          max: summary.max,
+          median: summary.median,
+          best_node_id: summary.best_node_id,
+        })
+        .collect(),
+    }))
+  }
+
   async fn get_player_ping_maps(
     &self,
     request: Request<GetPlayerPingMapsRequest>,
@@ -468,13 +1117,24 @@ impl FloController for FloControllerService {
     &self,
     request: Request<CreateGameAsBotRequest>,
   ) -> Result<Response<CreateGameAsBotReply>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let api_player_id = request.get_api_player_id();
+    let default_region = request.get_api_client_default_region();
+    self.check_accepting_games().await?;
+    let mut params = CreateGameAsBotParams::unpack(request.into_inner()).map_err(Error::from)?;
+    if params.node_id == 0 && default_region.is_some() {
+      let nodes = self.state.nodes.send(ListNode).await.map_err(Error::from)?;
+      params.node_id = resolve_api_game_node(params.node_id, &nodes, default_region.as_deref())
+        .ok_or_else(|| Error::NodeNotFound)?;
+    }
+
     let game = self
       .state
       .games
       .send(CreateGameAsBot {
-        api_client_id: request.get_api_client_id(),
-        api_player_id: request.get_api_player_id(),
-        params: CreateGameAsBotParams::unpack(request.into_inner()).map_err(Error::from)?,
+        api_client_id,
+        api_player_id,
+        params,
       })
       .await
       .map_err(Error::from)??;
@@ -495,14 +1155,23 @@ impl FloController for FloControllerService {
     fn convert_map(
       map: HashMap<i32, PacketGameStartPlayerClientInfoRequest>,
     ) -> HashMap<i32, StartGamePlayerAck> {
+      let mut checks = get_player_ack_checks(&map);
       map
         .into_iter()
         .map(|(id, ack)| {
+          let check = checks.remove(&id);
+          let reason = check.as_ref().map(|check| check.reason.into_proto_enum());
+          let (expected_map_sha1, actual_map_sha1) = check
+            .map(|check| (check.expected_map_sha1, check.actual_map_sha1))
+            .unwrap_or_default();
           (
             id,
             StartGamePlayerAck {
               war3_version: ack.war3_version,
               map_sha1: ack.map_sha1,
+              reason: reason.unwrap_or_default().into(),
+              expected_map_sha1: expected_map_sha1.unwrap_or_default(),
+              actual_map_sha1: actual_map_sha1.unwrap_or_default(),
             },
           )
         })
@@ -552,17 +1221,109 @@ impl FloController for FloControllerService {
     Ok(Response::new(()))
   }
 
+  async fn update_server_config(
+    &self,
+    request: Request<UpdateServerConfigRequest>,
+  ) -> Result<Response<()>, Status> {
+    if !request.is_admin_api_client() {
+      return Err(Status::permission_denied("admin api client required"));
+    }
+    let params = request.into_inner();
+    let config = ServerConfig {
+      maintenance: params.maintenance,
+      game_create_disabled: params.game_create_disabled,
+      message: params.message,
+    };
+    let config = self
+      .state
+      .config
+      .send(UpdateServerConfig(config))
+      .await
+      .map_err(Error::from)??;
+    self.broadcast_server_config(&config).await?;
+    Ok(Response::new(()))
+  }
+
+  async fn enter_maintenance(
+    &self,
+    request: Request<EnterMaintenanceRequest>,
+  ) -> Result<Response<()>, Status> {
+    if !request.is_admin_api_client() {
+      return Err(Status::permission_denied("admin api client required"));
+    }
+    let drain_timeout = Duration::from_secs(request.into_inner().drain_timeout_secs as u64);
+    let config = self
+      .state
+      .config
+      .send(EnterMaintenance { drain_timeout })
+      .await
+      .map_err(Error::from)??;
+    self.broadcast_server_config(&config).await?;
+    Ok(Response::new(()))
+  }
+
+  async fn exit_maintenance(&self, request: Request<()>) -> Result<Response<()>, Status> {
+    if !request.is_admin_api_client() {
+      return Err(Status::permission_denied("admin api client required"));
+    }
+    let config = self
+      .state
+      .config
+      .send(ExitMaintenance)
+      .await
+      .map_err(Error::from)??;
+    self.broadcast_server_config(&config).await?;
+    Ok(Response::new(()))
+  }
+
+  async fn search_players(
+    &self,
+    request: Request<SearchPlayersRequest>,
+  ) -> Result<Response<SearchPlayersReply>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let params = request.into_inner();
+    let res = self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::player::db::search_by_name(
+          conn,
+          api_client_id,
+          &params.query,
+          params.next_id,
+          Some(params.limit as i64),
+        )
+      })
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(SearchPlayersReply {
+      players: res.players.pack().map_err(Error::from)?,
+      next_id: res.next_id,
+    }))
+  }
+
   async fn list_player_bans(
     &self,
     request: Request<ListPlayerBansRequest>,
   ) -> Result<Response<ListPlayerBansReply>, Status> {
     let api_client_id = request.get_api_client_id();
     let params = request.into_inner();
+    let ban_type = params
+      .ban_type
+      .and_then(flo_grpc::player::PlayerBanType::from_i32)
+      .map(PlayerBanType::unpack_enum);
     let res = self
       .state
       .db
       .exec(move |conn| {
-        crate::player::db::list_ban(conn, api_client_id, params.query.as_deref(), params.next_id)
+        crate::player::db::list_ban(
+          conn,
+          api_client_id,
+          params.query.as_deref(),
+          ban_type,
+          params.active_only,
+          params.next_id,
+        )
       })
       .await
       .map_err(Error::from)?;
@@ -584,6 +1345,8 @@ impl FloController for FloControllerService {
       .map(|t| DateTime::<Utc>::unpack(t))
       .transpose()
       .map_err(Status::internal)?;
+    let player_id = params.player_id;
+    let ban_type = PlayerBanType::unpack_enum(params.ban_type());
     self
       .state
       .db
@@ -592,30 +1355,304 @@ impl FloController for FloControllerService {
         crate::player::db::create_ban(
           conn,
           params.player_id,
-          PlayerBanType::unpack_enum(params.ban_type()),
+          ban_type,
           ban_expires_at,
+          params.reason.clone(),
         )
       })
       .await
       .map_err(Error::from)?;
+    self.state.events.publish(DomainEvent::PlayerBanCreated {
+      player_id,
+      ban_type,
+    });
     Ok(Response::new(()))
   }
 
   async fn remove_player_ban(
     &self,
     request: Request<RemovePlayerBanRequest>,
+  ) -> Result<Response<()>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let ban_id = request.into_inner().id;
+    self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::player::db::check_ban_api_client_id(conn, api_client_id, ban_id)?;
+        crate::player::db::remove_ban(conn, ban_id)
+      })
+      .await
+      .map_err(Error::from)?;
+    self
+      .state
+      .events
+      .publish(DomainEvent::PlayerBanRemoved { ban_id });
+    Ok(Response::new(()))
+  }
+
+  async fn get_player_moderation(
+    &self,
+    request: Request<GetPlayerModerationRequest>,
+  ) -> Result<Response<GetPlayerModerationReply>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let player_id = request.into_inner().player_id;
+    let ip = self
+      .state
+      .players
+      .send(GetPlayerIp { player_id })
+      .await
+      .map_err(Error::from)?;
+    let now = Utc::now();
+    let res = self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::player::db::check_player_api_client_id(conn, api_client_id, player_id)?;
+        crate::player::db::get_moderation(conn, player_id, ip, now)
+      })
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(GetPlayerModerationReply {
+      player_bans: res.player_bans.pack().map_err(Status::internal)?,
+      ip_bans: res.ip_bans.pack().map_err(Status::internal)?,
+      mutes: res.mutes.pack().map_err(Status::internal)?,
+    }))
+  }
+
+  async fn set_preferred_race(
+    &self,
+    request: Request<SetPreferredRaceRequest>,
+  ) -> Result<Response<()>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let params = request.into_inner();
+    let race = params
+      .race
+      .map(|value| {
+        flo_grpc::game::Race::from_i32(value)
+          .map(crate::game::Race::unpack_enum)
+          .ok_or_else(|| Status::invalid_argument(format!("invalid race: {}", value)))
+      })
+      .transpose()?;
+    self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::player::db::check_player_api_client_id(conn, api_client_id, params.player_id)?;
+        crate::player::db::set_preferred_race(conn, params.player_id, race)
+      })
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(()))
+  }
+
+  async fn create_player_mute(
+    &self,
+    request: Request<CreatePlayerMuteRequest>,
+  ) -> Result<Response<()>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let params = request.into_inner();
+    let mute_expires_at = params
+      .mute_expires_at
+      .clone()
+      .map(|t| DateTime::<Utc>::unpack(t))
+      .transpose()
+      .map_err(Status::internal)?;
+    self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::player::db::check_player_api_client_id(conn, api_client_id, params.player_id)?;
+        crate::player::db::create_player_mute(
+          conn,
+          params.player_id,
+          mute_expires_at,
+          params.reason.clone(),
+        )
+      })
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(()))
+  }
+
+  async fn remove_player_mute(
+    &self,
+    request: Request<RemovePlayerMuteRequest>,
+  ) -> Result<Response<()>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let player_id = request.into_inner().player_id;
+    self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::player::db::check_player_api_client_id(conn, api_client_id, player_id)?;
+        crate::player::db::remove_player_mute(conn, player_id)
+      })
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(()))
+  }
+
+  async fn create_ip_ban(
+    &self,
+    request: Request<CreateIpBanRequest>,
   ) -> Result<Response<()>, Status> {
     let api_client_id = request.get_api_client_id();
     let params = request.into_inner();
+    let network: IpNetwork = params.network.parse()?;
+    let ban_expires_at = params
+      .ban_expires_at
+      .clone()
+      .map(|t| DateTime::<Utc>::unpack(t))
+      .transpose()
+      .map_err(Status::internal)?;
+    self
+      .state
+      .db
+      .exec(move |conn| {
+        crate::player::db::create_ip_ban(
+          conn,
+          api_client_id,
+          network,
+          ban_expires_at,
+          params.reason.clone(),
+        )
+      })
+      .await
+      .map_err(Error::from)?;
+    Ok(Response::new(()))
+  }
+
+  async fn remove_ip_ban(
+    &self,
+    request: Request<RemoveIpBanRequest>,
+  ) -> Result<Response<()>, Status> {
+    let api_client_id = request.get_api_client_id();
+    let ban_id = request.into_inner().id;
     self
       .state
       .db
       .exec(move |conn| {
-        crate::player::db::check_ban_api_client_id(conn, api_client_id, params.id)?;
-        crate::player::db::remove_ban(conn, params.id)
+        crate::player::db::check_ip_ban_api_client_id(conn, api_client_id, ban_id)?;
+        crate::player::db::remove_ip_ban(conn, ban_id)
       })
       .await
       .map_err(Error::from)?;
     Ok(Response::new(()))
   }
 }
+
+fn check_node_available<I>(node_ids: I, full_node_ids: &[i32]) -> Result<()>
+where
+  I: IntoIterator<Item = i32>,
+{
+  if node_ids.into_iter().any(|id| !full_node_ids.contains(&id)) {
+    Ok(())
+  } else {
+    Err(Error::NodeUnavailable)
+  }
+}
+
+#[test]
+fn test_check_node_available() {
+  assert!(check_node_available(vec![1, 2], &[1]).is_ok());
+  assert!(matches!(
+    check_node_available(vec![1, 2], &[1, 2]),
+    Err(Error::NodeUnavailable)
+  ));
+  assert!(matches!(
+    check_node_available(vec![], &[]),
+    Err(Error::NodeUnavailable)
+  ));
+}
+
+#[tokio::test]
+async fn test_create_game_refused_in_maintenance() {
+  use crate::config::REQUEST_META_API_CLIENT_ID;
+  use crate::state::ControllerState;
+  use std::sync::Arc;
+  use tonic::metadata::MetadataValue;
+
+  dotenv::dotenv().ok();
+  if std::env::var("DATABASE_URL").is_err() {
+    eprintln!("DATABASE_URL not set, skipped");
+    return;
+  }
+
+  let state = Arc::new(ControllerState::init().await.unwrap());
+  let service = FloControllerService::new(state.clone());
+  let request = || {
+    let mut req = Request::new(CreateGameRequest::default());
+    req.metadata_mut().insert_bin(
+      REQUEST_META_API_CLIENT_ID,
+      MetadataValue::from_bytes(&1_i32.to_le_bytes()),
+    );
+    req
+  };
+  let update = |maintenance| {
+    state.config.send(UpdateServerConfig(ServerConfig {
+      maintenance,
+      ..Default::default()
+    }))
+  };
+
+  // the maintenance flag is persisted, don't leave it set if an assertion fails
+  struct MaintenanceGuard;
+  impl Drop for MaintenanceGuard {
+    fn drop(&mut self) {
+      crate::db::test::with_pool(1, |pool| {
+        crate::config::clear_maintenance(&pool.get().unwrap())
+      });
+    }
+  }
+  let _guard = MaintenanceGuard;
+
+  update(true).await.unwrap().unwrap();
+  let res = service.create_game(request()).await;
+  update(false).await.unwrap().unwrap();
+  assert_eq!(
+    res.err().map(|status| status.code()),
+    Some(tonic::Code::Unavailable)
+  );
+
+  // once maintenance is over the request reaches the game registry
+  let res = service.create_game(request()).await;
+  assert_ne!(
+    res.err().map(|status| status.code()),
+    Some(tonic::Code::Unavailable)
+  );
+}
+
+#[tokio::test]
+async fn test_update_server_config_requires_admin() {
+  use crate::config::{GetServerConfig, ADMIN_API_CLIENT_IDS, REQUEST_META_API_CLIENT_ID};
+  use crate::state::ControllerState;
+  use std::sync::Arc;
+  use tonic::metadata::MetadataValue;
+
+  dotenv::dotenv().ok();
+  if std::env::var("DATABASE_URL").is_err() {
+    eprintln!("DATABASE_URL not set, skipped");
+    return;
+  }
+
+  let state = Arc::new(ControllerState::init().await.unwrap());
+  let service = FloControllerService::new(state.clone());
+  let api_client_id = ADMIN_API_CLIENT_IDS.iter().max().map_or(1, |id| id + 1);
+  let mut req = Request::new(UpdateServerConfigRequest {
+    message: "denied".to_string(),
+    ..Default::default()
+  });
+  req.metadata_mut().insert_bin(
+    REQUEST_META_API_CLIENT_ID,
+    MetadataValue::from_bytes(&api_client_id.to_le_bytes()),
+  );
+
+  let res = service.update_server_config(req).await;
+  assert_eq!(
+    res.err().map(|status| status.code()),
+    Some(tonic::Code::PermissionDenied)
+  );
+  let config = state.config.send(GetServerConfig).await.unwrap();
+  assert_ne!(config.message, "denied");
+}
@@ -17,12 +17,14 @@ mod schema;
 mod client;
 mod config;
 pub mod error;
+mod event;
 pub mod game;
 mod grpc;
 pub mod host;
 pub mod map;
 pub mod node;
 pub mod player;
+mod rate_limit;
 mod state;
 
 pub use client::serve as serve_socket;
@@ -0,0 +1,52 @@
+use crate::error::*;
+use flo_w3map::{MapChecksum, W3Map};
+
+/// Largest map file accepted by `compute_map_checksum`, Reforged allows maps up to 128 MiB
+pub const MAX_MAP_FILE_SIZE: usize = 128 * 1024 * 1024;
+
+/// Appends an uploaded chunk of a map file
+pub fn append_chunk(buf: &mut Vec<u8>, chunk: &[u8], max_size: usize) -> Result<()> {
+  if buf.len() + chunk.len() > max_size {
+    return Err(Error::MapFileTooLarge(max_size));
+  }
+  buf.extend_from_slice(chunk);
+  Ok(())
+}
+
+pub fn compute(bytes: &[u8]) -> Result<MapChecksum> {
+  if bytes.is_empty() {
+    return Err(Error::MapFileInvalid("empty".to_string()));
+  }
+  W3Map::calc_checksum_memory(bytes).map_err(|err| Error::MapFileInvalid(err.to_string()))
+}
+
+#[test]
+fn test_compute() {
+  let path = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../deps/wc3-samples/map/test_tft.w3x"
+  );
+  let bytes = std::fs::read(path).unwrap();
+  let mut buf = vec![];
+  for chunk in bytes.chunks(64 * 1024) {
+    append_chunk(&mut buf, chunk, MAX_MAP_FILE_SIZE).unwrap();
+  }
+  let checksum = compute(&buf).unwrap();
+  let (_, expected) = W3Map::open_with_checksum(path).unwrap();
+  assert_eq!(checksum, expected);
+  assert_eq!(checksum.file_size, bytes.len());
+
+  assert!(matches!(compute(&[]), Err(Error::MapFileInvalid(_))));
+  assert!(matches!(
+    compute(b"not a map file"),
+    Err(Error::MapFileInvalid(_))
+  ));
+
+  let mut buf = vec![];
+  append_chunk(&mut buf, &[0; 8], 10).unwrap();
+  assert!(matches!(
+    append_chunk(&mut buf, &[0; 3], 10),
+    Err(Error::MapFileTooLarge(10))
+  ));
+  assert_eq!(buf.len(), 8);
+}
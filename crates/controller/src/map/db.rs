@@ -1,9 +1,11 @@
 use diesel::prelude::*;
-use s2_grpc_utils::S2ProtoUnpack;
+use s2_grpc_utils::{S2ProtoEnum, S2ProtoPack, S2ProtoUnpack};
 use serde::Deserialize;
+use serde_json::Value;
 
 use crate::db::DbConn;
 use crate::error::*;
+use crate::map::{Map, MapValidationError};
 use crate::schema::map_checksum;
 
 pub fn search_checksum(conn: &DbConn, sha1: String) -> Result<Option<u32>> {
@@ -25,35 +27,154 @@ pub fn search_checksum(conn: &DbConn, sha1: String) -> Result<Option<u32>> {
   Ok(value)
 }
 
+/// The map info imported with the checksum, `None` if the sha1 is unknown or was imported
+/// without map info
+pub fn get_by_sha1(conn: &DbConn, sha1: String) -> Result<Option<Map>> {
+  use map_checksum::dsl;
+  let meta = map_checksum::table
+    .filter(dsl::sha1.eq(sha1))
+    .select(dsl::meta)
+    .first::<Option<Value>>(conn)
+    .optional()?
+    .flatten();
+  parse_meta(meta)
+}
+
+fn parse_meta(meta: Option<Value>) -> Result<Option<Map>> {
+  meta
+    .map(|value| serde_json::from_value(value).map_err(Into::into))
+    .transpose()
+}
+
 #[derive(Debug, Deserialize, S2ProtoUnpack)]
 #[s2_grpc(message_type = "flo_grpc::game::MapChecksumImportItem")]
 pub struct ImportItem {
   pub sha1: String,
   pub checksum: u32,
+  #[serde(default)]
+  pub map: Option<Map>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportResult {
+  pub updated: usize,
+  pub skipped: Vec<ImportSkip>,
+}
+
+#[derive(Debug, PartialEq, S2ProtoPack)]
+#[s2_grpc(message_type = "flo_grpc::game::MapChecksumImportSkip")]
+pub struct ImportSkip {
+  pub sha1: String,
+  #[s2_grpc(proto_enum)]
+  pub reason: ImportSkipReason,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, S2ProtoEnum)]
+#[repr(i32)]
+#[s2_grpc(proto_enum_type(flo_grpc::game::MapChecksumImportSkipReason))]
+pub enum ImportSkipReason {
+  /// Another item in the batch has the same sha1, the first one is imported
+  Duplicate = 0,
+  /// `sha1` is not a 40 characters hex digest
+  BadSha1Length = 1,
+  /// Map info has a zero width or height
+  InvalidDimensions = 2,
+  /// Map info has a slot layout that fails `Map::validate`
+  InvalidLayout = 3,
+}
+
+impl ImportSkipReason {
+  fn from_validation_error(err: &MapValidationError) -> Self {
+    match *err {
+      MapValidationError::ZeroDimensions { .. } => ImportSkipReason::InvalidDimensions,
+      MapValidationError::ForcePlayerOutOfRange { .. }
+      | MapValidationError::TwelvePlayersUnsupported { .. } => ImportSkipReason::InvalidLayout,
+    }
+  }
+}
+
+/// Drops malformed and duplicated items, returns the reason each dropped item was skipped
+fn filter_items(items: &mut Vec<ImportItem>) -> Vec<ImportSkip> {
+  let mut skipped = vec![];
+  items.retain(|item| {
+    let reason = if item.sha1.len() != 40 {
+      Some(ImportSkipReason::BadSha1Length)
+    } else {
+      match item.map.as_ref().map(Map::validate) {
+        Some(Err(err)) => {
+          tracing::warn!(sha1 = %item.sha1, "skipping map checksum import item: {}", err);
+          Some(ImportSkipReason::from_validation_error(&err))
+        }
+        _ => None,
+      }
+    };
+    match reason {
+      Some(reason) => {
+        skipped.push(ImportSkip {
+          sha1: item.sha1.clone(),
+          reason,
+        });
+        false
+      }
+      None => true,
+    }
+  });
+
+  items.sort_by_cached_key(|i| i.sha1.clone());
+  let mut prev: Option<String> = None;
+  items.retain(|item| {
+    if prev.as_ref() == Some(&item.sha1) {
+      skipped.push(ImportSkip {
+        sha1: item.sha1.clone(),
+        reason: ImportSkipReason::Duplicate,
+      });
+      false
+    } else {
+      prev = Some(item.sha1.clone());
+      true
+    }
+  });
+
+  skipped
 }
 
-pub fn import(conn: &DbConn, mut items: Vec<ImportItem>) -> Result<usize> {
+pub fn import(conn: &DbConn, mut items: Vec<ImportItem>) -> Result<ImportResult> {
   use diesel::pg::upsert::excluded;
   use map_checksum::dsl;
 
-  items.sort_by_cached_key(|i| i.sha1.clone());
-  items.dedup_by(|a, b| a.sha1 == b.sha1);
+  let skipped = filter_items(&mut items);
+  if items.is_empty() {
+    return Ok(ImportResult {
+      updated: 0,
+      skipped,
+    });
+  }
 
-  let inserts: Vec<_> = items
+  let inserts = items
     .iter()
-    .map(|item| Insert {
-      sha1: item.sha1.as_ref(),
-      checksum: item.checksum.to_le_bytes().to_vec(),
+    .map(|item| -> Result<_> {
+      Ok(Insert {
+        sha1: item.sha1.as_ref(),
+        checksum: item.checksum.to_le_bytes().to_vec(),
+        meta: item.map.as_ref().map(serde_json::to_value).transpose()?,
+      })
     })
-    .collect();
+    .collect::<Result<Vec<_>>>()?;
 
-  diesel::insert_into(map_checksum::table)
+  // items imported without map info keep the stored one
+  let updated = diesel::insert_into(map_checksum::table)
     .values(inserts)
     .on_conflict(dsl::sha1)
     .do_update()
-    .set(dsl::checksum.eq(excluded(dsl::checksum)))
-    .execute(conn)
-    .map_err(Into::into)
+    .set((
+      dsl::checksum.eq(excluded(dsl::checksum)),
+      dsl::meta.eq(diesel::dsl::sql::<
+        diesel::sql_types::Nullable<diesel::sql_types::Jsonb>,
+      >("coalesce(excluded.meta, map_checksum.meta)")),
+    ))
+    .execute(conn)?;
+
+  Ok(ImportResult { updated, skipped })
 }
 
 #[derive(Debug, Insertable)]
@@ -61,4 +182,89 @@ pub fn import(conn: &DbConn, mut items: Vec<ImportItem>) -> Result<usize> {
 struct Insert<'a> {
   sha1: &'a str,
   checksum: Vec<u8>,
+  meta: Option<Value>,
+}
+
+#[test]
+fn test_filter_items() {
+  let map = |width: u32, player_set: u32| Map {
+    width,
+    ..crate::map::test_map(0, player_set)
+  };
+  let item = |sha1: &str, map: Option<Map>| ImportItem {
+    sha1: sha1.to_string(),
+    checksum: 1,
+    map,
+  };
+
+  let sha1 = |c: char| std::iter::repeat(c).take(40).collect::<String>();
+  let skip = |sha1: String, reason: ImportSkipReason| ImportSkip { sha1, reason };
+
+  let mut items = vec![
+    item(&sha1('b'), Some(map(64, 0))),
+    item(&sha1('a'), None),
+    item(&sha1('c'), Some(map(0, 0))),
+    item(&sha1('d'), Some(map(64, 0b1))),
+    item("abc", None),
+    item(&sha1('a'), Some(map(64, 0))),
+  ];
+  assert_eq!(
+    filter_items(&mut items),
+    vec![
+      skip(sha1('c'), ImportSkipReason::InvalidDimensions),
+      skip(sha1('d'), ImportSkipReason::InvalidLayout),
+      skip("abc".to_string(), ImportSkipReason::BadSha1Length),
+      skip(sha1('a'), ImportSkipReason::Duplicate),
+    ]
+  );
+  assert_eq!(
+    items.iter().map(|i| i.sha1.clone()).collect::<Vec<_>>(),
+    vec![sha1('a'), sha1('b')]
+  );
+  // the first item of a duplicated sha1 is kept
+  assert!(items[0].map.is_none());
+}
+
+#[test]
+fn test_filter_items_one_good_two_bad() {
+  let item = |sha1: &str| ImportItem {
+    sha1: sha1.to_string(),
+    checksum: 1,
+    map: None,
+  };
+  let good = std::iter::repeat('f').take(40).collect::<String>();
+
+  let mut items = vec![item(&good), item(&good), item("f")];
+  let skipped = filter_items(&mut items);
+  assert_eq!(
+    skipped.iter().map(|s| s.reason).collect::<Vec<_>>(),
+    vec![ImportSkipReason::BadSha1Length, ImportSkipReason::Duplicate]
+  );
+  assert_eq!(items.len(), 1);
+  assert_eq!(items[0].sha1, good);
+}
+
+#[test]
+fn test_parse_meta() {
+  use crate::map::{test_map, MapSha1};
+
+  let mut map = test_map(2, 0b11);
+  map.sha1 = MapSha1([0xab; 20]);
+  map.name = "Echo Isles".to_string();
+  map.author = "Blizzard".to_string();
+  map.width = 96;
+  map.height = 96;
+
+  // stored by `import`, read by `get_by_sha1`
+  let stored = serde_json::to_value(&map).unwrap();
+  let parsed = parse_meta(Some(stored)).unwrap().unwrap();
+  assert_eq!(parsed.sha1.to_hex(), "ab".repeat(20));
+  assert_eq!(parsed.name, "Echo Isles");
+  assert_eq!(parsed.author, "Blizzard");
+  assert_eq!((parsed.width, parsed.height), (96, 96));
+  assert_eq!(parsed.players.len(), 2);
+  assert_eq!(parsed.forces[0].player_set, 0b11);
+
+  assert!(parse_meta(None).unwrap().is_none());
+  assert!(parse_meta(Some(Value::Bool(true))).is_err());
 }
@@ -1,8 +1,12 @@
+pub mod checksum;
 pub mod db;
 
+use crate::error::Error;
 use s2_grpc_utils::result::Error as ProtoError;
 use s2_grpc_utils::{S2ProtoPack, S2ProtoUnpack};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Serialize, Deserialize, S2ProtoPack, S2ProtoUnpack, Clone)]
 #[s2_grpc(message_type = "flo_grpc::game::Map")]
@@ -21,6 +25,48 @@ pub struct Map {
   pub twelve_p: bool,
 }
 
+impl Map {
+  /// Rejects maps whose dimensions or slot layout can't have come from a real map file
+  pub fn validate(&self) -> Result<(), MapValidationError> {
+    if self.width == 0 || self.height == 0 {
+      return Err(MapValidationError::ZeroDimensions {
+        width: self.width,
+        height: self.height,
+      });
+    }
+
+    self.validate_forces(&self.forces)?;
+
+    let players = self.players.len();
+    if self.twelve_p && players > 12 {
+      return Err(MapValidationError::TwelvePlayersUnsupported { players });
+    }
+
+    Ok(())
+  }
+
+  /// Rejects forces referencing a player slot the map doesn't have
+  pub fn validate_forces(&self, forces: &[MapForce]) -> Result<(), MapValidationError> {
+    let players = self.players.len();
+    for (force, item) in forces.iter().enumerate() {
+      if let Some(index) = (0..32).find(|i| item.player_set & (1 << i) != 0 && *i >= players) {
+        return Err(MapValidationError::ForcePlayerOutOfRange { force, index });
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum MapValidationError {
+  #[error("Invalid map dimensions: {width}x{height}")]
+  ZeroDimensions { width: u32, height: u32 },
+  #[error("Force {force} references player {index} which does not exist")]
+  ForcePlayerOutOfRange { force: usize, index: usize },
+  #[error("Map is flagged as 12 players but has {players} player slots")]
+  TwelvePlayersUnsupported { players: usize },
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(transparent)]
 pub struct MapSha1(pub [u8; 20]);
@@ -29,6 +75,37 @@ impl MapSha1 {
   pub fn to_vec(&self) -> Vec<u8> {
     self.0.to_vec()
   }
+
+  /// Lowercase 40-char hex string
+  pub fn to_hex(&self) -> String {
+    self.0.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  pub fn from_hex(s: &str) -> Result<Self, Error> {
+    if s.len() != 40 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+      return Err(Error::MapSha1HexInvalid(s.to_string()));
+    }
+    let mut bytes = [0_u8; 20];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+      *byte = u8::from_str_radix(&s[(i * 2)..(i * 2 + 2)], 16)
+        .map_err(|_| Error::MapSha1HexInvalid(s.to_string()))?;
+    }
+    Ok(MapSha1(bytes))
+  }
+}
+
+impl fmt::Display for MapSha1 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.to_hex())
+  }
+}
+
+impl FromStr for MapSha1 {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Self::from_hex(s)
+  }
 }
 
 impl S2ProtoUnpack<Vec<u8>> for MapSha1 {
@@ -65,3 +142,127 @@ pub struct MapForce {
   pub flags: u32,
   pub player_set: u32,
 }
+
+#[test]
+fn test_map_sha1_hex() {
+  let sha1 = MapSha1([
+    0x00, 0x01, 0x0a, 0xff, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80, 0x90, 0xa0, 0xb0, 0xc0,
+    0xd0, 0xe0, 0xf0, 0x7f,
+  ]);
+  let hex = sha1.to_hex();
+  assert_eq!(hex, "00010aff102030405060708090a0b0c0d0e0f07f");
+  assert_eq!(sha1.to_string(), hex);
+  assert_eq!(MapSha1::from_hex(&hex).unwrap().0, sha1.0);
+  assert_eq!(hex.to_uppercase().parse::<MapSha1>().unwrap().0, sha1.0);
+
+  // odd length
+  assert!(MapSha1::from_hex(&hex[..39]).is_err());
+  assert!(MapSha1::from_hex(&hex[..38]).is_err());
+  assert!(MapSha1::from_hex(&format!("{}0", hex)).is_err());
+  assert!(MapSha1::from_hex(&hex.replace("f", "g")).is_err());
+  assert!(MapSha1::from_hex("+1010aff102030405060708090a0b0c0d0e0f07f").is_err());
+  assert!(MapSha1::from_hex("").is_err());
+}
+
+#[cfg(test)]
+pub(crate) fn test_map(players: usize, player_set: u32) -> Map {
+  Map {
+    sha1: MapSha1([0; 20]),
+    checksum: 0,
+    name: "map".to_string(),
+    description: String::new(),
+    author: String::new(),
+    path: "maps/map.w3x".to_string(),
+    width: 64,
+    height: 64,
+    players: (0..players)
+      .map(|i| MapPlayer {
+        name: format!("Player {}", i + 1),
+        r#type: 1,
+        race: 0,
+        flags: 0,
+      })
+      .collect(),
+    forces: vec![MapForce {
+      name: "Force 1".to_string(),
+      flags: 0,
+      player_set,
+    }],
+    twelve_p: false,
+  }
+}
+
+#[test]
+fn test_map_validate() {
+  let map = test_map(2, 0b11);
+  assert_eq!(map.validate(), Ok(()));
+
+  let mut map = test_map(24, 0x00FF_FFFF);
+  assert_eq!(map.validate(), Ok(()));
+  map.twelve_p = true;
+  assert_eq!(
+    map.validate(),
+    Err(MapValidationError::TwelvePlayersUnsupported { players: 24 })
+  );
+
+  let mut map = test_map(12, 0x0FFF);
+  map.twelve_p = true;
+  assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_map_validate_zero_dimensions() {
+  let mut map = test_map(2, 0b11);
+  map.width = 0;
+  assert_eq!(
+    map.validate(),
+    Err(MapValidationError::ZeroDimensions {
+      width: 0,
+      height: 64
+    })
+  );
+
+  let mut map = test_map(2, 0b11);
+  map.height = 0;
+  assert_eq!(
+    map.validate(),
+    Err(MapValidationError::ZeroDimensions {
+      width: 64,
+      height: 0
+    })
+  );
+}
+
+#[test]
+fn test_map_validate_forces() {
+  let force = |player_set| MapForce {
+    name: "Force".to_string(),
+    flags: 0,
+    player_set,
+  };
+  let map = test_map(4, 0b1111);
+  assert_eq!(map.validate_forces(&[force(0b0011), force(0b1100)]), Ok(()));
+  assert_eq!(
+    map.validate_forces(&[force(0b0011), force(0b11100)]),
+    Err(MapValidationError::ForcePlayerOutOfRange { force: 1, index: 4 })
+  );
+}
+
+#[test]
+fn test_map_validate_force_player_out_of_range() {
+  let map = test_map(2, 0b101);
+  assert_eq!(
+    map.validate(),
+    Err(MapValidationError::ForcePlayerOutOfRange { force: 0, index: 2 })
+  );
+
+  let mut map = test_map(0, 0);
+  map.forces[0].player_set = 1 << 31;
+  assert_eq!(
+    map.validate(),
+    Err(MapValidationError::ForcePlayerOutOfRange {
+      force: 0,
+      index: 31
+    })
+  );
+}
@@ -2,11 +2,13 @@ pub mod db;
 mod state;
 mod types;
 
-pub use state::conn::NodeConnActor;
+pub use state::conn::{NodeConnActor, NodeHealth};
 pub use state::request::PlayerLeaveResponse;
 pub use state::NodeRegistry;
 pub use types::*;
 pub mod messages {
-  pub use crate::node::state::conn::{NodeCreateGame, NodePlayerLeave};
-  pub use crate::node::state::ListNode;
+  pub use crate::node::state::conn::{NodeCreateGame, NodePlayerLeave, NodeRenewPlayerToken};
+  pub use crate::node::state::{
+    GetFullNodeIds, GetNodeHealth, ListNode, ReserveNodeGame, SetNodeGame,
+  };
 }
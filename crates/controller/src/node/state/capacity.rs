@@ -0,0 +1,98 @@
+use crate::error::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Active games per node, used to enforce `Node::max_games`
+#[derive(Debug, Default)]
+pub struct NodeGameCounter {
+  game_node_map: BTreeMap<i32, i32>,
+  node_games_map: BTreeMap<i32, BTreeSet<i32>>,
+}
+
+impl NodeGameCounter {
+  pub fn count(&self, node_id: i32) -> usize {
+    self
+      .node_games_map
+      .get(&node_id)
+      .map(|games| games.len())
+      .unwrap_or(0)
+  }
+
+  pub fn is_full(&self, node_id: i32, max_games: Option<i32>) -> bool {
+    max_games
+      .map(|max| self.count(node_id) >= max.max(0) as usize)
+      .unwrap_or(false)
+  }
+
+  /// Moves `game_id` to `node_id` if the node has room for it,
+  /// returns the node the game was on before
+  pub fn reserve(
+    &mut self,
+    game_id: i32,
+    node_id: Option<i32>,
+    max_games: Option<i32>,
+  ) -> Result<Option<i32>> {
+    let prev = self.game_node_map.get(&game_id).cloned();
+    if let Some(node_id) = node_id {
+      if prev != Some(node_id) && self.is_full(node_id, max_games) {
+        return Err(Error::NodeAtCapacity);
+      }
+    }
+    self.set(game_id, node_id);
+    Ok(prev)
+  }
+
+  /// Moves `game_id` to `node_id` regardless of the capacity
+  pub fn set(&mut self, game_id: i32, node_id: Option<i32>) {
+    if let Some(prev) = self.game_node_map.remove(&game_id) {
+      if let Some(games) = self.node_games_map.get_mut(&prev) {
+        games.remove(&game_id);
+        if games.is_empty() {
+          self.node_games_map.remove(&prev);
+        }
+      }
+    }
+    if let Some(node_id) = node_id {
+      self.game_node_map.insert(game_id, node_id);
+      self
+        .node_games_map
+        .entry(node_id)
+        .or_default()
+        .insert(game_id);
+    }
+  }
+}
+
+#[test]
+fn test_node_game_counter() {
+  let mut counter = NodeGameCounter::default();
+
+  // fill node 1 to capacity
+  assert_eq!(counter.reserve(1, Some(1), Some(2)).unwrap(), None);
+  assert_eq!(counter.reserve(2, Some(1), Some(2)).unwrap(), None);
+  assert_eq!(counter.count(1), 2);
+  assert!(counter.is_full(1, Some(2)));
+  assert!(matches!(
+    counter.reserve(3, Some(1), Some(2)),
+    Err(Error::NodeAtCapacity)
+  ));
+  assert_eq!(counter.count(1), 2);
+
+  // re-selecting the same node doesn't need room
+  assert_eq!(counter.reserve(2, Some(1), Some(2)).unwrap(), Some(1));
+
+  // unlimited nodes never fill up
+  assert_eq!(counter.reserve(3, Some(2), None).unwrap(), None);
+  assert!(!counter.is_full(2, None));
+
+  // moving a game away frees a slot
+  assert_eq!(counter.reserve(2, Some(2), None).unwrap(), Some(1));
+  assert_eq!(counter.count(1), 1);
+  assert_eq!(counter.reserve(3, Some(1), Some(2)).unwrap(), Some(2));
+  assert_eq!(counter.count(2), 1);
+
+  // ended games free their slot
+  counter.set(1, None);
+  assert_eq!(counter.count(1), 1);
+  assert!(!counter.is_full(1, Some(2)));
+  assert!(counter.is_full(1, Some(0)));
+}
@@ -3,10 +3,11 @@ use crate::game::state::GameRegistry;
 use crate::game::state::{GameSlotClientStatusUpdate, GameStatusUpdate};
 use crate::game::{Game, GameStatus};
 use crate::node::state::request::{CreatedGameInfo, NodeRequestActor, NodeRequestExt};
-use crate::node::{NodeConnConfig, PlayerLeaveResponse};
+use crate::node::{NodeConnConfig, PlayerLeaveResponse, PlayerToken};
 use crate::state::ActorMapExt;
 use backoff::backoff::Backoff;
 use backoff::ExponentialBackoff;
+use chrono::{DateTime, Utc};
 use flo_net::packet::*;
 use flo_net::proto::flo_node::*;
 use flo_net::stream::FloStream;
@@ -15,17 +16,21 @@ use flo_state::{async_trait, Actor, Addr, Context, Handler, Message, Owner};
 use s2_grpc_utils::{S2ProtoEnum, S2ProtoUnpack};
 use std::collections::BTreeMap;
 
-use crate::game::state::registry::Remove;
+use crate::game::state::registry::{NodeDisconnected, Remove};
 use crate::player::PlayerBanType;
 use flo_net::ping::{PingMsg, PingStream};
 use futures::StreamExt;
+use parking_lot::Mutex;
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::time::sleep;
 use tracing_futures::Instrument;
 
 const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// 3 missed pings
+const NODE_HEALTH_TIMEOUT: Duration = Duration::from_secs(90);
 
 pub struct NodeConnActor {
   config: NodeConnConfig,
@@ -33,16 +38,37 @@ pub struct NodeConnActor {
   status: NodeConnStatus,
   request_actor: Option<Owner<NodeRequestActor>>,
   game_reg_addr: Addr<GameRegistry>,
+  health: Arc<Mutex<NodeHealth>>,
 }
 
 impl NodeConnActor {
   pub fn new(config: NodeConnConfig, game_reg_addr: Addr<GameRegistry>) -> Self {
+    let health = Arc::new(Mutex::new(NodeHealth {
+      node_id: config.id,
+      connected: false,
+      last_seen: None,
+    }));
     Self {
       config,
       status: NodeConnStatus::Connecting,
       reconnect_backoff: None,
       request_actor: None,
       game_reg_addr,
+      health,
+    }
+  }
+
+  /// Shared with the registry so health checks don't wait for a reconnecting actor
+  pub fn health(&self) -> Arc<Mutex<NodeHealth>> {
+    self.health.clone()
+  }
+
+  fn set_status(&mut self, status: NodeConnStatus) {
+    self.status = status;
+    let mut health = self.health.lock();
+    health.connected = status == NodeConnStatus::Connected;
+    if health.connected {
+      health.last_seen = Some(Utc::now());
     }
   }
 
@@ -84,6 +110,7 @@ impl From<flo_net::error::Error> for NodeConnectError {
 impl NodeConnActor {
   fn schedule_reconnect(&mut self, ctx: &mut Context<Self>) {
     self.request_actor.take();
+    self.set_status(NodeConnStatus::Connecting);
 
     let delay = self
       .reconnect_backoff
@@ -134,7 +161,12 @@ impl NodeConnActor {
     Ok(stream)
   }
 
-  async fn stream_worker(addr: Addr<Self>, mut rx: mpsc::Receiver<Frame>, mut stream: FloStream) {
+  async fn stream_worker(
+    addr: Addr<Self>,
+    mut rx: mpsc::Receiver<Frame>,
+    mut stream: FloStream,
+    health: Arc<Mutex<NodeHealth>>,
+  ) {
     let mut ping = PingStream::interval(Duration::from_secs(30), Duration::from_secs(10));
     ping.start();
 
@@ -166,6 +198,8 @@ impl NodeConnActor {
         res = stream.recv_frame() => {
           match res {
             Ok(frame) => {
+              health.lock().last_seen = Some(Utc::now());
+
               if frame.type_id == PacketTypeId::Pong {
                 ping.capture_pong(frame);
                 continue;
@@ -212,7 +246,7 @@ impl Handler<Connect> for NodeConnActor {
     let (ip, port) = match parse_addr(&self.config.addr) {
       Ok(v) => v,
       Err(err) => {
-        self.status = NodeConnStatus::Error;
+        self.set_status(NodeConnStatus::Error);
         tracing::error!(node_id = self.config.id, "parse node address: {}", err);
         return;
       }
@@ -227,18 +261,19 @@ impl Handler<Connect> for NodeConnActor {
         return;
       }
       Err(NodeConnectError::Fatal(err)) => {
-        self.status = NodeConnStatus::Error;
+        self.set_status(NodeConnStatus::Error);
         tracing::error!(node_id, "fatal error: {}", err);
         return;
       }
     };
     let (tx, rx) = mpsc::channel(32);
     ctx.spawn(
-      Self::stream_worker(ctx.addr(), rx, stream)
+      Self::stream_worker(ctx.addr(), rx, stream, self.health.clone())
         .instrument(tracing::debug_span!("stream_worker", node_id)),
     );
     self.request_actor = NodeRequestActor::new(tx).start().into();
     self.reconnect_backoff.take();
+    self.set_status(NodeConnStatus::Connected);
   }
 }
 
@@ -252,6 +287,13 @@ impl Message for Disconnected {
 impl Handler<Disconnected> for NodeConnActor {
   async fn handle(&mut self, ctx: &mut Context<Self>, _: Disconnected) {
     self.schedule_reconnect(ctx);
+    self
+      .game_reg_addr
+      .notify(NodeDisconnected {
+        node_id: self.config.id,
+      })
+      .await
+      .ok();
   }
 }
 
@@ -322,6 +364,29 @@ impl Handler<IncomingFrame> for NodeConnActor {
             )
           )
         }
+        packet: PacketControllerRenewPlayerTokenAccept => {
+          let token = PlayerToken::unpack(packet.player_token.extract()?)?;
+          Parsed::Response(
+            RequestDone::new(
+              RequestId::RenewPlayerToken {
+                game_id: packet.game_id,
+                player_id: token.player_id,
+              },
+              Ok(Response::PlayerTokenRenewed(token)),
+            )
+          )
+        }
+        packet: PacketControllerRenewPlayerTokenReject => {
+          Parsed::Response(
+            RequestDone::new(
+              RequestId::RenewPlayerToken {
+                game_id: packet.game_id,
+                player_id: packet.player_id,
+              },
+              Err(Error::PlayerNotInGame),
+            )
+          )
+        }
         packet: PacketClientUpdateSlotClientStatus => {
           Parsed::GameSlotClientStatusUpdate(S2ProtoUnpack::unpack(packet)?)
         }
@@ -440,6 +505,59 @@ impl Handler<NodePlayerLeave> for NodeConnActor {
   }
 }
 
+pub struct NodeRenewPlayerToken {
+  pub game_id: i32,
+  pub player_id: i32,
+}
+
+impl Message for NodeRenewPlayerToken {
+  type Result = Result<FutureReply<Result<PlayerToken>>>;
+}
+
+#[async_trait]
+impl Handler<NodeRenewPlayerToken> for NodeConnActor {
+  async fn handle(
+    &mut self,
+    ctx: &mut Context<Self>,
+    NodeRenewPlayerToken { game_id, player_id }: NodeRenewPlayerToken,
+  ) -> Result<FutureReply<Result<PlayerToken>>> {
+    let addr = self
+      .request_actor
+      .as_ref()
+      .map(|v| v.addr())
+      .ok_or_else(|| Error::NodeNotReady)?;
+    let (tx, rx) = FutureReply::channel();
+    ctx.spawn(async move {
+      tx.send(addr.renew_player_token(game_id, player_id).await)
+        .ok();
+    });
+    Ok(rx)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeHealth {
+  pub node_id: i32,
+  pub connected: bool,
+  pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl NodeHealth {
+  pub fn is_healthy(&self, now: DateTime<Utc>) -> bool {
+    self.connected
+      && self
+        .last_seen
+        .map(|t| {
+          now
+            .signed_duration_since(t)
+            .to_std()
+            .map(|d| d < NODE_HEALTH_TIMEOUT)
+            .unwrap_or(true)
+        })
+        .unwrap_or(false)
+  }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 enum NodeConnStatus {
   Connecting,
@@ -470,3 +588,22 @@ fn parse_addr(addr: &str) -> Result<(Ipv4Addr, u16)> {
   };
   Ok((ip, port))
 }
+
+#[test]
+fn test_node_health() {
+  let now = Utc::now();
+  let health = |connected, last_seen_secs_ago: Option<i64>| NodeHealth {
+    node_id: 1,
+    connected,
+    last_seen: last_seen_secs_ago.map(|secs| now - chrono::Duration::seconds(secs)),
+  };
+
+  assert!(health(true, Some(0)).is_healthy(now));
+  assert!(health(true, Some(60)).is_healthy(now));
+  // node went down, the controller is reconnecting
+  assert!(!health(false, Some(0)).is_healthy(now));
+  // never connected
+  assert!(!health(false, None).is_healthy(now));
+  // connection still open but nothing received for too long
+  assert!(!health(true, Some(90)).is_healthy(now));
+}
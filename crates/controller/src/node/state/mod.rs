@@ -1,3 +1,4 @@
+pub mod capacity;
 pub mod conn;
 pub mod request;
 
@@ -8,10 +9,12 @@ use crate::node::{Node, NodeConnConfig};
 use crate::player::state::sender::PlayerRegistryHandle;
 use crate::state::{Data, GetActorEntry, Reload};
 use arc_swap::ArcSwap;
-use conn::NodeConnActor;
+use capacity::NodeGameCounter;
+use conn::{NodeConnActor, NodeHealth};
 use flo_state::{
   async_trait, Actor, Addr, Context, Deferred, Handler, Message, Owner, RegistryRef, Service,
 };
+use parking_lot::Mutex;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
@@ -20,6 +23,8 @@ pub struct NodeRegistry {
   game_reg_addr: Deferred<GameRegistry, Data>,
   player_reg_handle: PlayerRegistryHandle,
   map: BTreeMap<i32, Owner<NodeConnActor>>,
+  health_map: BTreeMap<i32, Arc<Mutex<NodeHealth>>>,
+  game_counter: NodeGameCounter,
   nodes_snapshot: ArcSwap<Vec<Node>>,
 }
 
@@ -35,6 +40,8 @@ impl Service<Data> for NodeRegistry {
       game_reg_addr,
       player_reg_handle: PlayerRegistryHandle::from(player_reg_addr),
       map: BTreeMap::new(),
+      health_map: BTreeMap::new(),
+      game_counter: NodeGameCounter::default(),
       nodes_snapshot: ArcSwap::new(Arc::new(vec![])),
     })
   }
@@ -56,17 +63,38 @@ impl NodeRegistry {
 
     for node in &nodes {
       tracing::debug!(node_id = node.id, "added");
-      self.map.insert(
-        node.id,
-        NodeConnActor::new(node.into(), game_reg_addr.clone()).start(),
-      );
+      self.add_conn(node.into(), game_reg_addr.clone());
     }
 
     self.nodes_snapshot.swap(Arc::new(nodes));
 
+    let game_nodes = self
+      .db
+      .exec(|conn| crate::game::db::get_active_game_nodes(conn))
+      .await?;
+    for (game_id, node_id) in game_nodes {
+      self.game_counter.set(game_id, Some(node_id));
+    }
+
     Ok(())
   }
 
+  fn max_games(&self, node_id: i32) -> Option<i32> {
+    self
+      .nodes_snapshot
+      .load()
+      .iter()
+      .find(|node| node.id == node_id)
+      .and_then(|node| node.max_games)
+  }
+
+  fn add_conn(&mut self, config: NodeConnConfig, game_reg_addr: Addr<GameRegistry>) {
+    let id = config.id;
+    let actor = NodeConnActor::new(config, game_reg_addr);
+    self.health_map.insert(id, actor.health());
+    self.map.insert(id, actor.start());
+  }
+
   async fn load_snapshot(&mut self) -> Result<Vec<Node>> {
     let nodes = self
       .db
@@ -103,6 +131,7 @@ impl Handler<Reload> for NodeRegistry {
       for id in self.map.keys().cloned().collect::<Vec<i32>>() {
         if !new_ids.contains(&id) {
           self.map.remove(&id);
+          self.health_map.remove(&id);
           broadcast_frames.push(PacketRemoveNode { node_id: id }.encode_as_frame()?);
           tracing::info!(id, "node removed");
         }
@@ -112,10 +141,8 @@ impl Handler<Reload> for NodeRegistry {
       let config = NodeConnConfig::from(node);
       if !self.map.contains_key(&config.id) {
         tracing::info!(id = config.id, "node added: {}", config.addr);
-        self.map.insert(
-          config.id,
-          NodeConnActor::new(config, self.game_reg_addr.resolve().await?).start(),
-        );
+        let game_reg_addr = self.game_reg_addr.resolve().await?;
+        self.add_conn(config, game_reg_addr);
         broadcast_frames.push(
           PacketAddNode {
             node: node.clone().pack()?,
@@ -150,3 +177,80 @@ impl Handler<ListNode> for NodeRegistry {
     Vec::<_>::clone(&self.nodes_snapshot.load())
   }
 }
+
+pub struct GetNodeHealth;
+
+impl Message for GetNodeHealth {
+  type Result = Vec<NodeHealth>;
+}
+
+#[async_trait]
+impl Handler<GetNodeHealth> for NodeRegistry {
+  async fn handle(&mut self, _: &mut Context<Self>, _: GetNodeHealth) -> Vec<NodeHealth> {
+    self
+      .health_map
+      .values()
+      .map(|health| health.lock().clone())
+      .collect()
+  }
+}
+
+/// Assigns a game to a node if the node is below its `max_games`
+pub struct ReserveNodeGame {
+  pub game_id: i32,
+  pub node_id: Option<i32>,
+}
+
+impl Message for ReserveNodeGame {
+  type Result = Result<Option<i32>>;
+}
+
+#[async_trait]
+impl Handler<ReserveNodeGame> for NodeRegistry {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    ReserveNodeGame { game_id, node_id }: ReserveNodeGame,
+  ) -> Result<Option<i32>> {
+    let max_games = node_id.and_then(|node_id| self.max_games(node_id));
+    self.game_counter.reserve(game_id, node_id, max_games)
+  }
+}
+
+/// Assigns a game to a node without checking the capacity,
+/// used to roll back a reservation and to release the node of a removed game
+pub struct SetNodeGame {
+  pub game_id: i32,
+  pub node_id: Option<i32>,
+}
+
+impl Message for SetNodeGame {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<SetNodeGame> for NodeRegistry {
+  async fn handle(&mut self, _: &mut Context<Self>, SetNodeGame { game_id, node_id }: SetNodeGame) {
+    self.game_counter.set(game_id, node_id);
+  }
+}
+
+/// Ids of the nodes hosting `max_games` active games
+pub struct GetFullNodeIds;
+
+impl Message for GetFullNodeIds {
+  type Result = Vec<i32>;
+}
+
+#[async_trait]
+impl Handler<GetFullNodeIds> for NodeRegistry {
+  async fn handle(&mut self, _: &mut Context<Self>, _: GetFullNodeIds) -> Vec<i32> {
+    self
+      .nodes_snapshot
+      .load()
+      .iter()
+      .filter(|node| self.game_counter.is_full(node.id, node.max_games))
+      .map(|node| node.id)
+      .collect()
+  }
+}
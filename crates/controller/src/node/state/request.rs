@@ -54,12 +54,14 @@ impl Drop for PendingRequest {
 pub enum RequestId {
   CreateGame(i32),
   PlayerLeave(PlayerLeaveRequestId),
+  RenewPlayerToken { game_id: i32, player_id: i32 },
 }
 
 #[derive(Debug)]
 pub enum Response {
   GameCreated(CreatedGameInfo),
   PlayerLeave(PlayerLeaveResponse),
+  PlayerTokenRenewed(PlayerToken),
 }
 
 #[derive(Debug, S2ProtoUnpack)]
@@ -233,6 +235,7 @@ pub trait NodeRequestExt {
     ban_list_map: BTreeMap<i32, Vec<PlayerBanType>>,
   ) -> Result<CreatedGameInfo>;
   async fn player_force_leave(&self, game_id: i32, player_id: i32) -> Result<PlayerLeaveResponse>;
+  async fn renew_player_token(&self, game_id: i32, player_id: i32) -> Result<PlayerToken>;
 }
 
 #[async_trait]
@@ -323,4 +326,19 @@ impl NodeRequestExt for Addr<NodeRequestActor> {
       }
     }
   }
+  async fn renew_player_token(&self, game_id: i32, player_id: i32) -> Result<PlayerToken> {
+    let req = Request {
+      id: RequestId::RenewPlayerToken { game_id, player_id },
+      frame: PacketControllerRenewPlayerToken { game_id, player_id }.encode_as_frame()?,
+    };
+
+    let res = self.send(req).await??;
+    match res.await? {
+      Response::PlayerTokenRenewed(token) => Ok(token),
+      other => {
+        tracing::error!(game_id, "unexpected node response: {:?}", other);
+        Err(Error::NodeResponseUnexpected)
+      }
+    }
+  }
 }
@@ -20,6 +20,9 @@ pub struct Node {
   pub country_id: String,
   #[s2_grpc(skip_pack)]
   pub disabled: bool,
+  /// Maximum number of active games hosted at the same time, unlimited if not set
+  #[s2_grpc(skip_pack)]
+  pub max_games: Option<i32>,
 }
 
 pub type NodeRefColumns = (
@@ -1,11 +1,17 @@
 use crate::db::DbConn;
 use crate::error::*;
-use crate::player::{Player, PlayerBan, PlayerBanType, PlayerRef, PlayerSource, SourceState};
-use crate::schema::{player, player_ban, player_mute};
+use crate::game::db::split_page;
+use crate::game::Race;
+use crate::player::{
+  IpBan, IpNetwork, Player, PlayerBan, PlayerBanType, PlayerMute, PlayerRef, PlayerSource,
+  SourceState,
+};
+use crate::schema::{ip_ban, player, player_ban, player_mute, player_mutes};
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 
 pub fn get(conn: &DbConn, id: i32) -> Result<Player> {
   player::table
@@ -17,6 +23,32 @@ pub fn get(conn: &DbConn, id: i32) -> Result<Player> {
     .map_err(Into::into)
 }
 
+/// Loads players in the order of `ids`, unknown ids are skipped
+pub fn get_many(conn: &DbConn, ids: &[i32]) -> Result<Vec<Player>> {
+  use diesel::pg::expression::dsl::any;
+  use player::dsl;
+  let rows = player::table
+    .filter(dsl::id.eq(any(ids)))
+    .load::<Row>(conn)?;
+  Ok(
+    sort_by_ids(ids, rows, |row| row.id)
+      .into_iter()
+      .map(Into::into)
+      .collect(),
+  )
+}
+
+fn sort_by_ids<T, F>(ids: &[i32], items: Vec<T>, get_id: F) -> Vec<T>
+where
+  F: Fn(&T) -> i32,
+{
+  let mut map: HashMap<i32, T> = items
+    .into_iter()
+    .map(|item| (get_id(&item), item))
+    .collect();
+  ids.iter().filter_map(|id| map.remove(id)).collect()
+}
+
 pub fn get_ref(conn: &DbConn, id: i32) -> Result<PlayerRef> {
   use player::dsl;
   player::table
@@ -72,6 +104,101 @@ pub fn get_player_map_by_api_source_ids(
   Ok(pairs.into_iter().collect())
 }
 
+pub const SEARCH_QUERY_MIN_LEN: usize = 2;
+const SEARCH_DEFAULT_PAGE_SIZE: i64 = 50;
+const SEARCH_MAX_PAGE_SIZE: i64 = 200;
+
+pub struct SearchPlayers {
+  pub players: Vec<PlayerRef>,
+  pub next_id: Option<i32>,
+}
+
+/// Finds players of `api_client_id` whose name contains `query`, case-insensitive
+pub fn search_by_name(
+  conn: &DbConn,
+  api_client_id: i32,
+  query: &str,
+  next_id: Option<i32>,
+  limit: Option<i64>,
+) -> Result<SearchPlayers> {
+  use player::dsl;
+  let pattern = search_pattern(query)?;
+  let page_size = search_page_size(limit);
+  let mut q = player::table
+    .filter(dsl::api_client_id.eq(api_client_id))
+    .filter(dsl::name.ilike(pattern))
+    .select((dsl::id, dsl::name, dsl::source, dsl::realm))
+    .order(dsl::id)
+    .limit(page_size + 1)
+    .into_boxed();
+
+  if let Some(id) = next_id {
+    q = q.filter(dsl::id.ge(id));
+  }
+
+  let mut players = q.load::<PlayerRef>(conn)?;
+  let next_id = split_page(&mut players, page_size as usize, |player| player.id);
+
+  Ok(SearchPlayers { players, next_id })
+}
+
+/// Builds a `LIKE` pattern matching `query` literally anywhere in the name
+fn search_pattern(query: &str) -> Result<String> {
+  let query = query.trim();
+  if query.chars().count() < SEARCH_QUERY_MIN_LEN {
+    return Err(Error::PlayerSearchQueryTooShort(SEARCH_QUERY_MIN_LEN));
+  }
+  let mut pattern = String::with_capacity(query.len() + 2);
+  pattern.push('%');
+  for c in query.chars() {
+    if matches!(c, '%' | '_' | '\\') {
+      pattern.push('\\');
+    }
+    pattern.push(c);
+  }
+  pattern.push('%');
+  Ok(pattern)
+}
+
+fn search_page_size(limit: Option<i64>) -> i64 {
+  limit
+    .filter(|v| *v > 0)
+    .unwrap_or(SEARCH_DEFAULT_PAGE_SIZE)
+    .min(SEARCH_MAX_PAGE_SIZE)
+}
+
+/// Account a player row was created from, the same account can map to
+/// different players through different API clients
+#[derive(Debug, Clone, PartialEq, Queryable)]
+pub struct SourceIdentity {
+  pub player_id: i32,
+  pub source: PlayerSource,
+  pub source_id: String,
+}
+
+pub fn get_source_identities(conn: &DbConn, ids: &[i32]) -> Result<Vec<SourceIdentity>> {
+  use player::dsl;
+  player::table
+    .filter(dsl::id.eq_any(ids))
+    .select((dsl::id, dsl::source, dsl::source_id))
+    .load(conn)
+    .map_err(Into::into)
+}
+
+/// Returns the id of another player sharing the source account of `player_id`
+pub fn find_source_conflict(player_id: i32, identities: &[SourceIdentity]) -> Option<i32> {
+  let joining = identities.iter().find(|i| i.player_id == player_id)?;
+  if joining.source_id.is_empty() {
+    return None;
+  }
+  identities
+    .iter()
+    .find(|i| {
+      i.player_id != player_id && i.source == joining.source && i.source_id == joining.source_id
+    })
+    .map(|i| i.player_id)
+}
+
 #[derive(Debug, Insertable)]
 #[table_name = "player"]
 pub struct UpsertPlayer {
@@ -90,6 +217,8 @@ pub fn upsert(conn: &DbConn, data: &UpsertPlayer) -> Result<Player> {
     return Err(Error::PlayerSourceIdInvalid);
   }
 
+  crate::player::validate_name(&data.name)?;
+
   diesel::insert_into(player::table)
     .values(data)
     .on_conflict((dsl::api_client_id, dsl::source, dsl::source_id))
@@ -104,6 +233,29 @@ pub fn upsert(conn: &DbConn, data: &UpsertPlayer) -> Result<Player> {
     .map_err(Into::into)
 }
 
+/// Race a player is seated with when joining a game, `None` keeps the slot default
+pub fn set_preferred_race(conn: &DbConn, player_id: i32, race: Option<Race>) -> Result<()> {
+  use player::dsl;
+  let n = diesel::update(player::table.find(player_id))
+    .set(dsl::preferred_race.eq(race))
+    .execute(conn)?;
+  if n == 0 {
+    return Err(Error::PlayerNotFound);
+  }
+  Ok(())
+}
+
+pub fn get_preferred_race(conn: &DbConn, player_id: i32) -> Result<Option<Race>> {
+  use player::dsl;
+  player::table
+    .find(player_id)
+    .select(dsl::preferred_race)
+    .first::<Option<Race>>(conn)
+    .optional()
+    .map(Option::flatten)
+    .map_err(Into::into)
+}
+
 pub fn add_mute(conn: &DbConn, player_id: i32, mute_player_id: i32) -> Result<()> {
   #[derive(Insertable)]
   #[table_name = "player_mute"]
@@ -166,6 +318,8 @@ pub fn list_ban(
   conn: &DbConn,
   api_client_id: i32,
   query: Option<&str>,
+  ban_type: Option<PlayerBanType>,
+  active_only: bool,
   next_id: Option<i32>,
 ) -> Result<ListPlayerBan> {
   const PAGE_SIZE: i64 = 200;
@@ -181,6 +335,18 @@ pub fn list_ban(
     q = q.filter(player::name.ilike(format!("%{}%", v)));
   }
 
+  if let Some(v) = ban_type {
+    q = q.filter(player_ban::ban_type.eq(v));
+  }
+
+  if active_only {
+    q = q.filter(
+      player_ban::ban_expires_at
+        .is_null()
+        .or(player_ban::ban_expires_at.gt(Utc::now())),
+    );
+  }
+
   if let Some(id) = next_id {
     q = q.filter(player_ban::id.ge(id));
   }
@@ -214,6 +380,7 @@ pub fn create_ban(
   player_id: i32,
   ban_type: PlayerBanType,
   ban_expires_at: Option<DateTime<Utc>>,
+  reason: Option<String>,
 ) -> Result<()> {
   #[derive(Insertable)]
   #[table_name = "player_ban"]
@@ -221,22 +388,38 @@ pub fn create_ban(
     player_id: i32,
     ban_type: PlayerBanType,
     ban_expires_at: Option<DateTime<Utc>>,
+    reason: Option<String>,
   }
 
+  let reason = reason.filter(|v| !v.is_empty()).map(truncate_ban_reason);
+
   diesel::insert_into(player_ban::table)
     .values(&Insert {
       player_id,
       ban_type,
       ban_expires_at,
+      reason: reason.clone(),
     })
     .on_conflict((player_ban::player_id, player_ban::ban_type))
     .do_update()
-    .set(player_ban::ban_expires_at.eq(ban_expires_at))
+    .set((
+      player_ban::ban_expires_at.eq(ban_expires_at),
+      player_ban::reason.eq(reason),
+    ))
     .execute(conn)?;
 
   Ok(())
 }
 
+const MAX_BAN_REASON_CHARS: usize = 500;
+
+fn truncate_ban_reason(reason: String) -> String {
+  match reason.char_indices().nth(MAX_BAN_REASON_CHARS) {
+    Some((idx, _)) => reason[..idx].to_string(),
+    None => reason,
+  }
+}
+
 pub fn remove_ban_by_type(conn: &DbConn, player_id: i32, ban_type: PlayerBanType) -> Result<()> {
   diesel::delete(
     player_ban::table.filter(
@@ -294,9 +477,244 @@ pub fn get_ban_list_map(
       .or_insert_with(|| vec![])
       .push(ban_type);
   }
+  merge_muted_players(&mut map, get_muted_player_ids(conn, player_ids)?);
   Ok(map)
 }
 
+/// Moderator mutes are sent to the node as chat bans,
+/// muted players can still play and read the chat.
+/// Stored apart from `player_ban`, so removing a mute keeps a chat ban in place.
+pub fn create_player_mute(
+  conn: &DbConn,
+  player_id: i32,
+  mute_expires_at: Option<DateTime<Utc>>,
+  reason: Option<String>,
+) -> Result<()> {
+  #[derive(Insertable)]
+  #[table_name = "player_mutes"]
+  struct Insert {
+    player_id: i32,
+    mute_expires_at: Option<DateTime<Utc>>,
+    reason: Option<String>,
+  }
+
+  let reason = reason.filter(|v| !v.is_empty()).map(truncate_ban_reason);
+
+  diesel::insert_into(player_mutes::table)
+    .values(&Insert {
+      player_id,
+      mute_expires_at,
+      reason: reason.clone(),
+    })
+    .on_conflict(player_mutes::player_id)
+    .do_update()
+    .set((
+      player_mutes::mute_expires_at.eq(mute_expires_at),
+      player_mutes::reason.eq(reason),
+    ))
+    .execute(conn)?;
+
+  Ok(())
+}
+
+pub fn remove_player_mute(conn: &DbConn, player_id: i32) -> Result<()> {
+  diesel::delete(player_mutes::table.filter(player_mutes::player_id.eq(player_id)))
+    .execute(conn)?;
+  Ok(())
+}
+
+fn get_muted_player_ids(conn: &DbConn, player_ids: &[i32]) -> Result<Vec<i32>> {
+  use diesel::dsl::sql;
+  use diesel::pg::expression::dsl::any;
+  player_mutes::table
+    .select(player_mutes::player_id)
+    .filter(
+      player_mutes::player_id.eq(any(player_ids)).and(
+        player_mutes::mute_expires_at
+          .gt(sql("now()"))
+          .or(player_mutes::mute_expires_at.is_null()),
+      ),
+    )
+    .load(conn)
+    .map_err(Into::into)
+}
+
+fn merge_muted_players(map: &mut BTreeMap<i32, Vec<PlayerBanType>>, muted_player_ids: Vec<i32>) {
+  for player_id in muted_player_ids {
+    let bans = map.entry(player_id).or_insert_with(|| vec![]);
+    if !bans.contains(&PlayerBanType::Chat) {
+      bans.push(PlayerBanType::Chat);
+    }
+  }
+}
+
+pub fn create_ip_ban(
+  conn: &DbConn,
+  api_client_id: i32,
+  network: IpNetwork,
+  ban_expires_at: Option<DateTime<Utc>>,
+  reason: Option<String>,
+) -> Result<()> {
+  #[derive(Insertable)]
+  #[table_name = "ip_ban"]
+  struct Insert {
+    api_client_id: i32,
+    network: String,
+    ban_expires_at: Option<DateTime<Utc>>,
+    reason: Option<String>,
+  }
+
+  let reason = reason.filter(|v| !v.is_empty()).map(truncate_ban_reason);
+
+  diesel::insert_into(ip_ban::table)
+    .values(&Insert {
+      api_client_id,
+      network: network.to_string(),
+      ban_expires_at,
+      reason: reason.clone(),
+    })
+    .on_conflict((ip_ban::api_client_id, ip_ban::network))
+    .do_update()
+    .set((
+      ip_ban::ban_expires_at.eq(ban_expires_at),
+      ip_ban::reason.eq(reason),
+    ))
+    .execute(conn)?;
+
+  Ok(())
+}
+
+pub fn remove_ip_ban(conn: &DbConn, id: i32) -> Result<()> {
+  diesel::delete(ip_ban::table.find(id)).execute(conn)?;
+  Ok(())
+}
+
+pub fn check_ip_ban_api_client_id(conn: &DbConn, api_client_id: i32, id: i32) -> Result<()> {
+  let n = ip_ban::table
+    .filter(
+      ip_ban::id
+        .eq(id)
+        .and(ip_ban::api_client_id.eq(api_client_id)),
+    )
+    .count()
+    .get_result::<i64>(conn)?;
+  if n == 0 {
+    return Err(Error::IpBanOwnerCheckFailed);
+  }
+  Ok(())
+}
+
+/// Returns the id and network of the first active IP ban
+/// of the player's API client that matches `ip`
+pub fn find_ip_ban(conn: &DbConn, player_id: i32, ip: IpAddr) -> Result<Option<(i32, String)>> {
+  use diesel::dsl::sql;
+  let api_client_id: i32 = player::table
+    .find(player_id)
+    .select(player::api_client_id)
+    .first(conn)?;
+  let bans: Vec<(i32, String)> = ip_ban::table
+    .select((ip_ban::id, ip_ban::network))
+    .filter(
+      ip_ban::api_client_id.eq(api_client_id).and(
+        ip_ban::ban_expires_at
+          .gt(sql("now()"))
+          .or(ip_ban::ban_expires_at.is_null()),
+      ),
+    )
+    .order(ip_ban::id)
+    .load(conn)?;
+  Ok(match_ip_ban(bans, ip))
+}
+
+fn match_ip_ban(bans: Vec<(i32, String)>, ip: IpAddr) -> Option<(i32, String)> {
+  bans
+    .into_iter()
+    .find(|(id, network)| ip_ban_contains(*id, network, ip))
+}
+
+fn ip_ban_contains(id: i32, network: &str, ip: IpAddr) -> bool {
+  match network.parse::<IpNetwork>() {
+    Ok(network) => network.contains(ip),
+    Err(err) => {
+      tracing::warn!(ip_ban_id = id, "skipping ip ban: {}", err);
+      false
+    }
+  }
+}
+
+pub struct PlayerModeration {
+  pub player_bans: Vec<PlayerBan>,
+  pub ip_bans: Vec<IpBan>,
+  pub mutes: Vec<PlayerMute>,
+}
+
+impl PlayerModeration {
+  fn retain_active(&mut self, ip: Option<IpAddr>, now: DateTime<Utc>) {
+    self
+      .player_bans
+      .retain(|v| is_active(v.ban_expires_at, now));
+    self.mutes.retain(|v| is_active(v.mute_expires_at, now));
+    self.ip_bans.retain(|v| {
+      is_active(v.ban_expires_at, now)
+        && ip
+          .map(|ip| ip_ban_contains(v.id, &v.network, ip))
+          .unwrap_or(false)
+    });
+  }
+}
+
+fn is_active(expires_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+  expires_at.map(|t| t > now).unwrap_or(true)
+}
+
+/// Returns the bans and mutes of a player that are active at `now`.
+/// IP bans are matched against `ip`, the address the player is connected from,
+/// none are returned if the player is offline.
+pub fn get_moderation(
+  conn: &DbConn,
+  player_id: i32,
+  ip: Option<IpAddr>,
+  now: DateTime<Utc>,
+) -> Result<PlayerModeration> {
+  let api_client_id: i32 = player::table
+    .find(player_id)
+    .select(player::api_client_id)
+    .first(conn)?;
+  let player_bans = player_ban::table
+    .inner_join(player::table)
+    .select(PlayerBan::COLUMNS)
+    .filter(player_ban::player_id.eq(player_id))
+    .order(player_ban::id)
+    .load(conn)?;
+  let mutes = player_mutes::table
+    .select(PlayerMute::COLUMNS)
+    .filter(player_mutes::player_id.eq(player_id))
+    .order(player_mutes::id)
+    .load(conn)?;
+  let ip_bans = if ip.is_some() {
+    ip_ban::table
+      .select(IpBan::COLUMNS)
+      .filter(
+        ip_ban::api_client_id.eq(api_client_id).and(
+          ip_ban::ban_expires_at
+            .is_null()
+            .or(ip_ban::ban_expires_at.gt(now)),
+        ),
+      )
+      .order(ip_ban::id)
+      .load(conn)?
+  } else {
+    vec![]
+  };
+  let mut moderation = PlayerModeration {
+    player_bans,
+    ip_bans,
+    mutes,
+  };
+  moderation.retain_active(ip, now);
+  Ok(moderation)
+}
+
 pub fn check_player_api_client_id(conn: &DbConn, api_client_id: i32, player_id: i32) -> Result<()> {
   let n = player::table
     .filter(
@@ -342,6 +760,7 @@ pub struct Row {
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
   pub api_client_id: i32,
+  pub preferred_race: Option<Race>,
 }
 
 impl From<Row> for Player {
@@ -373,3 +792,302 @@ impl From<Row> for PlayerRef {
     }
   }
 }
+
+#[test]
+fn test_sort_by_ids() {
+  let items = vec![(3, "c"), (1, "a"), (2, "b")];
+  assert_eq!(
+    sort_by_ids(&[2, 4, 1, 3], items, |item| item.0),
+    vec![(2, "b"), (1, "a"), (3, "c")]
+  );
+  assert_eq!(sort_by_ids(&[5], vec![(1, "a")], |item| item.0), vec![]);
+}
+
+#[test]
+fn test_get_many() {
+  crate::db::test::with_transaction(|conn| {
+    let a = crate::db::test::create_player(conn, "get_many_a")?;
+    let b = crate::db::test::create_player(conn, "get_many_b")?;
+    let players = get_many(conn, &[b.id, -1, a.id])?;
+    assert_eq!(
+      players.into_iter().map(|p| p.id).collect::<Vec<_>>(),
+      vec![b.id, a.id]
+    );
+    assert!(get_many(conn, &[-1])?.is_empty());
+    Ok(())
+  });
+}
+
+#[test]
+fn test_truncate_ban_reason() {
+  assert_eq!(truncate_ban_reason("spam".to_string()), "spam");
+  let long = "é".repeat(MAX_BAN_REASON_CHARS + 10);
+  let truncated = truncate_ban_reason(long);
+  assert_eq!(truncated.chars().count(), MAX_BAN_REASON_CHARS);
+  assert!(truncated.chars().all(|c| c == 'é'));
+}
+
+#[test]
+fn test_ban_reason() {
+  crate::db::test::with_transaction(|conn| {
+    let api_client_id = crate::db::test::create_api_client(conn, "ban_reason")?;
+    let player = crate::db::test::create_api_player(conn, api_client_id, "ban_reason")?;
+    let get_reason = || -> Result<Option<String>> {
+      let list = list_ban(conn, api_client_id, None, None, false, None)?;
+      assert_eq!(list.player_bans.len(), 1);
+      Ok(list.player_bans[0].reason.clone())
+    };
+
+    create_ban(
+      conn,
+      player.id,
+      PlayerBanType::Chat,
+      None,
+      Some("spam".to_string()),
+    )?;
+    assert_eq!(get_reason()?, Some("spam".to_string()));
+
+    // updating the ban replaces the reason
+    create_ban(
+      conn,
+      player.id,
+      PlayerBanType::Chat,
+      None,
+      Some("x".repeat(MAX_BAN_REASON_CHARS + 1)),
+    )?;
+    assert_eq!(get_reason()?, Some("x".repeat(MAX_BAN_REASON_CHARS)));
+
+    create_ban(
+      conn,
+      player.id,
+      PlayerBanType::Chat,
+      None,
+      Some(String::new()),
+    )?;
+    assert_eq!(get_reason()?, None);
+    Ok(())
+  });
+}
+
+#[test]
+fn test_list_ban_filters() {
+  use chrono::Duration;
+
+  crate::db::test::with_transaction(|conn| {
+    let api_client_id = crate::db::test::create_api_client(conn, "list_ban")?;
+    let mut ids = vec![];
+    let now = Utc::now();
+    for (name, ban_expires_at) in &[
+      ("list_ban_a", None),
+      ("list_ban_b", Some(now + Duration::hours(1))),
+      ("list_ban_c", Some(now - Duration::hours(1))),
+    ] {
+      let player = crate::db::test::create_api_player(conn, api_client_id, name)?;
+      create_ban(conn, player.id, PlayerBanType::Chat, *ban_expires_at, None)?;
+      ids.push(player.id);
+    }
+    let list = |ban_type, active_only, next_id| -> Result<Vec<i32>> {
+      Ok(
+        list_ban(conn, api_client_id, None, ban_type, active_only, next_id)?
+          .player_bans
+          .into_iter()
+          .map(|v| v.player.id)
+          .collect(),
+      )
+    };
+
+    assert_eq!(list(None, false, None)?, ids);
+    assert_eq!(list(Some(PlayerBanType::Chat), false, None)?, ids);
+    // expired bans are skipped
+    assert_eq!(list(None, true, None)?, ids[..2].to_vec());
+
+    // both filters work with the cursor
+    let bans = list_ban(conn, api_client_id, None, None, false, None)?.player_bans;
+    assert_eq!(
+      list(Some(PlayerBanType::Chat), true, Some(bans[1].id))?,
+      vec![ids[1]]
+    );
+    assert_eq!(list(None, true, Some(bans[2].id))?, vec![]);
+    Ok(())
+  });
+}
+
+#[test]
+fn test_find_source_conflict() {
+  fn identity(player_id: i32, source_id: &str) -> SourceIdentity {
+    SourceIdentity {
+      player_id,
+      source: PlayerSource::BNet,
+      source_id: source_id.to_string(),
+    }
+  }
+
+  let identities = vec![
+    identity(1, "account"),
+    identity(2, "account"),
+    identity(3, "other"),
+  ];
+  assert_eq!(find_source_conflict(1, &identities), Some(2));
+  assert_eq!(find_source_conflict(3, &identities), None);
+
+  // empty source ids and other sources don't conflict
+  let identities = vec![
+    identity(1, ""),
+    identity(2, ""),
+    SourceIdentity {
+      player_id: 3,
+      source: PlayerSource::Api,
+      source_id: "account".to_string(),
+    },
+    identity(4, "account"),
+  ];
+  assert_eq!(find_source_conflict(1, &identities), None);
+  assert_eq!(find_source_conflict(4, &identities), None);
+  assert_eq!(find_source_conflict(5, &identities), None);
+}
+
+#[test]
+fn test_search_pattern() {
+  assert_eq!(search_pattern("Foo").unwrap(), "%Foo%");
+  assert_eq!(search_pattern("  ab ").unwrap(), "%ab%");
+  assert_eq!(search_pattern("5%_x\\").unwrap(), "%5\\%\\_x\\\\%");
+  assert_eq!(search_pattern("我们").unwrap(), "%我们%");
+  assert!(matches!(
+    search_pattern(" a "),
+    Err(Error::PlayerSearchQueryTooShort(SEARCH_QUERY_MIN_LEN))
+  ));
+  assert!(search_pattern("").is_err());
+
+  assert_eq!(search_page_size(None), SEARCH_DEFAULT_PAGE_SIZE);
+  assert_eq!(search_page_size(Some(0)), SEARCH_DEFAULT_PAGE_SIZE);
+  assert_eq!(search_page_size(Some(10)), 10);
+  assert_eq!(search_page_size(Some(10_000)), SEARCH_MAX_PAGE_SIZE);
+}
+
+#[test]
+fn test_merge_muted_players() {
+  let mut map = BTreeMap::new();
+  map.insert(1, vec![PlayerBanType::Chat]);
+  merge_muted_players(&mut map, vec![1, 2]);
+  assert_eq!(map.get(&1), Some(&vec![PlayerBanType::Chat]));
+  assert_eq!(map.get(&2), Some(&vec![PlayerBanType::Chat]));
+  assert_eq!(map.get(&3), None);
+}
+
+#[test]
+fn test_remove_player_mute_keeps_chat_ban() {
+  crate::db::test::with_transaction(|conn| {
+    let banned = crate::db::test::create_player(conn, "mute_banned")?;
+    let muted = crate::db::test::create_player(conn, "mute_only")?;
+    let ids = [banned.id, muted.id];
+    create_ban(conn, banned.id, PlayerBanType::Chat, None, None)?;
+    create_player_mute(conn, banned.id, None, Some("spam".to_string()))?;
+    create_player_mute(conn, muted.id, None, None)?;
+
+    let map = get_ban_list_map(conn, &ids)?;
+    assert_eq!(map.get(&banned.id), Some(&vec![PlayerBanType::Chat]));
+    assert_eq!(map.get(&muted.id), Some(&vec![PlayerBanType::Chat]));
+
+    remove_player_mute(conn, banned.id)?;
+    remove_player_mute(conn, muted.id)?;
+    let map = get_ban_list_map(conn, &ids)?;
+    assert_eq!(map.get(&banned.id), Some(&vec![PlayerBanType::Chat]));
+    assert_eq!(map.get(&muted.id), None);
+    Ok(())
+  });
+}
+
+#[test]
+fn test_match_ip_ban() {
+  let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+  let bans = || {
+    vec![
+      (1, "invalid".to_string()),
+      (2, "192.168.1.10/32".to_string()),
+      (3, "10.1.0.0/16".to_string()),
+    ]
+  };
+
+  assert_eq!(
+    match_ip_ban(bans(), ip("192.168.1.10")),
+    Some((2, "192.168.1.10/32".to_string()))
+  );
+  assert_eq!(match_ip_ban(bans(), ip("192.168.1.11")), None);
+  assert_eq!(
+    match_ip_ban(bans(), ip("10.1.42.1")),
+    Some((3, "10.1.0.0/16".to_string()))
+  );
+  assert_eq!(match_ip_ban(bans(), ip("10.2.0.1")), None);
+  assert_eq!(match_ip_ban(vec![], ip("10.1.42.1")), None);
+}
+
+#[test]
+fn test_player_moderation_retain_active() {
+  use chrono::Duration;
+
+  let now = Utc::now();
+  let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+  let player = PlayerRef {
+    id: 1,
+    name: "player".to_string(),
+    source: PlayerSource::Test,
+    realm: None,
+  };
+  let ban = |id, ban_expires_at| PlayerBan {
+    id,
+    player: player.clone(),
+    ban_type: PlayerBanType::Chat,
+    ban_expires_at,
+    created_at: now,
+    reason: None,
+  };
+  let ip_ban = |id, network: &str, ban_expires_at| IpBan {
+    id,
+    network: network.to_string(),
+    ban_expires_at,
+    created_at: now,
+    reason: None,
+  };
+  let mute = |id, mute_expires_at| PlayerMute {
+    id,
+    player_id: 1,
+    mute_expires_at,
+    created_at: now,
+    reason: None,
+  };
+  let moderation = || PlayerModeration {
+    player_bans: vec![
+      ban(1, None),
+      ban(2, Some(now - Duration::minutes(1))),
+      ban(3, Some(now + Duration::minutes(1))),
+      ban(4, Some(now)),
+    ],
+    ip_bans: vec![
+      ip_ban(1, "10.1.0.0/16", None),
+      ip_ban(2, "10.1.0.0/16", Some(now - Duration::minutes(1))),
+      ip_ban(3, "10.1.2.3", Some(now + Duration::minutes(1))),
+      ip_ban(4, "192.168.1.0/24", None),
+    ],
+    mutes: vec![mute(1, Some(now - Duration::days(1))), mute(2, None)],
+  };
+  let ids = |m: &PlayerModeration| {
+    (
+      m.player_bans.iter().map(|v| v.id).collect::<Vec<_>>(),
+      m.ip_bans.iter().map(|v| v.id).collect::<Vec<_>>(),
+      m.mutes.iter().map(|v| v.id).collect::<Vec<_>>(),
+    )
+  };
+
+  let mut m = moderation();
+  m.retain_active(Some(ip("10.1.2.3")), now);
+  assert_eq!(ids(&m), (vec![1, 3], vec![1, 3], vec![2]));
+
+  // offline players have no matching IP bans
+  let mut m = moderation();
+  m.retain_active(None, now);
+  assert_eq!(ids(&m), (vec![1, 3], vec![], vec![2]));
+
+  let mut m = moderation();
+  m.retain_active(Some(ip("10.1.2.3")), now + Duration::hours(1));
+  assert_eq!(ids(&m), (vec![1], vec![1], vec![2]));
+}
@@ -0,0 +1,109 @@
+use crate::error::*;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A single address or a CIDR block, e.g. `10.0.0.1` or `10.0.0.0/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+  addr: IpAddr,
+  prefix: u8,
+}
+
+impl IpNetwork {
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    let ip = match (self.addr, ip) {
+      (IpAddr::V4(_), IpAddr::V6(v6)) => match v6.octets() {
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, a, b, c, d] => IpAddr::from([a, b, c, d]),
+        _ => return false,
+      },
+      (_, ip) => ip,
+    };
+    match (self.addr, ip) {
+      (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_eq(&net.octets(), &ip.octets(), self.prefix),
+      (IpAddr::V6(net), IpAddr::V6(ip)) => prefix_eq(&net.octets(), &ip.octets(), self.prefix),
+      _ => false,
+    }
+  }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+  let bytes = (prefix / 8) as usize;
+  let bits = prefix % 8;
+  if a[..bytes] != b[..bytes] {
+    return false;
+  }
+  if bits == 0 {
+    return true;
+  }
+  let mask = 0xFFu8 << (8 - bits);
+  a[bytes] & mask == b[bytes] & mask
+}
+
+impl FromStr for IpNetwork {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let (addr, prefix) = match s.split_once('/') {
+      Some((addr, prefix)) => (addr, Some(prefix)),
+      None => (s, None),
+    };
+    let addr: IpAddr = addr
+      .trim()
+      .parse()
+      .map_err(|_| Error::IpNetworkInvalid(s.to_string()))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+      Some(prefix) => prefix
+        .trim()
+        .parse::<u8>()
+        .ok()
+        .filter(|v| *v <= max)
+        .ok_or_else(|| Error::IpNetworkInvalid(s.to_string()))?,
+      None => max,
+    };
+    Ok(Self { addr, prefix })
+  }
+}
+
+impl std::fmt::Display for IpNetwork {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}/{}", self.addr, self.prefix)
+  }
+}
+
+#[test]
+fn test_ip_network() {
+  let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+  let exact: IpNetwork = "192.168.1.10".parse().unwrap();
+  assert_eq!(exact.to_string(), "192.168.1.10/32");
+  assert!(exact.contains(ip("192.168.1.10")));
+  assert!(exact.contains(ip("::ffff:192.168.1.10")));
+  assert!(!exact.contains(ip("192.168.1.11")));
+
+  let subnet: IpNetwork = "10.1.0.0/16".parse().unwrap();
+  assert!(subnet.contains(ip("10.1.0.1")));
+  assert!(subnet.contains(ip("10.1.255.255")));
+  assert!(!subnet.contains(ip("10.2.0.1")));
+  assert!(!subnet.contains(ip("::1")));
+
+  let odd: IpNetwork = "172.16.0.0/12".parse().unwrap();
+  assert!(odd.contains(ip("172.31.255.1")));
+  assert!(!odd.contains(ip("172.32.0.1")));
+
+  let v6: IpNetwork = "2001:db8::/32".parse().unwrap();
+  assert!(v6.contains(ip("2001:db8:1::1")));
+  assert!(!v6.contains(ip("2001:db9::1")));
+  assert!(!v6.contains(ip("10.1.0.1")));
+
+  let all: IpNetwork = "0.0.0.0/0".parse().unwrap();
+  assert!(all.contains(ip("8.8.8.8")));
+
+  for s in &["", "10.0.0.0/33", "::/129", "10.0.0/8", "10.0.0.0/x"] {
+    assert!(
+      matches!(s.parse::<IpNetwork>(), Err(Error::IpNetworkInvalid(_))),
+      "{}",
+      s
+    );
+  }
+}
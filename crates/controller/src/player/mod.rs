@@ -1,4 +1,6 @@
 pub mod db;
+mod ip_ban;
+mod name;
 pub mod session;
 pub(crate) mod state;
 pub mod token;
@@ -8,4 +10,6 @@ pub mod message {
   pub use super::state::ping::{GetPlayersPingSnapshot, UpdatePing};
 }
 
+pub use ip_ban::IpNetwork;
+pub use name::{validate_name, MAX_NAME_BYTES};
 pub use types::*;
@@ -0,0 +1,50 @@
+use crate::error::*;
+
+/// Warcraft III truncates player names longer than this
+pub const MAX_NAME_BYTES: usize = 15;
+
+/// Checks that `name` can be displayed in the game lobby
+pub fn validate_name(name: &str) -> Result<()> {
+  if name.is_empty() {
+    return Err(Error::PlayerNameInvalid("empty"));
+  }
+  if name.len() > MAX_NAME_BYTES {
+    return Err(Error::PlayerNameInvalid("too long"));
+  }
+  if name.chars().any(char::is_control) {
+    return Err(Error::PlayerNameInvalid("control character"));
+  }
+  Ok(())
+}
+
+#[test]
+fn test_validate_name() {
+  for name in &["a", "Player", "123456789012345", "Игрок", "選手"] {
+    assert!(validate_name(name).is_ok(), "{}", name);
+  }
+
+  assert!(matches!(
+    validate_name(""),
+    Err(Error::PlayerNameInvalid("empty"))
+  ));
+  for name in &["1234567890123456", "ИгрокИгрокИ"] {
+    assert!(
+      matches!(
+        validate_name(name),
+        Err(Error::PlayerNameInvalid("too long"))
+      ),
+      "{}",
+      name
+    );
+  }
+  for name in &["a\0b", "tab\t", "new\nline", "esc\u{1b}", "del\u{7f}"] {
+    assert!(
+      matches!(
+        validate_name(name),
+        Err(Error::PlayerNameInvalid("control character"))
+      ),
+      "{:?}",
+      name
+    );
+  }
+}
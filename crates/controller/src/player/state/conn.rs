@@ -2,10 +2,12 @@ use super::PlayerRegistry;
 use crate::client::PlayerSender;
 use crate::player::state::PlayerState;
 use flo_state::{async_trait, Context, Handler, Message};
+use std::net::IpAddr;
 
 pub struct Connect {
   pub game_id: Option<i32>,
   pub sender: PlayerSender,
+  pub ip: IpAddr,
 }
 
 impl Message for Connect {
@@ -18,7 +20,7 @@ impl Handler<Connect> for PlayerRegistry {
     let player_id = message.sender.player_id();
     let removed = self.registry.insert(
       player_id,
-      PlayerState::new(player_id, message.game_id, message.sender),
+      PlayerState::new(player_id, message.game_id, message.sender, message.ip),
     );
     if let Some(state) = removed {
       state.shutdown().await;
@@ -43,3 +45,44 @@ impl Handler<Disconnect> for PlayerRegistry {
     }
   }
 }
+
+/// Disconnects players not in a game with the `Maintenance` reason
+pub struct DisconnectIdle;
+
+impl Message for DisconnectIdle {
+  type Result = usize;
+}
+
+#[async_trait]
+impl Handler<DisconnectIdle> for PlayerRegistry {
+  async fn handle(&mut self, _: &mut Context<Self>, _: DisconnectIdle) -> usize {
+    let ids: Vec<i32> = self
+      .registry
+      .values()
+      .filter(|state| state.game_id.is_none())
+      .map(|state| state.player_id)
+      .collect();
+    for player_id in &ids {
+      if let Some(state) = self.registry.remove(player_id) {
+        state.shutdown_maintenance().await;
+      }
+    }
+    ids.len()
+  }
+}
+
+/// The address a player is connected from, `None` if offline
+pub struct GetPlayerIp {
+  pub player_id: i32,
+}
+
+impl Message for GetPlayerIp {
+  type Result = Option<IpAddr>;
+}
+
+#[async_trait]
+impl Handler<GetPlayerIp> for PlayerRegistry {
+  async fn handle(&mut self, _: &mut Context<Self>, message: GetPlayerIp) -> Option<IpAddr> {
+    self.registry.get(&message.player_id).map(|state| state.ip)
+  }
+}
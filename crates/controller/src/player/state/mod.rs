@@ -5,11 +5,13 @@ pub mod sender;
 use crate::client::PlayerSender;
 use crate::error::Error;
 use crate::state::Data;
+use flo_net::connect::Capabilities;
 use flo_state::{async_trait, Actor, RegistryRef, Service};
 use flo_types::ping::PingStats;
 
 use crate::player::state::sender::PlayerFrames;
 use std::collections::BTreeMap;
+use std::net::IpAddr;
 
 #[derive(Debug)]
 pub struct PlayerRegistry {
@@ -41,18 +43,24 @@ pub struct PlayerState {
   pub ping_map: BTreeMap<i32, PingStats>,
   pub game_id: Option<i32>,
   pub sender: PlayerSender,
+  pub ip: IpAddr,
 }
 
 impl PlayerState {
-  fn new(player_id: i32, game_id: Option<i32>, sender: PlayerSender) -> PlayerState {
+  fn new(player_id: i32, game_id: Option<i32>, sender: PlayerSender, ip: IpAddr) -> PlayerState {
     Self {
       player_id,
       game_id,
       ping_map: Default::default(),
       sender,
+      ip,
     }
   }
 
+  fn supports(&self, capability: Capabilities) -> bool {
+    self.sender.capabilities().contains(capability)
+  }
+
   fn try_send_frames(&mut self, frames: PlayerFrames) -> bool {
     for frame in frames {
       if !self.sender.try_send(frame) {
@@ -65,4 +73,8 @@ impl PlayerState {
   async fn shutdown(mut self) {
     self.sender.disconnect_multi().await;
   }
+
+  async fn shutdown_maintenance(mut self) {
+    self.sender.disconnect_maintenance().await;
+  }
 }
@@ -38,6 +38,56 @@ pub struct NodePlayersPingSnapshot {
   pub map: BTreeMap<i32, BTreeMap<i32, PingStats>>,
 }
 
+impl NodePlayersPingSnapshot {
+  /// Aggregates the ping of each player across nodes,
+  /// players without any sample are skipped
+  pub fn summaries(&self) -> Vec<PlayerPingSummary> {
+    self
+      .map
+      .iter()
+      .filter_map(|(player_id, map)| PlayerPingSummary::from_ping_map(*player_id, map))
+      .collect()
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PlayerPingSummary {
+  pub player_id: i32,
+  pub min: u32,
+  pub max: u32,
+  pub median: u32,
+  /// Node with the lowest ping, the lowest id wins ties
+  pub best_node_id: i32,
+}
+
+impl PlayerPingSummary {
+  fn from_ping_map(player_id: i32, map: &BTreeMap<i32, PingStats>) -> Option<Self> {
+    let mut samples: Vec<(u32, i32)> = map
+      .iter()
+      .filter_map(|(node_id, stats)| Some((stats.avg.or(stats.current)?, *node_id)))
+      .collect();
+    if samples.is_empty() {
+      return None;
+    }
+    samples.sort();
+
+    let len = samples.len();
+    let median = if len % 2 == 0 {
+      (samples[len / 2 - 1].0 + samples[len / 2].0) / 2
+    } else {
+      samples[len / 2].0
+    };
+
+    Some(Self {
+      player_id,
+      min: samples[0].0,
+      max: samples[len - 1].0,
+      median,
+      best_node_id: samples[0].1,
+    })
+  }
+}
+
 impl Message for GetPlayersPingSnapshot {
   type Result = NodePlayersPingSnapshot;
 }
@@ -58,3 +108,58 @@ impl Handler<GetPlayersPingSnapshot> for PlayerRegistry {
     NodePlayersPingSnapshot { map }
   }
 }
+
+#[test]
+fn test_ping_summaries() {
+  let stats = |avg: Option<u32>, current: Option<u32>| PingStats {
+    min: None,
+    max: None,
+    avg,
+    current,
+    loss_rate: 0.0,
+  };
+  let snapshot = NodePlayersPingSnapshot {
+    map: vec![
+      (
+        1,
+        vec![
+          (10, stats(Some(80), None)),
+          (11, stats(Some(40), None)),
+          (12, stats(None, Some(60))),
+          (13, stats(None, None)),
+        ]
+        .into_iter()
+        .collect(),
+      ),
+      (
+        2,
+        vec![(10, stats(Some(50), None)), (11, stats(Some(30), None))]
+          .into_iter()
+          .collect(),
+      ),
+      (3, vec![(10, stats(None, None))].into_iter().collect()),
+      (4, BTreeMap::new()),
+    ]
+    .into_iter()
+    .collect(),
+  };
+  assert_eq!(
+    snapshot.summaries(),
+    vec![
+      PlayerPingSummary {
+        player_id: 1,
+        min: 40,
+        max: 80,
+        median: 60,
+        best_node_id: 11,
+      },
+      PlayerPingSummary {
+        player_id: 2,
+        min: 30,
+        max: 50,
+        median: 40,
+        best_node_id: 11,
+      },
+    ]
+  );
+}
@@ -2,15 +2,20 @@ use super::{PlayerRegistry, PlayerState};
 use crate::error::*;
 use crate::game::Game;
 use crate::player::session::get_session_update_packet;
+use crate::player::state::ping::{GetPlayersPingSnapshot, NodePlayersPingSnapshot};
+use flo_net::connect::Capabilities;
 use flo_net::packet::{FloPacket, Frame};
 use flo_state::{async_trait, Addr, Context, Handler, Message};
 use s2_grpc_utils::S2ProtoPack;
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 
+/// Frames are only sent to players whose client supports `capability`,
+/// `Capabilities::empty()` sends to everyone
 #[derive(Debug)]
 struct Send {
   player_id: i32,
+  capability: Capabilities,
   frames: PlayerFrames,
 }
 
@@ -20,13 +25,22 @@ impl Message for Send {
 
 #[async_trait]
 impl Handler<Send> for PlayerRegistry {
-  async fn handle(&mut self, _: &mut Context<Self>, Send { player_id, frames }: Send) {
-    send_to_player(&mut self.registry, player_id, frames);
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    Send {
+      player_id,
+      capability,
+      frames,
+    }: Send,
+  ) {
+    send_to_player(&mut self.registry, player_id, capability, frames);
   }
 }
 
 #[derive(Debug)]
 struct BroadcastToAll {
+  capability: Capabilities,
   frames: PlayerFrames,
 }
 
@@ -36,9 +50,16 @@ impl Message for BroadcastToAll {
 
 #[async_trait]
 impl Handler<BroadcastToAll> for PlayerRegistry {
-  async fn handle(&mut self, _: &mut Context<Self>, BroadcastToAll { frames }: BroadcastToAll) {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    BroadcastToAll { capability, frames }: BroadcastToAll,
+  ) {
     let mut remove_list = vec![];
     for (player_id, state) in self.registry.iter_mut() {
+      if !state.supports(capability) {
+        continue;
+      }
       let remove = { !state.try_send_frames(frames.clone()) };
       if remove {
         let player_id = *player_id;
@@ -55,6 +76,7 @@ impl Handler<BroadcastToAll> for PlayerRegistry {
 #[derive(Debug)]
 struct Broadcast {
   players: Vec<i32>,
+  capability: Capabilities,
   frames: PlayerFrames,
 }
 
@@ -64,9 +86,17 @@ impl Message for Broadcast {
 
 #[async_trait]
 impl Handler<Broadcast> for PlayerRegistry {
-  async fn handle(&mut self, _: &mut Context<Self>, Broadcast { players, frames }: Broadcast) {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    Broadcast {
+      players,
+      capability,
+      frames,
+    }: Broadcast,
+  ) {
     for player_id in players {
-      send_to_player(&mut self.registry, player_id, frames.clone());
+      send_to_player(&mut self.registry, player_id, capability, frames.clone());
     }
   }
 }
@@ -84,7 +114,7 @@ impl Message for BroadcastMap {
 impl Handler<BroadcastMap> for PlayerRegistry {
   async fn handle(&mut self, _: &mut Context<Self>, BroadcastMap { map }: BroadcastMap) {
     for (player_id, frames) in map {
-      send_to_player(&mut self.registry, player_id, frames);
+      send_to_player(&mut self.registry, player_id, Capabilities::empty(), frames);
     }
   }
 }
@@ -351,10 +381,15 @@ impl Iterator for PlayerFramesIntoIterator {
   }
 }
 
-fn send_to_player(map: &mut BTreeMap<i32, PlayerState>, player_id: i32, frames: PlayerFrames) {
+fn send_to_player(
+  map: &mut BTreeMap<i32, PlayerState>,
+  player_id: i32,
+  capability: Capabilities,
+  frames: PlayerFrames,
+) {
   let remove = {
     let entry = map.get_mut(&player_id);
-    if let Some(entry) = entry {
+    if let Some(entry) = entry.filter(|entry| entry.supports(capability)) {
       !entry.try_send_frames(frames)
     } else {
       false
@@ -370,6 +405,21 @@ fn send_to_player(map: &mut BTreeMap<i32, PlayerState>, player_id: i32, frames:
 pub struct PlayerRegistryHandle(Addr<PlayerRegistry>);
 impl PlayerRegistryHandle {
   pub async fn send<T>(&self, player_id: i32, frames: T) -> Result<()>
+  where
+    T: Into<PlayerFrames>,
+  {
+    self
+      .send_if_supported(player_id, Capabilities::empty(), frames)
+      .await
+  }
+
+  /// Skips the player if their client doesn't support `capability`
+  pub async fn send_if_supported<T>(
+    &self,
+    player_id: i32,
+    capability: Capabilities,
+    frames: T,
+  ) -> Result<()>
   where
     T: Into<PlayerFrames>,
   {
@@ -377,6 +427,7 @@ impl PlayerRegistryHandle {
       .0
       .send(Send {
         player_id,
+        capability,
         frames: frames.into(),
       })
       .await?;
@@ -384,12 +435,26 @@ impl PlayerRegistryHandle {
   }
 
   pub async fn broadcast_to_all<T>(&self, frames: T) -> Result<()>
+  where
+    T: Into<PlayerFrames>,
+  {
+    self
+      .broadcast_to_all_if_supported(Capabilities::empty(), frames)
+      .await
+  }
+
+  pub async fn broadcast_to_all_if_supported<T>(
+    &self,
+    capability: Capabilities,
+    frames: T,
+  ) -> Result<()>
   where
     T: Into<PlayerFrames>,
   {
     self
       .0
       .send(BroadcastToAll {
+        capability,
         frames: frames.into(),
       })
       .await?;
@@ -397,6 +462,20 @@ impl PlayerRegistryHandle {
   }
 
   pub async fn broadcast<T>(&self, players: Vec<i32>, frames: T) -> Result<()>
+  where
+    T: Into<PlayerFrames>,
+  {
+    self
+      .broadcast_if_supported(players, Capabilities::empty(), frames)
+      .await
+  }
+
+  pub async fn broadcast_if_supported<T>(
+    &self,
+    players: Vec<i32>,
+    capability: Capabilities,
+    frames: T,
+  ) -> Result<()>
   where
     T: Into<PlayerFrames>,
   {
@@ -404,6 +483,7 @@ impl PlayerRegistryHandle {
       .0
       .send(Broadcast {
         players,
+        capability,
         frames: frames.into(),
       })
       .await?;
@@ -475,6 +555,10 @@ impl PlayerRegistryHandle {
       .await??;
     Ok(())
   }
+
+  pub async fn players_ping_snapshot(&self, players: Vec<i32>) -> Result<NodePlayersPingSnapshot> {
+    Ok(self.0.send(GetPlayersPingSnapshot { players }).await?)
+  }
 }
 
 impl From<Addr<PlayerRegistry>> for PlayerRegistryHandle {
@@ -482,3 +566,41 @@ impl From<Addr<PlayerRegistry>> for PlayerRegistryHandle {
     Self(value)
   }
 }
+
+#[test]
+fn test_send_to_player_capability() {
+  use crate::client::{PlayerSender, PlayerSenderMessage};
+  use flo_net::proto::flo_connect::PacketGameLobbyMessage;
+
+  let mut map = BTreeMap::new();
+  let (sender, mut legacy_rx) = PlayerSender::new(1, Capabilities::PING_MAP);
+  map.insert(1, PlayerState::new(1, None, sender, [127, 0, 0, 1].into()));
+  let (sender, mut rx) = PlayerSender::new(2, Capabilities::SUPPORTED);
+  map.insert(2, PlayerState::new(2, None, sender, [127, 0, 0, 1].into()));
+
+  let frame = PacketGameLobbyMessage {
+    game_id: 1,
+    message: "high ping".to_string(),
+  }
+  .encode_as_frame()
+  .unwrap();
+  for player_id in 1..=2 {
+    send_to_player(
+      &mut map,
+      player_id,
+      Capabilities::LOBBY_MESSAGE,
+      frame.clone().into(),
+    );
+  }
+
+  // skipped, not removed
+  assert!(legacy_rx.try_recv().is_err());
+  assert!(map.contains_key(&1));
+  assert!(matches!(rx.try_recv(), Ok(PlayerSenderMessage::Frame(_))));
+
+  send_to_player(&mut map, 1, Capabilities::empty(), frame.into());
+  assert!(matches!(
+    legacy_rx.try_recv(),
+    Ok(PlayerSenderMessage::Frame(_))
+  ));
+}
@@ -1,5 +1,4 @@
 use chrono::Utc;
-use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
@@ -8,38 +7,92 @@ use crate::error::*;
 
 // 1 month
 const TOKEN_EXPIRATION_SECS: i64 = 3600 * 24 * 30;
+// expired tokens can be refreshed for 1 week
+const TOKEN_REFRESH_GRACE_SECS: i64 = 3600 * 24 * 7;
+// tokens without `exp` are accepted until 2027-01-01
+const LEGACY_TOKEN_ACCEPTED_UNTIL: i64 = 1798761600;
 const TOKEN_SUB: &str = "flo";
 
+static ENCODING_KEY: Lazy<EncodingKey> = Lazy::new(|| {
+  EncodingKey::from_base64_secret(&crate::config::JWT_SECRET_BASE64)
+    .expect("EncodingKey::from_base64_secret")
+});
+
+static DECODING_KEY: Lazy<DecodingKey<'static>> = Lazy::new(|| {
+  DecodingKey::from_base64_secret(&crate::config::JWT_SECRET_BASE64)
+    .expect("DecodingKey::from_base64_secret")
+});
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlayerToken {
   pub sub: String,
   pub player_id: i32,
-  pub exp: usize,
+  /// Expiration as a unix timestamp, `None` for tokens issued before expiry was enforced
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub exp: Option<usize>,
 }
 
 pub fn create_player_token(player_id: i32) -> Result<String> {
-  static ENCODING_KEY: Lazy<EncodingKey> = Lazy::new(|| {
-    EncodingKey::from_base64_secret(&crate::config::JWT_SECRET_BASE64)
-      .expect("DecodingKey::from_base64_secret")
-  });
+  create_player_token_at(&ENCODING_KEY, player_id, Utc::now().timestamp())
+}
+
+pub fn validate_player_token(token: &str) -> Result<PlayerToken> {
+  validate_player_token_at(&DECODING_KEY, token, Utc::now().timestamp())
+}
 
-  let exp = Utc::now().timestamp() + TOKEN_EXPIRATION_SECS;
+/// Issues a new token for a valid token or a token expired less than
+/// `TOKEN_REFRESH_GRACE_SECS` ago
+pub fn refresh_player_token(old: &str) -> Result<String> {
+  refresh_player_token_at(&ENCODING_KEY, &DECODING_KEY, old, Utc::now().timestamp())
+}
+
+fn create_player_token_at(key: &EncodingKey, player_id: i32, now: i64) -> Result<String> {
   let claims = PlayerToken {
     sub: TOKEN_SUB.to_string(),
     player_id,
-    exp: exp as usize,
+    exp: Some((now + TOKEN_EXPIRATION_SECS) as usize),
   };
-  encode(&Header::default(), &claims, &ENCODING_KEY).map_err(Into::into)
+  encode(&Header::default(), &claims, key).map_err(Into::into)
 }
 
-pub fn validate_player_token(token: &str) -> Result<PlayerToken> {
-  let decoding_key = DecodingKey::from_base64_secret(&crate::config::JWT_SECRET_BASE64)?;
-  decode(token, &decoding_key, &Validation::default())
+fn validate_player_token_at(key: &DecodingKey, token: &str, now: i64) -> Result<PlayerToken> {
+  let token = decode_player_token(key, token)?;
+  if is_expired(&token, now, 0) {
+    return Err(Error::PlayerTokenExpired);
+  }
+  Ok(token)
+}
+
+fn refresh_player_token_at(
+  encoding_key: &EncodingKey,
+  decoding_key: &DecodingKey,
+  old: &str,
+  now: i64,
+) -> Result<String> {
+  let token = decode_player_token(decoding_key, old)?;
+  if is_expired(&token, now, TOKEN_REFRESH_GRACE_SECS) {
+    return Err(Error::PlayerTokenExpired);
+  }
+  create_player_token_at(encoding_key, token.player_id, now)
+}
+
+// expiry is checked by `is_expired` to support tokens without `exp`
+fn decode_player_token(key: &DecodingKey, token: &str) -> Result<PlayerToken> {
+  let validation = Validation {
+    validate_exp: false,
+    ..Default::default()
+  };
+  decode(token, key, &validation)
     .map(|data| data.claims)
-    .map_err(|e| match e.kind() {
-      ErrorKind::ExpiredSignature => Error::PlayerTokenExpired,
-      _ => e.into(),
-    })
+    .map_err(Into::into)
+}
+
+fn is_expired(token: &PlayerToken, now: i64, grace_secs: i64) -> bool {
+  let exp = token
+    .exp
+    .map(|exp| exp as i64)
+    .unwrap_or(LEGACY_TOKEN_ACCEPTED_UNTIL);
+  now >= exp + grace_secs
 }
 
 #[test]
@@ -49,3 +102,66 @@ fn test_player_token() {
   let token = validate_player_token(&token).unwrap();
   dbg!(token);
 }
+
+#[test]
+fn test_player_token_expiry() {
+  let encoding_key = EncodingKey::from_secret(b"secret");
+  let decoding_key = DecodingKey::from_secret(b"secret");
+  let issued_at = 1_700_000_000;
+  let exp = issued_at + TOKEN_EXPIRATION_SECS;
+
+  let token = create_player_token_at(&encoding_key, 100, issued_at).unwrap();
+  let claims = validate_player_token_at(&decoding_key, &token, exp - 1).unwrap();
+  assert_eq!(claims.player_id, 100);
+  assert_eq!(claims.exp, Some(exp as usize));
+  assert!(matches!(
+    validate_player_token_at(&decoding_key, &token, exp),
+    Err(Error::PlayerTokenExpired)
+  ));
+
+  // refresh within the grace window
+  let refresh_at = exp + TOKEN_REFRESH_GRACE_SECS - 1;
+  let refreshed =
+    refresh_player_token_at(&encoding_key, &decoding_key, &token, refresh_at).unwrap();
+  let claims = validate_player_token_at(&decoding_key, &refreshed, refresh_at).unwrap();
+  assert_eq!(claims.player_id, 100);
+  assert_eq!(
+    claims.exp,
+    Some((refresh_at + TOKEN_EXPIRATION_SECS) as usize)
+  );
+  assert!(matches!(
+    refresh_player_token_at(
+      &encoding_key,
+      &decoding_key,
+      &token,
+      exp + TOKEN_REFRESH_GRACE_SECS
+    ),
+    Err(Error::PlayerTokenExpired)
+  ));
+
+  // tokens issued without `exp`
+  let legacy = encode(
+    &Header::default(),
+    &PlayerToken {
+      sub: TOKEN_SUB.to_string(),
+      player_id: 200,
+      exp: None,
+    },
+    &encoding_key,
+  )
+  .unwrap();
+  let claims =
+    validate_player_token_at(&decoding_key, &legacy, LEGACY_TOKEN_ACCEPTED_UNTIL - 1).unwrap();
+  assert_eq!(claims.player_id, 200);
+  assert!(matches!(
+    validate_player_token_at(&decoding_key, &legacy, LEGACY_TOKEN_ACCEPTED_UNTIL),
+    Err(Error::PlayerTokenExpired)
+  ));
+
+  // signed with another key
+  let other = create_player_token_at(&EncodingKey::from_secret(b"other"), 100, issued_at).unwrap();
+  assert!(matches!(
+    validate_player_token_at(&decoding_key, &other, issued_at),
+    Err(Error::JsonWebToken(_))
+  ));
+}
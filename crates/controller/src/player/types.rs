@@ -4,7 +4,7 @@ use s2_grpc_utils::result::Error as ProtoError;
 use s2_grpc_utils::{S2ProtoEnum, S2ProtoPack, S2ProtoUnpack};
 use serde::{Deserialize, Serialize};
 
-use crate::schema::{player, player_ban};
+use crate::schema::{ip_ban, player, player_ban, player_mutes};
 
 #[derive(Debug, Serialize, Deserialize, S2ProtoPack, S2ProtoUnpack)]
 #[s2_grpc(message_type = "flo_grpc::player::Player")]
@@ -115,6 +115,7 @@ pub struct PlayerBan {
   pub ban_type: PlayerBanType,
   pub ban_expires_at: Option<DateTime<Utc>>,
   pub created_at: DateTime<Utc>,
+  pub reason: Option<String>,
 }
 
 pub(crate) type PlayerBanColumns = (
@@ -123,6 +124,7 @@ pub(crate) type PlayerBanColumns = (
   player_ban::ban_type,
   player_ban::ban_expires_at,
   player_ban::created_at,
+  player_ban::reason,
 );
 
 impl PlayerBan {
@@ -132,5 +134,62 @@ impl PlayerBan {
     player_ban::ban_type,
     player_ban::ban_expires_at,
     player_ban::created_at,
+    player_ban::reason,
   );
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Queryable, S2ProtoPack)]
+#[s2_grpc(message_type = "flo_grpc::player::IpBan")]
+pub struct IpBan {
+  pub id: i32,
+  pub network: String,
+  pub ban_expires_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+  pub reason: Option<String>,
+}
+
+pub(crate) type IpBanColumns = (
+  ip_ban::id,
+  ip_ban::network,
+  ip_ban::ban_expires_at,
+  ip_ban::created_at,
+  ip_ban::reason,
+);
+
+impl IpBan {
+  pub(crate) const COLUMNS: IpBanColumns = (
+    ip_ban::id,
+    ip_ban::network,
+    ip_ban::ban_expires_at,
+    ip_ban::created_at,
+    ip_ban::reason,
+  );
+}
+
+#[derive(Debug, Queryable, S2ProtoPack)]
+#[s2_grpc(message_type = "flo_grpc::player::PlayerMute")]
+pub struct PlayerMute {
+  pub id: i32,
+  pub player_id: i32,
+  pub mute_expires_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+  pub reason: Option<String>,
+}
+
+pub(crate) type PlayerMuteColumns = (
+  player_mutes::id,
+  player_mutes::player_id,
+  player_mutes::mute_expires_at,
+  player_mutes::created_at,
+  player_mutes::reason,
+);
+
+impl PlayerMute {
+  pub(crate) const COLUMNS: PlayerMuteColumns = (
+    player_mutes::id,
+    player_mutes::player_id,
+    player_mutes::mute_expires_at,
+    player_mutes::created_at,
+    player_mutes::reason,
+  );
+}
@@ -0,0 +1,234 @@
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::env;
+use std::time::Instant;
+
+/// Write requests per minute allowed for API clients without their own
+/// `api_client.rate_limit_per_minute`, e.g. `FLO_API_CLIENT_RATE_LIMIT=120`.
+/// Such clients are not rate limited if it is not set.
+pub static DEFAULT_RATE_LIMIT_PER_MINUTE: Lazy<Option<u32>> = Lazy::new(|| {
+  env::var("FLO_API_CLIENT_RATE_LIMIT")
+    .ok()
+    .and_then(|v| v.trim().parse().ok())
+    .filter(|v| *v > 0)
+});
+
+/// Read-only methods get a bucket this many times larger
+pub const READ_RATE_LIMIT_MULTIPLIER: u32 = 10;
+
+/// gRPC methods that don't change any state, in the order of `FloController` in grpc.rs.
+/// Methods missing here are counted as writes.
+const READ_ONLY_METHODS: &[&str] = &[
+  "GetPlayer",
+  "GetPlayers",
+  "GetPlayerByToken",
+  "ListNodes",
+  "ListNodeGames",
+  "ListGames",
+  "GetGame",
+  "GetGameOccupancy",
+  "GetGameDiagnostics",
+  "WatchGame",
+  "SubscribeEvents",
+  "ValidateCreateGame",
+  "SearchMapChecksum",
+  "GetMapBySha1",
+  "GetPlayersBySourceIds",
+  "GetPlayerPingSummary",
+  "GetPlayerPingMaps",
+  "SearchPlayers",
+  "ListPlayerBans",
+  "GetPlayerModeration",
+];
+
+/// gRPC methods callable without an API secret, they are not rate limited
+const PUBLIC_METHODS: &[&str] = &["HealthCheck"];
+
+/// Path of the gRPC method being called, e.g. `/flo_controller.FloController/GetGame`
+#[derive(Debug, Clone)]
+pub struct GrpcMethod(pub String);
+
+impl GrpcMethod {
+  /// Copies the request path into the extensions, where the interceptor can see it
+  pub fn tag(
+    mut req: http::Request<tonic::transport::Body>,
+  ) -> http::Request<tonic::transport::Body> {
+    let method = GrpcMethod(req.uri().path().to_string());
+    req.extensions_mut().insert(method);
+    req
+  }
+
+  fn name(&self) -> &str {
+    self.0.rsplit('/').next().unwrap_or_default()
+  }
+
+  pub fn is_public(&self) -> bool {
+    PUBLIC_METHODS.contains(&self.name())
+  }
+
+  pub fn class(&self) -> RateLimitClass {
+    if READ_ONLY_METHODS.contains(&self.name()) {
+      RateLimitClass::Read
+    } else {
+      RateLimitClass::Write
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitClass {
+  Read,
+  Write,
+}
+
+/// Requests per minute, also the burst size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+  pub per_minute: u32,
+}
+
+impl RateLimit {
+  /// The limit of an API client, `None` uses the default.
+  /// Returns `None` if the client is not rate limited.
+  pub fn for_class(per_minute: Option<i32>, class: RateLimitClass) -> Option<Self> {
+    Self::with_default(per_minute, *DEFAULT_RATE_LIMIT_PER_MINUTE, class)
+  }
+
+  fn with_default(
+    per_minute: Option<i32>,
+    default: Option<u32>,
+    class: RateLimitClass,
+  ) -> Option<Self> {
+    let per_minute = per_minute
+      .filter(|v| *v > 0)
+      .map(|v| v as u32)
+      .or(default)?;
+    Some(match class {
+      RateLimitClass::Read => RateLimit {
+        per_minute: per_minute.saturating_mul(READ_RATE_LIMIT_MULTIPLIER),
+      },
+      RateLimitClass::Write => RateLimit { per_minute },
+    })
+  }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+  tokens: f64,
+  updated_at: Instant,
+}
+
+impl TokenBucket {
+  fn new(limit: RateLimit, now: Instant) -> Self {
+    TokenBucket {
+      tokens: limit.per_minute as f64,
+      updated_at: now,
+    }
+  }
+
+  /// The limit is passed on every call so reloaded limits apply to existing buckets
+  fn try_take(&mut self, limit: RateLimit, now: Instant) -> bool {
+    let capacity = limit.per_minute as f64;
+    let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+    self.updated_at = now;
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// Token buckets keyed by API client and method class
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+  buckets: Mutex<HashMap<(i32, RateLimitClass), TokenBucket>>,
+}
+
+impl RateLimiter {
+  /// Returns false if the API client is over its limit
+  pub fn check(
+    &self,
+    api_client_id: i32,
+    class: RateLimitClass,
+    limit: RateLimit,
+    now: Instant,
+  ) -> bool {
+    self
+      .buckets
+      .lock()
+      .entry((api_client_id, class))
+      .or_insert_with(|| TokenBucket::new(limit, now))
+      .try_take(limit, now)
+  }
+}
+
+#[test]
+fn test_rate_limiter() {
+  use std::time::Duration;
+
+  let t = Instant::now();
+  let limiter = RateLimiter::default();
+  let limit = RateLimit { per_minute: 3 };
+  for _ in 0..3 {
+    assert!(limiter.check(1, RateLimitClass::Write, limit, t));
+  }
+  assert!(!limiter.check(1, RateLimitClass::Write, limit, t));
+
+  // other clients and classes have their own buckets
+  assert!(limiter.check(2, RateLimitClass::Write, limit, t));
+  assert!(limiter.check(1, RateLimitClass::Read, limit, t));
+
+  // one token every 20 seconds
+  assert!(limiter.check(1, RateLimitClass::Write, limit, t + Duration::from_secs(20)));
+  assert!(!limiter.check(1, RateLimitClass::Write, limit, t + Duration::from_secs(20)));
+  assert!(!limiter.check(1, RateLimitClass::Write, limit, t + Duration::from_secs(39)));
+
+  // refills up to the limit only
+  let later = t + Duration::from_secs(3600);
+  for _ in 0..3 {
+    assert!(limiter.check(1, RateLimitClass::Write, limit, later));
+  }
+  assert!(!limiter.check(1, RateLimitClass::Write, limit, later));
+
+  // a lowered limit applies to the existing bucket
+  let later = later + Duration::from_secs(3600);
+  let lowered = RateLimit { per_minute: 1 };
+  assert!(limiter.check(1, RateLimitClass::Write, lowered, later));
+  assert!(!limiter.check(1, RateLimitClass::Write, lowered, later));
+}
+
+#[test]
+fn test_rate_limit_class() {
+  let method = |path: &str| GrpcMethod(path.to_string()).class();
+  assert_eq!(
+    method("/flo_controller.FloController/GetGame"),
+    RateLimitClass::Read
+  );
+  assert_eq!(
+    method("/flo_controller.FloController/CreateGame"),
+    RateLimitClass::Write
+  );
+  assert_eq!(
+    method("/flo_controller.FloController/GetMapBySha1"),
+    RateLimitClass::Read
+  );
+  assert_eq!(method(""), RateLimitClass::Write);
+
+  let limit = |per_minute, default, class| {
+    RateLimit::with_default(per_minute, default, class).map(|limit| limit.per_minute)
+  };
+  assert_eq!(limit(None, Some(120), RateLimitClass::Write), Some(120));
+  assert_eq!(limit(Some(0), Some(120), RateLimitClass::Write), Some(120));
+  assert_eq!(
+    limit(Some(10), None, RateLimitClass::Read),
+    Some(10 * READ_RATE_LIMIT_MULTIPLIER)
+  );
+
+  // nothing configured
+  assert_eq!(limit(None, None, RateLimitClass::Write), None);
+  assert_eq!(limit(Some(0), None, RateLimitClass::Read), None);
+}
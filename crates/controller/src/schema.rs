@@ -6,6 +6,7 @@ diesel::table! {
         name -> Text,
         secret_key -> Text,
         created_at -> Timestamptz,
+        rate_limit_per_minute -> Nullable<Int4>,
     }
 }
 
@@ -33,6 +34,9 @@ diesel::table! {
         enable_ping_equalizer -> Bool,
         flo_tv_delay_override_secs -> Nullable<Int4>,
         map_twelve_p -> Bool,
+        tags -> Array<Text>,
+        game_flags -> Int4,
+        lobby_timeout_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -56,11 +60,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    game_results (id) {
+        id -> Int4,
+        game_id -> Int4,
+        player_id -> Int4,
+        outcome -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    ip_ban (id) {
+        id -> Int4,
+        api_client_id -> Int4,
+        network -> Text,
+        ban_expires_at -> Nullable<Timestamptz>,
+        reason -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     map_checksum (id) {
         id -> Int4,
         sha1 -> Text,
         checksum -> Bytea,
+        meta -> Nullable<Jsonb>,
     }
 }
 
@@ -75,6 +101,7 @@ diesel::table! {
         updated_at -> Timestamptz,
         country_id -> Text,
         disabled -> Bool,
+        max_games -> Nullable<Int4>,
     }
 }
 
@@ -89,6 +116,7 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         api_client_id -> Int4,
+        preferred_race -> Nullable<Int4>,
     }
 }
 
@@ -99,6 +127,7 @@ diesel::table! {
         ban_type -> Int4,
         ban_expires_at -> Nullable<Timestamptz>,
         created_at -> Timestamptz,
+        reason -> Nullable<Text>,
     }
 }
 
@@ -111,20 +140,46 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    player_mutes (id) {
+        id -> Int4,
+        player_id -> Int4,
+        mute_expires_at -> Nullable<Timestamptz>,
+        reason -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    server_maintenance (id) {
+        id -> Int4,
+        drain_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::joinable!(game -> node (node_id));
 diesel::joinable!(game -> player (created_by));
 diesel::joinable!(game_used_slot -> game (game_id));
 diesel::joinable!(game_used_slot -> player (player_id));
+diesel::joinable!(game_results -> game (game_id));
+diesel::joinable!(game_results -> player (player_id));
+diesel::joinable!(ip_ban -> api_client (api_client_id));
 diesel::joinable!(player -> api_client (api_client_id));
 diesel::joinable!(player_ban -> player (player_id));
+diesel::joinable!(player_mutes -> player (player_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     api_client,
     game,
     game_used_slot,
+    game_results,
+    ip_ban,
     map_checksum,
     node,
     player,
     player_ban,
     player_mute,
+    player_mutes,
+    server_maintenance,
 );
@@ -12,12 +12,14 @@ use crate::node::NodeRegistry;
 use crate::player::state::PlayerRegistry;
 
 use crate::config::ConfigStorage;
+use crate::event::{ControllerEvent, ControllerEventBus};
 use crate::player::state::sender::PlayerRegistryHandle;
 pub use actor_map::{ActorMapExt, GetActorEntry};
 
 #[derive(Debug)]
 pub struct Data {
   pub db: ExecutorRef,
+  pub events: ControllerEventBus,
 }
 
 pub struct ControllerState {
@@ -28,6 +30,7 @@ pub struct ControllerState {
   pub players: Addr<PlayerRegistry>,
   pub player_packet_sender: PlayerRegistryHandle,
   pub config: Addr<ConfigStorage>,
+  pub events: ControllerEventBus,
 }
 
 pub type ControllerStateRef = Arc<ControllerState>;
@@ -41,7 +44,11 @@ impl ControllerState {
       db.exec(|conn| crate::migration::run(conn)).await?;
     }
 
-    let registry = Registry::with_data(Data { db: db.clone() });
+    let events = ControllerEventBus::default();
+    let registry = Registry::with_data(Data {
+      db: db.clone(),
+      events: events.clone(),
+    });
 
     let nodes = registry.resolve().await?;
     let games = registry.resolve().await?;
@@ -56,12 +63,14 @@ impl ControllerState {
       players: players.clone(),
       player_packet_sender: PlayerRegistryHandle::from(players),
       config,
+      events,
     })
   }
 
   pub async fn reload(&self) -> Result<()> {
     self.config.send(Reload).await??;
     self.nodes.send(Reload).await??;
+    self.events.publish(ControllerEvent::NodesReloaded);
     Ok(())
   }
 
@@ -10,6 +10,10 @@ pub enum Error {
   BonjourRegister(std::io::Error),
   #[error("bonjour update: {0}")]
   BonjourUpdate(String),
+  #[error("no network interface has address: {0}")]
+  InterfaceNotFound(std::net::Ipv4Addr),
+  #[error("list network interfaces: {0}")]
+  ListInterfaces(std::io::Error),
   #[error("get hostname: {0}")]
   GetHostName(std::io::Error),
   #[error("couldn't find game info record in the replay file")]
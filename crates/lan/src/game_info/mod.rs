@@ -12,6 +12,21 @@ use crate::error::*;
 use crate::proto;
 use flo_w3gs::protocol::constants::GameSettingFlags;
 
+/// Longest game name in bytes the LAN game list can display
+pub const MAX_GAME_NAME_LEN: usize = 31;
+
+/// Truncates `name` to at most `max_len` bytes without splitting a character
+pub fn truncate_game_name(name: &str, max_len: usize) -> &str {
+  if name.len() <= max_len {
+    return name;
+  }
+  let mut end = max_len;
+  while !name.is_char_boundary(end) {
+    end -= 1;
+  }
+  &name[..end]
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct GameInfo {
   pub(crate) message_id: i32,
@@ -229,6 +244,10 @@ impl GameInfo {
   pub fn set_port(&mut self, port: u16) {
     self.data.port = port;
   }
+
+  pub fn set_game_setting_flags(&mut self, flags: GameSettingFlags) {
+    self.data.settings.game_setting_flags = flags;
+  }
 }
 
 #[derive(Debug, BinEncode, BinDecode, PartialEq, Clone)]
@@ -289,3 +308,26 @@ fn test_decode_gamedata_2() {
   let data = GameData::decode(&mut bytes.as_slice()).unwrap();
   println!("{:#?}", data);
 }
+
+#[test]
+fn test_truncate_game_name() {
+  assert_eq!(truncate_game_name("short", MAX_GAME_NAME_LEN), "short");
+  let long = "a".repeat(40);
+  assert_eq!(truncate_game_name(&long, MAX_GAME_NAME_LEN).len(), 31);
+  // `é` is 2 bytes and would be split at byte 31
+  let name = format!("{}é", "a".repeat(30));
+  assert_eq!(truncate_game_name(&name, MAX_GAME_NAME_LEN), "a".repeat(30));
+  assert_eq!(truncate_game_name("abc", 0), "");
+}
+
+#[test]
+fn test_game_settings_builder_matches_game_info() {
+  let map_path = "Maps/frozenthrone/(4)TwistedMeadows.w3x";
+  let map_sha1 = [0xAB; 20];
+  let map_checksum = 0xDEADBEEF;
+  let game_info = GameInfo::new(1, "FLO", map_path, map_sha1, map_checksum).unwrap();
+  assert_eq!(
+    GameSettings::builder(map_path, map_sha1, map_checksum).build(),
+    game_info.data.settings
+  );
+}
@@ -6,6 +6,6 @@ mod proto {
 
 pub mod error;
 
-pub use self::game_info::GameInfo;
-pub use self::mdns::publisher::MdnsPublisher;
+pub use self::game_info::{truncate_game_name, GameInfo, MAX_GAME_NAME_LEN};
+pub use self::mdns::publisher::{get_local_ipv4_addrs, MdnsPublisher, DEFAULT_MAX_NAME_SUFFIX};
 pub use self::mdns::search::{search_lan_games, LanGame};
@@ -1,37 +1,68 @@
 use crate::error::*;
-use crate::game_info::GameInfo;
+use crate::game_info::{truncate_game_name, GameInfo, MAX_GAME_NAME_LEN};
 use futures::future::TryFutureExt;
 use parking_lot::RwLock;
+use std::collections::BTreeSet;
+use std::net::Ipv4Addr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
 use tracing_futures::Instrument;
 
+/// How long to browse for existing services before picking a name
+const NAME_SCAN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Highest suffix appended to a game name already advertised on the network, e.g. ` (9)`
+pub const DEFAULT_MAX_NAME_SUFFIX: u32 = 9;
+
 type GameInfoRef = Arc<RwLock<GameInfo>>;
 type UpdateTx = mpsc::Sender<oneshot::Sender<()>>;
 
 #[derive(Debug)]
 pub struct MdnsPublisher {
   update_tx: UpdateTx,
+  goodbye_tx: UpdateTx,
   game_info: GameInfoRef,
 }
 
 impl MdnsPublisher {
-  pub async fn start(game_version: String, game_info: GameInfo) -> Result<Self> {
+  /// Starts advertising `game_info`.
+  ///
+  /// If `bind_addr` is set, the service is only registered on the interface owning that address,
+  /// otherwise the mDNS responder picks the interfaces.
+  ///
+  /// If the game name is already registered, ` (2)` up to ` (max_name_suffix)` is appended to it.
+  pub async fn start(
+    game_version: String,
+    game_info: GameInfo,
+    bind_addr: Option<Ipv4Addr>,
+    max_name_suffix: u32,
+  ) -> Result<Self> {
+    let interface = resolve_interface(bind_addr)?;
     let game_name = game_info.name.to_string_lossy().to_string();
     let game_info = Arc::new(RwLock::new(game_info));
     let (update_tx, update_rx) = mpsc::channel::<oneshot::Sender<()>>(1);
+    let (goodbye_tx, goodbye_rx) = mpsc::channel::<oneshot::Sender<()>>(1);
 
     tokio::spawn(
-      Self::worker(game_version, game_info.clone(), game_name, update_rx)
-        .map_err(|err| {
-          tracing::error!("worker exited with error: {}", err);
-        })
-        .instrument(tracing::debug_span!("worker")),
+      Self::worker(
+        game_version,
+        game_info.clone(),
+        game_name,
+        interface,
+        max_name_suffix,
+        update_rx,
+        goodbye_rx,
+      )
+      .map_err(|err| {
+        tracing::error!("worker exited with error: {}", err);
+      })
+      .instrument(tracing::debug_span!("worker")),
     );
 
     Ok(Self {
       update_tx,
+      goodbye_tx,
       game_info,
     })
   }
@@ -40,50 +71,41 @@ impl MdnsPublisher {
     game_version: String,
     game_info: GameInfoRef,
     game_name: String,
+    interface: async_dnssd::Interface,
+    max_name_suffix: u32,
     mut update_rx: mpsc::Receiver<oneshot::Sender<()>>,
+    mut goodbye_rx: mpsc::Receiver<oneshot::Sender<()>>,
   ) -> Result<()> {
-    let name = if game_name.bytes().len() > 31 {
-      let name = game_name
-        .char_indices()
-        .filter_map(|(i, c)| {
-          if i < 31 || (i == 31 && c.len_utf8() == 1) {
-            Some(c)
-          } else {
-            None
-          }
-        })
-        .collect::<String>();
-      name
-    } else {
-      game_name
-    };
-
-    use async_dnssd::{register_extended, RegisterData, Type};
-
     let (port, data) = {
       let mut game_info = game_info.write();
       game_info.message_id = game_info.message_id + 1;
       (game_info.data.port, game_info.encode_to_bytes()?)
     };
     let reg_type = super::get_reg_type(&game_version)?;
-    let reg = register_extended(
-      &reg_type,
-      port,
-      RegisterData {
-        flags: async_dnssd::RegisterFlags::NO_AUTO_RENAME | async_dnssd::RegisterFlags::UNIQUE,
-        name: Some(&name),
-        ..Default::default()
-      },
-    )
-    .map_err(Error::BonjourRegister)?;
 
-    let record = reg
-      .add_record(Type(66), &data, 4500)
-      .map_err(Error::BonjourRegister)?;
-
-    let (_reg, res) = reg.await.map_err(Error::BonjourRegister)?;
-
-    tracing::debug!("register result: {:?}", res);
+    let name = truncate_game_name(&game_name, MAX_GAME_NAME_LEN);
+    // only browse for the names in use if the plain name is taken
+    let (reg, record) = match register(&reg_type, port, name, interface, &data).await {
+      Err(err) if max_name_suffix > 1 && is_name_conflict(&err) => {
+        let taken =
+          match super::search::search_service_names(&game_version, NAME_SCAN_TIMEOUT).await {
+            Ok(names) => names,
+            Err(err) => {
+              tracing::warn!("scan service names: {}", err);
+              BTreeSet::new()
+            }
+          };
+        let name = disambiguate_game_name(&game_name, &taken, max_name_suffix);
+        tracing::info!(
+          "game name `{}` is taken, publishing as `{}`",
+          game_name,
+          name
+        );
+        register(&reg_type, port, &name, interface, &data).await
+      }
+      res => res,
+    }
+    .map_err(Error::BonjourRegister)?;
 
     loop {
       tokio::select! {
@@ -102,6 +124,21 @@ impl MdnsPublisher {
             break;
           }
         },
+        goodbye = goodbye_rx.recv() => {
+          if let Some(ack) = goodbye {
+            tracing::debug!("goodbye");
+            let data = game_info.read().encode_to_bytes()?;
+            // announce the record with TTL 0 so browsers evict it immediately,
+            // dropping the registration then deregisters the service
+            if let Err(err) = record.update_record(&data, 0) {
+              tracing::warn!("goodbye record update: {}", err);
+            }
+            drop(record);
+            drop(reg);
+            ack.send(()).ok();
+            break;
+          }
+        },
       }
     }
 
@@ -121,6 +158,22 @@ impl MdnsPublisher {
     Ok(())
   }
 
+  /// Withdraws the advertisement so the game disappears from the LAN list right away.
+  /// The publisher stops advertising after this call.
+  pub async fn goodbye(&mut self) -> Result<()> {
+    let (ack_tx, ack_rx) = oneshot::channel();
+    self
+      .goodbye_tx
+      .send(ack_tx)
+      .await
+      .map_err(|_| Error::BonjourUpdate("worker dead: send".to_string()))?;
+
+    tokio::time::timeout(Duration::from_secs(1), ack_rx)
+      .await
+      .map_err(|_| Error::BonjourUpdate("timeout".to_string()))?
+      .map_err(|_| Error::BonjourUpdate("worker dead: recv".to_string()))
+  }
+
   pub async fn refresh(&mut self) -> Result<()> {
     let (ack_tx, ack_rx) = oneshot::channel();
     self
@@ -135,3 +188,168 @@ impl MdnsPublisher {
       .map_err(|_| Error::BonjourUpdate("worker dead: recv".to_string()))
   }
 }
+
+async fn register(
+  reg_type: &str,
+  port: u16,
+  name: &str,
+  interface: async_dnssd::Interface,
+  data: &[u8],
+) -> std::io::Result<(async_dnssd::Registration, async_dnssd::Record)> {
+  use async_dnssd::{register_extended, RegisterData, Type};
+
+  let reg = register_extended(
+    reg_type,
+    port,
+    RegisterData {
+      flags: async_dnssd::RegisterFlags::NO_AUTO_RENAME | async_dnssd::RegisterFlags::UNIQUE,
+      name: Some(name),
+      interface,
+      ..Default::default()
+    },
+  )?;
+
+  let record = reg.add_record(Type(66), data, 4500)?;
+
+  let (reg, res) = reg.await?;
+
+  tracing::debug!("register result: {:?}", res);
+
+  Ok((reg, record))
+}
+
+fn is_name_conflict(err: &std::io::Error) -> bool {
+  use async_dnssd::{Error, ErrorCode};
+
+  matches!(
+    err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+    Some(Error::KnownError(ErrorCode::NameConflict))
+  )
+}
+
+/// Appends ` (2)`, ` (3)`... up to ` (max_suffix)` to `name` if it is already in `taken`,
+/// shortening the name so the result fits `MAX_GAME_NAME_LEN`.
+///
+/// mDNS names compare case-insensitively. If every suffix is taken the plain name is returned
+/// and the registration is left to fail.
+fn disambiguate_game_name(name: &str, taken: &BTreeSet<String>, max_suffix: u32) -> String {
+  let is_taken = |candidate: &str| taken.iter().any(|v| v.eq_ignore_ascii_case(candidate));
+  let name = truncate_game_name(name, MAX_GAME_NAME_LEN);
+  if !is_taken(name) {
+    return name.to_string();
+  }
+  for n in 2..=max_suffix {
+    let suffix = format!(" ({})", n);
+    let candidate = format!(
+      "{}{}",
+      truncate_game_name(name, MAX_GAME_NAME_LEN.saturating_sub(suffix.len())),
+      suffix
+    );
+    if !is_taken(&candidate) {
+      return candidate;
+    }
+  }
+  tracing::warn!("no free name suffix for `{}`", name);
+  name.to_string()
+}
+
+/// IPv4 addresses of the local non-loopback interfaces, polled to detect network changes
+pub fn get_local_ipv4_addrs() -> Result<BTreeSet<Ipv4Addr>> {
+  Ok(
+    if_addrs::get_if_addrs()
+      .map_err(Error::ListInterfaces)?
+      .into_iter()
+      .filter(|iface| !iface.is_loopback())
+      .filter_map(|iface| match iface.ip() {
+        std::net::IpAddr::V4(addr) => Some(addr),
+        std::net::IpAddr::V6(_) => None,
+      })
+      .collect(),
+  )
+}
+
+fn resolve_interface(bind_addr: Option<Ipv4Addr>) -> Result<async_dnssd::Interface> {
+  use async_dnssd::Interface;
+
+  let addr = if let Some(addr) = bind_addr {
+    addr
+  } else {
+    return Ok(Interface::Any);
+  };
+
+  if_addrs::get_if_addrs()
+    .map_err(Error::ListInterfaces)?
+    .into_iter()
+    .find(|iface| iface.ip() == std::net::IpAddr::V4(addr))
+    .and_then(|iface| iface.index)
+    .map(Interface::Index)
+    .ok_or_else(|| Error::InterfaceNotFound(addr))
+}
+
+#[test]
+fn test_resolve_interface() {
+  use async_dnssd::Interface;
+
+  assert!(matches!(resolve_interface(None).unwrap(), Interface::Any));
+  assert!(matches!(
+    resolve_interface(Some(Ipv4Addr::new(192, 0, 2, 1))),
+    Err(Error::InterfaceNotFound(_))
+  ));
+
+  // the registration is pinned to the interface owning the address
+  for iface in if_addrs::get_if_addrs().unwrap() {
+    if let (std::net::IpAddr::V4(addr), Some(index)) = (iface.ip(), iface.index) {
+      assert!(matches!(
+        resolve_interface(Some(addr)).unwrap(),
+        Interface::Index(v) if v == index
+      ));
+    }
+  }
+}
+
+#[test]
+fn test_disambiguate_game_name() {
+  let taken: BTreeSet<String> = vec!["flo-1".to_string(), "FLO-1 (2)".to_string()]
+    .into_iter()
+    .collect();
+
+  assert_eq!(disambiguate_game_name("flo-2", &taken, 9), "flo-2");
+  // `flo-1` and, case-insensitively, `flo-1 (2)` are already on the network
+  assert_eq!(disambiguate_game_name("flo-1", &taken, 9), "flo-1 (3)");
+  // cap reached
+  assert_eq!(disambiguate_game_name("flo-1", &taken, 2), "flo-1");
+
+  let long = "a".repeat(40);
+  let taken: BTreeSet<String> = vec!["a".repeat(31)].into_iter().collect();
+  assert_eq!(
+    disambiguate_game_name(&long, &taken, 9),
+    format!("{} (2)", "a".repeat(27))
+  );
+}
+
+#[test]
+fn test_is_name_conflict() {
+  use async_dnssd::{Error, ErrorCode};
+
+  let err: std::io::Error = Error::KnownError(ErrorCode::NameConflict).into();
+  assert!(is_name_conflict(&err));
+  let err: std::io::Error = Error::KnownError(ErrorCode::Unknown).into();
+  assert!(!is_name_conflict(&err));
+  assert!(!is_name_conflict(&std::io::ErrorKind::Other.into()));
+}
+
+#[tokio::test]
+async fn test_publisher_bind_addr() {
+  let game_info = GameInfo::new(1, "test", "maps/test.w3x", [0; 20], 0).unwrap();
+  // an address that isn't local fails the start instead of advertising on every interface
+  let addr = Ipv4Addr::new(192, 0, 2, 1);
+  let err = MdnsPublisher::start(
+    "1.33.0.00000".to_string(),
+    game_info,
+    Some(addr),
+    DEFAULT_MAX_NAME_SUFFIX,
+  )
+  .await
+  .unwrap_err();
+  assert!(matches!(err, Error::InterfaceNotFound(v) if v == addr));
+}
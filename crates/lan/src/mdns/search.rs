@@ -1,6 +1,6 @@
-use crate::error::Error;
+use crate::error::{Error, Result};
 use crate::game_info::GameInfo;
-use async_dnssd::{browse, query_record, Type};
+use async_dnssd::{browse, query_record, BrowsedFlags, Type};
 use futures::stream::TryStreamExt;
 use std::collections::BTreeSet;
 use std::net::{SocketAddr, SocketAddrV4};
@@ -106,6 +106,38 @@ pub async fn search_lan_games(game_version: String, timeout: Duration) -> Vec<La
   records
 }
 
+/// Service names currently advertised for `game_version`, collected until `timeout`.
+/// Services removed while browsing are not included.
+pub async fn search_service_names(
+  game_version: &str,
+  timeout: Duration,
+) -> Result<BTreeSet<String>> {
+  let mut browse_stream = browse(&super::get_reg_type(game_version)?);
+  let mut names = BTreeSet::new();
+
+  let timeout = sleep(timeout);
+  tokio::pin!(timeout);
+  loop {
+    tokio::select! {
+      _ = &mut timeout => break,
+      res = browse_stream.try_next() => {
+        match res? {
+          Some(res) => {
+            if res.flags.contains(BrowsedFlags::ADD) {
+              names.insert(res.service_name);
+            } else {
+              names.remove(&res.service_name);
+            }
+          }
+          None => break,
+        }
+      }
+    }
+  }
+
+  Ok(names)
+}
+
 #[tokio::test]
 async fn test_search() {
   dbg!(search_lan_games("1.34.0.00000".into(), Duration::from_secs(5)).await);
@@ -0,0 +1,52 @@
+use bitflags::bitflags;
+
+bitflags! {
+  /// Optional features of the controller protocol.
+  ///
+  /// Both sides send the features they support in the handshake and only use
+  /// the ones supported by both, additive features don't need a version bump.
+  pub struct Capabilities: u32 {
+    /// `PacketPlayerPingMapUpdateRequest` and the ping map updates
+    const PING_MAP = 0x01;
+    /// `PacketServerConfig`
+    const SERVER_CONFIG = 0x02;
+    /// `PacketGameLobbyMessage`
+    const LOBBY_MESSAGE = 0x04;
+  }
+}
+
+impl Capabilities {
+  /// Features of this build
+  pub const SUPPORTED: Self = Self::all();
+
+  /// Features of peers built before the negotiation, they send no capabilities
+  pub const LEGACY: Self = Self::PING_MAP
+    .union(Self::SERVER_CONFIG)
+    .union(Self::LOBBY_MESSAGE);
+
+  /// Features used with a peer that sent `peer`, unknown bits from newer peers are ignored
+  pub fn negotiate(self, peer: Option<u32>) -> Self {
+    self & peer.map(Self::from_bits_truncate).unwrap_or(Self::LEGACY)
+  }
+}
+
+#[test]
+fn test_negotiate_capabilities() {
+  let supported = Capabilities::SUPPORTED;
+
+  // a newer peer with a feature this build doesn't know about
+  let peer = (Capabilities::PING_MAP | Capabilities::LOBBY_MESSAGE).bits() | 0x8000;
+  let negotiated = supported.negotiate(Some(peer));
+  assert_eq!(
+    negotiated,
+    Capabilities::PING_MAP | Capabilities::LOBBY_MESSAGE
+  );
+  assert!(!negotiated.contains(Capabilities::SERVER_CONFIG));
+
+  // an older peer with a reduced feature set
+  let reduced = Capabilities::PING_MAP;
+  assert_eq!(reduced.negotiate(Some(supported.bits())), reduced);
+
+  assert_eq!(supported.negotiate(Some(0)), Capabilities::empty());
+  assert_eq!(supported.negotiate(None), Capabilities::LEGACY);
+}
@@ -1,2 +1,4 @@
+mod capability;
 mod packets;
+pub use capability::*;
 pub use packets::*;
@@ -35,3 +35,5 @@ packet_type!(RemoveNode, PacketRemoveNode);
 packet_type!(PlayerMuteListUpdate, PacketPlayerMuteListUpdate);
 packet_type!(PlayerMuteAddRequest, PacketPlayerMuteAddRequest);
 packet_type!(PlayerMuteRemoveRequest, PacketPlayerMuteRemoveRequest);
+packet_type!(ServerConfig, PacketServerConfig);
+packet_type!(GameLobbyMessage, PacketGameLobbyMessage);
@@ -16,6 +16,15 @@ packet_type!(ControllerCreateGame, PacketControllerCreateGame);
 packet_type!(ControllerCreateGameAccept, PacketControllerCreateGameAccept);
 packet_type!(ControllerCreateGameReject, PacketControllerCreateGameReject);
 packet_type!(ControllerQueryGameStatus, PacketControllerQueryGameStatus);
+packet_type!(ControllerRenewPlayerToken, PacketControllerRenewPlayerToken);
+packet_type!(
+  ControllerRenewPlayerTokenAccept,
+  PacketControllerRenewPlayerTokenAccept
+);
+packet_type!(
+  ControllerRenewPlayerTokenReject,
+  PacketControllerRenewPlayerTokenReject
+);
 packet_type!(ClientConnect, PacketClientConnect);
 packet_type!(ClientConnectAccept, PacketClientConnectAccept);
 packet_type!(ClientConnectReject, PacketClientConnectReject);
@@ -31,5 +40,6 @@ packet_type!(
   ClientUpdateSlotClientStatusReject,
   PacketClientUpdateSlotClientStatusReject
 );
+packet_type!(ClientTickChecksum, PacketClientTickChecksum);
 packet_type!(NodeGameStatusUpdate, PacketNodeGameStatusUpdate);
 packet_type!(NodeGameStatusUpdateBulk, PacketNodeGameStatusUpdateBulk);
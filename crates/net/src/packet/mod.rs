@@ -236,6 +236,10 @@ pub enum PacketTypeId {
   PlayerMuteAddRequest,
   #[bin(value = 0x1F)]
   PlayerMuteRemoveRequest,
+  #[bin(value = 0x20)]
+  ServerConfig,
+  #[bin(value = 0x21)]
+  GameLobbyMessage,
 
   // Lobby <-> Node
   #[bin(value = 0x30)]
@@ -258,6 +262,12 @@ pub enum PacketTypeId {
   ControllerUpdateSlotStatusReject,
   #[bin(value = 0x39)]
   ControllerQueryGameStatus,
+  #[bin(value = 0x3A)]
+  ControllerRenewPlayerToken,
+  #[bin(value = 0x3B)]
+  ControllerRenewPlayerTokenAccept,
+  #[bin(value = 0x3C)]
+  ControllerRenewPlayerTokenReject,
 
   // Client <-> Node
   #[bin(value = 0x40)]
@@ -276,6 +286,8 @@ pub enum PacketTypeId {
   ClientShutdown,
   #[bin(value = 0x47)]
   ClientShutdownAck,
+  #[bin(value = 0x48)]
+  ClientTickChecksum,
 
   // Node -> [Client, Controller]
   #[bin(value = 0x50)]
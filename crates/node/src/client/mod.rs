@@ -69,7 +69,7 @@ pub async fn serve_client(state: GlobalStateRef) -> Result<()> {
           }
         } else {
           if let Err((stream, err)) = session
-            .register_player_stream(claim.player_id, stream)
+            .register_player_stream(claim.player_id, claim.tick_checksums, stream)
             .await
           {
             tracing::error!(
@@ -125,6 +125,7 @@ async fn handshake(state: &GlobalState, stream: &mut FloStream) -> Result<Claim>
     player_id: pending.player_id,
     shutdown_retry: connect.retry_shutdown,
     leave_reason: connect.leave_reason.map(LeaveReason::from),
+    tick_checksums: connect.tick_checksums,
   })
 }
 
@@ -134,4 +135,5 @@ pub struct Claim {
   player_id: i32,
   shutdown_retry: bool,
   leave_reason: Option<LeaveReason>,
+  tick_checksums: bool,
 }
@@ -13,6 +13,12 @@ pub static GAME_DEFAULT_STEP_MS: Lazy<u16> = Lazy::new(|| {
     .and_then(|v| v.parse().ok())
     .unwrap_or(30)
 });
+pub static GAME_PLAYER_MAX_RECONNECTS: Lazy<u32> = Lazy::new(|| {
+  std::env::var("FLO_GAME_MAX_RECONNECTS")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(20)
+});
 pub const GAME_PING_INTERVAL: Duration = Duration::from_secs(1);
 pub const GAME_PING_TIMEOUT: Duration = Duration::from_secs(5);
 pub const GAME_CLOCK_MAX_PAUSE: Duration = Duration::from_secs(60 - 3);
@@ -181,6 +181,10 @@ async fn handle_frame(state: &Arc<State>, mut frame: Frame) -> Result<()> {
         let frame = state.g_state.handle_controller_create_game(ControllerServerHandle::new(state.clone()), pkt)?;
         flo_log::result_ok!("create game", tx.send(frame).await);
       }
+      pkt: PacketControllerRenewPlayerToken => {
+        let frame = state.g_state.handle_controller_renew_player_token(pkt)?;
+        flo_log::result_ok!("renew player token", tx.send(frame).await);
+      }
       pkt: PacketControllerUpdateSlotStatus => {
         let frame = state.g_state.handle_controller_update_slot_client_status(pkt).await?;
         flo_log::result_ok!("update slot status", tx.send(frame).await);
@@ -21,6 +21,8 @@ pub enum Error {
   PlayerChannelBroken,
   #[error("player already left")]
   PlayerAlreadyLeft,
+  #[error("player reconnect limit exceeded")]
+  PlayerReconnectLimitExceeded,
   #[error("invalid player slot client status: {0:?}")]
   InvalidPlayerSlotClientStatus(SlotClientStatus),
   #[error("invalid slot id")]
@@ -13,7 +13,7 @@ use crate::game::{
   SlotClientStatusUpdateSource,
 };
 use crate::observer::ObserverPublisherHandle;
-use flo_net::packet::{Frame, PacketTypeId};
+use flo_net::packet::{FloPacket, Frame, PacketTypeId};
 use flo_net::ping::{PingMsg, PingStream};
 use flo_net::w3gs::{W3GSFrameExt, W3GSMetadata, W3GSPacket, W3GSPacketTypeId};
 use flo_observer::record::{RTTStats, RTTStatsItem};
@@ -481,7 +481,7 @@ impl State {
     &mut self,
     stream: PlayerStream,
     peer_tx: &Sender<PeerMsg>,
-    _action_tx: &mut Sender<ActionMsg>,
+    action_tx: &mut Sender<ActionMsg>,
     out_tx: &mut GameEventSender,
   ) -> Result<PlayerStreamHandle> {
     let game_id = self.game_id;
@@ -492,6 +492,31 @@ impl State {
       return Err(Error::PlayerAlreadyLeft);
     }
 
+    let reconnect_allowed = {
+      let mut guard = self.shared.lock();
+      let player = guard
+        .get_player(player_id)
+        .ok_or_else(|| Error::PlayerAlreadyLeft)?;
+      player.pristine() || player.record_reconnect(*crate::constants::GAME_PLAYER_MAX_RECONNECTS)
+    };
+
+    if !reconnect_allowed {
+      tracing::warn!(
+        game_id = self.game_id,
+        player_id,
+        "reconnect limit exceeded, removing player"
+      );
+      self
+        .handle_player_leave(
+          player_id,
+          Some(LeaveReason::LeaveDisconnect),
+          action_tx,
+          out_tx,
+        )
+        .await?;
+      return Err(Error::PlayerReconnectLimitExceeded);
+    }
+
     let (peer_cmd_tx, peer_cmd_rx) = channel(crate::constants::PEER_CHANNEL_SIZE);
 
     let sender = PlayerStreamHandle::new(&stream, peer_cmd_tx.clone());
@@ -864,7 +889,7 @@ impl State {
       }
     }
 
-    if self.chat_banned_player_ids.contains(&player_id) && chat.is_in_game_chat() {
+    if is_chat_dropped(&self.chat_banned_player_ids, player_id, &chat) {
       return Ok(());
     }
 
@@ -1538,6 +1563,7 @@ impl Shared {
           self
             .obs
             .push_tick_checksum(self.game_id, res.game_tick, checksum);
+          self.send_tick_checksum(res.player_tick, checksum);
         }
         res
       }
@@ -1575,6 +1601,24 @@ impl Shared {
     }
   }
 
+  /// Sends the checksum all players agreed on for `tick` to the clients that requested it
+  fn send_tick_checksum(&mut self, tick: u32, checksum: u32) {
+    let frame = match (flo_net::proto::flo_node::PacketClientTickChecksum { tick, checksum })
+      .encode_as_frame()
+    {
+      Ok(frame) => frame,
+      Err(err) => {
+        tracing::warn!("encode tick checksum packet: {}", err);
+        return;
+      }
+    };
+    for info in self.map.values_mut() {
+      if info.tick_checksums() {
+        info.send(frame.clone()).ok();
+      }
+    }
+  }
+
   fn handle_desync(&mut self, desync: Vec<PlayerDesync>) -> Result<()> {
     let mut handled = BTreeSet::new();
     let mut targets = vec![];
@@ -1967,3 +2011,118 @@ enum ClosePlayerStreamResult {
   ClosedLagging,
   Skipped,
 }
+
+/// In-game chat of chat banned or muted players is dropped,
+/// they still receive chat from other players
+fn is_chat_dropped(chat_banned_player_ids: &[i32], player_id: i32, chat: &ChatToHost) -> bool {
+  chat_banned_player_ids.contains(&player_id) && chat.is_in_game_chat()
+}
+
+#[test]
+fn test_is_chat_dropped() {
+  use flo_w3gs::protocol::chat::MessageScope;
+
+  let muted = [2];
+  let chat = ChatToHost::in_game(MessageScope::All, 1, &[2, 3], "gl hf");
+  assert!(!is_chat_dropped(&muted, 1, &chat));
+  assert!(is_chat_dropped(&muted, 2, &chat));
+  assert!(!is_chat_dropped(
+    &muted,
+    2,
+    &ChatToHost::lobby(2, &[1], "ready")
+  ));
+}
+
+#[tokio::test]
+async fn test_register_stream_reconnect_limit() {
+  use crate::constants::GAME_PLAYER_MAX_RECONNECTS;
+  use crate::game::{Computer, GamePlayer, GameSlotSettings, Race};
+  use crate::observer::ObserverPublisher;
+  use flo_net::listener::FloListener;
+  use flo_net::stream::FloStream;
+  use std::net::SocketAddr;
+
+  let slots: Vec<_> = (1..=2)
+    .map(|player_id| PlayerSlot {
+      id: (player_id - 1) as u32,
+      settings: GameSlotSettings {
+        team: 0,
+        color: player_id - 1,
+        computer: Computer::Easy,
+        handicap: 100,
+        race: Race::Human,
+      },
+      player: GamePlayer {
+        player_id,
+        name: format!("player{}", player_id),
+        ban_list: vec![],
+      },
+      client_status: SlotClientStatus::Pending,
+      sender: None,
+    })
+    .collect();
+
+  let obs = ObserverPublisher::new();
+  let ct = CancellationToken::new();
+  let (_status_tx, status_rx) = watch::channel(DispatchStatus::Pending);
+  let (mut action_tx, _action_rx) = channel(16);
+  let (peer_tx, _peer_rx) = channel(16);
+  let (mut out_tx, mut out_rx) = channel(16);
+  let mut state = State::new(
+    1,
+    GameHostOptions {
+      enabled_ping_equalizer: false,
+    },
+    &slots,
+    obs.handle(),
+    status_rx,
+    action_tx.clone(),
+    ct.clone(),
+  );
+
+  let mut listener = FloListener::bind_v4(0).await.unwrap();
+  let addr: SocketAddr = ([127, 0, 0, 1], listener.port()).into();
+  let mut clients = vec![];
+  let mut results = vec![];
+
+  // the first connection is not a reconnect
+  for _ in 0..(*GAME_PLAYER_MAX_RECONNECTS + 3) {
+    clients.push(FloStream::connect_no_delay(addr).await.unwrap());
+    let stream = listener.incoming().next().await.unwrap().unwrap();
+    let res = state
+      .register_stream(
+        PlayerStream::new(1, stream),
+        &peer_tx,
+        &mut action_tx,
+        &mut out_tx,
+      )
+      .await;
+    let mut statuses = vec![];
+    while let Ok(GameEvent::PlayerStatusChange(player_id, status, _)) = out_rx.try_recv() {
+      assert_eq!(player_id, 1);
+      statuses.push(status);
+    }
+    results.push((res.err(), statuses));
+  }
+
+  let allowed = *GAME_PLAYER_MAX_RECONNECTS as usize + 1;
+  for (err, statuses) in &results[..allowed] {
+    assert!(err.is_none());
+    assert_eq!(statuses, &[SlotClientStatus::Connected]);
+  }
+
+  let (err, statuses) = &results[allowed];
+  assert!(matches!(err, Some(Error::PlayerReconnectLimitExceeded)));
+  assert_eq!(statuses, &[SlotClientStatus::Left]);
+  assert!(state.left_players.contains(&1));
+  assert!(state.shared.lock().get_player(1).is_none());
+
+  let (err, statuses) = &results[allowed + 1];
+  assert!(matches!(err, Some(Error::PlayerAlreadyLeft)));
+  assert!(statuses.is_empty());
+
+  // the other player is not affected
+  assert!(state.shared.lock().get_player(2).is_some());
+
+  ct.cancel();
+}
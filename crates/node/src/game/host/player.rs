@@ -21,6 +21,7 @@ pub struct PlayerDispatchInfo {
   lag_slot_ids: BTreeSet<u8>,
   delay: Option<Duration>,
   last_disconnect: Option<Instant>,
+  reconnects: u32,
   rtt_stats: PlayerRTTStats,
   last_rtt_stats: Option<PlayerRTTStats>,
   is_observer: bool,
@@ -40,6 +41,7 @@ impl PlayerDispatchInfo {
       lag_slot_ids: BTreeSet::new(),
       delay: None,
       last_disconnect: None,
+      reconnects: 0,
       rtt_stats: PlayerRTTStats::default(),
       last_rtt_stats: None,
       is_observer: slot.settings.team == 24,
@@ -105,10 +107,25 @@ impl PlayerDispatchInfo {
     }
   }
 
+  /// Counts a reconnect, returns `false` if the player has used up all reconnects
+  /// and the stream should be refused
+  pub fn record_reconnect(&mut self, max_reconnects: u32) -> bool {
+    record_reconnect(&mut self.reconnects, max_reconnects)
+  }
+
   pub fn stream_id(&self) -> Option<u64> {
     self.tx.as_ref().map(|v| v.stream_id())
   }
 
+  /// The connected client requested the agreed tick checksums
+  pub fn tick_checksums(&self) -> bool {
+    self
+      .tx
+      .as_ref()
+      .map(|v| v.tick_checksums())
+      .unwrap_or(false)
+  }
+
   pub fn send_w3gs(&mut self, pkt: W3GSPacket) -> Result<(), PlayerSendError> {
     let meta = self.enqueue_w3gs(pkt.clone());
     self.send(Frame::from_w3gs(meta, pkt))
@@ -283,6 +300,11 @@ impl PlayerDispatchInfo {
   }
 }
 
+fn record_reconnect(reconnects: &mut u32, max_reconnects: u32) -> bool {
+  *reconnects = reconnects.saturating_add(1);
+  *reconnects <= max_reconnects
+}
+
 pub enum PlayerSendError {
   NotConnected(Frame),
   Closed(Frame),
@@ -324,3 +346,17 @@ impl PlayerRTTStats {
     }
   }
 }
+
+#[test]
+fn test_record_reconnect() {
+  let mut reconnects = 0;
+  for _ in 0..3 {
+    assert!(record_reconnect(&mut reconnects, 3));
+  }
+  assert!(!record_reconnect(&mut reconnects, 3));
+  assert!(!record_reconnect(&mut reconnects, 3));
+  assert_eq!(reconnects, 5);
+
+  let mut reconnects = 0;
+  assert!(!record_reconnect(&mut reconnects, 0));
+}
@@ -14,6 +14,7 @@ pub struct PlayerStream {
   player_id: i32,
   stream: FloStream,
   ct: CancellationToken,
+  tick_checksums: bool,
 }
 
 impl PlayerStream {
@@ -25,10 +26,17 @@ impl PlayerStream {
       player_id,
       stream,
       ct: CancellationToken::new(),
+      tick_checksums: false,
     };
     stream
   }
 
+  /// Sends `PacketClientTickChecksum` to the client for every tick the players agreed on
+  pub fn with_tick_checksums(mut self, value: bool) -> Self {
+    self.tick_checksums = value;
+    self
+  }
+
   pub fn id(&self) -> u64 {
     self.id
   }
@@ -63,6 +71,7 @@ pub struct PlayerStreamHandle {
   stream_id: u64,
   tx: Sender<PlayerStreamCmd>,
   ct: CancellationToken,
+  tick_checksums: bool,
 }
 
 impl PlayerStreamHandle {
@@ -71,6 +80,7 @@ impl PlayerStreamHandle {
       stream_id: stream.id(),
       tx,
       ct: stream.ct.clone(),
+      tick_checksums: stream.tick_checksums,
     }
   }
 
@@ -78,6 +88,10 @@ impl PlayerStreamHandle {
     self.stream_id
   }
 
+  pub fn tick_checksums(&self) -> bool {
+    self.tick_checksums
+  }
+
   pub fn close(&self) {
     self.ct.cancel();
   }
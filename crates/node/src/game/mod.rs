@@ -160,6 +160,7 @@ impl GameSessionHandle {
   pub async fn register_player_stream(
     &self,
     player_id: i32,
+    tick_checksums: bool,
     stream: FloStream,
   ) -> Result<(), (Option<FloStream>, Error)> {
     use host::stream::PlayerStream;
@@ -186,7 +187,7 @@ impl GameSessionHandle {
       };
     };
 
-    let stream = PlayerStream::new(player_id, stream);
+    let stream = PlayerStream::new(player_id, stream).with_tick_checksums(tick_checksums);
     let snapshot = guard.get_status_snapshot();
     let sender = guard
       .host
@@ -14,8 +14,9 @@ use flo_net::packet::{FloPacket, Frame, OptionalFieldExt};
 use flo_net::proto::flo_node::{
   ControllerCreateGameRejectReason, Game, PacketControllerCreateGame,
   PacketControllerCreateGameAccept, PacketControllerCreateGameReject,
-  PacketControllerUpdateSlotStatus, PacketControllerUpdateSlotStatusAccept,
-  PacketControllerUpdateSlotStatusReject,
+  PacketControllerRenewPlayerToken, PacketControllerRenewPlayerTokenAccept,
+  PacketControllerRenewPlayerTokenReject, PacketControllerUpdateSlotStatus,
+  PacketControllerUpdateSlotStatusAccept, PacketControllerUpdateSlotStatusReject,
 };
 
 use crate::controller::ControllerServerHandle;
@@ -149,6 +150,26 @@ impl GlobalState {
     )
   }
 
+  pub fn handle_controller_renew_player_token(
+    &self,
+    packet: PacketControllerRenewPlayerToken,
+  ) -> Result<Frame> {
+    let game_id = packet.game_id;
+    let player_id = packet.player_id;
+    let frame = match self.players.renew(game_id, player_id) {
+      Some(token) => PacketControllerRenewPlayerTokenAccept {
+        game_id,
+        player_token: Some(flo_net::proto::flo_node::PlayerToken {
+          player_id,
+          token: token.to_vec(),
+        }),
+      }
+      .encode_as_frame()?,
+      None => PacketControllerRenewPlayerTokenReject { game_id, player_id }.encode_as_frame()?,
+    };
+    Ok(frame)
+  }
+
   pub async fn handle_controller_update_slot_client_status(
     &self,
     packet: PacketControllerUpdateSlotStatus,
@@ -306,6 +327,25 @@ impl PlayerRegistry {
     }
   }
 
+  // for controller, the old token stops working
+  fn renew(&self, game_id: i32, player_id: i32) -> Option<PlayerToken> {
+    let mut state = self.state.write();
+    let state = &mut *state;
+    let (_, token) = state
+      .game_tokens
+      .get_mut(&game_id)?
+      .iter_mut()
+      .find(|(id, _)| *id == player_id)?;
+    // the player joined another game since
+    let player = state.map.remove(&*token)?;
+    let old = std::mem::replace(token, PlayerToken::new_uuid());
+    state.map.insert(token.clone(), player);
+    if state.player_token.get(&player_id) == Some(&old) {
+      state.player_token.insert(player_id, token.clone());
+    }
+    Some(token.clone())
+  }
+
   pub fn get_by_token(&self, token: &PlayerToken) -> Option<RegisteredPlayer> {
     self.state.read().map.get(&token).cloned()
   }
@@ -360,3 +400,32 @@ impl GameRegistry {
     }
   }
 }
+
+#[test]
+fn test_player_registry_renew() {
+  let players = PlayerRegistry::new();
+  let token = PlayerToken::new_uuid();
+  players.register(GamePlayerTokens {
+    game_id: 1,
+    pairs: vec![(
+      token.clone(),
+      RegisteredPlayer {
+        player_id: 10,
+        game_id: 1,
+      },
+    )],
+  });
+
+  let renewed = players.renew(1, 10).unwrap();
+  assert_ne!(renewed, token);
+  assert!(players.get_by_token(&token).is_none());
+  assert_eq!(players.get_by_token(&renewed).unwrap().player_id, 10);
+
+  assert!(players.renew(1, 11).is_none());
+  assert!(players.renew(2, 10).is_none());
+
+  // the renewed token is released with the game
+  players.remove_game(1);
+  assert!(players.get_by_token(&renewed).is_none());
+  assert!(players.state.read().player_token.is_empty());
+}
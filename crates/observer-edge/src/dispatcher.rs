@@ -37,6 +37,12 @@ impl Dispatcher {
     }
   }
 
+  /// Drops the state kept for `game_id` besides its `GameHandler`
+  fn remove_game_state(&mut self, game_id: i32) {
+    self.snapshots.remove_game(game_id);
+    self.streams.set_max_spectators(game_id, None);
+  }
+
   async fn run_iter(addr: Addr<Self>, mut iter: DataStreamIterator) {
     while let Some(v) = iter.next().await {
       if addr.notify(HandleChunk(v)).await.is_err() {
@@ -90,7 +96,7 @@ impl Dispatcher {
             if self.slots.len() == self.slots.cap() {
               if let Some((game_id, mut removed)) = self.slots.pop_lru() {
                 tracing::info!(game_id, "expired");
-                self.snapshots.remove_game(game_id);
+                self.remove_game_state(game_id);
                 Self::upload_archive(self.services.clone(), &mut removed);
               }
             }
@@ -109,7 +115,7 @@ impl Dispatcher {
             if self.slots.len() == self.slots.cap() {
               if let Some((game_id, mut removed)) = self.slots.pop_lru() {
                 tracing::info!(game_id, "expired");
-                self.snapshots.remove_game(game_id);
+                self.remove_game_state(game_id);
                 Self::upload_archive(self.services.clone(), &mut removed);
               }
             }
@@ -122,7 +128,7 @@ impl Dispatcher {
         if let Some(mut removed) = self.slots.pop(&game_id) {
           Self::upload_archive(self.services.clone(), &mut removed);
         }
-        self.snapshots.remove_game(game_id);
+        self.remove_game_state(game_id);
       }
     }
   }
@@ -347,7 +353,7 @@ impl Handler<CreateGameStreamServer> for Dispatcher {
         let (snapshot, rx) =
           self
             .streams
-            .subscribe(game_id, handler.initial_arrival_time(), handler.records());
+            .subscribe(game_id, handler.initial_arrival_time(), handler.records())?;
         Ok(GameStreamServer::new(game_id, delay_secs, snapshot, rx))
       }
       _ => {
@@ -357,6 +363,45 @@ impl Handler<CreateGameStreamServer> for Dispatcher {
   }
 }
 
+pub struct GetMaxSpectators {
+  pub game_id: i32,
+}
+
+impl Message for GetMaxSpectators {
+  type Result = Option<usize>;
+}
+
+#[async_trait]
+impl Handler<GetMaxSpectators> for Dispatcher {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    GetMaxSpectators { game_id }: GetMaxSpectators,
+  ) -> Option<usize> {
+    self.streams.max_spectators(game_id)
+  }
+}
+
+pub struct SetMaxSpectators {
+  pub game_id: i32,
+  pub max: Option<usize>,
+}
+
+impl Message for SetMaxSpectators {
+  type Result = ();
+}
+
+#[async_trait]
+impl Handler<SetMaxSpectators> for Dispatcher {
+  async fn handle(
+    &mut self,
+    _: &mut Context<Self>,
+    SetMaxSpectators { game_id, max }: SetMaxSpectators,
+  ) {
+    self.streams.set_max_spectators(game_id, max)
+  }
+}
+
 struct HandleChunk(Chunk);
 
 impl Message for HandleChunk {
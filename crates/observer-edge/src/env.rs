@@ -14,6 +14,8 @@ pub struct Env {
   pub aws_access_key_id: Option<String>,
   pub aws_secret_access_key: Option<String>,
   pub admin_secret: Option<String>,
+  /// Broadcast delay applied to every observer stream, tokens can only ask for a longer delay
+  pub observer_min_delay_secs: Option<i64>,
 }
 
 pub static ENV: Lazy<Env> = Lazy::new(|| {
@@ -34,5 +36,9 @@ pub static ENV: Lazy<Env> = Lazy::new(|| {
     aws_access_key_id: env::var("AWS_ACCESS_KEY_ID").ok(),
     aws_secret_access_key: env::var("AWS_SECRET_ACCESS_KEY").ok(),
     admin_secret: env::var("ADMIN_SECRET").ok(),
+    observer_min_delay_secs: env::var("OBSERVER_MIN_DELAY_SECS")
+      .ok()
+      .and_then(|v| v.parse().ok())
+      .filter(|v| *v > 0),
   }
 });
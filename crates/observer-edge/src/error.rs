@@ -14,6 +14,8 @@ pub enum Error {
     range: [u32; 2],
     len: usize,
   },
+  #[error("spectator limit reached: {0}")]
+  SpectatorLimitReached(i32),
   #[error("game version unknown")]
   GameVersionUnknown,
   #[error("peer lagged: {0} events dropped")]
@@ -1,19 +1,25 @@
 use crate::broadcast::{BroadcastReceiver, BroadcastSender};
+use crate::constants::FLO_STATS_MAX_IN_MEMORY_GAMES;
+use crate::error::{Error, Result};
 use bytes::{Bytes, BytesMut};
 use flo_kinesis::iterator::GameChunk;
 use flo_observer::record::GameRecordData;
+use lru::LruCache;
 use std::collections::BTreeMap;
 
 pub const MAX_STREAM_FRAME_SIZE: usize = 8 * 1024;
 
 pub struct GameStreamMap {
   map: BTreeMap<i32, GameStream>,
+  // Caps can be set before the game starts streaming, games that never do are evicted
+  max_spectators: LruCache<i32, usize>,
 }
 
 impl GameStreamMap {
   pub fn new() -> Self {
     GameStreamMap {
       map: BTreeMap::new(),
+      max_spectators: LruCache::new(*FLO_STATS_MAX_IN_MEMORY_GAMES),
     }
   }
 
@@ -22,9 +28,15 @@ impl GameStreamMap {
     game_id: i32,
     initial_arrival_time: f64,
     initial_records: &[GameRecordData],
-  ) -> (GameStreamDataSnapshot, BroadcastReceiver<GameStreamEvent>) {
+  ) -> Result<(GameStreamDataSnapshot, BroadcastReceiver<GameStreamEvent>)> {
+    if let Some(max) = self.max_spectators.peek(&game_id).cloned() {
+      if self.spectator_count(game_id) >= max {
+        return Err(Error::SpectatorLimitReached(game_id));
+      }
+    }
+
     use std::collections::btree_map::Entry;
-    match self.map.entry(game_id) {
+    let pair = match self.map.entry(game_id) {
       Entry::Vacant(e) => {
         let (stream, rx) = GameStream::new(game_id, initial_arrival_time, initial_records);
         let snapshot = stream.make_data_snapshot();
@@ -36,13 +48,42 @@ impl GameStreamMap {
         let snapshot = stream.make_data_snapshot();
         (snapshot, stream.tx.subscribe())
       }
+    };
+    Ok(pair)
+  }
+
+  pub fn spectator_count(&self, game_id: i32) -> usize {
+    self
+      .map
+      .get(&game_id)
+      .map(|stream| stream.tx.receiver_count())
+      .unwrap_or_default()
+  }
+
+  pub fn max_spectators(&self, game_id: i32) -> Option<usize> {
+    self.max_spectators.peek(&game_id).cloned()
+  }
+
+  // Only applies to new subscriptions, existing spectators are kept if the limit is lowered below the current count.
+  pub fn set_max_spectators(&mut self, game_id: i32, max: Option<usize>) {
+    match max {
+      Some(max) => {
+        self.max_spectators.put(game_id, max);
+      }
+      None => {
+        self.max_spectators.pop(&game_id);
+      }
     }
   }
 
   pub fn dispatch_game_records(&mut self, game_id: i32, chunk: &GameChunk) {
+    self.dispatch_records(game_id, &chunk.records)
+  }
+
+  fn dispatch_records(&mut self, game_id: i32, records: &[GameRecordData]) {
     let mut should_remove = false;
     if let Some(stream) = self.map.get_mut(&game_id) {
-      should_remove = !stream.dispatch_records(&chunk.records);
+      should_remove = !stream.dispatch_records(records);
     }
     if should_remove {
       tracing::debug!(game_id, "sender closed");
@@ -302,3 +343,51 @@ async fn test_game_stream() {
     assert_eq!(a, b)
   }
 }
+
+#[test]
+fn test_game_stream_max_spectators() {
+  let records = |tick| {
+    vec![GameRecordData::TickChecksum {
+      tick,
+      checksum: tick,
+    }]
+  };
+
+  let mut map = GameStreamMap::new();
+  let (_, mut rx1) = map.subscribe(1, 0., &records(0)).unwrap();
+  let (_, mut rx2) = map.subscribe(1, 0., &records(0)).unwrap();
+  assert_eq!(map.spectator_count(1), 2);
+
+  // lowering the cap below the current count keeps existing spectators
+  map.set_max_spectators(1, Some(1));
+  assert_eq!(map.max_spectators(1), Some(1));
+  assert!(matches!(
+    map.subscribe(1, 0., &[]),
+    Err(Error::SpectatorLimitReached(1))
+  ));
+  map.dispatch_records(1, &records(1));
+  assert!(rx1.try_recv().is_ok());
+  assert!(rx2.try_recv().is_ok());
+
+  // a slot opens up once a spectator leaves
+  drop(rx2);
+  assert!(map.subscribe(1, 0., &[]).is_ok());
+
+  // other games are not affected
+  assert!(map.subscribe(2, 0., &[]).is_ok());
+
+  map.set_max_spectators(1, None);
+  assert!(map.subscribe(1, 0., &[]).is_ok());
+}
+
+#[test]
+fn test_game_stream_max_spectators_bounded() {
+  let mut map = GameStreamMap::new();
+  let max_games = *FLO_STATS_MAX_IN_MEMORY_GAMES as i32;
+  for game_id in 0..=max_games {
+    map.set_max_spectators(game_id, Some(1));
+  }
+  assert_eq!(map.max_spectators.len(), max_games as usize);
+  assert_eq!(map.max_spectators(0), None);
+  assert_eq!(map.max_spectators(max_games), Some(1));
+}
@@ -12,7 +12,8 @@ mod version;
 use crate::broadcast::BroadcastReceiver;
 use crate::env::Env;
 use dispatcher::{
-  AddIterator, Dispatcher, GetGame, ListGames, SubscribeGameListUpdate, SubscribeGameUpdate,
+  AddIterator, Dispatcher, GetGame, GetMaxSpectators, ListGames, SetMaxSpectators,
+  SubscribeGameListUpdate, SubscribeGameUpdate,
 };
 use error::Result;
 use flo_kinesis::{data_stream::DataStream, iterator::ShardIteratorType};
@@ -133,4 +134,20 @@ impl FloObserverEdgeHandle {
   ) -> Result<(GameSnapshotWithStats, BroadcastReceiver<GameUpdateEvent>)> {
     self.0.send(SubscribeGameUpdate { game_id }).await?
   }
+
+  pub async fn get_max_spectators(&self, game_id: i32) -> Result<Option<usize>> {
+    self
+      .0
+      .send(GetMaxSpectators { game_id })
+      .await
+      .map_err(Into::into)
+  }
+
+  pub async fn set_max_spectators(&self, game_id: i32, max: Option<usize>) -> Result<()> {
+    self
+      .0
+      .send(SetMaxSpectators { game_id, max })
+      .await
+      .map_err(Into::into)
+  }
 }
@@ -7,6 +7,7 @@ use crate::error::Result;
 use crate::Dispatcher;
 use flo_net::{listener::FloListener, observer::ObserverConnectRejectReason, stream::FloStream};
 use flo_state::Addr;
+use peer::GameStreamServer;
 use std::time::SystemTime;
 use tokio_stream::StreamExt;
 
@@ -54,15 +55,7 @@ impl Handler {
       }
     };
 
-    let server = self
-      .dispatcher
-      .send(CreateGameStreamServer {
-        game_id: accepted.game_id,
-        delay_secs: accepted.delay_secs,
-      })
-      .await??;
-
-    server.run(self.transport).await?;
+    accepted.server.run(self.transport).await?;
 
     Ok(())
   }
@@ -105,19 +98,37 @@ impl Handler {
       }
     };
 
+    let delay_secs = get_delay_secs(token.delay_secs, crate::env::ENV.observer_min_delay_secs);
     let start_time = meta.started_at.timestamp();
     let now = (SystemTime::now().duration_since(SystemTime::UNIX_EPOCH))
       .unwrap()
       .as_secs() as i64;
-    let expected = start_time + token.delay_secs.unwrap_or_default();
 
-    if expected > now {
+    if let Some(expected) = get_delay_ends_at(start_time, delay_secs, now) {
       self
         .reject(ObserverConnectRejectReason::DelayNotOver, expected.into())
         .await?;
       return Ok(None);
     }
 
+    let server = match self
+      .dispatcher
+      .send(CreateGameStreamServer {
+        game_id: token.game_id,
+        delay_secs,
+      })
+      .await?
+    {
+      Ok(server) => server,
+      Err(Error::SpectatorLimitReached(_)) => {
+        self
+          .reject(ObserverConnectRejectReason::SpectatorLimitReached, None)
+          .await?;
+        return Ok(None);
+      }
+      Err(err) => return Err(err),
+    };
+
     self
       .transport
       .send(PacketObserverConnectAccept {
@@ -127,14 +138,11 @@ impl Handler {
           patch: crate::version::FLO_OBSERVER_VERSION.patch,
         }),
         game: Some(game),
-        delay_secs: token.delay_secs.clone(),
+        delay_secs,
       })
       .await?;
 
-    Ok(Some(Accepted {
-      game_id: token.game_id,
-      delay_secs: token.delay_secs,
-    }))
+    Ok(Some(Accepted { server }))
   }
 
   async fn reject(
@@ -159,6 +167,60 @@ impl Handler {
 }
 
 struct Accepted {
-  game_id: i32,
-  delay_secs: Option<i64>,
+  server: GameStreamServer,
+}
+
+/// The stream is delayed by whichever is longer, the delay requested by the token
+/// or the server-wide minimum delay.
+fn get_delay_secs(token_delay_secs: Option<i64>, min_delay_secs: Option<i64>) -> Option<i64> {
+  match (token_delay_secs, min_delay_secs) {
+    (Some(token), Some(min)) => Some(token.max(min)),
+    (token, min) => token.or(min),
+  }
+}
+
+/// Returns when observers may connect to a game started at `start_time`,
+/// or `None` if the delay is already over at `now`
+fn get_delay_ends_at(start_time: i64, delay_secs: Option<i64>, now: i64) -> Option<i64> {
+  let expected = start_time + delay_secs.unwrap_or_default();
+  if expected > now {
+    Some(expected)
+  } else {
+    None
+  }
+}
+
+#[test]
+fn test_get_delay_secs() {
+  assert_eq!(get_delay_secs(None, None), None);
+  assert_eq!(get_delay_secs(Some(30), None), Some(30));
+  assert_eq!(get_delay_secs(None, Some(120)), Some(120));
+  assert_eq!(get_delay_secs(Some(30), Some(120)), Some(120));
+  assert_eq!(get_delay_secs(Some(300), Some(120)), Some(300));
+}
+
+#[test]
+fn test_observer_min_delay() {
+  let start_time = 1_700_000_000;
+  let delay_secs = get_delay_secs(None, Some(120));
+
+  // an observer token without a delay still waits for the server-wide delay
+  assert_eq!(
+    get_delay_ends_at(start_time, delay_secs, start_time + 60),
+    Some(start_time + 120)
+  );
+  assert_eq!(
+    get_delay_ends_at(start_time, delay_secs, start_time + 119),
+    Some(start_time + 120)
+  );
+  assert_eq!(
+    get_delay_ends_at(start_time, delay_secs, start_time + 120),
+    None
+  );
+
+  // without any delay the stream is live
+  assert_eq!(
+    get_delay_ends_at(start_time, get_delay_secs(None, None), start_time),
+    None
+  );
 }
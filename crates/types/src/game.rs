@@ -1,4 +1,6 @@
 use crate::node::*;
+use bitflags::bitflags;
+use flo_w3gs::constants::GameSettingFlags;
 use s2_grpc_utils::{S2ProtoEnum, S2ProtoPack, S2ProtoUnpack};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -9,6 +11,14 @@ pub enum DisconnectReason {
   Unknown = 0,
   Multi = 1,
   Maintenance = 2,
+  VersionMismatch = 3,
+}
+
+impl DisconnectReason {
+  /// Unpacks a proto value, reasons unknown to this client map to `Unknown`
+  pub fn unpack_i32_or_unknown(value: i32) -> Self {
+    Self::unpack_i32(value).unwrap_or(DisconnectReason::Unknown)
+  }
 }
 
 #[derive(Debug, S2ProtoUnpack, Serialize, Clone)]
@@ -55,6 +65,7 @@ pub enum RejectReason {
   Unknown = 0,
   ClientVersionTooOld = 1,
   InvalidToken = 2,
+  Banned = 3,
 }
 
 #[derive(Debug, S2ProtoUnpack, Serialize, Clone)]
@@ -71,6 +82,7 @@ pub struct GameInfo {
   pub random_seed: i32,
   pub created_by: Option<PlayerInfo>,
   pub mask_player_names: bool,
+  pub game_flags: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +100,52 @@ pub struct LocalGameInfo {
   pub slots: Vec<Slot>,
   pub host_player: Option<PlayerInfo>,
   pub mask_player_names: bool,
+  pub game_flags: GameFlags,
+}
+
+bitflags! {
+  /// Game options picked at creation, on top of the settings derived from the map
+  pub struct GameFlags: u32 {
+    /// Every player for themselves, teams are neither fixed nor placed together
+    const FFA           = 0x01;
+    /// Players can't change teams in the lobby
+    const TEAMS_LOCKED  = 0x02;
+    /// Allies share full unit control
+    const ALLIES_SHARED = 0x04;
+  }
+}
+
+impl GameFlags {
+  /// Rejects unknown bits and combinations Warcraft doesn't accept
+  pub fn validate(bits: u32) -> Option<Self> {
+    let flags = GameFlags::from_bits(bits)?;
+    if flags.contains(GameFlags::FFA)
+      && flags.intersects(GameFlags::TEAMS_LOCKED | GameFlags::ALLIES_SHARED)
+    {
+      return None;
+    }
+    Some(flags)
+  }
+
+  /// Applies the options to `flags`, an empty set leaves them unchanged
+  pub fn apply(self, mut flags: GameSettingFlags) -> GameSettingFlags {
+    if self.contains(GameFlags::FFA) {
+      flags.remove(GameSettingFlags::TEAMS_TOGETHER | GameSettingFlags::TEAMS_FIXED);
+    }
+    if self.contains(GameFlags::TEAMS_LOCKED) {
+      flags.insert(GameSettingFlags::TEAMS_FIXED);
+    }
+    if self.contains(GameFlags::ALLIES_SHARED) {
+      flags.insert(GameSettingFlags::SHARED_CONTROL);
+    }
+    flags
+  }
+}
+
+impl Default for GameFlags {
+  fn default() -> Self {
+    GameFlags::empty()
+  }
 }
 
 #[derive(Debug, S2ProtoEnum, PartialEq, Copy, Clone, Serialize)]
@@ -301,3 +359,51 @@ pub struct MapForceOwned {
   pub flags: u32,
   pub player_set: u32,
 }
+
+#[test]
+fn test_disconnect_reason_unpack() {
+  use flo_net::proto::flo_connect::ClientDisconnectReason as Proto;
+
+  let known = [
+    (Proto::Unknown, DisconnectReason::Unknown),
+    (Proto::Multi, DisconnectReason::Multi),
+    (Proto::Maintenance, DisconnectReason::Maintenance),
+    (Proto::VersionMismatch, DisconnectReason::VersionMismatch),
+  ];
+  for (value, expected) in known.iter() {
+    assert_eq!(
+      DisconnectReason::unpack_i32_or_unknown(*value as i32),
+      *expected
+    );
+  }
+  assert_eq!(
+    DisconnectReason::unpack_i32_or_unknown(99),
+    DisconnectReason::Unknown
+  );
+}
+
+#[test]
+fn test_game_flags() {
+  assert_eq!(GameFlags::validate(0), Some(GameFlags::empty()));
+  assert_eq!(
+    GameFlags::validate((GameFlags::TEAMS_LOCKED | GameFlags::ALLIES_SHARED).bits()),
+    Some(GameFlags::TEAMS_LOCKED | GameFlags::ALLIES_SHARED)
+  );
+  assert_eq!(
+    GameFlags::validate((GameFlags::FFA | GameFlags::TEAMS_LOCKED).bits()),
+    None
+  );
+  assert_eq!(
+    GameFlags::validate((GameFlags::FFA | GameFlags::ALLIES_SHARED).bits()),
+    None
+  );
+  assert_eq!(GameFlags::validate(0x80), None);
+
+  let base = GameSettingFlags::default();
+  assert_eq!(GameFlags::empty().apply(base), base);
+  let ffa = GameFlags::FFA.apply(base);
+  assert!(!ffa.intersects(GameSettingFlags::TEAMS_TOGETHER | GameSettingFlags::TEAMS_FIXED));
+  assert!(GameFlags::ALLIES_SHARED
+    .apply(base)
+    .contains(GameSettingFlags::SHARED_CONTROL));
+}
@@ -24,6 +24,32 @@ pub enum SlotClientStatus {
   Left = 6,
 }
 
+impl SlotClientStatus {
+  /// Returns `true` if a player in this status can move to `next`.
+  ///
+  /// Legal edges:
+  /// - any status to itself
+  /// - forward along `Pending -> Connected -> Joined -> Loading -> Loaded`, skipping is allowed
+  /// - any active status to `Disconnected` or `Left`
+  /// - `Disconnected -> Left`
+  /// - `Disconnected -> Connected` or `Disconnected -> Loaded`, a player reconnecting to the node
+  /// - `Joined -> Connected`, the game client rejoining the lobby
+  pub fn can_transition_to(&self, next: SlotClientStatus) -> bool {
+    use SlotClientStatus::*;
+    if *self == next {
+      return true;
+    }
+    match (*self, next) {
+      (Left, _) => false,
+      (Disconnected, Left) | (Disconnected, Connected) | (Disconnected, Loaded) => true,
+      (Disconnected, _) => false,
+      (_, Disconnected) | (_, Left) => true,
+      (Joined, Connected) => true,
+      (current, next) => (current as i32) < (next as i32),
+    }
+  }
+}
+
 #[derive(Debug, S2ProtoUnpack)]
 #[s2_grpc(message_type = "flo_net::proto::flo_node::PacketClientConnectAccept")]
 pub struct NodeGameStatusSnapshot {
@@ -31,3 +57,76 @@ pub struct NodeGameStatusSnapshot {
   pub game_status: NodeGameStatus,
   pub player_game_client_status_map: HashMap<i32, SlotClientStatus>,
 }
+
+#[test]
+fn test_slot_client_status_transition() {
+  use SlotClientStatus::*;
+
+  let legal = [
+    (Pending, Pending),
+    (Pending, Connected),
+    (Pending, Loading),
+    (Connected, Joined),
+    (Joined, Loading),
+    (Loading, Loaded),
+    (Loaded, Disconnected),
+    (Joined, Left),
+    (Disconnected, Left),
+    (Disconnected, Connected),
+    (Disconnected, Loaded),
+    (Joined, Connected),
+  ];
+  for (current, next) in legal.iter() {
+    assert!(
+      current.can_transition_to(*next),
+      "{:?} -> {:?}",
+      current,
+      next
+    );
+  }
+
+  let illegal = [
+    (Disconnected, Loading),
+    (Disconnected, Joined),
+    (Left, Disconnected),
+    (Left, Pending),
+    (Loaded, Loading),
+    (Loaded, Connected),
+    (Connected, Pending),
+  ];
+  for (current, next) in illegal.iter() {
+    assert!(
+      !current.can_transition_to(*next),
+      "{:?} -> {:?}",
+      current,
+      next
+    );
+  }
+}
+
+#[test]
+fn test_slot_client_status_reconnect() {
+  use SlotClientStatus::*;
+
+  // drops while loading, reconnects and finishes loading, drops again in game
+  let sequence = [
+    Pending,
+    Connected,
+    Joined,
+    Loading,
+    Disconnected,
+    Connected,
+    Loaded,
+    Disconnected,
+    Loaded,
+    Left,
+  ];
+  for pair in sequence.windows(2) {
+    assert!(
+      pair[0].can_transition_to(pair[1]),
+      "{:?} -> {:?}",
+      pair[0],
+      pair[1]
+    );
+  }
+}
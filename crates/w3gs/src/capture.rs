@@ -0,0 +1,141 @@
+//! Raw W3GS packet capture used to reproduce desyncs.
+//!
+//! A capture file is a sequence of records, all integers are little endian:
+//! `direction: u8`, `time_micros: u64`, `len: u32`, followed by `len` bytes of the encoded packet.
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use flo_util::binary::*;
+
+use crate::error::{Error, Result};
+use crate::protocol::packet::{Header, Packet};
+
+const RECORD_HEADER_LEN: usize = 1 + 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum CaptureDirection {
+  /// Game -> Node
+  Sent = 0,
+  /// Node -> Game
+  Received = 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+  pub direction: CaptureDirection,
+  /// Time elapsed since the capture started
+  pub time: Duration,
+  pub packet: Packet,
+}
+
+impl CaptureRecord {
+  pub fn encode(&self, buf: &mut BytesMut) {
+    buf.reserve(RECORD_HEADER_LEN + self.packet.get_encode_len());
+    buf.put_u8(self.direction as u8);
+    buf.put_u64_le(self.time.as_micros() as u64);
+    buf.put_u32_le(self.packet.get_encode_len() as u32);
+    self.packet.encode(buf);
+  }
+
+  pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+    let mut buf = BytesMut::new();
+    self.encode(&mut buf);
+    w.write_all(&buf)?;
+    Ok(())
+  }
+}
+
+/// Iterates the records of a capture file
+pub struct CaptureReader<R> {
+  inner: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+  pub fn new(inner: R) -> Self {
+    Self { inner }
+  }
+
+  fn read_record(&mut self) -> Result<Option<CaptureRecord>> {
+    let mut head = [0_u8; RECORD_HEADER_LEN];
+    if self.inner.read(&mut head[..1])? == 0 {
+      return Ok(None);
+    }
+    self.inner.read_exact(&mut head[1..])?;
+
+    let mut head = &head[..];
+    let direction = match head.get_u8() {
+      0 => CaptureDirection::Sent,
+      1 => CaptureDirection::Received,
+      other => return Err(Error::InvalidCaptureDirection(other)),
+    };
+    let time = Duration::from_micros(head.get_u64_le());
+    let len = head.get_u32_le() as usize;
+
+    let mut buf = BytesMut::new();
+    buf.resize(len, 0);
+    self.inner.read_exact(&mut buf)?;
+    let header = Header::decode(&mut buf)?;
+    if header.len as usize != len {
+      return Err(Error::InvalidPacketLength(header.len));
+    }
+    let packet = Packet::decode(header, &mut buf)?;
+
+    Ok(Some(CaptureRecord {
+      direction,
+      time,
+      packet,
+    }))
+  }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+  type Item = Result<CaptureRecord>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.read_record().transpose()
+  }
+}
+
+#[test]
+fn test_capture_round_trip() {
+  use crate::protocol::ping::PingFromHost;
+
+  let records: Vec<CaptureRecord> = (0..5)
+    .map(|i| CaptureRecord {
+      direction: if i % 2 == 0 {
+        CaptureDirection::Sent
+      } else {
+        CaptureDirection::Received
+      },
+      time: Duration::from_millis(i * 100),
+      packet: Packet::simple(PingFromHost::with_payload(i as u32)).unwrap(),
+    })
+    .collect();
+
+  let mut file = vec![];
+  for record in &records {
+    record.write_to(&mut file).unwrap();
+  }
+
+  let decoded: Vec<CaptureRecord> = CaptureReader::new(file.as_slice())
+    .collect::<Result<_>>()
+    .unwrap();
+  assert_eq!(decoded.len(), records.len());
+  for (a, b) in records.iter().zip(decoded.iter()) {
+    assert_eq!(a.direction, b.direction);
+    assert_eq!(a.time, b.time);
+    assert_eq!(a.packet.type_id(), b.packet.type_id());
+    assert_eq!(a.packet.payload, b.packet.payload);
+    assert_eq!(
+      b.packet.decode_simple::<PingFromHost>().unwrap(),
+      PingFromHost::with_payload(a.time.as_millis() as u32 / 100)
+    );
+  }
+
+  // truncated record
+  let truncated = &file[..file.len() - 1];
+  let res: Result<Vec<_>> = CaptureReader::new(truncated).collect();
+  assert!(matches!(res, Err(Error::Io(_))));
+}
@@ -8,6 +8,8 @@ pub enum Error {
   StreamClosed,
   #[error("IPv6 is not supported")]
   Ipv6NotSupported,
+  #[error("no free port in range {0}-{1}")]
+  NoFreePort(u16, u16),
   #[error("payload size overflow")]
   PayloadSizeOverflow,
   #[error("invalid packet length: {0}")]
@@ -29,6 +31,8 @@ pub enum Error {
   },
   #[error("invalid checksum")]
   InvalidChecksum,
+  #[error("invalid capture direction: {0}")]
+  InvalidCaptureDirection(u8),
   #[error("bin decode: {0}")]
   BinDecode(#[from] flo_util::binary::BinDecodeError),
   #[error("protobuf decode: {0}")]
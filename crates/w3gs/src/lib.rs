@@ -1,3 +1,4 @@
+pub mod capture;
 pub mod error;
 pub mod net;
 pub mod protocol;
@@ -2,6 +2,7 @@ use futures::sink::SinkExt;
 use futures::stream::TryStreamExt;
 use futures::{ready, StreamExt};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::ops::RangeInclusive;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
@@ -30,6 +31,18 @@ impl W3GSListener {
     })
   }
 
+  /// Binds the first free port in `range`, in ascending order
+  pub async fn bind_range(range: RangeInclusive<u16>) -> Result<Self, Error> {
+    let listener = bind_first_free(range)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let local_addr = listener.local_addr()?;
+    Ok(W3GSListener {
+      listener,
+      local_addr,
+    })
+  }
+
   pub fn incoming(&mut self) -> Incoming {
     Incoming::new(&mut self.listener)
   }
@@ -50,6 +63,18 @@ impl W3GSListener {
   }
 }
 
+fn bind_first_free(range: RangeInclusive<u16>) -> Result<std::net::TcpListener> {
+  let (start, end) = (*range.start(), *range.end());
+  for port in range {
+    match std::net::TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)) {
+      Ok(listener) => return Ok(listener),
+      Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => continue,
+      Err(err) => return Err(err.into()),
+    }
+  }
+  Err(Error::NoFreePort(start, end))
+}
+
 #[derive(Debug)]
 pub struct W3GSStream {
   local_addr: SocketAddr,
@@ -158,3 +183,29 @@ impl Stream for Incoming<'_> {
     Poll::Ready(Some(Ok(stream)))
   }
 }
+
+#[test]
+fn test_bind_first_free() {
+  use std::net::TcpListener;
+
+  // find 3 consecutive ports, occupy the first 2
+  let (range, _occupied) = (0..100)
+    .find_map(|_| {
+      let first = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+      let start = first.local_addr().ok()?.port();
+      let end = start.checked_add(2)?;
+      let second = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, start + 1)).ok()?;
+      TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, end)).ok()?;
+      Some((start..=end, vec![first, second]))
+    })
+    .unwrap();
+
+  let listener = bind_first_free(range.clone()).unwrap();
+  assert_eq!(listener.local_addr().unwrap().port(), *range.end());
+
+  let start = *range.start();
+  assert!(matches!(
+    bind_first_free(start..=start + 1),
+    Err(Error::NoFreePort(s, e)) if s == start && e == start + 1
+  ));
+}
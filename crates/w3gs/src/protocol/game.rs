@@ -39,6 +39,21 @@ impl GameSettings {
     }
   }
 
+  /// Starts from the settings `flo_lan::GameInfo::new` advertises,
+  /// default flags, unknown map size and `FLO` as the host
+  pub fn builder(map_path: &str, map_sha1: [u8; 20], map_checksum: u32) -> GameSettingsBuilder {
+    GameSettingsBuilder {
+      flags: GameSettingFlags::default(),
+      map: GameSettingsMap {
+        path: map_path.to_string(),
+        width: 0,
+        height: 0,
+        sha1: map_sha1,
+        checksum: map_checksum,
+      },
+    }
+  }
+
   fn get_encode_size(&self) -> usize {
     size_of::<u32>() /* Flags */
     + 1 /* 0x0 */
@@ -52,6 +67,29 @@ impl GameSettings {
   }
 }
 
+#[derive(Debug)]
+pub struct GameSettingsBuilder {
+  flags: GameSettingFlags,
+  map: GameSettingsMap,
+}
+
+impl GameSettingsBuilder {
+  pub fn flags(mut self, flags: GameSettingFlags) -> Self {
+    self.flags = flags;
+    self
+  }
+
+  pub fn map_size(mut self, width: u16, height: u16) -> Self {
+    self.map.width = width;
+    self.map.height = height;
+    self
+  }
+
+  pub fn build(self) -> GameSettings {
+    GameSettings::new(self.flags, self.map)
+  }
+}
+
 impl BinEncode for GameSettings {
   fn encode<T: BufMut>(&self, buf: &mut T) {
     let len = self.get_encode_size();
@@ -186,3 +224,17 @@ fn test_game_loaded_self() {
 fn test_player_loaded() {
   crate::packet::test_simple_payload_type("player_loaded.bin", &PlayerLoaded { player_id: 2 })
 }
+
+#[test]
+fn test_game_settings_builder() {
+  let settings = GameSettings::builder("Maps/(2)EchoIsles.w3x", [1; 20], 0x12345678)
+    .flags(GameSettingFlags::OBS_FULL)
+    .map_size(116, 116)
+    .build();
+  assert_eq!(settings.game_setting_flags, GameSettingFlags::OBS_FULL);
+  assert_eq!((settings.map_width, settings.map_height), (116, 116));
+  assert_eq!(settings.map_checksum, 0x12345678);
+  assert_eq!(settings.map_sha1, [1; 20]);
+  assert_eq!(settings.map_path.to_str().unwrap(), "Maps/(2)EchoIsles.w3x");
+  assert_eq!(settings.host_name.to_str().unwrap(), "FLO");
+}
@@ -10,6 +10,7 @@ pub mod map;
 pub mod packet;
 pub mod ping;
 pub mod player;
+pub mod pool;
 pub mod slot;
 
 mod protobuf {
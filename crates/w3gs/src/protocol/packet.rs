@@ -81,13 +81,18 @@ impl Packet {
 
     payload.encode(&mut buf);
 
-    if buf.len() > (std::u16::MAX - 4) as usize {
+    Self::from_payload_bytes(T::PACKET_TYPE_ID, buf.freeze())
+  }
+
+  #[inline]
+  pub(crate) fn from_payload_bytes(type_id: PacketTypeId, payload: Bytes) -> Result<Packet> {
+    if payload.len() > (std::u16::MAX - 4) as usize {
       return Err(Error::PayloadSizeOverflow);
     }
 
     Ok(Packet {
-      header: Header::new(T::PACKET_TYPE_ID, (buf.len() as u16) + 4),
-      payload: buf.freeze(),
+      header: Header::new(type_id, (payload.len() as u16) + 4),
+      payload,
     })
   }
 
@@ -261,6 +266,10 @@ impl ProtoBufPayload {
 pub struct SimplePayload<T>(T);
 
 impl<T> SimplePayload<T> {
+  pub(crate) fn new(payload: T) -> Self {
+    SimplePayload(payload)
+  }
+
   pub fn into_inner(self) -> T {
     self.0
   }
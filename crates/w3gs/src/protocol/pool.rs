@@ -0,0 +1,184 @@
+use std::sync::Mutex;
+
+use flo_util::binary::*;
+
+use crate::error::Result;
+use crate::protocol::packet::{Packet, PacketPayload, PacketPayloadEncode, SimplePayload};
+
+const DEFAULT_MAX_BUFFERS: usize = 16;
+const DEFAULT_BUFFER_CAPACITY: usize = 16 * 1024;
+
+lazy_static::lazy_static! {
+  static ref SHARED: PacketBufferPool = PacketBufferPool::default();
+}
+
+/// Reusable encode buffers for building packets.
+///
+/// Payloads are split off a pooled buffer, the allocation is reclaimed
+/// by the next `reserve` once every packet split off it has been dropped.
+#[derive(Debug)]
+pub struct PacketBufferPool {
+  buffers: Mutex<Vec<BytesMut>>,
+  max_buffers: usize,
+  buffer_capacity: usize,
+}
+
+impl PacketBufferPool {
+  pub fn new(max_buffers: usize, buffer_capacity: usize) -> Self {
+    Self {
+      buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+      max_buffers,
+      buffer_capacity,
+    }
+  }
+
+  /// Process wide pool
+  pub fn shared() -> &'static PacketBufferPool {
+    &SHARED
+  }
+
+  /// Pooled version of `Packet::with_payload`
+  pub fn with_payload<T>(&self, payload: T) -> Result<Packet>
+  where
+    T: PacketPayload + PacketPayloadEncode,
+  {
+    let mut buf = self.take();
+    buf.reserve(payload.encode_len().unwrap_or(0));
+    payload.encode(&mut buf);
+    let bytes = buf.split().freeze();
+    self.put(buf);
+    Packet::from_payload_bytes(T::PACKET_TYPE_ID, bytes)
+  }
+
+  /// Pooled version of `Packet::simple`
+  pub fn simple<T>(&self, payload: T) -> Result<Packet>
+  where
+    T: PacketPayload + BinEncode,
+  {
+    self.with_payload(SimplePayload::new(payload))
+  }
+
+  /// Number of idle buffers
+  pub fn idle_buffers(&self) -> usize {
+    self.buffers.lock().unwrap().len()
+  }
+
+  fn take(&self) -> BytesMut {
+    let buf = self.buffers.lock().unwrap().pop();
+    match buf {
+      Some(mut buf) => {
+        buf.clear();
+        buf
+      }
+      None => BytesMut::with_capacity(self.buffer_capacity),
+    }
+  }
+
+  fn put(&self, buf: BytesMut) {
+    let mut buffers = self.buffers.lock().unwrap();
+    if buffers.len() < self.max_buffers {
+      buffers.push(buf);
+    }
+  }
+}
+
+impl Default for PacketBufferPool {
+  fn default() -> Self {
+    Self::new(DEFAULT_MAX_BUFFERS, DEFAULT_BUFFER_CAPACITY)
+  }
+}
+
+#[cfg(test)]
+fn test_packets(pool: Option<&PacketBufferPool>, i: u32) -> Vec<Packet> {
+  use crate::protocol::chat::ChatFromHost;
+  use crate::protocol::ping::PingFromHost;
+  use crate::protocol::player::PlayerInfo;
+
+  let name = format!("Player {}", i);
+  let message = "m".repeat(i as usize % 200);
+  match pool {
+    Some(pool) => vec![
+      pool.simple(PingFromHost::with_payload(i)).unwrap(),
+      pool.simple(PlayerInfo::new(1, &name)).unwrap(),
+      pool.simple(ChatFromHost::lobby(1, &[2], &message)).unwrap(),
+    ],
+    None => vec![
+      Packet::simple(PingFromHost::with_payload(i)).unwrap(),
+      Packet::simple(PlayerInfo::new(1, &name)).unwrap(),
+      Packet::simple(ChatFromHost::lobby(1, &[2], &message)).unwrap(),
+    ],
+  }
+}
+
+#[cfg(test)]
+fn encode_packets(packets: &[Packet]) -> BytesMut {
+  let mut buf = BytesMut::new();
+  for packet in packets {
+    packet.encode(&mut buf);
+  }
+  buf
+}
+
+#[test]
+fn test_packet_buffer_pool_identical_bytes() {
+  let pool = PacketBufferPool::new(2, 64);
+  // larger payloads first so shorter ones reuse buffers with stale bytes
+  for i in (0..300).rev() {
+    let pooled = test_packets(Some(&pool), i);
+    let plain = test_packets(None, i);
+    assert_eq!(encode_packets(&pooled), encode_packets(&plain));
+  }
+  assert!(pool.idle_buffers() <= 2);
+}
+
+#[test]
+fn test_packet_buffer_pool_threads() {
+  use std::sync::Arc;
+
+  let pool = Arc::new(PacketBufferPool::new(4, 256));
+  let handles: Vec<_> = (0..8)
+    .map(|t| {
+      let pool = pool.clone();
+      std::thread::spawn(move || {
+        for i in 0..200 {
+          let i = t * 1000 + i;
+          let pooled = test_packets(Some(&pool), i);
+          let plain = test_packets(None, i);
+          assert_eq!(encode_packets(&pooled), encode_packets(&plain));
+        }
+      })
+    })
+    .collect();
+  for handle in handles {
+    handle.join().unwrap();
+  }
+  assert!(pool.idle_buffers() <= 4);
+}
+
+/// Compares pooled and non-pooled packet building,
+/// run with `cargo test -p flo-w3gs --release -- --ignored --nocapture bench_packet_buffer_pool`
+#[test]
+#[ignore]
+fn bench_packet_buffer_pool() {
+  use std::time::Instant;
+
+  const N: u32 = 200_000;
+  let pool = PacketBufferPool::default();
+
+  let t = Instant::now();
+  for i in 0..N {
+    test_packets(None, i);
+  }
+  let plain = t.elapsed();
+
+  let t = Instant::now();
+  for i in 0..N {
+    test_packets(Some(&pool), i);
+  }
+  let pooled = t.elapsed();
+
+  println!(
+    "{} iterations: non-pooled = {:?}, pooled = {:?}",
+    N, plain, pooled
+  );
+}
@@ -7,6 +7,9 @@ const CHUNK_SIZE: usize = 200 * 1024;
 #[derive(Debug, Clone, PartialEq)]
 pub struct MapChecksum {
   pub xoro: u32,
+  /// `xoro` as computed by classic clients, which only load JASS map scripts,
+  /// 0 if the map only has a Lua script
+  pub classic_xoro: u32,
   pub crc32: u32,
   pub sha1: [u8; 20],
   pub file_size: usize,
@@ -42,51 +45,12 @@ impl MapChecksum {
       }
     }
 
-    let xoro = {
-      let mut xoro = XoroHasher::new();
-
-      let files: &[&[&str]] = &[
-        &[
-          "war3map.j",
-          "scripts\\war3map.j",
-          "war3map.lua",
-          "scripts\\war3map.lua",
-        ],
-        &["war3map.w3e"],
-        &["war3map.wpm"],
-        &["war3map.doo"],
-        &["war3map.w3u"],
-        &["war3map.w3b"],
-        &["war3map.w3d"],
-        &["war3map.w3a"],
-        &["war3map.w3q"],
-      ];
-
-      for (i, paths) in files.into_iter().enumerate() {
-        let mut found = false;
-        for path in *paths {
-          if let Some(bytes) = archive.read_file_all_opt(path)? {
-            if i == 0 {
-              xoro.update(&bytes);
-            } else {
-              let mut h = XoroHasher::new();
-              h.update(&bytes);
-              let v = h.finalize();
-              xoro.update(&v.to_le_bytes());
-            }
-            found = true;
-            break;
-          }
-        }
-        if !found && i == 0 {
-          return Err(Error::MapScriptNotFound);
-        }
-      }
-      xoro
-    };
+    let xoro = compute_xoro(archive, SCRIPT_PATHS)?.ok_or(Error::MapScriptNotFound)?;
+    let classic_xoro = compute_xoro(archive, CLASSIC_SCRIPT_PATHS)?.unwrap_or(0);
 
     Ok(Self {
-      xoro: xoro.finalize(),
+      xoro,
+      classic_xoro,
       crc32: crc32.finalize(),
       sha1: sha1.digest().bytes(),
       file_size,
@@ -98,6 +62,54 @@ impl MapChecksum {
   }
 }
 
+const SCRIPT_PATHS: &[&str] = &[
+  "war3map.j",
+  "scripts\\war3map.j",
+  "war3map.lua",
+  "scripts\\war3map.lua",
+];
+const CLASSIC_SCRIPT_PATHS: &[&str] = &["war3map.j", "scripts\\war3map.j"];
+
+/// Returns `None` if none of `script_paths` exists
+fn compute_xoro(archive: &mut Archive, script_paths: &[&str]) -> Result<Option<u32>> {
+  let mut xoro = XoroHasher::new();
+
+  let files: &[&[&str]] = &[
+    script_paths,
+    &["war3map.w3e"],
+    &["war3map.wpm"],
+    &["war3map.doo"],
+    &["war3map.w3u"],
+    &["war3map.w3b"],
+    &["war3map.w3d"],
+    &["war3map.w3a"],
+    &["war3map.w3q"],
+  ];
+
+  for (i, paths) in files.into_iter().enumerate() {
+    let mut found = false;
+    for path in *paths {
+      if let Some(bytes) = archive.read_file_all_opt(path)? {
+        if i == 0 {
+          xoro.update(&bytes);
+        } else {
+          let mut h = XoroHasher::new();
+          h.update(&bytes);
+          let v = h.finalize();
+          xoro.update(&v.to_le_bytes());
+        }
+        found = true;
+        break;
+      }
+    }
+    if !found && i == 0 {
+      return Ok(None);
+    }
+  }
+
+  Ok(Some(xoro.finalize()))
+}
+
 struct XoroHasher(u32);
 
 impl XoroHasher {
@@ -81,6 +81,10 @@ impl W3Map {
     Ok((map, checksum))
   }
 
+  pub fn calc_checksum_memory(bytes: &[u8]) -> Result<MapChecksum> {
+    MapChecksum::compute(&mut Self::open_archive_memory(bytes)?)
+  }
+
   #[cfg(feature = "w3storage")]
   pub fn calc_checksum(storage: &W3Storage, path: &str) -> Result<MapChecksum> {
     use flo_w3storage::Data;
@@ -421,6 +425,7 @@ fn test_open_storage_with_checksum() {
     checksum,
     MapChecksum {
       xoro: 2039165270,
+      classic_xoro: 2039165270,
       crc32: 1444344839,
       sha1: [
         201, 228, 110, 214, 86, 255, 142, 141, 140, 96, 141, 57, 3, 110, 63, 27, 250, 11, 28, 194,
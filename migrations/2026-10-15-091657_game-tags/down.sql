@@ -0,0 +1,2 @@
+DROP INDEX game_tags_idx;
+ALTER TABLE game DROP COLUMN tags;
\ No newline at end of file
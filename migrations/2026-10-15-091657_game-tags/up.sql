@@ -0,0 +1,2 @@
+ALTER TABLE game ADD COLUMN tags text[] NOT NULL DEFAULT '{}';
+CREATE INDEX game_tags_idx ON game USING gin (tags);
\ No newline at end of file
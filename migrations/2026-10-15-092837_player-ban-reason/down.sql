@@ -0,0 +1,2 @@
+alter table player_ban
+    drop column reason;
@@ -0,0 +1,2 @@
+alter table player_ban
+    add column reason text;
@@ -0,0 +1 @@
+drop table player_mutes;
\ No newline at end of file
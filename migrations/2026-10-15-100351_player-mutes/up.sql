@@ -0,0 +1,8 @@
+create table player_mutes (
+    id serial not null primary key,
+    player_id integer not null references player(id),
+    mute_expires_at timestamp with time zone,
+    reason text,
+    created_at timestamp with time zone default now() not null,
+    unique(player_id)
+);
@@ -0,0 +1 @@
+drop table ip_ban;
\ No newline at end of file
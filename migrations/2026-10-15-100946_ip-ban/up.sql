@@ -0,0 +1,9 @@
+create table ip_ban (
+    id serial not null primary key,
+    api_client_id integer not null references api_client(id),
+    network text not null,
+    ban_expires_at timestamp with time zone,
+    reason text,
+    created_at timestamp with time zone default now() not null,
+    unique(api_client_id, network)
+);
@@ -0,0 +1 @@
+alter table node drop column max_games;
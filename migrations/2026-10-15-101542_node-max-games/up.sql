@@ -0,0 +1 @@
+alter table node add column max_games integer;
@@ -0,0 +1 @@
+alter table player drop column preferred_race;
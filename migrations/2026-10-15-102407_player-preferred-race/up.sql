@@ -0,0 +1 @@
+alter table player add column preferred_race integer;
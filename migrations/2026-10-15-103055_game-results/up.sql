@@ -0,0 +1,8 @@
+create table game_results (
+    id serial not null primary key,
+    game_id integer not null references game(id),
+    player_id integer not null references player(id),
+    outcome integer not null,
+    created_at timestamp with time zone default now() not null,
+    unique(game_id, player_id)
+);
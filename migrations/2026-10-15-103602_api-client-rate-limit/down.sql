@@ -0,0 +1 @@
+alter table api_client drop column rate_limit_per_minute;
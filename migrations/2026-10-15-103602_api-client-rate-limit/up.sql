@@ -0,0 +1 @@
+alter table api_client add column rate_limit_per_minute integer;
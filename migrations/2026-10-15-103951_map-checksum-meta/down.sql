@@ -0,0 +1 @@
+alter table map_checksum drop column meta;
\ No newline at end of file
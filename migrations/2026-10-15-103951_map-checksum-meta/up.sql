@@ -0,0 +1 @@
+alter table map_checksum add column meta jsonb;
\ No newline at end of file
@@ -0,0 +1 @@
+drop table server_maintenance;
\ No newline at end of file
@@ -0,0 +1,5 @@
+create table server_maintenance (
+    id integer not null primary key default 1 check (id = 1),
+    drain_at timestamp with time zone,
+    created_at timestamp with time zone default now() not null
+);
@@ -0,0 +1,2 @@
+alter table game
+    add column game_flags integer default 0 not null;
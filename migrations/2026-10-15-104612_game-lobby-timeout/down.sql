@@ -0,0 +1,2 @@
+alter table game
+    drop column lobby_timeout_at;
@@ -0,0 +1,2 @@
+alter table game
+    add column lobby_timeout_at timestamptz;